@@ -0,0 +1,302 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use console::style;
+use flate2::read::GzDecoder;
+use git2::Repository;
+
+use crate::git::operations::add_and_commit;
+use crate::utils::{http_get, is_offline};
+
+/// The largest archive that is downloaded, in bytes
+const MAX_ARCHIVE_SIZE: u64 = 100 * 1024 * 1024;
+
+/// The most an archive may unpack to, in bytes, so that a small compressed archive can't fill the
+/// disk
+const MAX_UNPACKED_SIZE: u64 = 500 * 1024 * 1024;
+
+/// The formats of archives dotfiles can be installed from, told apart by the extension of their
+/// URL
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveFormat {
+    TarGz,
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Return the format of the archive at `url`, or None if it isn't the HTTP(S) URL of an archive
+    fn of_url(url: &str) -> Option<ArchiveFormat> {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return None;
+        }
+        let path = url.split(&['?', '#'][..]).next()?.to_lowercase();
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if path.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Return whether `url` is the URL of an archive (e.g. a release asset) to install dotfiles from,
+/// rather than of a git repository
+pub fn is_archive_url(url: &str) -> bool {
+    ArchiveFormat::of_url(url).is_some()
+}
+
+/// Return an error if `repository` is an archive, as changes can't be `action` (e.g. "synced") to
+/// it
+pub fn ensure_not_archive(repository: &str, action: &str) -> Result<(), Box<dyn Error>> {
+    if is_archive_url(repository) {
+        return Err(format!(
+            "{} is an archive rather than a git repository, so dotfiles can't be {} to it",
+            repository, action
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Download the archive at the HTTPS `url` and unpack it into `target_dir`, returning a repository
+/// of its contents with a single commit so that they can be installed like those of any other
+/// repository. If the archive only contains a directory (as the source archives of a release do),
+/// the repository is made of its contents instead
+pub fn open_archive(url: &str, target_dir: &Path) -> Result<Repository, Box<dyn Error>> {
+    let format = ArchiveFormat::of_url(url)
+        .ok_or_else(|| format!("{} is not a .tar.gz, .tgz, .tar or .zip archive", url))?;
+    // Nothing else vouches for the contents of an archive, which can run install steps
+    if !url.starts_with("https://") {
+        return Err(format!(
+            "Refusing to download {} over plain HTTP, as it could be tampered with on the way. \
+            Use an https:// URL instead",
+            url
+        )
+        .into());
+    }
+    if is_offline() {
+        return Err(format!("Cannot download {} while offline", url).into());
+    }
+
+    debug!("Downloading {} into {}", url, target_dir.to_string_lossy());
+    let mut contents = vec![];
+    http_get(url)?
+        .into_reader()
+        .take(MAX_ARCHIVE_SIZE + 1)
+        .read_to_end(&mut contents)?;
+    if contents.len() as u64 > MAX_ARCHIVE_SIZE {
+        return Err(format!(
+            "{} is larger than the limit of {} MiB",
+            url,
+            MAX_ARCHIVE_SIZE / 1024 / 1024
+        )
+        .into());
+    }
+    unpack(format, &contents, target_dir, MAX_UNPACKED_SIZE)
+        .map_err(|err| format!("Could not unpack {}: {}", url, err))?;
+
+    let repo = Repository::init(archive_root(target_dir)?)?;
+    add_and_commit(
+        &repo,
+        None,
+        &format!("Unpack {}", url),
+        Some(vec![]),
+        Some("HEAD"),
+    )?;
+    success!("Downloaded {}", url);
+    Ok(repo)
+}
+
+/// Unpack `contents` into `target_dir`, failing once more than `max_size` bytes have been unpacked
+fn unpack(
+    format: ArchiveFormat,
+    contents: &[u8],
+    target_dir: &Path,
+    max_size: u64,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ArchiveFormat::TarGz => unpack_tar(GzDecoder::new(contents), target_dir, max_size),
+        ArchiveFormat::Tar => unpack_tar(contents, target_dir, max_size),
+        ArchiveFormat::Zip => unpack_zip(contents, target_dir, max_size),
+    }
+}
+
+/// Add `size` bytes to the `unpacked` total, returning an error if it goes over `max_size`
+fn count_unpacked(unpacked: &mut u64, size: u64, max_size: u64) -> Result<(), Box<dyn Error>> {
+    *unpacked += size;
+    if *unpacked > max_size {
+        return Err(format!(
+            "it unpacks to more than the limit of {} MiB",
+            max_size / 1024 / 1024
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn unpack_tar(reader: impl Read, target_dir: &Path, max_size: u64) -> Result<(), Box<dyn Error>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut unpacked = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        // Sparse files unpack to more than the size in their header
+        if entry.header().entry_type().is_gnu_sparse() {
+            return Err(format!(
+                "{} is a sparse file, which isn't supported",
+                entry.path()?.to_string_lossy()
+            )
+            .into());
+        }
+        count_unpacked(&mut unpacked, entry.size(), max_size)?;
+        // Entries outside of target_dir, e.g. with "../" in their path, are skipped
+        entry.unpack_in(target_dir)?;
+    }
+    Ok(())
+}
+
+fn unpack_zip(contents: &[u8], target_dir: &Path, max_size: u64) -> Result<(), Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(contents))?;
+    let mut unpacked = 0;
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        // As with tar entries, files outside of target_dir are skipped
+        let path = match file.enclosed_name() {
+            Some(path) => target_dir.join(path),
+            None => continue,
+        };
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // The size a zip file claims to unpack to can't be trusted, so count what is written
+        let mut output = fs::File::create(&path)?;
+        let limit = max_size - unpacked + 1;
+        let written = io::copy(&mut (&mut file).take(limit), &mut output)?;
+        count_unpacked(&mut unpacked, written, max_size)?;
+        #[cfg(unix)]
+        if let Some(mode) = file.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+/// Return the directory the contents of the archive unpacked into `dir` are in: the only directory
+/// in `dir` if it contains nothing else, or `dir` itself otherwise
+fn archive_root(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    match entries.as_slice() {
+        [entry] if entry.file_type()?.is_dir() => Ok(entry.path()),
+        _ => Ok(dir.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempfile::tempdir;
+
+    use crate::git::operations::get_head;
+
+    use super::*;
+
+    #[test]
+    fn test_is_archive_url() {
+        assert!(is_archive_url(
+            "https://github.com/dob9601/dotfiles/releases/download/v1/dotfiles.tar.gz"
+        ));
+        assert!(is_archive_url("https://example.com/dotfiles.ZIP?token=abc"));
+        assert!(is_archive_url("http://example.com/dotfiles.tgz"));
+        assert!(!is_archive_url("https://github.com/dob9601/dotfiles.git"));
+        assert!(!is_archive_url("git@github.com:dob9601/dotfiles.tar.gz"));
+        assert!(!is_archive_url("/home/dob9601/dotfiles.zip"));
+    }
+
+    #[test]
+    fn test_open_archive_over_http() {
+        let dir = tempdir().unwrap();
+        match open_archive("http://example.com/dotfiles.tar.gz", dir.path()) {
+            Err(err) => assert!(err.to_string().starts_with("Refusing to download")),
+            Ok(_) => panic!("Downloaded an archive over plain HTTP"),
+        }
+    }
+
+    #[test]
+    fn test_unpack_archive() {
+        let mut builder = tar::Builder::new(vec![]);
+        let contents = b"kitty:\n  file: kitty.conf\n  target: ~/.config/kitty/kitty.conf\n";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "dotfiles-1.0/jtd.yaml", &contents[..])
+            .unwrap();
+        let mut tarball = GzEncoder::new(vec![], Compression::default());
+        tarball.write_all(&builder.into_inner().unwrap()).unwrap();
+        let tarball = tarball.finish().unwrap();
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file("jtd.yaml", Default::default()).unwrap();
+        zip.write_all(contents).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        for (format, archive, root) in [
+            (ArchiveFormat::TarGz, tarball, Some("dotfiles-1.0")),
+            (ArchiveFormat::Zip, zip, None),
+        ] {
+            let dir = tempdir().unwrap();
+            unpack(format, &archive, dir.path(), MAX_UNPACKED_SIZE).unwrap();
+            let expected_root = match root {
+                Some(root) => dir.path().join(root),
+                None => dir.path().to_path_buf(),
+            };
+            assert_eq!(archive_root(dir.path()).unwrap(), expected_root);
+
+            let repo = Repository::init(archive_root(dir.path()).unwrap()).unwrap();
+            add_and_commit(&repo, None, "Unpack", Some(vec![]), Some("HEAD")).unwrap();
+            assert!(get_head(&repo)
+                .unwrap()
+                .tree()
+                .unwrap()
+                .get_name("jtd.yaml")
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn test_unpack_archive_over_limit() {
+        let contents = vec![b'#'; 2048];
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "vimrc", &contents[..])
+            .unwrap();
+        let tarball = builder.into_inner().unwrap();
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file("vimrc", Default::default()).unwrap();
+        zip.write_all(&contents).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        for (format, archive) in [(ArchiveFormat::Tar, tarball), (ArchiveFormat::Zip, zip)] {
+            let dir = tempdir().unwrap();
+            assert!(unpack(format, &archive, dir.path(), 1024).is_err());
+            unpack(format, &archive, dir.path(), 2048).unwrap();
+        }
+    }
+}
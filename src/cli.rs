@@ -1,19 +1,310 @@
-use clap::Parser;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+
+use clap::{ArgEnum, Command, CommandFactory, ErrorKind, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
 
 use crate::git::remote::{ConnectionMethod, RepoHostName};
+use crate::log::OutputFormat;
+use crate::structs::{MergeStrategy, OnConflict, RepositoryOrigin, UserConfig};
+use crate::subcommands::{ExportFormat, Wizard};
 
 #[derive(Parser, Debug)]
 #[clap(name = "jointhedots", bin_name = "jtd", about, version)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: JoinTheDots,
+
+    #[clap(
+        help = "Print more details of what jtd is doing. Pass twice for even more",
+        long = "verbose",
+        short = 'v',
+        parse(from_occurrences),
+        global = true
+    )]
+    pub verbose: u64,
+
+    #[clap(
+        help = "Only print warnings and errors",
+        long = "quiet",
+        short = 'q',
+        conflicts_with = "verbose",
+        global = true
+    )]
+    pub quiet: bool,
+
+    #[clap(
+        help = "Print what git is doing when cloning, fetching and pushing (refs fetched, \
+        credentials tried, push negotiation), to diagnose failed clones and pushes",
+        long = "debug-git",
+        global = true
+    )]
+    pub debug_git: bool,
+
+    #[clap(
+        help = "The SSH key to authenticate with, instead of trying every key in ssh-agent and \
+        ~/.ssh. Defaults to $JTD_SSH_KEY",
+        long = "ssh-key",
+        global = true
+    )]
+    pub ssh_key: Option<PathBuf>,
+
+    #[clap(
+        help = "The HTTP(S) proxy to clone, fetch and push through, e.g. http://proxy:3128. \
+        Defaults to $https_proxy, $http_proxy or $all_proxy",
+        long = "proxy",
+        global = true
+    )]
+    pub proxy: Option<String>,
+
+    #[clap(
+        help = "Wait for other jtd runs changing the installed dotfiles to finish instead of \
+        failing",
+        long = "wait",
+        global = true
+    )]
+    pub wait: bool,
+
+    #[clap(
+        help = "The directory to store the metadata of the installed dotfiles, backups and the \
+        operation log in. Defaults to $JTD_DATA_DIR, or jointhedots in $XDG_DATA_HOME \
+        (~/.local/share)",
+        long = "data-dir",
+        global = true
+    )]
+    pub data_dir: Option<PathBuf>,
+}
+
+/// The subcommands that default to the repository and manifest the dotfiles were installed from
+/// rather than those in the user config, as they operate on the installed dotfiles
+const INSTALLED_FROM_SUBCOMMANDS: [&str; 3] = ["sync", "diff", "push"];
+
+impl Cli {
+    /// Return the value of `--data-dir` in `args`, if passed. It is needed before the arguments
+    /// are parsed, as the defaults they are parsed with come from the data directory
+    pub fn find_data_dir<T: AsRef<OsStr>>(args: &[T]) -> Option<PathBuf> {
+        let mut args = args.iter().map(AsRef::as_ref);
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                return None;
+            } else if arg == "--data-dir" {
+                return args.next().map(PathBuf::from);
+            } else if let Some(data_dir) =
+                arg.to_str().and_then(|arg| arg.strip_prefix("--data-dir="))
+            {
+                return Some(PathBuf::from(data_dir));
+            }
+        }
+        None
+    }
+
+    /// Parse `args` as the arguments of jtd, with the defaults in `user_config` replacing the
+    /// built-in defaults of any subcommand that takes them. Subcommands operating on the installed
+    /// dotfiles default to the repository and manifest they were `installed_from` instead.
+    ///
+    /// As the repository is a positional argument followed by the dotfiles, a default repository
+    /// is used when no positional arguments are given, or when the first one doesn't look like a
+    /// repository (see [looks_like_repository]), in which case it is taken as the first dotfile
+    pub fn try_parse_with_defaults<I, T>(
+        args: I,
+        user_config: &UserConfig,
+        installed_from: Option<&RepositoryOrigin>,
+    ) -> Result<Cli, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        let command = Cli::command_with_defaults(user_config, installed_from)?;
+        let default_repository =
+            |subcommand: &str| default_repository(subcommand, user_config, installed_from);
+
+        let result = command.clone().try_get_matches_from(&args);
+        let default_needed = match &result {
+            Ok(matches) => matches
+                .subcommand()
+                .filter(|(name, _)| has_repository_arg(&command, name))
+                .and_then(|(_, matches)| matches.value_of("repository"))
+                .is_some_and(|repository| !looks_like_repository(repository)),
+            Err(err) => err.kind() == ErrorKind::MissingRequiredArgument,
+        };
+        let matches = match default_needed {
+            true => match insert_repository(&command, &args, default_repository) {
+                Some(args) => command.try_get_matches_from(args)?,
+                None => result?,
+            },
+            false => result?,
+        };
+
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        if user_config.trust {
+            match &mut cli.command {
+                JoinTheDots::Install(args) => args.trust = true,
+                JoinTheDots::Update(args) => args.trust = true,
+                JoinTheDots::Uninstall(args) => args.trust = true,
+                JoinTheDots::Restore(args) => args.trust = true,
+                _ => {}
+            }
+        }
+        if let JoinTheDots::Diff(args) = &mut cli.command {
+            if args.tool.is_none() {
+                args.tool = user_config.diff_tool.clone();
+            }
+        }
+        Ok(cli)
+    }
+
+    /// Return the [Command] for jtd, with the defaults in `user_config` applied, and the manifest
+    /// the dotfiles were `installed_from` for the subcommands operating on them
+    pub fn command_with_defaults(
+        user_config: &UserConfig,
+        installed_from: Option<&RepositoryOrigin>,
+    ) -> Result<Command<'static>, Box<dyn Error>> {
+        let source = user_config
+            .source
+            .as_deref()
+            .map(|source| arg_enum_name(source.parse::<RepoHostName>()?))
+            .transpose()
+            .map_err(|err| format!("Invalid source in config file: {}", err))?;
+        let method = user_config
+            .method
+            .as_deref()
+            .map(|method| arg_enum_name(method.parse::<ConnectionMethod>()?))
+            .transpose()
+            .map_err(|err| format!("Invalid method in config file: {}", err))?;
+        let defaults = [
+            ("source", source),
+            ("method", method),
+            ("manifest", user_config.manifest.clone()),
+            ("subdir", None),
+        ];
+
+        let mut command = Cli::command();
+        for subcommand in command.get_subcommands_mut() {
+            let mut defaults = defaults.clone();
+            if let Some(origin) = installed_from {
+                if INSTALLED_FROM_SUBCOMMANDS.contains(&subcommand.get_name()) {
+                    defaults[2].1 = Some(origin.manifest.clone());
+                    defaults[3].1 = origin.subdir.clone();
+                }
+            }
+            for (id, default) in &defaults {
+                let default = match default {
+                    Some(default) => default,
+                    None => continue,
+                };
+                if !subcommand.get_arguments().any(|arg| arg.get_id() == *id) {
+                    continue;
+                }
+                // Clap requires defaults to live as long as the command
+                let default: &'static str = Box::leak(default.clone().into_boxed_str());
+                *subcommand = subcommand
+                    .clone()
+                    .mut_arg(*id, |arg| arg.default_value(default));
+            }
+        }
+        Ok(command)
+    }
+}
+
+/// Return the repository the subcommand `subcommand` uses when none is passed, i.e. the one in
+/// `user_config`, or the one the dotfiles were `installed_from` for the subcommands operating on
+/// them
+pub(crate) fn default_repository<'a>(
+    subcommand: &str,
+    user_config: &'a UserConfig,
+    installed_from: Option<&'a RepositoryOrigin>,
+) -> Option<&'a str> {
+    match installed_from {
+        Some(origin) if INSTALLED_FROM_SUBCOMMANDS.contains(&subcommand) => {
+            Some(origin.repository.as_str())
+        }
+        _ => user_config.repository.as_deref(),
+    }
+}
+
+/// Whether `value`, given where a repository is expected, is a repository rather than the name
+/// of a dotfile. Repositories are always given as USERNAME/REPONAME, a URL or a path, so contain a
+/// slash or colon
+pub fn looks_like_repository(value: &str) -> bool {
+    value.contains('/') || value.contains(':')
+}
+
+/// Whether the subcommand `name` of `command` takes a repository
+fn has_repository_arg(command: &Command, name: &str) -> bool {
+    command.find_subcommand(name).is_some_and(|subcommand| {
+        subcommand
+            .get_arguments()
+            .any(|arg| arg.get_id() == "repository")
+    })
+}
+
+/// Insert the default repository of the subcommand in `args` directly after it, if it takes a
+/// repository and has a default
+fn insert_repository<'a>(
+    command: &Command,
+    args: &[OsString],
+    default_repository: impl Fn(&str) -> Option<&'a str>,
+) -> Option<Vec<OsString>> {
+    let (position, subcommand) = args
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find_map(|(position, arg)| {
+            arg.to_str()
+                .filter(|arg| has_repository_arg(command, arg))
+                .map(|arg| (position + 1, arg))
+        })?;
+    let repository = default_repository(subcommand)?;
+
+    let mut args = args.to_vec();
+    args.insert(position, repository.into());
+    Some(args)
+}
+
+/// Return the name `value` is given on the command line
+fn arg_enum_name<T: ArgEnum>(value: T) -> Result<String, Box<dyn Error>> {
+    Ok(value
+        .to_possible_value()
+        .ok_or("Value can't be passed on the command line")?
+        .get_name()
+        .to_string())
+}
+
+#[derive(Subcommand, Debug)]
 pub enum JoinTheDots {
     Install(InstallSubcommandArgs),
     Sync(SyncSubcommandArgs),
+    Push(PushSubcommandArgs),
+    Lock(LockSubcommandArgs),
     Interactive(InteractiveSubcommandArgs),
+    Validate(ValidateSubcommandArgs),
+    Doctor(DoctorSubcommandArgs),
+    Completions(CompletionsSubcommandArgs),
+    Vars(VarsSubcommandArgs),
+    State(StateSubcommandArgs),
+    Trust(TrustSubcommandArgs),
+    Rollback(RollbackSubcommandArgs),
+    Uninstall(UninstallSubcommandArgs),
+    Diff(DiffSubcommandArgs),
+    Export(ExportSubcommandArgs),
+    Migrate(MigrateSubcommandArgs),
+    Update(UpdateSubcommandArgs),
+    SelfUpdate(SelfUpdateSubcommandArgs),
+    Watch(WatchSubcommandArgs),
+    Which(WhichSubcommandArgs),
+    History(HistorySubcommandArgs),
+    Restore(RestoreSubcommandArgs),
 }
 
 #[derive(clap::Args, Debug)]
 #[clap(about = "Install a specified JTD repository", version)]
 pub struct InstallSubcommandArgs {
-    #[clap(help = "The location of the repository in the form USERNAME/REPONAME")]
+    #[clap(
+        help = "The location of the repository in the form USERNAME/REPONAME. Can also be a git \
+        URL or the path of a local clone"
+    )]
     pub repository: String,
 
     #[clap(
@@ -34,7 +325,51 @@ pub struct InstallSubcommandArgs {
     pub manifest: String,
 
     #[clap(
-        help = "The dotfiles to install. If unspecified, install all of them",
+        long = "subdir",
+        help = "The subdirectory of the repository the manifest is in, for dotfiles kept in a \
+        larger repository. The file paths of the dotfiles are relative to it"
+    )]
+    pub subdir: Option<String>,
+
+    #[clap(
+        long = "branch",
+        short = 'b',
+        help = "The branch of the repository to use. If unspecified, the default branch of the \
+        remote is used"
+    )]
+    pub branch: Option<String>,
+
+    #[clap(
+        long = "depth",
+        help = "The number of commits of history to clone. Use 0 to clone the full history",
+        default_value = "1"
+    )]
+    pub depth: u32,
+
+    #[clap(
+        long = "no-cache",
+        help = "Clone the repository into a temporary directory instead of using the cached clone in \
+        ~/.cache/jointhedots"
+    )]
+    pub no_cache: bool,
+
+    #[clap(
+        long = "repo-dir",
+        help = "Install from your own clone of the repository at this path (e.g. ~/dotfiles), \
+        fetching from its origin, instead of a cached or temporary clone",
+        conflicts_with_all = &["no-cache", "all-repos"]
+    )]
+    pub repo_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "offline",
+        help = "Install from the cached clone of the repository without accessing the network",
+        conflicts_with = "no-cache"
+    )]
+    pub offline: bool,
+
+    #[clap(
+        help = "The dotfiles or groups of dotfiles to install. If unspecified, install all of them",
         conflicts_with = "all"
     )]
     pub target_dotfiles: Vec<String>,
@@ -42,7 +377,7 @@ pub struct InstallSubcommandArgs {
     #[clap(
         arg_enum,
         default_value = "GitHub",
-        help = "Whether to source the repo from GitHub or GitLab",
+        help = "The host to source the repo from",
         long = "source",
         short = 's',
         ignore_case = true
@@ -57,7 +392,32 @@ pub struct InstallSubcommandArgs {
     pub force: bool,
 
     #[clap(
-        help = "Whether to run any pre_install/post_install commands without prompting",
+        arg_enum,
+        help = "What to do with dotfiles that already exist on disk instead of asking: keep them, \
+        overwrite them with the version in the repository or merge the two",
+        long = "on-conflict",
+        conflicts_with = "force"
+    )]
+    pub on_conflict: Option<OnConflict>,
+
+    #[clap(
+        help = "Keep a copy of each overwritten file next to it, named with the backup_suffix of \
+        the manifest (\".jtd-bak\" by default), e.g. kitty.conf.jtd-bak",
+        long = "keep-backup"
+    )]
+    pub keep_backup: bool,
+
+    #[clap(
+        help = "Install each dotfile from the commit it is locked to in the jtd.lock next to the \
+        manifest, as written by \"jtd lock\". Fails if there is no lockfile, a dotfile isn't \
+        locked or a locked commit isn't in the history of the repository",
+        long = "locked"
+    )]
+    pub locked: bool,
+
+    #[clap(
+        help = "Whether to run any pre_install/post_install commands and only_if conditions, and \
+        write requires_root dotfiles as root, without prompting",
         long = "trust",
         short = 't'
     )]
@@ -69,6 +429,171 @@ pub struct InstallSubcommandArgs {
         short = 'a'
     )]
     pub all: bool,
+
+    #[clap(
+        help = "Reinstall the dotfiles installed from every repository dotfiles have been installed from (or all of their dotfiles, with \"--all\"), instead of installing from REPOSITORY",
+        long = "all-repos"
+    )]
+    pub all_repos: bool,
+
+    #[clap(
+        help = "Print the file copies and install steps that would happen without performing them",
+        long = "dry-run"
+    )]
+    pub dry_run: bool,
+
+    #[clap(
+        help = "When no dotfiles are specified, pick the ones to install in a full-screen interface \
+        that shows their targets and install steps and previews their changes",
+        long = "tui"
+    )]
+    pub tui: bool,
+
+    #[clap(
+        help = "Run without prompting for input, answering \"yes\" to confirmations and failing if \
+        any other input is required. Can also be enabled with JTD_NONINTERACTIVE=1",
+        long = "yes",
+        short = 'y',
+        alias = "non-interactive"
+    )]
+    pub yes: bool,
+
+    #[clap(
+        arg_enum,
+        long = "output",
+        short = 'o',
+        help = "The format to print the results in",
+        default_value = "text"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Reinstall the installed dotfiles that have changed in a JTD repository",
+    version
+)]
+pub struct UpdateSubcommandArgs {
+    #[clap(
+        help = "The location of the repository in the form USERNAME/REPONAME. Can also be a git \
+        URL or the path of a local clone"
+    )]
+    pub repository: String,
+
+    #[clap(
+        arg_enum,
+        long = "method",
+        short = 'm',
+        help = "The method to use for cloning/pushing the repository",
+        default_value = "https"
+    )]
+    pub method: ConnectionMethod,
+
+    #[clap(
+        long = "manifest",
+        short = 'n',
+        help = "The manifest to use in the repository",
+        default_value = "jtd.yaml"
+    )]
+    pub manifest: String,
+
+    #[clap(
+        long = "subdir",
+        help = "The subdirectory of the repository the manifest is in, for dotfiles kept in a \
+        larger repository. The file paths of the dotfiles are relative to it"
+    )]
+    pub subdir: Option<String>,
+
+    #[clap(
+        long = "branch",
+        short = 'b',
+        help = "The branch of the repository to use. If unspecified, the default branch of the \
+        remote is used"
+    )]
+    pub branch: Option<String>,
+
+    #[clap(
+        long = "depth",
+        help = "The number of commits of history to clone. Use 0 to clone the full history",
+        default_value = "1"
+    )]
+    pub depth: u32,
+
+    #[clap(
+        long = "no-cache",
+        help = "Clone the repository into a temporary directory instead of using the cached clone in \
+        ~/.cache/jointhedots"
+    )]
+    pub no_cache: bool,
+
+    #[clap(
+        long = "offline",
+        help = "Update from the cached clone of the repository without accessing the network",
+        conflicts_with = "no-cache"
+    )]
+    pub offline: bool,
+
+    #[clap(
+        help = "The dotfiles or groups of dotfiles to update. If unspecified, update every installed \
+        dotfile"
+    )]
+    pub target_dotfiles: Vec<String>,
+
+    #[clap(
+        arg_enum,
+        default_value = "GitHub",
+        help = "The host to source the repo from",
+        long = "source",
+        short = 's',
+        ignore_case = true
+    )]
+    pub source: RepoHostName,
+
+    #[clap(
+        help = "Whether to overwrite unsynchronised configs without prompt",
+        long = "force",
+        short = 'f'
+    )]
+    pub force: bool,
+
+    #[clap(
+        help = "Keep a copy of each overwritten file next to it, named with the backup_suffix of \
+        the manifest (\".jtd-bak\" by default), e.g. kitty.conf.jtd-bak",
+        long = "keep-backup"
+    )]
+    pub keep_backup: bool,
+
+    #[clap(
+        help = "Whether to run any pre_install/post_install commands and only_if conditions, and \
+        write requires_root dotfiles as root, without prompting",
+        long = "trust",
+        short = 't'
+    )]
+    pub trust: bool,
+
+    #[clap(
+        help = "Print the file copies and install steps that would happen without performing them",
+        long = "dry-run"
+    )]
+    pub dry_run: bool,
+
+    #[clap(
+        help = "Run without prompting for input, answering \"yes\" to confirmations and failing if \
+        any other input is required. Can also be enabled with JTD_NONINTERACTIVE=1",
+        long = "yes",
+        short = 'y',
+        alias = "non-interactive"
+    )]
+    pub yes: bool,
+
+    #[clap(
+        arg_enum,
+        long = "output",
+        short = 'o',
+        help = "The format to print the results in",
+        default_value = "text"
+    )]
+    pub output: OutputFormat,
 }
 
 #[derive(clap::Args, Debug)]
@@ -77,11 +602,14 @@ pub struct InstallSubcommandArgs {
     version
 )]
 pub struct SyncSubcommandArgs {
-    #[clap(help = "The location of the repository in the form USERNAME/REPONAME")]
+    #[clap(
+        help = "The location of the repository in the form USERNAME/REPONAME. Can also be a git \
+        URL or the path of a local clone"
+    )]
     pub repository: String,
 
     #[clap(
-        help = "The dotfiles to sync. If unspecified, sync all of them",
+        help = "The dotfiles or groups of dotfiles to sync. If unspecified, sync all of them",
         conflicts_with = "all"
     )]
     pub target_dotfiles: Vec<String>,
@@ -93,6 +621,12 @@ pub struct SyncSubcommandArgs {
     )]
     pub all: bool,
 
+    #[clap(
+        help = "Sync the installed dotfiles with every repository they were installed from, instead of with REPOSITORY",
+        long = "all-repos"
+    )]
+    pub all_repos: bool,
+
     #[clap(
         arg_enum,
         long = "method",
@@ -110,11 +644,48 @@ pub struct SyncSubcommandArgs {
     )]
     pub manifest: String,
 
+    #[clap(
+        long = "subdir",
+        help = "The subdirectory of the repository the manifest is in, for dotfiles kept in a \
+        larger repository. The file paths of the dotfiles are relative to it"
+    )]
+    pub subdir: Option<String>,
+
+    #[clap(
+        long = "branch",
+        short = 'b',
+        help = "The branch of the repository to use. If unspecified, the default branch of the \
+        remote is used"
+    )]
+    pub branch: Option<String>,
+
+    #[clap(
+        long = "depth",
+        help = "The number of commits of history to clone. If unspecified, clone the full history"
+    )]
+    pub depth: Option<u32>,
+
+    #[clap(
+        long = "no-cache",
+        help = "Clone the repository into a temporary directory instead of using the cached clone in \
+        ~/.cache/jointhedots"
+    )]
+    pub no_cache: bool,
+
+    #[clap(
+        long = "repo-dir",
+        help = "Sync with your own clone of the repository at this path (e.g. ~/dotfiles), \
+        fetching and committing there, instead of a cached or temporary clone",
+        conflicts_with_all = &["no-cache", "all-repos"]
+    )]
+    pub repo_dir: Option<PathBuf>,
+
     #[clap(
         arg_enum,
         default_value = "GitHub",
-        help = "Whether to source the repo from GitHub or GitLab",
-        long = "source"
+        help = "The host to source the repo from",
+        long = "source",
+        ignore_case = true
     )]
     pub source: RepoHostName,
 
@@ -131,8 +702,1103 @@ pub struct SyncSubcommandArgs {
         long = "naive"
     )]
     pub naive: bool,
+
+    #[clap(
+        arg_enum,
+        long = "strategy",
+        help = "How to resolve merge conflicts, overriding the merge_strategy in the manifest. \
+        \"ours\" prefers the local changes and \"theirs\" prefers the remote repo"
+    )]
+    pub strategy: Option<MergeStrategy>,
+
+    #[clap(
+        long = "push-branch",
+        help = "The branch of the remote to push the sync commits to, e.g. machine/laptop, instead of \
+        the branch they were made on. Can contain {hostname} and {date}, overriding the push_branch in \
+        the manifest"
+    )]
+    pub push_branch: Option<String>,
+
+    #[clap(
+        long = "create-pr",
+        help = "Open a pull request on GitHub from the push branch into the branch synced from, or \
+        update the one already open. Requires a push branch, set with --push-branch or push_branch \
+        in the manifest"
+    )]
+    pub create_pr: bool,
+
+    #[clap(
+        long = "create-mr",
+        help = "Open a merge request on GitLab from the push branch into the branch synced from, or \
+        update the one already open. Requires a push branch, set with --push-branch or push_branch \
+        in the manifest",
+        conflicts_with = "create-pr"
+    )]
+    pub create_mr: bool,
+
+    #[clap(
+        long = "gitlab-url",
+        help = "The HTTPS URL of the GitLab instance to open the merge request on, e.g. \
+        https://git.example.com. Defaults to the host of the repository if it is gitlab.com or in \
+        gitlab_hosts in the config file",
+        requires = "create-mr"
+    )]
+    pub gitlab_url: Option<String>,
+
+    #[clap(
+        help = "Only commit the changes to the cached repository, to be pushed later with \"jtd push\"",
+        long = "no-push",
+        conflicts_with_all = &["create-pr", "create-mr"]
+    )]
+    pub no_push: bool,
+
+    #[clap(
+        help = "Print the file copies, commits and pushes that would happen without performing them",
+        long = "dry-run"
+    )]
+    pub dry_run: bool,
+
+    #[clap(
+        help = "When no dotfiles are specified, pick the ones to sync in a full-screen interface \
+        that shows their targets and install steps and previews their changes",
+        long = "tui"
+    )]
+    pub tui: bool,
+
+    #[clap(
+        help = "Show each hunk of the changes to the dotfiles and only sync the ones you pick, like \
+        \"git add -p\". The rest are left in the local copy only",
+        long = "interactive-hunks",
+        conflicts_with = "yes"
+    )]
+    pub interactive_hunks: bool,
+
+    #[clap(
+        help = "Run without prompting for input, answering \"yes\" to confirmations and failing if \
+        any other input is required. Can also be enabled with JTD_NONINTERACTIVE=1",
+        long = "yes",
+        short = 'y',
+        alias = "non-interactive"
+    )]
+    pub yes: bool,
+
+    #[clap(
+        arg_enum,
+        long = "output",
+        short = 'o',
+        help = "The format to print the results in",
+        default_value = "text"
+    )]
+    pub output: OutputFormat,
 }
 
 #[derive(clap::Args, Debug)]
-#[clap(about = "Interactively install dotfiles", version)]
-pub struct InteractiveSubcommandArgs {}
+#[clap(
+    about = "Push the sync commits left unpushed in the cached clone of a JTD repository",
+    version
+)]
+pub struct PushSubcommandArgs {
+    #[clap(
+        help = "The location of the repository in the form USERNAME/REPONAME. Can also be a git \
+        URL or the path of a local clone"
+    )]
+    pub repository: String,
+
+    #[clap(
+        arg_enum,
+        long = "method",
+        short = 'm',
+        help = "The method to use for pushing the repository",
+        default_value = "ssh"
+    )]
+    pub method: ConnectionMethod,
+
+    #[clap(
+        long = "manifest",
+        short = 'n',
+        help = "The manifest to use in the repository",
+        default_value = "jtd.yaml"
+    )]
+    pub manifest: String,
+
+    #[clap(
+        long = "subdir",
+        help = "The subdirectory of the repository the manifest is in, for dotfiles kept in a \
+        larger repository. The file paths of the dotfiles are relative to it"
+    )]
+    pub subdir: Option<String>,
+
+    #[clap(
+        long = "branch",
+        short = 'b',
+        help = "The branch of the repository the commits were made on. If unspecified, the default \
+        branch of the remote is used"
+    )]
+    pub branch: Option<String>,
+
+    #[clap(
+        arg_enum,
+        default_value = "GitHub",
+        help = "The host to source the repo from",
+        long = "source",
+        ignore_case = true
+    )]
+    pub source: RepoHostName,
+
+    #[clap(
+        long = "push-branch",
+        help = "The branch of the remote to push the commits to, e.g. machine/laptop, instead of the \
+        branch they were made on. Can contain {hostname} and {date}, overriding the push_branch in \
+        the manifest"
+    )]
+    pub push_branch: Option<String>,
+
+    #[clap(
+        arg_enum,
+        long = "strategy",
+        help = "How to resolve merge conflicts, overriding the merge_strategy in the manifest. \
+        \"ours\" prefers the local changes and \"theirs\" prefers the remote repo"
+    )]
+    pub strategy: Option<MergeStrategy>,
+
+    #[clap(
+        help = "Replace the history of the remote branch with the local one (e.g. after squashing \
+        commits), as long as the remote branch hasn't changed since it was last fetched",
+        long = "force-with-lease",
+        conflicts_with = "strategy"
+    )]
+    pub force_with_lease: bool,
+
+    #[clap(
+        help = "Print the commits that would be pushed without pushing them",
+        long = "dry-run"
+    )]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Record the commit each installed dotfile was installed from in jtd.lock and commit it \
+    to the JTD repository, so that other machines can install the same versions",
+    version
+)]
+pub struct LockSubcommandArgs {
+    #[clap(
+        help = "The location of the repository in the form USERNAME/REPONAME. Can also be a git \
+        URL or the path of a local clone"
+    )]
+    pub repository: String,
+
+    #[clap(
+        arg_enum,
+        long = "method",
+        short = 'm',
+        help = "The method to use for cloning/pushing the repository",
+        default_value = "ssh"
+    )]
+    pub method: ConnectionMethod,
+
+    #[clap(
+        long = "manifest",
+        short = 'n',
+        help = "The manifest to use in the repository",
+        default_value = "jtd.yaml"
+    )]
+    pub manifest: String,
+
+    #[clap(
+        long = "subdir",
+        help = "The subdirectory of the repository the manifest is in, for dotfiles kept in a \
+        larger repository. The file paths of the dotfiles are relative to it"
+    )]
+    pub subdir: Option<String>,
+
+    #[clap(
+        long = "branch",
+        short = 'b',
+        help = "The branch of the repository to use. If unspecified, the default branch of the \
+        remote is used"
+    )]
+    pub branch: Option<String>,
+
+    #[clap(
+        arg_enum,
+        default_value = "GitHub",
+        help = "The host to source the repo from",
+        long = "source",
+        ignore_case = true
+    )]
+    pub source: RepoHostName,
+
+    #[clap(
+        help = "Only commit the lockfile to the cached repository, to be pushed later with \"jtd push\"",
+        long = "no-push"
+    )]
+    pub no_push: bool,
+
+    #[clap(
+        help = "Print the lockfile that would be committed without committing it",
+        long = "dry-run"
+    )]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(about = "Interactively install or sync dotfiles", version)]
+pub struct InteractiveSubcommandArgs {
+    #[clap(
+        arg_enum,
+        help = "The wizard to run. If unspecified, you will be asked which one to run"
+    )]
+    pub wizard: Option<Wizard>,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Export the manifest of a local JTD repository for another dotfile manager",
+    version
+)]
+pub struct ExportSubcommandArgs {
+    #[clap(
+        help = "The path of a local clone of the repository",
+        default_value = "."
+    )]
+    pub repository: PathBuf,
+
+    #[clap(
+        arg_enum,
+        long = "format",
+        short = 'f',
+        help = "The dotfile manager to export to"
+    )]
+    pub format: ExportFormat,
+
+    #[clap(
+        long = "manifest",
+        short = 'n',
+        help = "The manifest to use in the repository",
+        default_value = "jtd.yaml"
+    )]
+    pub manifest: String,
+
+    #[clap(
+        long = "output",
+        short = 'o',
+        help = "Where to write the export. Defaults to install.conf.yaml in the repository for \
+        dotbot, and the stow directory in the repository for stow"
+    )]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Create a JTD repository from a bare repository that uses the home directory as its \
+    work tree",
+    version
+)]
+pub struct MigrateSubcommandArgs {
+    #[clap(help = "The bare repository to migrate from, e.g. ~/.dotfiles")]
+    pub git_dir: String,
+
+    #[clap(help = "Where to create the JTD repository")]
+    pub destination: PathBuf,
+
+    #[clap(
+        long = "work-tree",
+        help = "The work tree of the bare repository, which the dotfiles are installed into",
+        default_value = "~"
+    )]
+    pub work_tree: String,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(about = "Print where an installed dotfile is installed to", version)]
+pub struct WhichSubcommandArgs {
+    #[clap(help = "The name of the installed dotfile")]
+    pub dotfile: String,
+
+    #[clap(
+        help = "Print the path of the dotfile in the local or cached clone of its repository \
+        instead",
+        long = "source"
+    )]
+    pub source: bool,
+
+    #[clap(
+        help = "The repository the dotfile was installed from, for dotfiles installed from \
+        several repositories. Either a path, a URL or the end of one (e.g. USERNAME/REPONAME)",
+        long = "from"
+    )]
+    pub from: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(about = "Show the commits that changed an installed dotfile", version)]
+pub struct HistorySubcommandArgs {
+    #[clap(help = "The name of the installed dotfile")]
+    pub dotfile: String,
+
+    #[clap(
+        help = "The repository the dotfile was installed from, for dotfiles installed from \
+        several repositories. Either a path, a URL or the end of one (e.g. USERNAME/REPONAME)",
+        long = "from"
+    )]
+    pub from: Option<String>,
+
+    #[clap(
+        long = "offline",
+        help = "Show the history in the cached clone of the repository without accessing the \
+        network"
+    )]
+    pub offline: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Install the version of an installed dotfile at a past commit or tag",
+    version
+)]
+pub struct RestoreSubcommandArgs {
+    #[clap(help = "The name of the installed dotfile")]
+    pub dotfile: String,
+
+    #[clap(
+        help = "The commit, tag or branch to restore the dotfile to, e.g. a commit listed by \
+        \"jtd history\""
+    )]
+    pub git_ref: String,
+
+    #[clap(
+        help = "The repository the dotfile was installed from, for dotfiles installed from \
+        several repositories. Either a path, a URL or the end of one (e.g. USERNAME/REPONAME)",
+        long = "from"
+    )]
+    pub from: Option<String>,
+
+    #[clap(
+        help = "Restore the dotfile even if it has been changed since it was last synced",
+        long = "force",
+        short = 'f'
+    )]
+    pub force: bool,
+
+    #[clap(
+        help = "Print the file copies that would happen without performing them",
+        long = "dry-run"
+    )]
+    pub dry_run: bool,
+
+    #[clap(
+        help = "Whether to run the filters of the dotfile and write a requires_root dotfile as root \
+        without prompting",
+        long = "trust",
+        short = 't'
+    )]
+    pub trust: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(about = "Validate the manifest of a JTD repository", version)]
+pub struct ValidateSubcommandArgs {
+    #[clap(
+        help = "The location of the repository in the form USERNAME/REPONAME. Can also be a git URL, \
+        a path to a local repository or a path to a manifest file"
+    )]
+    pub repository: String,
+
+    #[clap(
+        arg_enum,
+        long = "method",
+        short = 'm',
+        help = "The method to use for cloning the repository",
+        default_value = "https"
+    )]
+    pub method: ConnectionMethod,
+
+    #[clap(
+        long = "manifest",
+        short = 'n',
+        help = "The manifest to use in the repository",
+        default_value = "jtd.yaml"
+    )]
+    pub manifest: String,
+
+    #[clap(
+        arg_enum,
+        default_value = "GitHub",
+        help = "The host to source the repo from",
+        long = "source",
+        short = 's',
+        ignore_case = true
+    )]
+    pub source: RepoHostName,
+
+    #[clap(
+        help = "Whether to treat warnings (such as suspicious install steps) as errors",
+        long = "strict"
+    )]
+    pub strict: bool,
+
+    #[clap(
+        arg_enum,
+        long = "output",
+        short = 'o',
+        help = "The format to print the results in",
+        default_value = "text"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Check the health of the currently installed dotfiles and the provided remote repo",
+    version
+)]
+pub struct DoctorSubcommandArgs {
+    #[clap(
+        help = "The location of the repository in the form USERNAME/REPONAME. Can also be a git \
+        URL or the path of a local clone"
+    )]
+    pub repository: String,
+
+    #[clap(
+        arg_enum,
+        long = "method",
+        short = 'm',
+        help = "The method to use for cloning/pushing the repository",
+        default_value = "ssh"
+    )]
+    pub method: ConnectionMethod,
+
+    #[clap(
+        long = "manifest",
+        short = 'n',
+        help = "The manifest to use in the repository",
+        default_value = "jtd.yaml"
+    )]
+    pub manifest: String,
+
+    #[clap(
+        arg_enum,
+        default_value = "GitHub",
+        help = "The host to source the repo from",
+        long = "source",
+        short = 's',
+        ignore_case = true
+    )]
+    pub source: RepoHostName,
+
+    #[clap(
+        arg_enum,
+        long = "output",
+        short = 'o',
+        help = "The format to print the results in",
+        default_value = "text"
+    )]
+    pub output: OutputFormat,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(about = "Generate a shell completion script for jtd", version)]
+pub struct CompletionsSubcommandArgs {
+    #[clap(
+        arg_enum,
+        help = "The shell to generate the completion script for",
+        required_unless_present = "dotfiles"
+    )]
+    pub shell: Option<Shell>,
+
+    #[clap(
+        help = "Print the names of the dotfiles to complete for the command line given after \
+        \"--\", as used by the completion script",
+        long = "dotfiles",
+        hide = true
+    )]
+    pub dotfiles: bool,
+
+    #[clap(help = "The command line to complete", last = true, hide = true)]
+    pub words: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Restore dotfiles to their state before they were last installed",
+    version
+)]
+pub struct RollbackSubcommandArgs {
+    #[clap(
+        help = "The dotfiles to roll back. If unspecified, roll back every dotfile installed by the \
+        last \"jtd install\""
+    )]
+    pub target_dotfiles: Vec<String>,
+
+    #[clap(
+        help = "The repository the dotfiles were installed from, for dotfiles installed from \
+        several repositories. Either a path, a URL or the end of one (e.g. USERNAME/REPONAME)",
+        long = "from"
+    )]
+    pub from: Option<String>,
+
+    #[clap(
+        help = "Restore the backups without prompting for confirmation",
+        long = "yes",
+        short = 'y',
+        alias = "non-interactive"
+    )]
+    pub yes: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(about = "Remove installed dotfiles", version)]
+pub struct UninstallSubcommandArgs {
+    #[clap(
+        help = "The dotfiles to uninstall",
+        required_unless_present = "all",
+        conflicts_with = "all"
+    )]
+    pub target_dotfiles: Vec<String>,
+
+    #[clap(
+        help = "Whether to uninstall every installed dotfile, or every dotfile installed from the \
+        repository passed to \"--from\"",
+        long = "all",
+        short = 'a'
+    )]
+    pub all: bool,
+
+    #[clap(
+        help = "The repository the dotfiles were installed from, for dotfiles installed from \
+        several repositories. Either a path, a URL or the end of one (e.g. USERNAME/REPONAME)",
+        long = "from"
+    )]
+    pub from: Option<String>,
+
+    #[clap(
+        help = "Whether to run any pre_uninstall/post_uninstall commands without prompting",
+        long = "trust",
+        short = 't'
+    )]
+    pub trust: bool,
+
+    #[clap(
+        help = "Print the file removals and uninstall steps that would happen without performing them",
+        long = "dry-run"
+    )]
+    pub dry_run: bool,
+
+    #[clap(
+        help = "Run without prompting for input, answering \"yes\" to confirmations. Can also be \
+        enabled with JTD_NONINTERACTIVE=1",
+        long = "yes",
+        short = 'y',
+        alias = "non-interactive"
+    )]
+    pub yes: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Show the differences between the dotfiles in a JTD repository and the local copies",
+    version
+)]
+pub struct DiffSubcommandArgs {
+    #[clap(
+        help = "The location of the repository in the form USERNAME/REPONAME. Can also be a git \
+        URL or the path of a local clone"
+    )]
+    pub repository: String,
+
+    #[clap(
+        help = "The dotfiles or groups of dotfiles to diff. If unspecified, diff every installed \
+        dotfile",
+        conflicts_with = "all"
+    )]
+    pub target_dotfiles: Vec<String>,
+
+    #[clap(
+        help = "Whether to diff all dotfiles in the manifest, including ones that aren't installed",
+        long = "all",
+        short = 'a'
+    )]
+    pub all: bool,
+
+    #[clap(
+        help = "Diff the installed dotfiles against every repository they were installed from, instead of against REPOSITORY",
+        long = "all-repos"
+    )]
+    pub all_repos: bool,
+
+    #[clap(
+        arg_enum,
+        long = "method",
+        short = 'm',
+        help = "The method to use for cloning the repository",
+        default_value = "https"
+    )]
+    pub method: ConnectionMethod,
+
+    #[clap(
+        long = "manifest",
+        short = 'n',
+        help = "The manifest to use in the repository",
+        default_value = "jtd.yaml"
+    )]
+    pub manifest: String,
+
+    #[clap(
+        long = "subdir",
+        help = "The subdirectory of the repository the manifest is in, for dotfiles kept in a \
+        larger repository. The file paths of the dotfiles are relative to it"
+    )]
+    pub subdir: Option<String>,
+
+    #[clap(
+        long = "branch",
+        short = 'b',
+        help = "The branch of the repository to use. If unspecified, the default branch of the \
+        remote is used"
+    )]
+    pub branch: Option<String>,
+
+    #[clap(
+        long = "no-cache",
+        help = "Clone the repository into a temporary directory instead of using the cached clone in \
+        ~/.cache/jointhedots"
+    )]
+    pub no_cache: bool,
+
+    #[clap(
+        long = "repo-dir",
+        help = "Diff against your own clone of the repository at this path (e.g. ~/dotfiles), \
+        fetching from its origin, instead of a cached or temporary clone",
+        conflicts_with_all = &["no-cache", "all-repos"]
+    )]
+    pub repo_dir: Option<PathBuf>,
+
+    #[clap(
+        long = "offline",
+        help = "Diff against the cached clone of the repository without accessing the network",
+        conflicts_with = "no-cache"
+    )]
+    pub offline: bool,
+
+    #[clap(
+        long = "tool",
+        help = "The external tool to show the differences with, e.g. \"delta\", \"difftastic\" \
+        or any command taking the two files to compare. Overrides diff_tool in the config file"
+    )]
+    pub tool: Option<String>,
+
+    #[clap(
+        long = "no-pager",
+        help = "Print the diff directly instead of paging it when it doesn't fit on the screen"
+    )]
+    pub no_pager: bool,
+
+    #[clap(
+        long = "patch",
+        help = "Print an uncoloured patch that can be applied to the repository with \"git apply\" \
+        or \"patch -p1\"",
+        conflicts_with = "tool"
+    )]
+    pub patch: bool,
+
+    #[clap(
+        arg_enum,
+        default_value = "GitHub",
+        help = "The host to source the repo from",
+        long = "source",
+        short = 's',
+        ignore_case = true
+    )]
+    pub source: RepoHostName,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Watch the installed dotfiles and sync them whenever they change",
+    version
+)]
+pub struct WatchSubcommandArgs {
+    #[clap(
+        help = "The location of the repository in the form USERNAME/REPONAME. Can also be a git \
+        URL or the path of a local clone"
+    )]
+    pub repository: String,
+
+    #[clap(
+        arg_enum,
+        long = "method",
+        short = 'm',
+        help = "The method to use for cloning/pushing the repository",
+        default_value = "ssh"
+    )]
+    pub method: ConnectionMethod,
+
+    #[clap(
+        long = "manifest",
+        short = 'n',
+        help = "The manifest to use in the repository",
+        default_value = "jtd.yaml"
+    )]
+    pub manifest: String,
+
+    #[clap(
+        long = "subdir",
+        help = "The subdirectory of the repository the manifest is in, for dotfiles kept in a \
+        larger repository. The file paths of the dotfiles are relative to it"
+    )]
+    pub subdir: Option<String>,
+
+    #[clap(
+        long = "branch",
+        short = 'b',
+        help = "The branch of the repository to use. If unspecified, the default branch of the \
+        remote is used"
+    )]
+    pub branch: Option<String>,
+
+    #[clap(
+        long = "no-cache",
+        help = "Clone the repository into a temporary directory instead of using the cached clone in \
+        ~/.cache/jointhedots"
+    )]
+    pub no_cache: bool,
+
+    #[clap(
+        arg_enum,
+        default_value = "GitHub",
+        help = "The host to source the repo from",
+        long = "source",
+        short = 's',
+        ignore_case = true
+    )]
+    pub source: RepoHostName,
+
+    #[clap(
+        long = "settle",
+        help = "The number of seconds a dotfile must go unchanged before it is synced",
+        default_value = "2"
+    )]
+    pub settle_secs: u64,
+
+    #[clap(
+        long = "no-push",
+        help = "Commit each change without pushing it, and push the commits in batches instead"
+    )]
+    pub no_push: bool,
+
+    #[clap(
+        long = "push-interval",
+        help = "The number of seconds between pushes of the commits batched by \"--no-push\". \
+        Any remaining commits are pushed when jtd stops watching",
+        default_value = "3600"
+    )]
+    pub push_interval_secs: u64,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(about = "Update jtd to the latest release from GitHub", version)]
+pub struct SelfUpdateSubcommandArgs {
+    #[clap(
+        help = "Only check whether a newer version is available",
+        long = "check"
+    )]
+    pub check: bool,
+
+    #[clap(
+        help = "Update without prompting for confirmation",
+        long = "yes",
+        short = 'y',
+        alias = "non-interactive"
+    )]
+    pub yes: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Manage machine-local variables for templated dotfiles",
+    version
+)]
+pub struct VarsSubcommandArgs {
+    #[clap(subcommand)]
+    pub action: VarsAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum VarsAction {
+    #[clap(about = "Print the value of a variable")]
+    Get {
+        #[clap(help = "The name of the variable")]
+        key: String,
+    },
+    #[clap(about = "Set the value of a variable")]
+    Set {
+        #[clap(help = "The name of the variable")]
+        key: String,
+        #[clap(help = "The value to set the variable to")]
+        value: String,
+    },
+    #[clap(about = "Remove a variable")]
+    Unset {
+        #[clap(help = "The name of the variable")]
+        key: String,
+    },
+    #[clap(about = "List all variables")]
+    List,
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Export the installed dotfiles of this machine, or import those of another",
+    version
+)]
+pub struct StateSubcommandArgs {
+    #[clap(subcommand)]
+    pub action: StateAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum StateAction {
+    #[clap(
+        about = "List the installed dotfiles of this machine, with where and when they were \
+        installed from"
+    )]
+    List,
+    #[clap(
+        about = "Print the installed dotfiles of this machine and the install steps run for them, \
+        so that they can be imported on another machine"
+    )]
+    Export,
+    #[clap(
+        about = "Adopt the installed dotfiles exported on another machine, so that installing them \
+        doesn't ask about install steps that were already run there"
+    )]
+    Import {
+        #[clap(
+            help = "The file to import, as printed by \"jtd state export\". If unspecified or \"-\", \
+            it is read from stdin"
+        )]
+        file: Option<PathBuf>,
+
+        #[clap(
+            help = "Replace the installed dotfiles of this machine instead of adding to them",
+            long = "replace"
+        )]
+        replace: bool,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(
+    about = "Manage the repositories and install steps that are run without asking when installing",
+    version
+)]
+pub struct TrustSubcommandArgs {
+    #[clap(subcommand)]
+    pub action: TrustAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum TrustAction {
+    #[clap(about = "Always run the install steps of a repository, or install steps with a hash")]
+    Add {
+        #[clap(
+            help = "The location of the repository in the form USERNAME/REPONAME. Can also be a \
+            git URL or the path of a local clone",
+            required_unless_present = "hash"
+        )]
+        repository: Option<String>,
+
+        #[clap(
+            help = "The hash of the pre-install or post-install steps to trust instead of a \
+            repository, as shown by \"jtd state export\"",
+            long = "hash",
+            conflicts_with = "repository"
+        )]
+        hash: Option<String>,
+
+        #[clap(
+            arg_enum,
+            long = "method",
+            short = 'm',
+            help = "The method the repository is cloned with. Either method trusts the repository \
+            however it is installed",
+            default_value = "https"
+        )]
+        method: ConnectionMethod,
+
+        #[clap(
+            arg_enum,
+            default_value = "GitHub",
+            help = "The host the repository is on",
+            long = "source",
+            ignore_case = true
+        )]
+        source: RepoHostName,
+    },
+    #[clap(about = "Stop trusting a repository, or install steps with a hash")]
+    Remove {
+        #[clap(
+            help = "The repository to stop trusting. Can be a git URL, the path of a local clone \
+            or the end of a URL (e.g. USERNAME/REPONAME)",
+            required_unless_present = "hash"
+        )]
+        repository: Option<String>,
+
+        #[clap(
+            help = "The hash of the install steps to stop trusting instead of a repository",
+            long = "hash",
+            conflicts_with = "repository"
+        )]
+        hash: Option<String>,
+    },
+    #[clap(about = "List the trusted repositories and install steps")]
+    List,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_parse_with_defaults() {
+        let user_config = UserConfig {
+            repository: Some("dob9601/dotfiles".to_string()),
+            method: Some("HTTPS".to_string()),
+            source: Some("gitlab".to_string()),
+            trust: true,
+            ..Default::default()
+        };
+
+        match Cli::try_parse_with_defaults(["jtd", "install", "--all"], &user_config, None)
+            .unwrap()
+            .command
+        {
+            JoinTheDots::Install(args) => {
+                assert_eq!(args.repository, "dob9601/dotfiles");
+                assert_eq!(args.method, ConnectionMethod::HTTPS);
+                assert_eq!(args.source, RepoHostName::GitLab);
+                assert_eq!(args.manifest, "jtd.yaml");
+                assert!(args.trust);
+            }
+            command => panic!("Parsed the wrong subcommand: {:?}", command),
+        }
+
+        match Cli::try_parse_with_defaults(
+            ["jtd", "sync", "me/other", "--method", "ssh"],
+            &user_config,
+            None,
+        )
+        .unwrap()
+        .command
+        {
+            JoinTheDots::Sync(args) => {
+                assert_eq!(args.repository, "me/other");
+                assert_eq!(args.method, ConnectionMethod::SSH);
+            }
+            command => panic!("Parsed the wrong subcommand: {:?}", command),
+        }
+
+        match Cli::try_parse_with_defaults(
+            ["jtd", "-v", "sync", "nvim", "kitty"],
+            &user_config,
+            None,
+        )
+        .unwrap()
+        .command
+        {
+            JoinTheDots::Sync(args) => {
+                assert_eq!(args.repository, "dob9601/dotfiles");
+                assert_eq!(args.target_dotfiles, ["nvim", "kitty"]);
+            }
+            command => panic!("Parsed the wrong subcommand: {:?}", command),
+        }
+
+        assert!(
+            Cli::try_parse_with_defaults(["jtd", "sync"], &UserConfig::default(), None).is_err()
+        );
+        let invalid_config = UserConfig {
+            method: Some("carrier-pigeon".to_string()),
+            ..Default::default()
+        };
+        assert!(Cli::try_parse_with_defaults(["jtd", "sync"], &invalid_config, None).is_err());
+    }
+
+    #[test]
+    fn test_find_data_dir() {
+        assert_eq!(
+            Cli::find_data_dir(&["jtd", "install", "--data-dir", "/data", "dob9601/dotfiles"]),
+            Some(PathBuf::from("/data"))
+        );
+        assert_eq!(
+            Cli::find_data_dir(&["jtd", "--data-dir=~/jtd", "sync"]),
+            Some(PathBuf::from("~/jtd"))
+        );
+        assert_eq!(
+            Cli::find_data_dir(&["jtd", "sync", "--", "--data-dir"]),
+            None
+        );
+        assert_eq!(Cli::find_data_dir(&["jtd", "sync"]), None);
+    }
+
+    #[test]
+    fn test_parse_with_installed_from() {
+        let user_config = UserConfig {
+            repository: Some("dob9601/dotfiles".to_string()),
+            diff_tool: Some("delta".to_string()),
+            ..Default::default()
+        };
+        let installed_from = RepositoryOrigin {
+            repository: "git@github.com:dob9601/work-dotfiles.git".to_string(),
+            host: Some("github.com".to_string()),
+            manifest: "hosts/work.yaml".to_string(),
+            subdir: None,
+            repo_dir: None,
+        };
+
+        match Cli::try_parse_with_defaults(["jtd", "diff"], &user_config, Some(&installed_from))
+            .unwrap()
+            .command
+        {
+            JoinTheDots::Diff(args) => {
+                assert_eq!(args.repository, installed_from.repository);
+                assert_eq!(args.manifest, "hosts/work.yaml");
+                assert_eq!(args.tool.as_deref(), Some("delta"));
+            }
+            command => panic!("Parsed the wrong subcommand: {:?}", command),
+        }
+
+        match Cli::try_parse_with_defaults(
+            ["jtd", "sync", "--manifest", "jtd.yaml", "nvim"],
+            &user_config,
+            Some(&installed_from),
+        )
+        .unwrap()
+        .command
+        {
+            JoinTheDots::Sync(args) => {
+                assert_eq!(args.repository, installed_from.repository);
+                assert_eq!(args.manifest, "jtd.yaml");
+                assert_eq!(args.target_dotfiles, ["nvim"]);
+            }
+            command => panic!("Parsed the wrong subcommand: {:?}", command),
+        }
+
+        // Installing isn't limited to the dotfiles already installed
+        match Cli::try_parse_with_defaults(
+            ["jtd", "install", "--all"],
+            &user_config,
+            Some(&installed_from),
+        )
+        .unwrap()
+        .command
+        {
+            JoinTheDots::Install(args) => {
+                assert_eq!(args.repository, "dob9601/dotfiles");
+                assert_eq!(args.manifest, "jtd.yaml");
+            }
+            command => panic!("Parsed the wrong subcommand: {:?}", command),
+        }
+    }
+}
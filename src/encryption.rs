@@ -0,0 +1,186 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::x25519::{Identity, Recipient};
+use age::{Decryptor, Encryptor};
+
+use crate::AGE_IDENTITY_PATH;
+
+pub const AGE_IDENTITY_ENV_VAR: &str = "JTD_AGE_IDENTITY";
+
+/// Return the path of the local age identity file, which can be overridden with the
+/// `JTD_AGE_IDENTITY` environment variable
+pub fn identity_path() -> PathBuf {
+    let path = env::var(AGE_IDENTITY_ENV_VAR).unwrap_or_else(|_| AGE_IDENTITY_PATH.to_string());
+    PathBuf::from(shellexpand::tilde(&path).as_ref())
+}
+
+/// Read the age identities from an identity file, as generated by `age-keygen`. Empty lines and
+/// comments are ignored.
+fn read_identities(path: &Path) -> Result<Vec<Identity>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path).map_err(|_| {
+        format!(
+            "Could not read age identity from {}. Generate one with \"age-keygen -o {}\" or set {} \
+            to the path of an existing identity",
+            path.to_string_lossy(),
+            path.to_string_lossy(),
+            AGE_IDENTITY_ENV_VAR
+        )
+    })?;
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.parse::<Identity>().map_err(|err| {
+                format!(
+                    "Invalid age identity in {}: {}",
+                    path.to_string_lossy(),
+                    err
+                )
+                .into()
+            })
+        })
+        .collect()
+}
+
+fn decrypt_with_identities(
+    ciphertext: &[u8],
+    identities: &[Identity],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let decryptor = Decryptor::new_buffered(ArmoredReader::new(ciphertext))?;
+    let mut reader = decryptor
+        .decrypt(
+            identities
+                .iter()
+                .map(|identity| identity as &dyn age::Identity),
+        )
+        .map_err(|err| format!("Could not decrypt file: {}", err))?;
+
+    let mut plaintext = vec![];
+    reader.read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+fn encrypt_to_recipients(
+    plaintext: &[u8],
+    recipients: &[Recipient],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let encryptor = Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient),
+    )?;
+
+    let mut ciphertext = vec![];
+    let armored_writer = ArmoredWriter::wrap_output(&mut ciphertext, Format::AsciiArmor)?;
+    let mut writer = encryptor.wrap_output(armored_writer)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?.finish()?;
+
+    Ok(ciphertext)
+}
+
+/// Decrypt an age-encrypted file (armored or binary) using the age identity at `identity_path`,
+/// usually the local one given by [identity_path()]
+pub fn decrypt(ciphertext: &[u8], identity_path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    decrypt_with_identities(ciphertext, &read_identities(identity_path)?)
+}
+
+/// Encrypt a file in the ASCII-armored age format.
+///
+/// # Arguments
+///
+/// * `plaintext` - The contents to encrypt
+/// * `recipients` - The age public keys to encrypt to. If empty, the contents are encrypted to the
+///   public key of the age identity at `identity_path`
+/// * `identity_path` - The path of the age identity, usually the local one given by
+///   [identity_path()]
+pub fn encrypt(
+    plaintext: &[u8],
+    recipients: &[String],
+    identity_path: &Path,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let recipients = if recipients.is_empty() {
+        read_identities(identity_path)?
+            .iter()
+            .map(Identity::to_public)
+            .collect()
+    } else {
+        recipients
+            .iter()
+            .map(|recipient| {
+                recipient
+                    .parse::<Recipient>()
+                    .map_err(|err| format!("Invalid age recipient {}: {}", recipient, err).into())
+            })
+            .collect::<Result<Vec<Recipient>, Box<dyn Error>>>()?
+    };
+
+    encrypt_to_recipients(plaintext, &recipients)
+}
+
+#[cfg(test)]
+mod tests {
+    use age::secrecy::ExposeSecret;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let identity = Identity::generate();
+
+        let ciphertext = encrypt_to_recipients(b"secret", &[identity.to_public()]).unwrap();
+
+        assert!(ciphertext.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"));
+        assert_eq!(
+            decrypt_with_identities(&ciphertext, &[identity]).unwrap(),
+            b"secret"
+        );
+    }
+
+    #[test]
+    fn test_decrypt_wrong_identity() {
+        let ciphertext =
+            encrypt_to_recipients(b"secret", &[Identity::generate().to_public()]).unwrap();
+
+        assert!(decrypt_with_identities(&ciphertext, &[Identity::generate()]).is_err());
+    }
+
+    #[test]
+    fn test_read_identities() {
+        let dir = tempdir().expect("Could not create temporary dir");
+        let path = dir.path().join("identity.txt");
+        let identity = Identity::generate();
+        fs::write(
+            &path,
+            format!(
+                "# created: 2022-04-01T00:00:00Z\n# public key: {}\n{}\n",
+                identity.to_public(),
+                identity.to_string().expose_secret()
+            ),
+        )
+        .unwrap();
+
+        let identities = read_identities(&path).unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert_eq!(
+            identities[0].to_public().to_string(),
+            identity.to_public().to_string()
+        );
+    }
+
+    #[test]
+    fn test_read_identities_missing_file() {
+        let dir = tempdir().expect("Could not create temporary dir");
+
+        assert!(read_identities(&dir.path().join("identity.txt")).is_err());
+    }
+}
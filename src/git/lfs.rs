@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use console::style;
+use sha2::{Digest, Sha256};
+
+use crate::utils::{get_proxy, is_offline};
+
+const LFS_POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+/// Pointer files are tiny, so anything larger is real content
+const MAX_LFS_POINTER_SIZE: usize = 1024;
+
+/// A Git LFS pointer file, which is stored in the repository in place of a file tracked with LFS
+#[derive(Debug, PartialEq)]
+pub struct LfsPointer {
+    /// The SHA-256 hash of the contents of the file, as hex
+    pub oid: String,
+    pub size: u64,
+}
+
+impl LfsPointer {
+    /// Parse `contents` as an LFS pointer file, returning None if it isn't one
+    pub fn parse(contents: &[u8]) -> Option<LfsPointer> {
+        if contents.len() > MAX_LFS_POINTER_SIZE {
+            return None;
+        }
+        let contents = std::str::from_utf8(contents).ok()?;
+        let mut lines = contents.lines();
+        if lines.next()? != LFS_POINTER_VERSION {
+            return None;
+        }
+
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            match line.split_once(' ') {
+                Some(("oid", value)) => oid = value.strip_prefix("sha256:").map(str::to_string),
+                Some(("size", value)) => size = value.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(LfsPointer {
+            oid: oid?,
+            size: size?,
+        })
+    }
+
+    /// Return whether `contents` are the contents of the file this pointer points to
+    pub fn matches(&self, contents: &[u8]) -> bool {
+        contents.len() as u64 == self.size && hex::encode(Sha256::digest(contents)) == self.oid
+    }
+}
+
+/// Replace the LFS pointer checked out at `file` in the working tree of the repository at
+/// `repo_dir` with the real contents of the file, fetching them from the LFS server unless running
+/// offline. Requires the `git lfs` command, as libgit2 doesn't support LFS
+pub fn smudge_lfs_file(repo_dir: &Path, file: &str) -> Result<(), Box<dyn Error>> {
+    let mut command = Command::new("git");
+    if let Some(proxy) = get_proxy() {
+        command.arg("-c").arg(format!("http.proxy={}", proxy));
+    }
+    command.arg("lfs");
+    if is_offline() {
+        // Only uses objects that have already been fetched into the repository
+        command.args(["checkout", "--", file]);
+    } else {
+        info!("Fetching {} from Git LFS", file);
+        command.args(["pull", "--include", file]);
+    }
+    let output = command.current_dir(repo_dir).output().map_err(|err| {
+        format!(
+            "{} is stored with Git LFS, install git and git-lfs to install it ({})",
+            file, err
+        )
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("'lfs' is not a git command") {
+            return Err(format!(
+                "{} is stored with Git LFS, install git-lfs to install it",
+                file
+            )
+            .into());
+        }
+        return Err(format!("Could not fetch {} from Git LFS: {}", file, stderr.trim()).into());
+    }
+
+    if LfsPointer::parse(&fs::read(repo_dir.join(file))?).is_some() {
+        return Err(format!(
+            "Could not fetch {} from Git LFS, it is still a pointer file",
+            file
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lfs_pointer() {
+        let contents = b"wallpaper";
+        let pointer = format!(
+            "{}\noid sha256:{}\nsize {}\n",
+            LFS_POINTER_VERSION,
+            hex::encode(Sha256::digest(contents)),
+            contents.len()
+        );
+
+        let parsed = LfsPointer::parse(pointer.as_bytes()).expect("Could not parse pointer");
+        assert_eq!(parsed.size, 9);
+        assert!(parsed.matches(contents));
+        assert!(!parsed.matches(b"other wallpaper"));
+
+        assert_eq!(LfsPointer::parse(b"set number\n"), None);
+        assert_eq!(
+            LfsPointer::parse(format!("{}\nsize 9\n", LFS_POINTER_VERSION).as_bytes()),
+            None
+        );
+    }
+}
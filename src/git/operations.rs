@@ -1,19 +1,35 @@
-use std::io::{stdin, stdout, Write};
-use std::{error::Error, path::Path, sync::RwLock};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::process::Command;
+use std::{env, error::Error, fs, path::Path, sync::RwLock};
 
 use console::style;
-use dialoguer::{Input, Password};
 use git2::build::CheckoutBuilder;
 use git2::{
-    AnnotatedCommit, Commit, Direction, PushOptions, RemoteCallbacks, Repository, Signature,
+    AnnotatedCommit, BranchType, CertificateCheckStatus, Commit, Cred, CredentialType, Direction,
+    ErrorCode, FetchOptions, ObjectType, Oid, ProxyOptions, PushOptions, RemoteCallbacks,
+    Repository, Signature, Sort, StatusOptions, SubmoduleUpdateOptions, Tree,
 };
-use git2::{Error as Git2Error, IndexAddOption, MergeOptions};
+use git2::{Error as Git2Error, FileFavor, Index, IndexAddOption, MergeOptions};
 use git2_credentials::{CredentialHandler, CredentialUI};
 
-use crate::utils::get_theme;
+use crate::archive::{is_archive_url, open_archive};
+use crate::git::remote::{get_access_token, local_repository_path};
+use crate::log::log_operation;
+use crate::merge::merge_structured;
+use crate::prompt::prompter;
+use crate::structs::{MergeDriver, MergeStrategy};
+use crate::utils::{
+    ensure_interactive, get_proxy, get_ssh_key, is_debug_git, is_offline, print_git_debug,
+    ProgressLine,
+};
+use crate::CACHE_PATH;
 use lazy_static::lazy_static;
+use serde_json::json;
 
-pub fn get_head(repo: &Repository) -> Result<Commit, Box<dyn Error>> {
+pub fn get_head(repo: &Repository) -> Result<Commit<'_>, Box<dyn Error>> {
     let commit = repo
         .head()?
         .resolve()?
@@ -27,6 +43,18 @@ pub fn get_head_hash(repo: &Repository) -> Result<String, Box<dyn Error>> {
     Ok(get_head(repo)?.id().to_string())
 }
 
+/// Return the name of the branch `HEAD` points at, e.g. `main`
+pub fn get_head_branch(repo: &Repository) -> Result<String, Box<dyn Error>> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err("HEAD is not on a branch".into());
+    }
+    Ok(head
+        .shorthand()
+        .ok_or("Invalid unicode in branch name")?
+        .to_string())
+}
+
 pub fn checkout_ref(repo: &Repository, reference: &str) -> Result<(), Box<dyn Error>> {
     let (object, reference) = repo
         .revparse_ext(reference)
@@ -47,26 +75,48 @@ pub fn get_commit<'a>(repo: &'a Repository, commit_hash: &str) -> Result<Commit<
     object.peel_to_commit()
 }
 
+/// Resolve a branch, tag or commit to the hash of the commit it points to. Branches that only exist
+/// on the `origin` remote are resolved too.
+pub fn resolve_ref(repo: &Repository, reference: &str) -> Result<String, Box<dyn Error>> {
+    get_commit(repo, reference)
+        .or_else(|_| get_commit(repo, &format!("origin/{}", reference)))
+        .map(|commit| commit.id().to_string())
+        .map_err(|_| {
+            format!(
+                "Could not find branch, tag or commit {} in the repository",
+                reference
+            )
+            .into()
+        })
+}
+
 lazy_static! {
     static ref CREDENTIAL_CACHE: RwLock<(Option<String>, Option<String>)> =
         RwLock::new((None, None));
+    static ref SIGNATURE_OVERRIDE: RwLock<(Option<String>, Option<String>)> =
+        RwLock::new((None, None));
+    static ref COMMIT_TRAILERS: RwLock<(Vec<(String, String)>, bool)> =
+        RwLock::new((vec![], false));
 }
 
+/// The prefix of the refs marking the commits on a branch that were deliberately left unpushed,
+/// e.g. by `jtd sync --no-push`, so that they survive updates of the cached repository
+const UNPUSHED_REF_PREFIX: &str = "refs/jtd/unpushed/";
+
+const DEFAULT_SIGNATURE_NAME: &str = "Jointhedots Sync";
+const DEFAULT_SIGNATURE_EMAIL: &str = "jtd@danielobr.ie";
+
 pub struct CredentialUIDialoguer;
 
 impl CredentialUI for CredentialUIDialoguer {
     fn ask_user_password(&self, username: &str) -> Result<(String, String), Box<dyn Error>> {
-        let theme = get_theme();
-
         let mut credential_cache = CREDENTIAL_CACHE.write()?;
 
         let user = match &credential_cache.0 {
             Some(username) => username.to_owned(),
             None => {
-                let user = Input::with_theme(&theme)
-                    .default(username.to_owned())
-                    .with_prompt("Username")
-                    .interact()?;
+                ensure_interactive("A username is required to access the repository")?;
+                let user = prompter().input("Username", Some(username))?;
                 credential_cache.0 = Some(user.to_owned());
                 user
             }
@@ -75,10 +125,8 @@ impl CredentialUI for CredentialUIDialoguer {
         let password = match &credential_cache.1 {
             Some(password) => password.to_owned(),
             None => {
-                let pass = Password::with_theme(&theme)
-                    .with_prompt("Password (hidden)")
-                    .allow_empty_password(true)
-                    .interact()?;
+                ensure_interactive("A password is required to access the repository")?;
+                let pass = prompter().password("Password (hidden)")?;
                 credential_cache.1 = Some(pass.to_owned());
                 pass
             }
@@ -93,13 +141,11 @@ impl CredentialUI for CredentialUIDialoguer {
         let passphrase = match &credential_cache.1 {
             Some(passphrase) => passphrase.to_owned(),
             None => {
-                let pass = Password::with_theme(&get_theme())
-                    .with_prompt(format!(
-                        "{} (leave blank for no password): ",
-                        passphrase_prompt
-                    ))
-                    .allow_empty_password(true)
-                    .interact()?;
+                ensure_interactive("A passphrase is required to unlock your SSH key")?;
+                let pass = prompter().password(&format!(
+                    "{} (leave blank for no password): ",
+                    passphrase_prompt
+                ))?;
                 credential_cache.1 = Some(pass.to_owned());
                 pass
             }
@@ -114,32 +160,725 @@ pub fn generate_callbacks() -> Result<RemoteCallbacks<'static>, Box<dyn Error>>
     let git_config = git2::Config::open_default()
         .map_err(|err| format!("Could not open default git config: {}", err))?;
     let mut ch = CredentialHandler::new_with_ui(git_config, Box::new(CredentialUIDialoguer {}));
-    cb.credentials(move |url, username, allowed| ch.try_next_credential(url, username, allowed));
+    let debug_git = is_debug_git();
+    let ssh_key = get_ssh_key();
+    let mut tried_access_token = false;
+    let mut ssh_key_attempts = 0;
+    cb.credentials(move |url, username, allowed| {
+        if debug_git {
+            print_git_debug(&format!(
+                "Requesting credentials for {} (username: {}, allowed types: {:?})",
+                url,
+                username.unwrap_or("none"),
+                allowed
+            ));
+        }
+        // Hosts such as GitHub no longer accept passwords over HTTPS, so try an access token
+        // first. It's only tried once, as libgit2 asks again if the credentials are rejected
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !tried_access_token {
+            if let Some(access_token) = get_access_token(url) {
+                tried_access_token = true;
+                if debug_git {
+                    print_git_debug(&format!(
+                        "Using the access token from {}",
+                        access_token.source
+                    ));
+                }
+                return Cred::userpass_plaintext(access_token.username, &access_token.token);
+            }
+        }
+
+        if let (true, Some(ssh_key)) = (allowed.contains(CredentialType::SSH_KEY), &ssh_key) {
+            let method = SshKeyMethod::for_attempt(ssh_key_attempts);
+            ssh_key_attempts += 1;
+            if debug_git {
+                print_git_debug(&format!(
+                    "Trying {} {}",
+                    ssh_key.to_string_lossy(),
+                    method
+                        .map(|method| method.to_string())
+                        .unwrap_or_else(|| "(no methods left)".to_string())
+                ));
+            }
+            return ssh_key_credential(ssh_key, username.unwrap_or("git"), method);
+        }
+
+        let result = ch.try_next_credential(url, username, allowed);
+        if let (true, Err(err)) = (debug_git, &result) {
+            print_git_debug(&format!("No credentials available: {}", err.message()));
+        }
+        result
+    });
+    if !debug_git {
+        return Ok(cb);
+    }
+
+    cb.certificate_check(|_cert, host| {
+        print_git_debug(&format!("Checking the certificate of {}", host));
+        Ok(CertificateCheckStatus::CertificatePassthrough)
+    });
+    cb.sideband_progress(|message| {
+        for line in String::from_utf8_lossy(message).lines() {
+            print_git_debug(&format!("remote: {}", line));
+        }
+        true
+    });
+    cb.update_tips(|refname, old, new| {
+        print_git_debug(&format!("Updated {} {} -> {}", refname, old, new));
+        true
+    });
+    cb.push_negotiation(|updates| {
+        for update in updates {
+            print_git_debug(&format!(
+                "Pushing {} ({}) to {} (currently {})",
+                update.src_refname().unwrap_or("?"),
+                update.dst(),
+                update.dst_refname().unwrap_or("?"),
+                update.src()
+            ));
+        }
+        Ok(())
+    });
+    cb.push_update_reference(|refname, status| {
+        match status {
+            Some(status) => print_git_debug(&format!("Remote rejected {}: {}", refname, status)),
+            None => print_git_debug(&format!("Remote updated {}", refname)),
+        }
+        Ok(())
+    });
 
     Ok(cb)
 }
 
-pub fn clone_repo(url: &str, target_dir: &Path) -> Result<git2::Repository, Box<dyn Error>> {
-    // Clone the project.
-    let cb = generate_callbacks()?;
+/// The ways of authenticating with an SSH key chosen with `--ssh-key`, in the order they're tried
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SshKeyMethod {
+    /// The key file without a passphrase
+    KeyFile,
+    /// ssh-agent, which may hold the key if it's protected by a passphrase
+    Agent,
+    /// The key file with a passphrase, prompted for if not already known
+    KeyFileWithPassphrase,
+}
+
+impl SshKeyMethod {
+    /// Return the method to try on the given (zero-based) attempt, skipping the agent if it
+    /// isn't running
+    fn for_attempt(attempt: usize) -> Option<SshKeyMethod> {
+        let mut methods = vec![SshKeyMethod::KeyFile];
+        if env::var_os("SSH_AUTH_SOCK").is_some() {
+            methods.push(SshKeyMethod::Agent);
+        }
+        methods.push(SshKeyMethod::KeyFileWithPassphrase);
+        methods.get(attempt).copied()
+    }
+}
 
-    // clone a repository
-    let mut fo = git2::FetchOptions::new();
-    fo.remote_callbacks(cb)
-        .download_tags(git2::AutotagOption::All)
-        .update_fetchhead(true);
-    let repo = git2::build::RepoBuilder::new()
-        .fetch_options(fo)
+impl std::fmt::Display for SshKeyMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SshKeyMethod::KeyFile => "without a passphrase",
+            SshKeyMethod::Agent => "via ssh-agent",
+            SshKeyMethod::KeyFileWithPassphrase => "with a passphrase",
+        })
+    }
+}
+
+fn ssh_key_credential(
+    ssh_key: &Path,
+    username: &str,
+    method: Option<SshKeyMethod>,
+) -> Result<Cred, Git2Error> {
+    if !ssh_key.is_file() {
+        return Err(Git2Error::from_str(&format!(
+            "SSH key {} does not exist",
+            ssh_key.to_string_lossy()
+        )));
+    }
+    match method {
+        Some(SshKeyMethod::KeyFile) => Cred::ssh_key(username, None, ssh_key, None),
+        Some(SshKeyMethod::Agent) => Cred::ssh_key_from_agent(username),
+        Some(SshKeyMethod::KeyFileWithPassphrase) => {
+            let passphrase = CredentialUIDialoguer
+                .ask_ssh_passphrase(&format!(
+                    "Enter passphrase for key '{}'",
+                    ssh_key.to_string_lossy()
+                ))
+                .map_err(|err| Git2Error::from_str(&err.to_string()))?;
+            Cred::ssh_key(username, None, ssh_key, Some(&passphrase))
+        }
+        None => Err(Git2Error::from_str(&format!(
+            "Could not authenticate with SSH key {}",
+            ssh_key.to_string_lossy()
+        ))),
+    }
+}
+
+/// Clone the repository at `url` into `target_dir`, checking out `branch` if given or the default
+/// branch of the remote otherwise. If `depth` is given and non-zero, a shallow clone of that many
+/// commits is made. Submodules are cloned too, see [update_submodules].
+pub fn clone_repo(
+    url: &str,
+    target_dir: &Path,
+    branch: Option<&str>,
+    depth: Option<u32>,
+) -> Result<git2::Repository, Box<dyn Error>> {
+    let mut builder = git2::build::RepoBuilder::new();
+    builder.fetch_options(generate_fetch_options(depth)?);
+    if let Some(branch) = branch {
+        builder.branch(branch);
+    }
+    let repo = builder
         .clone(url, target_dir)
         .map_err(|err| format!("Could not clone repo: {}", &err))?;
+    update_submodules(&repo)?;
 
     success!("Successfully cloned repository!");
 
     Ok(repo)
 }
 
-pub fn generate_signature() -> Result<Signature<'static>, Git2Error> {
-    Signature::now("Jointhedots Sync", "jtd@danielobr.ie")
+/// Initialise the submodules of `repo` and check out the commits recorded for them, recursively, so
+/// that dotfiles inside them (e.g. themes or plugin snapshots) can be installed. Submodules are
+/// fetched with the same credentials as the repository itself, unless running offline.
+pub fn update_submodules(repo: &Repository) -> Result<(), Box<dyn Error>> {
+    for mut submodule in repo.submodules()? {
+        let name = submodule.name().unwrap_or_default().to_string();
+        debug!("Updating submodule {}", name);
+
+        let mut options = SubmoduleUpdateOptions::new();
+        options
+            .fetch(generate_fetch_options(None)?)
+            .allow_fetch(!is_offline());
+        submodule
+            .update(true, Some(&mut options))
+            .map_err(|err| format!("Could not update submodule {}: {}", name, err))?;
+        update_submodules(&submodule.open()?)?;
+    }
+    Ok(())
+}
+
+/// The id of a blob and its contents
+pub type BlobContents = (Oid, Vec<u8>);
+
+/// Find the file at `path` in `tree` of `repo`, returning the id and contents of its blob, or
+/// None if it doesn't exist. Paths inside submodules are followed into the commit recorded for the
+/// submodule, which is looked up in its checkout within the working tree of `repo`
+pub fn find_file_in_tree(
+    repo: &Repository,
+    tree: &Tree,
+    path: &Path,
+) -> Result<Option<BlobContents>, Box<dyn Error>> {
+    if let Ok(entry) = tree.get_path(path) {
+        if entry.kind() != Some(ObjectType::Blob) {
+            return Ok(None);
+        }
+        let blob = repo.find_blob(entry.id())?;
+        return Ok(Some((entry.id(), blob.content().to_vec())));
+    }
+
+    // The contents of submodules aren't part of the tree, only the commit they are at
+    let mut prefix = PathBuf::new();
+    for component in path.components() {
+        prefix.push(component);
+        let entry = match tree.get_path(&prefix) {
+            Ok(entry) => entry,
+            Err(_) => return Ok(None),
+        };
+        if entry.kind() != Some(ObjectType::Commit) {
+            continue;
+        }
+        let submodule_repo = match Repository::open(get_repo_dir(repo).join(&prefix)) {
+            Ok(submodule_repo) => submodule_repo,
+            Err(_) => return Ok(None),
+        };
+        let commit = match submodule_repo.find_commit(entry.id()) {
+            Ok(commit) => commit,
+            Err(_) => return Ok(None),
+        };
+        return find_file_in_tree(
+            &submodule_repo,
+            &commit.tree()?,
+            path.strip_prefix(&prefix)?,
+        );
+    }
+    Ok(None)
+}
+
+/// Format a [ProgressLine] message for a clone or fetch
+fn describe_fetch_progress(
+    received_objects: usize,
+    total_objects: usize,
+    received_bytes: usize,
+    indexed_deltas: usize,
+    total_deltas: usize,
+) -> String {
+    if received_objects < total_objects {
+        format!(
+            "Receiving objects: {}% ({}/{}), {:.2} MiB",
+            received_objects * 100 / total_objects,
+            received_objects,
+            total_objects,
+            received_bytes as f64 / (1024.0 * 1024.0)
+        )
+    } else if let Some(percent) = (indexed_deltas * 100).checked_div(total_deltas) {
+        format!(
+            "Resolving deltas: {}% ({}/{})",
+            percent, indexed_deltas, total_deltas
+        )
+    } else {
+        format!("Received {} objects", received_objects)
+    }
+}
+
+/// Return the options for the proxy to connect to remotes through: the one given by [get_proxy],
+/// or otherwise whichever libgit2 detects from the git config and environment
+fn generate_proxy_options() -> ProxyOptions<'static> {
+    let mut options = ProxyOptions::new();
+    match get_proxy() {
+        Some(proxy) => {
+            if is_debug_git() {
+                print_git_debug(&format!("Connecting through proxy {}", proxy));
+            }
+            options.url(&proxy);
+        }
+        None => {
+            options.auto();
+        }
+    }
+    options
+}
+
+fn generate_fetch_options(depth: Option<u32>) -> Result<FetchOptions<'static>, Box<dyn Error>> {
+    let mut cb = generate_callbacks()?;
+    let mut progress = ProgressLine::new();
+    cb.transfer_progress(move |stats| {
+        progress.update(&describe_fetch_progress(
+            stats.received_objects(),
+            stats.total_objects(),
+            stats.received_bytes(),
+            stats.indexed_deltas(),
+            stats.total_deltas(),
+        ));
+        true
+    });
+
+    let mut fo = FetchOptions::new();
+    fo.remote_callbacks(cb)
+        .proxy_options(generate_proxy_options())
+        .download_tags(git2::AutotagOption::All)
+        .update_fetchhead(true);
+    if let Some(depth) = depth {
+        fo.depth(depth.min(i32::MAX as u32) as i32);
+    }
+    Ok(fo)
+}
+
+/// Return the directory the repository at `url` is cached in
+pub(crate) fn get_cache_dir(url: &str) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(CACHE_PATH).as_ref()).join(get_cache_subdir(url))
+}
+
+/// Return the directory a repository is cached in relative to the cache directory, i.e.
+/// `<host>/<owner>/<repo>`. SSH and HTTPS URLs of the same repository share a directory.
+fn get_cache_subdir(url: &str) -> PathBuf {
+    let location = url.split("://").last().unwrap_or(url);
+    let location = location.split_once('@').map_or(location, |(_, rest)| rest);
+    let location = location.replacen(':', "/", 1);
+
+    location
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != "..")
+        .collect()
+}
+
+/// Clone the repository at `url` into `cache_dir`, or fetch the latest changes into it if it has
+/// been cloned before. The working tree is then reset to `branch` (or the default branch of the
+/// remote) as it is on the remote, discarding anything left behind by previous runs. Commits
+/// marked with [mark_unpushed] that haven't reached the remote yet are kept, so that they can
+/// still be pushed.
+///
+/// If `offline` is true, nothing is fetched and the working tree is reset to the remote branch as
+/// of the last fetch instead. An error is returned if the repository has never been cached.
+fn clone_or_update_repo(
+    url: &str,
+    cache_dir: &Path,
+    branch: Option<&str>,
+    depth: Option<u32>,
+    offline: bool,
+) -> Result<git2::Repository, Box<dyn Error>> {
+    let repo = match Repository::open(cache_dir) {
+        Ok(repo) => repo,
+        Err(_) if offline => {
+            return Err(format!(
+                "Cannot run offline as {} has not been cached yet. Run jtd once without \
+                --offline to cache it",
+                url
+            )
+            .into())
+        }
+        Err(_) => {
+            if cache_dir.exists() {
+                fs::remove_dir_all(cache_dir)?;
+            }
+            fs::create_dir_all(cache_dir)?;
+            return clone_repo(url, cache_dir, branch, depth);
+        }
+    };
+
+    if !offline {
+        // The same cache is shared between SSH and HTTPS URLs, so make sure the requested one is
+        // used
+        repo.remote_set_url("origin", url)?;
+        let mut fo = generate_fetch_options(depth)?;
+        if depth.is_none() && repo.is_shallow() {
+            // libgit2 treats a depth of i32::MAX as a request to unshallow the repository
+            fo.depth(i32::MAX);
+        }
+        repo.find_remote("origin")?
+            .fetch::<&str>(&[], Some(&mut fo), None)
+            .map_err(|err| format!("Could not fetch from remote repo: {}", err))?;
+    }
+
+    let branch = match branch {
+        Some(branch) => branch.to_string(),
+        None => repo
+            .find_reference("refs/remotes/origin/HEAD")
+            .ok()
+            .and_then(|reference| {
+                reference.symbolic_target().map(|target| {
+                    target
+                        .trim_start_matches("refs/remotes/origin/")
+                        .to_string()
+                })
+            })
+            .map_or_else(|| get_head_branch(&repo), Ok)?,
+    };
+
+    let remote_commit = get_commit(&repo, &format!("origin/{}", branch))
+        .map_err(|_| format!("Could not find branch {} on the remote repo", branch))?;
+    let branch_ref = format!("refs/heads/{}", branch);
+    let target = match repo.refname_to_id(&format!("{}{}", UNPUSHED_REF_PREFIX, branch)) {
+        Ok(unpushed) if !repo.graph_descendant_of(remote_commit.id(), unpushed)? => {
+            if unpushed != remote_commit.id() {
+                info!("Keeping the unpushed commits on {}", branch);
+            }
+            unpushed
+        }
+        _ => remote_commit.id(),
+    };
+    repo.reference(&branch_ref, target, true, "jtd: update cache")?;
+    repo.set_head(&branch_ref)?;
+    repo.checkout_head(Some(CheckoutBuilder::new().force().remove_untracked(true)))?;
+    drop(remote_commit);
+    update_submodules(&repo)?;
+
+    if offline {
+        success!("Using cached repository {}", cache_dir.to_string_lossy());
+    } else {
+        success!("Successfully updated cached repository!");
+    }
+
+    Ok(repo)
+}
+
+/// Get the repository to operate on:
+///
+/// * If `repository` is explicitly the path of a local clone (see [local_repository_path]), it is
+///   opened and `branch` is checked out if given
+/// * If `url` is the URL of an archive, it is downloaded and unpacked into `target_dir` with
+///   [open_archive]
+/// * Otherwise, if `use_cache` is true, the repository at `url` is cloned into (or updated in)
+///   `~/.cache/jointhedots`
+/// * Otherwise the repository at `url` is cloned into `target_dir` with [clone_repo]
+///
+/// When running offline, the cached repository is used without fetching from `url`.
+pub fn open_or_clone_repo(
+    repository: &str,
+    url: &str,
+    target_dir: &Path,
+    branch: Option<&str>,
+    depth: Option<u32>,
+    use_cache: bool,
+) -> Result<git2::Repository, Box<dyn Error>> {
+    let local_path = match local_repository_path(repository) {
+        Some(local_path) => local_path,
+        None => {
+            if is_archive_url(url) {
+                return open_archive(url, target_dir);
+            }
+            if use_cache {
+                let cache_dir = get_cache_dir(url);
+                debug!(
+                    "Using the cache of {} at {}",
+                    url,
+                    cache_dir.to_string_lossy()
+                );
+                return clone_or_update_repo(url, &cache_dir, branch, depth, is_offline());
+            }
+            if is_offline() {
+                return Err("Cannot run offline without using the cached repository".into());
+            }
+            debug!("Cloning {} into {}", url, target_dir.to_string_lossy());
+            return clone_repo(url, target_dir, branch, depth);
+        }
+    };
+
+    let repo = Repository::open(&local_path).map_err(|err| {
+        format!(
+            "Could not open local repository {}: {}",
+            local_path.to_string_lossy(),
+            err
+        )
+    })?;
+    if let Some(branch) = branch {
+        checkout_ref(&repo, branch)?;
+    }
+    success!("Using local repository {}", local_path.to_string_lossy());
+
+    Ok(repo)
+}
+
+/// Open the user's own clone of the repository at `repo_dir` (e.g. `~/dotfiles`) to operate on in
+/// place of the cached clone, so that jtd fits into their usual git workflow. The `origin` remote
+/// is fetched (unless offline) and `branch` is checked out if given. The checked out branch is then
+/// fast-forwarded to the remote branch it tracks if it is behind it, and otherwise left as it is,
+/// so that local commits and uncommitted changes are never discarded.
+///
+/// A warning is printed if `origin` isn't the repository at `url`.
+pub fn open_user_checkout(
+    repo_dir: &Path,
+    url: &str,
+    branch: Option<&str>,
+) -> Result<git2::Repository, Box<dyn Error>> {
+    let repo_dir = PathBuf::from(shellexpand::tilde(&repo_dir.to_string_lossy()).as_ref());
+    let repo = Repository::open(&repo_dir).map_err(|err| {
+        format!(
+            "Could not open repository {}: {}",
+            repo_dir.to_string_lossy(),
+            err
+        )
+    })?;
+
+    let mut remote = repo.find_remote("origin").map_err(|_| {
+        format!(
+            "{} has no origin remote to sync with",
+            repo_dir.to_string_lossy()
+        )
+    })?;
+    if let Some(origin_url) = remote.url() {
+        if get_cache_subdir(origin_url) != get_cache_subdir(url) {
+            warn!(
+                "The origin of {} is {}, not {}",
+                repo_dir.to_string_lossy(),
+                origin_url,
+                url
+            );
+        }
+    }
+    if !is_offline() {
+        remote
+            .fetch::<&str>(&[], Some(&mut generate_fetch_options(None)?), None)
+            .map_err(|err| format!("Could not fetch from remote repo: {}", err))?;
+    }
+    drop(remote);
+
+    if let Some(branch) = branch {
+        if repo.find_branch(branch, BranchType::Local).is_err() {
+            let remote_commit = get_commit(&repo, &format!("origin/{}", branch))
+                .map_err(|_| format!("Could not find branch {} on the remote repo", branch))?;
+            repo.branch(branch, &remote_commit, false)?
+                .set_upstream(Some(&format!("origin/{}", branch)))?;
+        }
+        checkout_ref(&repo, branch)?;
+    }
+
+    let head_branch = get_head_branch(&repo)?;
+    let upstream = repo
+        .find_branch(&head_branch, BranchType::Local)?
+        .upstream()
+        .ok()
+        .and_then(|upstream| upstream.get().target());
+    let head = get_head(&repo)?.id();
+    let upstream = match upstream {
+        Some(upstream) if upstream != head => upstream,
+        _ => {
+            success!("Using local repository {}", repo_dir.to_string_lossy());
+            return Ok(repo);
+        }
+    };
+    if repo.graph_descendant_of(upstream, head)? {
+        // A safe checkout refuses to overwrite uncommitted changes to the files it updates
+        repo.checkout_tree(
+            &repo.find_object(upstream, None)?,
+            Some(CheckoutBuilder::new().safe()),
+        )
+        .map_err(|err| {
+            format!(
+                "Could not fast-forward {} to the remote: {}",
+                head_branch, err
+            )
+        })?;
+        repo.head()?
+            .set_target(upstream, "jtd: fast-forward to remote")?;
+        info!("Fast-forwarded {} to the remote", head_branch);
+    } else {
+        info!(
+            "{} has commits that aren't on the remote, using it as it is",
+            head_branch
+        );
+    }
+    success!("Using local repository {}", repo_dir.to_string_lossy());
+
+    Ok(repo)
+}
+
+/// Return whether any file tracked in `repo` has uncommitted changes
+pub fn has_uncommitted_changes(repo: &Repository) -> Result<bool, Box<dyn Error>> {
+    let statuses = repo.statuses(Some(StatusOptions::new().include_untracked(false)))?;
+    Ok(!statuses.is_empty())
+}
+
+/// Return an error if any file tracked in `repo` (the user's own clone) has uncommitted changes,
+/// which `action` (e.g. "syncing") would get in the way of or discard
+pub fn ensure_clean_tree(repo: &Repository, action: &str) -> Result<(), Box<dyn Error>> {
+    if has_uncommitted_changes(repo)? {
+        return Err(format!(
+            "{} has uncommitted changes, commit or stash them before {}",
+            get_repo_dir(repo).to_string_lossy(),
+            action
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// If `repo` is a shallow clone that doesn't contain `reference`, fetch the rest of its history so
+/// that it can be checked out
+pub fn ensure_ref_available(repo: &Repository, reference: &str) -> Result<(), Box<dyn Error>> {
+    if !repo.is_shallow() || resolve_ref(repo, reference).is_ok() {
+        return Ok(());
+    }
+    if is_offline() {
+        return Err(format!(
+            "Could not find {} in the cached repository and cannot fetch it while offline",
+            reference
+        )
+        .into());
+    }
+
+    info!(
+        "Fetching full history of the repository to find {}",
+        reference
+    );
+    unshallow(repo)
+}
+
+/// Return whether the commit `commit_hash` is `HEAD` or one of its ancestors
+pub fn is_in_head_history(repo: &Repository, commit_hash: &str) -> Result<bool, Box<dyn Error>> {
+    let head = get_head(repo)?.id();
+    Ok(match Oid::from_str(commit_hash) {
+        Ok(commit) if commit == head => true,
+        Ok(commit) => repo.graph_descendant_of(head, commit).unwrap_or(false),
+        Err(_) => false,
+    })
+}
+
+/// Fetch the rest of the history of the shallow clone `repo`
+pub fn unshallow(repo: &Repository) -> Result<(), Box<dyn Error>> {
+    // libgit2 treats a depth of i32::MAX as a request to unshallow the repository
+    let mut fo = generate_fetch_options(None)?;
+    fo.depth(i32::MAX);
+    repo.find_remote("origin")?
+        .fetch::<&str>(&[], Some(&mut fo), None)
+        .map_err(|err| format!("Could not fetch history of repo: {}", err))?;
+    Ok(())
+}
+
+/// Return the commits reachable from `HEAD` that changed the file at `path`, newest first. Like
+/// `git log`, a merge is only included if the file differs from every one of its parents
+pub fn file_history<'a>(
+    repo: &'a Repository,
+    path: &Path,
+) -> Result<Vec<Commit<'a>>, Box<dyn Error>> {
+    let blob_id = |commit: &Commit| Some(commit.tree().ok()?.get_path(path).ok()?.id());
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+
+    let mut commits = vec![];
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let current_blob_id = blob_id(&commit);
+        let changed = if commit.parent_count() == 0 {
+            current_blob_id.is_some()
+        } else {
+            commit
+                .parents()
+                .all(|parent| blob_id(&parent) != current_blob_id)
+        };
+        if changed {
+            commits.push(commit);
+        }
+    }
+    Ok(commits)
+}
+
+/// Override the name and/or email jtd commits with, taking precedence over the git config
+pub fn set_signature_override(name: Option<&str>, email: Option<&str>) {
+    if let Ok(mut signature_override) = SIGNATURE_OVERRIDE.write() {
+        *signature_override = (name.map(String::from), email.map(String::from));
+    }
+}
+
+/// Set the trailers (as key and value) appended to the messages of commits made with
+/// [add_and_commit], and whether to add a `Signed-off-by` trailer for the identity they are made
+/// with
+pub fn set_commit_trailers(trailers: Vec<(String, String)>, sign_off: bool) {
+    if let Ok(mut commit_trailers) = COMMIT_TRAILERS.write() {
+        *commit_trailers = (trailers, sign_off);
+    }
+}
+
+/// Return `message` with `trailers` appended in a paragraph of their own, leaving out any that the
+/// message already has
+fn append_trailers(message: &str, trailers: &[(String, String)]) -> String {
+    let trailer_lines: Vec<String> = trailers
+        .iter()
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .filter(|line| !message.lines().any(|message_line| message_line == line))
+        .collect();
+    if trailer_lines.is_empty() {
+        return message.to_string();
+    }
+    format!("{}\n\n{}", message.trim_end(), trailer_lines.join("\n"))
+}
+
+/// Return the name and email to commit with. These are taken from the override set with
+/// [set_signature_override] if any, then from `user.name`/`user.email` in `config`, and otherwise
+/// fall back to a generic jtd identity.
+fn get_identity(config: &git2::Config) -> (String, String) {
+    let (name_override, email_override) = SIGNATURE_OVERRIDE
+        .read()
+        .map(|signature_override| signature_override.clone())
+        .unwrap_or_default();
+
+    let name = name_override
+        .or_else(|| config.get_string("user.name").ok())
+        .unwrap_or_else(|| DEFAULT_SIGNATURE_NAME.to_string());
+    let email = email_override
+        .or_else(|| config.get_string("user.email").ok())
+        .unwrap_or_else(|| DEFAULT_SIGNATURE_EMAIL.to_string());
+    (name, email)
+}
+
+/// Generate the signature to commit to `repo` with, using the identity of the user where possible
+pub fn generate_signature(repo: &Repository) -> Result<Signature<'static>, Git2Error> {
+    let (name, email) = get_identity(&repo.config()?);
+    Signature::now(&name, &email)
 }
 
 pub fn add_all(repo: &Repository, file_paths: Option<Vec<&Path>>) -> Result<(), Box<dyn Error>> {
@@ -159,7 +898,7 @@ pub fn add_all(repo: &Repository, file_paths: Option<Vec<&Path>>) -> Result<(),
 ///
 /// * `repo` - The repository object
 /// * `file_paths` - Optionally the paths of the files to commit. If `None`, all changes are
-/// committed.
+///   committed.
 /// * `message` - The commit message to use
 /// * `parents` - Optionally the parent commits for the new commit. If None, `HEAD` is used
 /// * `update_head` - Optionally whether to update the commit the `HEAD` reference points at.
@@ -174,12 +913,12 @@ pub fn add_and_commit<'a>(
     maybe_parents: Option<Vec<&Commit>>,
     update_ref: Option<&str>,
 ) -> Result<Commit<'a>, Box<dyn Error>> {
-    add_all(&repo, file_paths)?;
+    add_all(repo, file_paths)?;
 
     let mut index = repo.index()?;
     let oid = index.write_tree()?;
     let tree = repo.find_tree(oid)?;
-    let signature = generate_signature()?;
+    let signature = generate_signature(repo)?;
 
     let head;
     let parents = match maybe_parents {
@@ -189,28 +928,338 @@ pub fn add_and_commit<'a>(
             vec![&head]
         }
     };
-    let oid = repo.commit(update_ref, &signature, &signature, message, &tree, &parents)?;
+    let (mut trailers, sign_off) = COMMIT_TRAILERS
+        .read()
+        .map(|commit_trailers| commit_trailers.clone())
+        .unwrap_or_default();
+    if sign_off {
+        trailers.push((
+            "Signed-off-by".to_string(),
+            format!(
+                "{} <{}>",
+                signature.name().unwrap_or_default(),
+                signature.email().unwrap_or_default()
+            ),
+        ));
+    }
+    let message = append_trailers(message, &trailers);
+
+    let oid = repo.commit(
+        update_ref, &signature, &signature, &message, &tree, &parents,
+    )?;
+    debug!(
+        "Created commit {} \"{}\"",
+        oid,
+        message.lines().next().unwrap_or_default()
+    );
+    log_operation(
+        "committed",
+        json!({ "commit": oid.to_string(), "message": message }),
+    );
 
     repo.find_commit(oid)
-        .map_err(|err| format!("Failed to commit to repo: {}", err.to_string()).into())
+        .map_err(|err| format!("Failed to commit to repo: {}", err).into())
+}
+
+/// Resolve every conflict in `idx` by taking the whole file from the side given by `favor`
+/// (`Ours` being `HEAD`), deleting it if it was deleted on that side.
+fn resolve_conflicts(idx: &mut Index, favor: FileFavor) -> Result<(), Box<dyn Error>> {
+    let conflicts = idx.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+    for conflict in conflicts {
+        let path = match [&conflict.ancestor, &conflict.our, &conflict.their]
+            .iter()
+            .find_map(|entry| entry.as_ref())
+        {
+            Some(entry) => String::from_utf8_lossy(&entry.path).to_string(),
+            None => continue,
+        };
+        let chosen = if favor == FileFavor::Ours {
+            conflict.our
+        } else {
+            conflict.their
+        };
+
+        // Removes every stage of the path, i.e. the conflict itself
+        idx.remove_path(Path::new(&path))?;
+        if let Some(mut entry) = chosen {
+            // Clear the stage bits so that the entry is added as resolved
+            entry.flags &= !0x3000;
+            idx.add(&entry)?;
+        }
+    }
+    idx.write()?;
+    Ok(())
+}
+
+/// Resolve the conflicts in `idx` in the files listed in `drivers` by merging them key by key with
+/// their merge driver, writing the merged files to the working tree. Files that can't be merged
+/// that way are left conflicted
+fn resolve_structured_conflicts(
+    repo: &Repository,
+    idx: &mut Index,
+    drivers: &[(&Path, MergeDriver)],
+) -> Result<(), Box<dyn Error>> {
+    let conflicts = idx.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+    for conflict in conflicts {
+        let (ancestor, our, their) = match (conflict.ancestor, conflict.our, conflict.their) {
+            (Some(ancestor), Some(our), Some(their)) => (ancestor, our, their),
+            // Added or deleted on one side, so there are no keys to merge
+            _ => continue,
+        };
+        let path = PathBuf::from(String::from_utf8_lossy(&our.path).to_string());
+        let driver = match drivers.iter().find(|(driver_path, _)| *driver_path == path) {
+            Some((_, driver)) if *driver != MergeDriver::Text => *driver,
+            _ => continue,
+        };
+
+        let read_blob =
+            |id| -> Result<Vec<u8>, Git2Error> { Ok(repo.find_blob(id)?.content().to_vec()) };
+        match merge_structured(
+            driver,
+            &read_blob(ancestor.id)?,
+            &read_blob(our.id)?,
+            &read_blob(their.id)?,
+        ) {
+            Ok(merged) => {
+                let mut entry = our;
+                entry.id = repo.blob(&merged)?;
+                entry.file_size = merged.len() as u32;
+                // Clear the stage bits so that the entry is added as resolved
+                entry.flags &= !0x3000;
+                idx.remove_path(&path)?;
+                idx.add(&entry)?;
+                fs::write(get_repo_dir(repo).join(&path), &merged)?;
+                info!(
+                    "Merged the changes to {} key by key",
+                    path.to_string_lossy()
+                );
+            }
+            Err(err) => {
+                warn!(
+                    "Could not merge {} key by key ({})",
+                    path.to_string_lossy(),
+                    err
+                );
+            }
+        }
+    }
+    idx.write()?;
+    Ok(())
+}
+
+/// A program that conflicted files can be opened in to resolve the conflicts
+#[derive(Debug, PartialEq)]
+enum MergeTool {
+    /// `git mergetool`, using the tool configured in `merge.tool`
+    GitMergetool(String),
+    /// The command in `$VISUAL` or `$EDITOR`
+    Editor(String),
+}
+
+impl MergeTool {
+    /// Return the merge tool configured in git, or the user's editor if there is none
+    fn find(repo: &Repository) -> Option<MergeTool> {
+        if let Ok(tool) = repo
+            .config()
+            .and_then(|config| config.get_string("merge.tool"))
+        {
+            return Some(MergeTool::GitMergetool(tool));
+        }
+        ["VISUAL", "EDITOR"]
+            .iter()
+            .find_map(|var| {
+                env::var(var)
+                    .ok()
+                    .filter(|editor| !editor.trim().is_empty())
+            })
+            .map(MergeTool::Editor)
+    }
+
+    /// Open `paths` in the merge tool, waiting for it to exit. Returns whether it exited
+    /// successfully, as merge tools exit with a non-zero status when the merge is aborted
+    fn launch(&self, repo: &Repository, paths: &[PathBuf]) -> Result<bool, Box<dyn Error>> {
+        let mut command = match self {
+            MergeTool::GitMergetool(_) => {
+                let mut command = Command::new("git");
+                command.args(["mergetool", "--no-prompt"]);
+                command
+            }
+            MergeTool::Editor(editor) => {
+                let mut components = editor.split_whitespace();
+                // Safe to unwrap, editors containing only whitespace are filtered out in `find`
+                let mut command = Command::new(components.next().unwrap());
+                command.args(components);
+                command
+            }
+        };
+        let status = command
+            .args(paths)
+            .current_dir(get_repo_dir(repo))
+            .status()
+            .map_err(|err| format!("Could not launch {}: {}", self, err))?;
+        Ok(status.success())
+    }
+}
+
+impl std::fmt::Display for MergeTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeTool::GitMergetool(tool) => write!(f, "git mergetool ({})", tool),
+            MergeTool::Editor(editor) => write!(f, "{}", editor),
+        }
+    }
+}
+
+/// Return the paths (relative to the working tree) of every conflicted file in `idx`
+fn get_conflicted_paths(idx: &Index) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = vec![];
+    for conflict in idx.conflicts()? {
+        let conflict = conflict?;
+        if let Some(entry) = [conflict.our, conflict.their, conflict.ancestor]
+            .iter()
+            .find_map(|entry| entry.as_ref())
+        {
+            paths.push(PathBuf::from(
+                String::from_utf8_lossy(&entry.path).to_string(),
+            ));
+        }
+    }
+    Ok(paths)
+}
+
+/// Stage every conflicted file in `idx` that no longer contains conflict markers, as editors don't
+/// do this themselves
+fn stage_resolved_files(repo: &Repository, idx: &mut Index) -> Result<(), Box<dyn Error>> {
+    for path in get_conflicted_paths(idx)? {
+        let full_path = get_repo_dir(repo).join(&path);
+        if !full_path.exists() {
+            idx.remove_path(&path)?;
+        } else if !fs::read_to_string(&full_path)
+            .map(|contents| contents.contains("<<<<<<<") || contents.contains(">>>>>>>"))
+            .unwrap_or(false)
+        {
+            idx.add_path(&path)?;
+        }
+    }
+    idx.write()?;
+    Ok(())
+}
+
+/// The result of merging the contents of a single file with [merge_file_contents]
+#[derive(Debug, PartialEq)]
+pub struct MergedFile {
+    pub contents: Vec<u8>,
+    /// Whether the merge conflicted, in which case `contents` contain conflict markers
+    pub conflicted: bool,
+}
+
+/// Three-way merge the contents `local` and `remote` of a file, which both started out as `base`,
+/// without touching the working tree or index of `repo`. Conflicting hunks are left in the result
+/// between conflict markers labelled `local` and `repository`. `name` is the name of the file,
+/// which merges are sensitive to through `.gitattributes`. Files with a structured `driver` are
+/// merged key by key, falling back to merging them line by line if that fails
+pub fn merge_file_contents(
+    repo: &Repository,
+    name: &str,
+    driver: MergeDriver,
+    base: &[u8],
+    local: &[u8],
+    remote: &[u8],
+) -> Result<MergedFile, Box<dyn Error>> {
+    if driver != MergeDriver::Text {
+        match merge_structured(driver, base, local, remote) {
+            Ok(contents) => {
+                return Ok(MergedFile {
+                    contents,
+                    conflicted: false,
+                })
+            }
+            Err(err) => {
+                warn!(
+                    "Could not merge {} key by key ({}), merging it line by line",
+                    name, err
+                );
+            }
+        }
+    }
+
+    let single_file_tree = |contents: &[u8]| -> Result<Tree, Git2Error> {
+        let mut builder = repo.treebuilder(None)?;
+        builder.insert(name, repo.blob(contents)?, 0o100644)?;
+        repo.find_tree(builder.write()?)
+    };
+    let mut options = MergeOptions::new();
+    options.standard_style(true).minimal(true);
+    let mut idx = repo.merge_trees(
+        &single_file_tree(base)?,
+        &single_file_tree(local)?,
+        &single_file_tree(remote)?,
+        Some(&options),
+    )?;
+
+    if !idx.has_conflicts() {
+        let entry = idx
+            .get_path(Path::new(name), 0)
+            .ok_or_else(|| format!("Merging {} produced no file", name))?;
+        return Ok(MergedFile {
+            contents: repo.find_blob(entry.id)?.content().to_vec(),
+            conflicted: false,
+        });
+    }
+
+    // Only checking out a conflicted index writes the conflict markers
+    let merge_dir = tempfile::tempdir()?;
+    repo.checkout_index(
+        Some(&mut idx),
+        Some(
+            CheckoutBuilder::new()
+                .target_dir(merge_dir.path())
+                .allow_conflicts(true)
+                .conflict_style_merge(true)
+                .our_label("local")
+                .their_label("repository")
+                .force(),
+        ),
+    )?;
+    Ok(MergedFile {
+        contents: fs::read(merge_dir.path().join(name))?,
+        conflicted: true,
+    })
 }
 
+/// Merge `feature_tip` into `main_tip`, which must be `HEAD`. Conflicting hunks are resolved in
+/// favour of the side given by `favor` (`Ours` being `main_tip`), or manually by the user if it is
+/// [FileFavor::Normal]. The user is offered to open the conflicted files in their merge tool or
+/// editor to do so. Conflicts in the files listed in `drivers` are first merged key by key with
+/// their merge driver.
 pub fn normal_merge<'a>(
     repo: &'a Repository,
     main_tip: &AnnotatedCommit,
     feature_tip: &AnnotatedCommit,
+    favor: FileFavor,
+    drivers: &[(&Path, MergeDriver)],
 ) -> Result<Commit<'a>, Box<dyn Error>> {
     let mut options = MergeOptions::new();
     options
         .standard_style(true)
         .minimal(true)
-        .fail_on_conflict(false);
+        .fail_on_conflict(false)
+        .file_favor(favor);
     repo.merge(&[feature_tip], Some(&mut options), None)?;
 
     let mut idx = repo.index()?;
     idx.read(false)?;
-    if idx.has_conflicts() {
+    if idx.has_conflicts() && !drivers.is_empty() {
+        resolve_structured_conflicts(repo, &mut idx, drivers)?;
+    }
+    if idx.has_conflicts() && favor != FileFavor::Normal {
+        // Conflicts that aren't within a file (e.g. a file modified on one side and deleted on the
+        // other) can't be resolved by the file favor
+        resolve_conflicts(&mut idx, favor)?;
+        repo.checkout_index(Some(&mut idx), Some(CheckoutBuilder::new().force()))?;
+    } else if idx.has_conflicts() {
         let repo_dir = repo.path().to_string_lossy().replace(".git/", "");
+        ensure_interactive("Merge conflicts were detected and must be resolved manually")?;
         repo.checkout_index(
             Some(&mut idx),
             Some(
@@ -219,27 +1268,65 @@ pub fn normal_merge<'a>(
                     .conflict_style_merge(true),
             ),
         )?;
-        error!(
-            "Merge conficts detected. Resolve them manually with the following steps:\n\n  \
-             1. Open the temporary repository (located in {}),\n  \
-             2. Resolve any merge conflicts as you would with any other repository\n  \
-             3. Adding the changed files but NOT committing them\n  \
-             4. Returning to this terminal and pressing the \"Enter\" key\n",
-            repo_dir
-        );
-        loop {
-            print!(
-                "{}",
-                style("Press ENTER when conflicts are resolved")
-                    .blue()
-                    .italic()
-            );
-            let _ = stdout().flush();
-
-            let mut _newline = String::new();
-            stdin().read_line(&mut _newline).unwrap_or(0);
 
-            idx.read(false)?;
+        let merge_tool = MergeTool::find(repo);
+        let mut shown_instructions = false;
+        loop {
+            let paths = get_conflicted_paths(&idx)?;
+            let use_merge_tool = match &merge_tool {
+                Some(merge_tool) => prompter().confirm(
+                    &format!(
+                        "Merge conflicts detected in {}. Open them in {}?",
+                        paths
+                            .iter()
+                            .map(|path| path.to_string_lossy())
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        merge_tool
+                    ),
+                    true,
+                )?,
+                None => false,
+            };
+
+            if let (true, Some(merge_tool)) = (use_merge_tool, &merge_tool) {
+                if merge_tool.launch(repo, &paths)? {
+                    idx.read(true)?;
+                    stage_resolved_files(repo, &mut idx)?;
+                } else {
+                    // The merge was aborted, so whatever the tool left in the conflicted files (or
+                    // staged of them) is discarded in favour of the conflict markers
+                    warn!(
+                        "{} exited unsuccessfully, discarding the changes made in it",
+                        merge_tool
+                    );
+                    let mut checkout = CheckoutBuilder::new();
+                    checkout
+                        .allow_conflicts(true)
+                        .conflict_style_merge(true)
+                        .force();
+                    for path in paths.iter() {
+                        checkout.path(path);
+                    }
+                    repo.checkout_index(Some(&mut idx), Some(&mut checkout))?;
+                    idx.write()?;
+                }
+            } else {
+                if !shown_instructions {
+                    error!(
+                        "Merge conficts detected. Resolve them manually with the following steps:\n\n  \
+                         1. Open the temporary repository (located in {}),\n  \
+                         2. Resolve any merge conflicts as you would with any other repository\n  \
+                         3. Adding the changed files but NOT committing them\n  \
+                         4. Returning to this terminal and pressing the \"Enter\" key\n",
+                        repo_dir
+                    );
+                    shown_instructions = true;
+                }
+                prompter().wait("Press ENTER when conflicts are resolved")?;
+
+                idx.read(false)?;
+            }
 
             if !idx.has_conflicts() {
                 break;
@@ -250,7 +1337,7 @@ pub fn normal_merge<'a>(
     }
 
     let tree = repo.find_tree(repo.index()?.write_tree()?)?;
-    let signature = generate_signature()?;
+    let signature = generate_signature(repo)?;
     repo.commit(
         Some("HEAD"),
         &signature,
@@ -263,27 +1350,365 @@ pub fn normal_merge<'a>(
         ],
     )?;
     repo.cleanup_state()?;
-    Ok(get_head(&repo)?)
-}
-
-pub fn get_repo_dir(repo: &Repository) -> &Path {
-    // Safe to unwrap here, repo.path() points to .git folder. Path will always
-    // have a component before .git
-    repo.path().parent().unwrap()
+    get_head(repo)
 }
 
-pub fn push(repo: &Repository) -> Result<(), Box<dyn Error>> {
-    let mut remote = repo.find_remote("origin")?;
-
-    remote.connect_auth(Direction::Push, Some(generate_callbacks()?), None)?;
-    let mut options = PushOptions::new();
-    options.remote_callbacks(generate_callbacks()?);
+/// Fetch `remote_branch` from the `remote_name` remote and integrate any commits on it that aren't
+/// in `HEAD` (which is on `branch`), so that `HEAD` can then be pushed to `remote_branch` without
+/// being rejected as a non-fast-forward. Diverged histories are merged with [normal_merge],
+/// resolving conflicts according to `strategy`. An error is returned if `repo` has uncommitted
+/// changes, as integrating the remote changes could overwrite them.
+pub fn integrate_remote_changes(
+    repo: &Repository,
+    remote_name: &str,
+    branch: &str,
+    remote_branch: &str,
+    strategy: MergeStrategy,
+) -> Result<(), Box<dyn Error>> {
+    ensure_clean_tree(repo, "pulling in the changes on the remote repo")?;
+    let remote_ref = match fetch_branch(repo, remote_name, remote_branch)? {
+        Some(remote_ref) => remote_ref,
+        // The branch doesn't exist on the remote yet, so there is nothing to integrate
+        None => return Ok(()),
+    };
+    let remote_tip = repo.reference_to_annotated_commit(&remote_ref)?;
+    let (analysis, _) = repo.merge_analysis(&[&remote_tip])?;
+
+    if analysis.is_up_to_date() {
+        return Ok(());
+    } else if analysis.is_fast_forward() {
+        info!("Fast-forwarding to the latest changes on the remote repo");
+        // A safe checkout refuses to overwrite changes to the files it updates, rather than
+        // silently discarding them
+        repo.checkout_tree(
+            &repo.find_object(remote_tip.id(), None)?,
+            Some(CheckoutBuilder::new().safe()),
+        )
+        .map_err(|err| format!("Could not fast-forward {} to the remote: {}", branch, err))?;
+        repo.reference(
+            &format!("refs/heads/{}", branch),
+            remote_tip.id(),
+            true,
+            "jtd: fast-forward",
+        )?;
+    } else {
+        info!("Merging changes that were pushed to the remote repo in the meantime");
+        let head_tip = repo.reference_to_annotated_commit(&repo.head()?)?;
+        normal_merge(repo, &head_tip, &remote_tip, strategy.file_favor(true), &[])
+            .map_err(|err| format!("Could not merge remote changes: {}", err))?;
+    }
+    Ok(())
+}
+
+/// Fetch `remote_branch` from the `remote_name` remote, returning its remote-tracking reference or
+/// None if the branch doesn't exist on the remote
+fn fetch_branch<'a>(
+    repo: &'a Repository,
+    remote_name: &str,
+    remote_branch: &str,
+) -> Result<Option<git2::Reference<'a>>, Box<dyn Error>> {
+    let refspec = format!(
+        "refs/heads/{0}:refs/remotes/{1}/{0}",
+        remote_branch, remote_name
+    );
+    repo.find_remote(remote_name)?
+        .fetch(&[&refspec], Some(&mut generate_fetch_options(None)?), None)
+        .map_err(|err| format!("Could not fetch from remote repo: {}", err))?;
+
+    Ok(repo
+        .find_reference(&format!("refs/remotes/{}/{}", remote_name, remote_branch))
+        .ok())
+}
+
+/// Fetch `remote_branch` from the `remote_name` remote and replay the commits on `branch` (which
+/// `HEAD` is on) that aren't on it on top of it, like `git pull --rebase`. Returns the hashes of
+/// the replayed commits, mapped to those of the commits they were replayed as. If replaying a
+/// commit conflicts, the rebase is aborted and an error is returned, leaving `branch` unchanged.
+pub fn rebase_onto_remote(
+    repo: &Repository,
+    remote_name: &str,
+    branch: &str,
+    remote_branch: &str,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let remote_ref = match fetch_branch(repo, remote_name, remote_branch)? {
+        Some(remote_ref) => remote_ref,
+        None => return Ok(HashMap::new()),
+    };
+    let upstream = repo.reference_to_annotated_commit(&remote_ref)?;
+    let local = repo
+        .reference_to_annotated_commit(&repo.find_reference(&format!("refs/heads/{}", branch))?)?;
+
+    let signature = generate_signature(repo)?;
+    let mut rebase = repo.rebase(Some(&local), Some(&upstream), None, None)?;
+    let mut rewritten = HashMap::new();
+    while let Some(operation) = rebase.next() {
+        let original = operation?.id();
+        if repo.index()?.has_conflicts() {
+            rebase.abort()?;
+            return Err(format!(
+                "replaying commit {} conflicts with the remote repo",
+                original
+            )
+            .into());
+        }
+        match rebase.commit(None, &signature, None) {
+            Ok(replayed) => {
+                rewritten.insert(original.to_string(), replayed.to_string());
+            }
+            // The changes of the commit are already on the remote
+            Err(err) if err.code() == ErrorCode::Applied => {
+                rewritten.insert(original.to_string(), upstream.id().to_string());
+            }
+            Err(err) => {
+                rebase.abort()?;
+                return Err(err.into());
+            }
+        }
+    }
+    rebase.finish(Some(&signature))?;
+    debug!(
+        "Replayed {} commit(s) on top of {}/{}",
+        rewritten.len(),
+        remote_name,
+        remote_branch
+    );
+    Ok(rewritten)
+}
+
+pub fn get_repo_dir(repo: &Repository) -> &Path {
+    // Safe to unwrap here, repo.path() points to .git folder. Path will always
+    // have a component before .git
+    repo.path().parent().unwrap()
+}
+
+/// Mark the commits on the current branch of `repo` as deliberately left unpushed, so that updating
+/// the cached repository doesn't discard them before they are pushed
+pub fn mark_unpushed(repo: &Repository) -> Result<(), Box<dyn Error>> {
+    let branch = get_head_branch(repo)?;
+    repo.reference(
+        &format!("{}{}", UNPUSHED_REF_PREFIX, branch),
+        get_head(repo)?.id(),
+        true,
+        "jtd: commit without pushing",
+    )?;
+    Ok(())
+}
+
+/// Return the number of commits on `branch` of `repo` that aren't on the `remote_name` remote as of
+/// the last fetch, neither on `remote_branch` nor on the branch of the same name
+pub fn count_unpushed_commits(
+    repo: &Repository,
+    remote_name: &str,
+    branch: &str,
+    remote_branch: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(repo.refname_to_id(&format!("refs/heads/{}", branch))?)?;
+    for pushed_branch in [branch, remote_branch] {
+        if let Ok(remote) =
+            repo.refname_to_id(&format!("refs/remotes/{}/{}", remote_name, pushed_branch))
+        {
+            revwalk.hide(remote)?;
+        }
+    }
+    Ok(revwalk.count())
+}
+
+/// Return the refspec that pushes the local `branch` to `remote_branch` on a remote
+fn push_refspec(branch: &str, remote_branch: &str) -> String {
+    format!("refs/heads/{}:refs/heads/{}", branch, remote_branch)
+}
+
+/// The error returned by [push] when the remote rejects the push because it has commits that
+/// aren't in the pushed branch, i.e. it isn't a fast-forward
+#[derive(Debug)]
+pub struct NonFastForwardError(pub String);
+
+impl Display for NonFastForwardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The remote repo rejected the push as {} has changed since it was fetched",
+            self.0
+        )
+    }
+}
+
+impl Error for NonFastForwardError {}
+
+/// Return whether `reason`, as given by a remote rejecting a pushed reference, means the push
+/// wasn't a fast-forward
+fn is_non_fast_forward(reason: &str) -> bool {
+    ["non-fast-forward", "fetch first", "stale info"]
+        .iter()
+        .any(|message| reason.contains(message))
+}
+
+/// Push the local `branch` to `remote_branch` on the `remote_name` remote. Returns a
+/// [NonFastForwardError] if the remote has changed in a way that the push doesn't include
+pub fn push(
+    repo: &Repository,
+    remote_name: &str,
+    branch: &str,
+    remote_branch: &str,
+) -> Result<(), Box<dyn Error>> {
+    push_ref(repo, remote_name, branch, remote_branch, None)
+}
+
+/// Force-push the local `branch` to `remote_branch` on the `remote_name` remote, replacing its
+/// history, like `git push --force-with-lease`. The push is refused if `remote_branch` has changed
+/// on the remote since it was last fetched, so that commits jtd hasn't seen are never overwritten
+pub fn force_push_with_lease(
+    repo: &Repository,
+    remote_name: &str,
+    branch: &str,
+    remote_branch: &str,
+) -> Result<(), Box<dyn Error>> {
+    // A branch that was never fetched must not exist on the remote either
+    let expected = repo
+        .refname_to_id(&format!("refs/remotes/{}/{}", remote_name, remote_branch))
+        .unwrap_or_else(|_| Oid::zero());
+    push_ref(repo, remote_name, branch, remote_branch, Some(expected))
+}
+
+/// Push the local `branch` to `remote_branch` on the `remote_name` remote. If `lease` is given,
+/// the push is forced, but only if `remote_branch` is at the `lease` commit on the remote
+fn push_ref(
+    repo: &Repository,
+    remote_name: &str,
+    branch: &str,
+    remote_branch: &str,
+    lease: Option<Oid>,
+) -> Result<(), Box<dyn Error>> {
+    let mut remote = repo.find_remote(remote_name)?;
+
+    remote.connect_auth(
+        Direction::Push,
+        Some(generate_callbacks()?),
+        Some(generate_proxy_options()),
+    )?;
+    // The reason the remote gave for rejecting the pushed reference, if it did
+    let rejection = RefCell::new(None);
+    let mut cb = generate_callbacks()?;
+    let mut progress = ProgressLine::new();
+    cb.push_transfer_progress(move |current, total, _| {
+        if let Some(percent) = (current * 100).checked_div(total) {
+            progress.update(&format!(
+                "Pushing objects: {}% ({}/{})",
+                percent, current, total
+            ));
+        }
+    });
+    // The commit the remote branch was actually at, if it broke the lease
+    let broken_lease = RefCell::new(None);
+    if let Some(lease) = lease {
+        let remote_ref = format!("refs/heads/{}", remote_branch);
+        let broken_lease = &broken_lease;
+        cb.push_negotiation(move |updates| {
+            for update in updates {
+                if is_debug_git() {
+                    print_git_debug(&format!(
+                        "Force-pushing {} ({}) to {} (currently {}, expected {})",
+                        update.src_refname().unwrap_or("?"),
+                        update.dst(),
+                        update.dst_refname().unwrap_or("?"),
+                        update.src(),
+                        lease
+                    ));
+                }
+                if update.dst_refname() == Some(remote_ref.as_str()) && update.src() != lease {
+                    *broken_lease.borrow_mut() = Some(update.src());
+                    return Err(Git2Error::from_str("the remote branch has changed"));
+                }
+            }
+            Ok(())
+        });
+    }
+    cb.push_update_reference(|refname, status| {
+        match status {
+            Some(status) => {
+                if is_debug_git() {
+                    print_git_debug(&format!("Remote rejected {}: {}", refname, status));
+                }
+                *rejection.borrow_mut() = Some(status.to_string());
+            }
+            None if is_debug_git() => print_git_debug(&format!("Remote updated {}", refname)),
+            None => {}
+        }
+        Ok(())
+    });
+    let mut options = PushOptions::new();
+    options
+        .remote_callbacks(cb)
+        .proxy_options(generate_proxy_options());
+    let mut refspec = push_refspec(branch, remote_branch);
+    if lease.is_some() {
+        refspec.insert(0, '+');
+    }
+    debug!(
+        "Pushing {} to {} on {}",
+        branch,
+        remote_branch,
+        remote.url().unwrap_or(remote_name)
+    );
+    let result = remote.push(&[&refspec], Some(&mut options));
+    drop(options);
+    if let Some(actual) = broken_lease.into_inner() {
+        return Err(format!(
+            "Refusing to force-push as {} on the remote repo is at {}, not {} as it was when last \
+            fetched. Fetch and review its changes first",
+            remote_branch,
+            actual,
+            lease.unwrap_or_else(Oid::zero)
+        )
+        .into());
+    }
+    match (result, rejection.into_inner()) {
+        (Err(err), _) if err.code() == ErrorCode::NotFastForward => {
+            return Err(NonFastForwardError(remote_branch.to_string()).into())
+        }
+        (Err(err), _) => return Err(format!("Could not push to remote repo: {}", err).into()),
+        (Ok(()), Some(reason)) if is_non_fast_forward(&reason) => {
+            return Err(NonFastForwardError(remote_branch.to_string()).into())
+        }
+        (Ok(()), Some(reason)) => {
+            return Err(format!("The remote repo rejected the push: {}", reason).into())
+        }
+        (Ok(()), None) => {}
+    }
+    if let Ok(mut unpushed) = repo.find_reference(&format!("{}{}", UNPUSHED_REF_PREFIX, branch)) {
+        unpushed.delete()?;
+    }
+    log_operation(
+        "pushed",
+        json!({
+            "branch": remote_branch,
+            "remote": remote.url(),
+            "commit": get_head_hash(repo)?,
+            "forced": lease.is_some(),
+        }),
+    );
+    Ok(())
+}
+
+/// Check that the credentials available to jtd are sufficient to push to the `origin` remote,
+/// without actually pushing anything.
+pub fn check_push_access(repo: &Repository) -> Result<(), Box<dyn Error>> {
+    let mut remote = repo.find_remote("origin")?;
+
     remote
-        .push(&["refs/heads/master:refs/heads/master"], Some(&mut options))
-        .map_err(|err| format!("Could not push to remote repo: {}", err).into())
+        .connect_auth(
+            Direction::Push,
+            Some(generate_callbacks()?),
+            Some(generate_proxy_options()),
+        )
+        .map_err(|err| format!("Could not authenticate with remote repo: {}", err))?;
+    remote.disconnect()?;
+    Ok(())
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_borrow, clippy::unnecessary_to_owned)]
 mod tests {
     use std::fs::File;
 
@@ -345,6 +1770,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolve_ref() {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+
+        let commit = add_and_commit(&repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+        repo.tag_lightweight("v1.0.0", commit.as_object(), false)
+            .unwrap();
+
+        assert_eq!(
+            resolve_ref(&repo, "v1.0.0").unwrap(),
+            commit.id().to_string()
+        );
+        assert!(resolve_ref(&repo, "v2.0.0").is_err());
+    }
+
     #[test]
     fn test_ask_user_password_with_cache() {
         {
@@ -389,18 +1830,263 @@ mod tests {
         // FIXME: Find some way to assert the return type of callbacks
     }
 
+    #[test]
+    fn test_ssh_key_credential() {
+        let key_dir = tempfile::tempdir().unwrap();
+        let key_path = key_dir.path().join("id_dotfiles");
+
+        let err = ssh_key_credential(&key_path, "git", Some(SshKeyMethod::KeyFile))
+            .err()
+            .unwrap();
+        assert!(err.message().ends_with("id_dotfiles does not exist"));
+
+        fs::write(&key_path, "not really a key").unwrap();
+        assert!(ssh_key_credential(&key_path, "git", Some(SshKeyMethod::KeyFile)).is_ok());
+        assert!(ssh_key_credential(&key_path, "git", None).is_err());
+
+        assert_eq!(SshKeyMethod::for_attempt(0), Some(SshKeyMethod::KeyFile));
+        assert_eq!(
+            SshKeyMethod::for_attempt(3),
+            None,
+            "Each method should only be tried once"
+        );
+    }
+
     #[test]
     fn test_clone_repo() {
         let repo_dir = tempdir().expect("Failed to create tempdir");
 
-        let _repo = clone_repo("https://github.com/dob9601/dotfiles.git", repo_dir.path())
-            .expect("Failed to clone repo");
+        let _repo = clone_repo(
+            "https://github.com/dob9601/dotfiles.git",
+            repo_dir.path(),
+            None,
+            None,
+        )
+        .expect("Failed to clone repo");
 
         assert!(Path::exists(
             &repo_dir.path().to_owned().join(Path::new("jtd.yaml"))
         ));
     }
 
+    #[test]
+    fn test_open_or_clone_repo_local() {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        let commit = add_and_commit(&repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+        repo.branch("laptop", &commit, false).unwrap();
+
+        let target_dir = tempdir().unwrap();
+        let local_repo = open_or_clone_repo(
+            &repo_dir.path().to_string_lossy(),
+            "https://example.com/unreachable.git",
+            target_dir.path(),
+            Some("laptop"),
+            Some(1),
+            true,
+        )
+        .expect("Failed to open local repo");
+
+        assert_eq!(
+            get_repo_dir(&local_repo).canonicalize().unwrap(),
+            repo_dir.path().canonicalize().unwrap()
+        );
+        assert_eq!(get_head_branch(&local_repo).unwrap(), "laptop");
+    }
+
+    #[test]
+    fn test_describe_fetch_progress() {
+        assert_eq!(
+            describe_fetch_progress(50, 200, 3 * 1024 * 1024, 0, 0),
+            "Receiving objects: 25% (50/200), 3.00 MiB"
+        );
+        assert_eq!(
+            describe_fetch_progress(200, 200, 3 * 1024 * 1024, 30, 40),
+            "Resolving deltas: 75% (30/40)"
+        );
+        assert_eq!(
+            describe_fetch_progress(200, 200, 3 * 1024 * 1024, 0, 0),
+            "Received 200 objects"
+        );
+    }
+
+    #[test]
+    fn test_get_cache_subdir() {
+        for url in [
+            "https://github.com/dob9601/dotfiles.git",
+            "git@github.com:dob9601/dotfiles.git",
+            "ssh://git@github.com/dob9601/dotfiles",
+        ] {
+            assert_eq!(
+                get_cache_subdir(url),
+                Path::new("github.com").join("dob9601").join("dotfiles")
+            );
+        }
+    }
+
+    #[test]
+    fn test_clone_or_update_repo() {
+        let remote_dir = tempdir().unwrap();
+        let remote_repo = Repository::init(&remote_dir).unwrap();
+        let first_commit =
+            add_and_commit(&remote_repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+        let remote_url = remote_dir.path().to_string_lossy().to_string();
+
+        let cache_dir = tempdir().unwrap();
+        let cache_path = cache_dir.path().join("dotfiles");
+
+        let repo = clone_or_update_repo(&remote_url, &cache_path, None, None, false)
+            .expect("Failed to clone repo into cache");
+        assert_eq!(get_head_hash(&repo).unwrap(), first_commit.id().to_string());
+
+        // Leave behind a commit that was never pushed, as a failed sync would
+        add_and_commit(&repo, None, "", None, Some("HEAD")).unwrap();
+
+        let second_commit = add_and_commit(&remote_repo, None, "", None, Some("HEAD")).unwrap();
+
+        let repo = clone_or_update_repo(&remote_url, &cache_path, None, None, false)
+            .expect("Failed to update cached repo");
+        assert_eq!(
+            get_head_hash(&repo).unwrap(),
+            second_commit.id().to_string()
+        );
+    }
+
+    #[test]
+    fn test_clone_or_update_repo_keeps_unpushed_commits() {
+        let remote_dir = tempdir().unwrap();
+        let remote_repo = Repository::init(&remote_dir).unwrap();
+        add_and_commit(&remote_repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+        let remote_url = remote_dir.path().to_string_lossy().to_string();
+
+        let cache_dir = tempdir().unwrap();
+        let cache_path = cache_dir.path().join("dotfiles");
+
+        let repo = clone_or_update_repo(&remote_url, &cache_path, None, None, false).unwrap();
+        let unpushed_commit = add_and_commit(&repo, None, "", None, Some("HEAD")).unwrap();
+        mark_unpushed(&repo).unwrap();
+        // A failed sync on top of the unpushed commit is still discarded
+        add_and_commit(&repo, None, "", None, Some("HEAD")).unwrap();
+
+        let repo = clone_or_update_repo(&remote_url, &cache_path, None, None, false)
+            .expect("Failed to update cached repo");
+        assert_eq!(
+            get_head_hash(&repo).unwrap(),
+            unpushed_commit.id().to_string()
+        );
+        let branch = get_head_branch(&repo).unwrap();
+        assert_eq!(
+            count_unpushed_commits(&repo, "origin", &branch, &branch).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_clone_repo_with_submodule() {
+        let theme_dir = tempdir().unwrap();
+        let theme_repo = Repository::init(&theme_dir).unwrap();
+        fs::write(theme_dir.path().join("theme.conf"), "background #000000").unwrap();
+        add_and_commit(
+            &theme_repo,
+            Some(vec![Path::new("theme.conf")]),
+            "Add theme",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let remote_dir = tempdir().unwrap();
+        let remote_repo = Repository::init(&remote_dir).unwrap();
+        let mut submodule = remote_repo
+            .submodule(
+                &theme_dir.path().to_string_lossy(),
+                Path::new("theme"),
+                true,
+            )
+            .unwrap();
+        submodule.clone(None).unwrap();
+        submodule.add_finalize().unwrap();
+        let commit = add_and_commit(
+            &remote_repo,
+            None,
+            "Add theme submodule",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let target_dir = tempdir().unwrap();
+        let repo = clone_repo(
+            &remote_dir.path().to_string_lossy(),
+            target_dir.path(),
+            None,
+            None,
+        )
+        .expect("Failed to clone repo with submodule");
+
+        assert_eq!(
+            fs::read_to_string(target_dir.path().join("theme").join("theme.conf")).unwrap(),
+            "background #000000"
+        );
+        let tree = repo.find_commit(commit.id()).unwrap().tree().unwrap();
+        let (_, contents) = find_file_in_tree(&repo, &tree, Path::new("theme/theme.conf"))
+            .unwrap()
+            .expect("Could not find file inside submodule");
+        assert_eq!(contents, b"background #000000");
+        assert!(
+            find_file_in_tree(&repo, &tree, Path::new("theme/missing.conf"))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_open_user_checkout() {
+        let remote_dir = tempdir().unwrap();
+        let remote_repo = Repository::init(&remote_dir).unwrap();
+        add_and_commit(&remote_repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+        let remote_url = remote_dir.path().to_string_lossy().to_string();
+
+        let checkout_dir = tempdir().unwrap();
+        let checkout = clone_repo(&remote_url, checkout_dir.path(), None, None).unwrap();
+        let second_commit = add_and_commit(&remote_repo, None, "", None, Some("HEAD")).unwrap();
+
+        let repo = open_user_checkout(checkout_dir.path(), &remote_url, None)
+            .expect("Failed to open user checkout");
+        assert_eq!(
+            get_head_hash(&repo).unwrap(),
+            second_commit.id().to_string()
+        );
+
+        // Local commits are kept rather than reset to the remote
+        let local_commit = add_and_commit(&checkout, None, "", None, Some("HEAD")).unwrap();
+        add_and_commit(&remote_repo, None, "", None, Some("HEAD")).unwrap();
+        let repo = open_user_checkout(checkout_dir.path(), &remote_url, None).unwrap();
+        assert_eq!(get_head_hash(&repo).unwrap(), local_commit.id().to_string());
+        assert!(!has_uncommitted_changes(&repo).unwrap());
+    }
+
+    #[test]
+    fn test_clone_or_update_repo_offline() {
+        let remote_dir = tempdir().unwrap();
+        let remote_repo = Repository::init(&remote_dir).unwrap();
+        let first_commit =
+            add_and_commit(&remote_repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+        let remote_url = remote_dir.path().to_string_lossy().to_string();
+
+        let cache_dir = tempdir().unwrap();
+        let cache_path = cache_dir.path().join("dotfiles");
+
+        assert!(clone_or_update_repo(&remote_url, &cache_path, None, None, true).is_err());
+
+        clone_or_update_repo(&remote_url, &cache_path, None, None, false).unwrap();
+        add_and_commit(&remote_repo, None, "", None, Some("HEAD")).unwrap();
+
+        let repo = clone_or_update_repo(&remote_url, &cache_path, None, None, true)
+            .expect("Failed to use cached repo offline");
+        assert_eq!(get_head_hash(&repo).unwrap(), first_commit.id().to_string());
+    }
+
     #[test]
     fn test_add_and_commit() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
@@ -465,17 +2151,615 @@ mod tests {
 
         checkout_ref(&repo, head_ref_name).expect("Failed to checkout new branch");
 
-        normal_merge(&repo, &annotated_main_head, &annotated_branch_head)
-            .expect("Failed to merge branch");
+        normal_merge(
+            &repo,
+            &annotated_main_head,
+            &annotated_branch_head,
+            FileFavor::Normal,
+            &[],
+        )
+        .expect("Failed to merge branch");
 
         // FIXME: Some assertion on the repo state after this
     }
 
+    /// Commit conflicting changes to `dotfile` on the current branch and on a new branch called
+    /// "branch", returning the ids of the tips of both
+    fn create_conflicting_branches(repo: &Repository) -> (git2::Oid, git2::Oid) {
+        create_diverged_branches(repo, "base", "main", "branch")
+    }
+
+    /// Commit `base` as `dotfile`, then `main` on top of it on the current branch and `branch` on
+    /// top of it on a new branch called "branch", returning the ids of the tips of both
+    fn create_diverged_branches(
+        repo: &Repository,
+        base: &str,
+        main: &str,
+        branch: &str,
+    ) -> (git2::Oid, git2::Oid) {
+        let file_path = get_repo_dir(repo).join("dotfile");
+
+        fs::write(&file_path, base).unwrap();
+        let base_commit = add_and_commit(
+            repo,
+            Some(vec![Path::new("dotfile")]),
+            "",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        repo.branch("branch", &base_commit, true).unwrap();
+        let main_ref_name = repo.head().unwrap().name().unwrap().to_string();
+
+        fs::write(&file_path, main).unwrap();
+        let main_tip = add_and_commit(
+            repo,
+            Some(vec![Path::new("dotfile")]),
+            "",
+            None,
+            Some("HEAD"),
+        )
+        .unwrap()
+        .id();
+
+        checkout_ref(repo, "branch").unwrap();
+        fs::write(&file_path, branch).unwrap();
+        let branch_tip = add_and_commit(
+            repo,
+            Some(vec![Path::new("dotfile")]),
+            "",
+            None,
+            Some("HEAD"),
+        )
+        .unwrap()
+        .id();
+        checkout_ref(repo, &main_ref_name).unwrap();
+
+        (main_tip, branch_tip)
+    }
+
+    #[test]
+    fn test_merge_tool_exit_status() {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+
+        assert!(MergeTool::Editor("true".to_string())
+            .launch(&repo, &[])
+            .unwrap());
+        assert!(!MergeTool::Editor("false".to_string())
+            .launch(&repo, &[])
+            .unwrap());
+    }
+
+    #[test]
+    fn test_stage_resolved_files() {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+
+        let (_, branch_tip) = create_conflicting_branches(&repo);
+        let branch_tip = repo.find_annotated_commit(branch_tip).unwrap();
+        let mut options = MergeOptions::new();
+        options.fail_on_conflict(false);
+        repo.merge(&[&branch_tip], Some(&mut options), None)
+            .unwrap();
+
+        let mut idx = repo.index().unwrap();
+        assert_eq!(
+            get_conflicted_paths(&idx).unwrap(),
+            vec![PathBuf::from("dotfile")]
+        );
+
+        // Still contains conflict markers, so shouldn't be staged
+        fs::write(
+            repo_dir.path().join("dotfile"),
+            "<<<<<<< ours\nmain\n=======\nbranch\n>>>>>>> theirs\n",
+        )
+        .unwrap();
+        stage_resolved_files(&repo, &mut idx).unwrap();
+        assert!(idx.has_conflicts());
+
+        fs::write(repo_dir.path().join("dotfile"), "resolved").unwrap();
+        stage_resolved_files(&repo, &mut idx).unwrap();
+        assert!(!idx.has_conflicts());
+    }
+
+    #[test]
+    fn test_normal_merge_favor() {
+        for (favor, expected) in [(FileFavor::Ours, "main"), (FileFavor::Theirs, "branch")] {
+            let repo_dir = tempdir().unwrap();
+            let repo = Repository::init(&repo_dir).unwrap();
+            let file_path = repo_dir.path().join("dotfile");
+
+            let (main_tip, branch_tip) = create_conflicting_branches(&repo);
+            let main_tip = repo.find_annotated_commit(main_tip).unwrap();
+            let branch_tip = repo.find_annotated_commit(branch_tip).unwrap();
+
+            normal_merge(&repo, &main_tip, &branch_tip, favor, &[])
+                .expect("Failed to merge branch");
+
+            assert_eq!(fs::read_to_string(&file_path).unwrap(), expected);
+            assert!(!repo.index().unwrap().has_conflicts());
+        }
+    }
+
+    #[test]
+    fn test_normal_merge_structured() {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+
+        let (main_tip, branch_tip) = create_diverged_branches(
+            &repo,
+            "{\n  \"a\": 1,\n  \"b\": 1\n}\n",
+            "{\n  \"a\": 2,\n  \"b\": 1\n}\n",
+            "{\n  \"a\": 1,\n  \"b\": 2\n}\n",
+        );
+        let main_tip = repo.find_annotated_commit(main_tip).unwrap();
+        let branch_tip = repo.find_annotated_commit(branch_tip).unwrap();
+
+        // Would wait for the conflicts to be resolved manually if they weren't merged key by key
+        normal_merge(
+            &repo,
+            &main_tip,
+            &branch_tip,
+            FileFavor::Normal,
+            &[(Path::new("dotfile"), MergeDriver::Json)],
+        )
+        .expect("Failed to merge branch");
+
+        assert_eq!(
+            fs::read_to_string(repo_dir.path().join("dotfile")).unwrap(),
+            "{\n  \"a\": 2,\n  \"b\": 2\n}\n"
+        );
+        let head_tree = get_head(&repo).unwrap().tree().unwrap();
+        let entry = head_tree.get_path(Path::new("dotfile")).unwrap();
+        assert_eq!(
+            repo.find_blob(entry.id()).unwrap().content(),
+            b"{\n  \"a\": 2,\n  \"b\": 2\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_ensure_ref_available_full_clone() {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+
+        let commit = add_and_commit(&repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+
+        ensure_ref_available(&repo, &commit.id().to_string())
+            .expect("Commit should already be available");
+    }
+
+    #[test]
+    fn test_append_trailers() {
+        let trailers = vec![
+            ("Synced-from".to_string(), "laptop".to_string()),
+            (
+                "Signed-off-by".to_string(),
+                "Daniel <daniel@example.com>".to_string(),
+            ),
+        ];
+
+        assert_eq!(
+            append_trailers("Sync kitty dotfile", &[]),
+            "Sync kitty dotfile"
+        );
+        assert_eq!(
+            append_trailers("Sync kitty dotfile\n", &trailers),
+            "Sync kitty dotfile\n\nSynced-from: laptop\nSigned-off-by: Daniel <daniel@example.com>"
+        );
+        assert_eq!(
+            append_trailers("Sync kitty dotfile\n\nSynced-from: laptop", &trailers[..1]),
+            "Sync kitty dotfile\n\nSynced-from: laptop"
+        );
+    }
+
+    #[test]
+    fn test_file_history() {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        let commit_file = |path: &str, contents: &str, message: &str| {
+            fs::write(repo_dir.path().join(path), contents).unwrap();
+            let parents = get_head(&repo).ok();
+            add_and_commit(
+                &repo,
+                Some(vec![Path::new(path)]),
+                message,
+                Some(parents.iter().collect()),
+                Some("HEAD"),
+            )
+            .unwrap()
+            .id()
+        };
+
+        let first = commit_file("vimrc", "set number", "Add vimrc");
+        commit_file("zshrc", "export EDITOR=vim", "Add zshrc");
+        let second = commit_file("vimrc", "set nonumber", "Turn off line numbers");
+        commit_file("vimrc", "set nonumber", "Touch vimrc");
+
+        let history: Vec<git2::Oid> = file_history(&repo, Path::new("vimrc"))
+            .unwrap()
+            .iter()
+            .map(|commit| commit.id())
+            .collect();
+        assert_eq!(history, vec![second, first]);
+        assert!(file_history(&repo, Path::new("missing"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_head_branch() {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+
+        let commit = add_and_commit(&repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+
+        assert_eq!(get_head_branch(&repo).unwrap(), "main");
+
+        repo.set_head_detached(commit.id()).unwrap();
+        assert!(get_head_branch(&repo).is_err());
+    }
+
+    #[test]
+    fn test_push_branch() {
+        let remote_dir = tempdir().unwrap();
+        let remote_repo = Repository::init_bare(&remote_dir).unwrap();
+
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        repo.remote("origin", &remote_dir.path().to_string_lossy())
+            .unwrap();
+
+        let commit = add_and_commit(&repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+
+        push(&repo, "origin", "main", "main").expect("Failed to push to remote");
+
+        assert_eq!(
+            remote_repo
+                .find_reference("refs/heads/main")
+                .unwrap()
+                .target()
+                .unwrap(),
+            commit.id()
+        );
+    }
+
+    #[test]
+    fn test_push_to_other_branch() {
+        let remote_dir = tempdir().unwrap();
+        let remote_repo = Repository::init_bare(&remote_dir).unwrap();
+
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        repo.remote("origin", &remote_dir.path().to_string_lossy())
+            .unwrap();
+
+        let commit = add_and_commit(&repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+
+        push(&repo, "origin", "main", "machine/laptop").expect("Failed to push to remote");
+
+        assert_eq!(
+            remote_repo
+                .find_reference("refs/heads/machine/laptop")
+                .unwrap()
+                .target()
+                .unwrap(),
+            commit.id()
+        );
+        assert!(remote_repo.find_reference("refs/heads/main").is_err());
+    }
+
+    #[test]
+    fn test_integrate_remote_changes() {
+        let remote_dir = tempdir().unwrap();
+        Repository::init_bare(&remote_dir).unwrap();
+        let remote_url = remote_dir.path().to_string_lossy().to_string();
+
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        repo.remote("origin", &remote_url).unwrap();
+        add_and_commit(&repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+        push(&repo, "origin", "main", "main").unwrap();
+
+        // Another machine pushes a change first
+        let other_dir = tempdir().unwrap();
+        let other_repo = clone_repo(&remote_url, other_dir.path(), Some("main"), None).unwrap();
+        File::create(other_dir.path().join("remote")).unwrap();
+        let remote_commit = add_and_commit(
+            &other_repo,
+            Some(vec![Path::new("remote")]),
+            "",
+            None,
+            Some("HEAD"),
+        )
+        .unwrap();
+        push(&other_repo, "origin", "main", "main").unwrap();
+
+        File::create(repo_dir.path().join("local")).unwrap();
+        let local_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("local")]),
+            "",
+            None,
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        integrate_remote_changes(&repo, "origin", "main", "main", MergeStrategy::Manual)
+            .expect("Failed to integrate remote changes");
+
+        let head = get_head(&repo).unwrap();
+        let parents: Vec<git2::Oid> = head.parent_ids().collect();
+        assert_eq!(parents, vec![local_commit.id(), remote_commit.id()]);
+        assert!(repo_dir.path().join("remote").exists());
+
+        push(&repo, "origin", "main", "main")
+            .expect("Failed to push after integrating remote changes");
+    }
+
+    #[test]
+    fn test_integrate_remote_changes_keeps_uncommitted_changes() {
+        let remote_dir = tempdir().unwrap();
+        Repository::init_bare(&remote_dir).unwrap();
+        let remote_url = remote_dir.path().to_string_lossy().to_string();
+
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        repo.remote("origin", &remote_url).unwrap();
+        fs::write(repo_dir.path().join("dotfile"), "original").unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+        push(&repo, "origin", "main", "main").unwrap();
+
+        let other_dir = tempdir().unwrap();
+        let other_repo = clone_repo(&remote_url, other_dir.path(), Some("main"), None).unwrap();
+        fs::write(other_dir.path().join("dotfile"), "remote").unwrap();
+        add_and_commit(
+            &other_repo,
+            Some(vec![Path::new("dotfile")]),
+            "",
+            None,
+            Some("HEAD"),
+        )
+        .unwrap();
+        push(&other_repo, "origin", "main", "main").unwrap();
+
+        fs::write(repo_dir.path().join("dotfile"), "uncommitted").unwrap();
+        assert!(
+            integrate_remote_changes(&repo, "origin", "main", "main", MergeStrategy::Manual)
+                .is_err()
+        );
+        assert_eq!(
+            fs::read_to_string(repo_dir.path().join("dotfile")).unwrap(),
+            "uncommitted"
+        );
+    }
+
+    #[test]
+    fn test_rebase_onto_remote_after_rejected_push() {
+        let remote_dir = tempdir().unwrap();
+        Repository::init_bare(&remote_dir).unwrap();
+        let remote_url = remote_dir.path().to_string_lossy().to_string();
+
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        repo.remote("origin", &remote_url).unwrap();
+        add_and_commit(&repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+        push(&repo, "origin", "main", "main").unwrap();
+
+        // Another machine pushes a change after this one last fetched
+        let other_dir = tempdir().unwrap();
+        let other_repo = clone_repo(&remote_url, other_dir.path(), Some("main"), None).unwrap();
+        File::create(other_dir.path().join("remote")).unwrap();
+        let remote_commit = add_and_commit(
+            &other_repo,
+            Some(vec![Path::new("remote")]),
+            "",
+            None,
+            Some("HEAD"),
+        )
+        .unwrap();
+        push(&other_repo, "origin", "main", "main").unwrap();
+
+        File::create(repo_dir.path().join("local")).unwrap();
+        let local_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("local")]),
+            "Sync local",
+            None,
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let err = push(&repo, "origin", "main", "main").unwrap_err();
+        assert!(err.is::<NonFastForwardError>());
+
+        let rewritten = rebase_onto_remote(&repo, "origin", "main", "main")
+            .expect("Failed to replay the local commit");
+        let head = get_head(&repo).unwrap();
+        assert_eq!(
+            rewritten[&local_commit.id().to_string()],
+            head.id().to_string()
+        );
+        assert_eq!(
+            head.parent_ids().collect::<Vec<_>>(),
+            vec![remote_commit.id()]
+        );
+        assert_eq!(head.message(), Some("Sync local"));
+        assert!(repo_dir.path().join("remote").exists());
+
+        push(&repo, "origin", "main", "main").expect("Failed to push after replaying commits");
+    }
+
+    #[test]
+    fn test_force_push_with_lease() {
+        let remote_dir = tempdir().unwrap();
+        let remote_repo = Repository::init_bare(&remote_dir).unwrap();
+        let remote_url = remote_dir.path().to_string_lossy().to_string();
+
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        repo.remote("origin", &remote_url).unwrap();
+        let first_commit = add_and_commit(&repo, None, "", Some(vec![]), Some("HEAD")).unwrap();
+        add_and_commit(&repo, None, "", None, Some("HEAD")).unwrap();
+        push(&repo, "origin", "main", "main").unwrap();
+        fetch_branch(&repo, "origin", "main").unwrap();
+
+        // Rewrite the history, replacing the second commit
+        repo.reset(first_commit.as_object(), git2::ResetType::Soft, None)
+            .unwrap();
+        let rewritten_commit =
+            add_and_commit(&repo, None, "Rewritten", None, Some("HEAD")).unwrap();
+        push(&repo, "origin", "main", "main")
+            .expect_err("Pushed a rewritten history without force");
+        force_push_with_lease(&repo, "origin", "main", "main")
+            .expect("Failed to force-push a rewritten history");
+        assert_eq!(
+            remote_repo.refname_to_id("refs/heads/main").unwrap(),
+            rewritten_commit.id()
+        );
+
+        // Another machine pushes a change that hasn't been fetched
+        let other_dir = tempdir().unwrap();
+        let other_repo = clone_repo(&remote_url, other_dir.path(), Some("main"), None).unwrap();
+        let other_commit = add_and_commit(&other_repo, None, "", None, Some("HEAD")).unwrap();
+        push(&other_repo, "origin", "main", "main").unwrap();
+
+        repo.reset(first_commit.as_object(), git2::ResetType::Soft, None)
+            .unwrap();
+        add_and_commit(&repo, None, "Rewritten again", None, Some("HEAD")).unwrap();
+        let err = force_push_with_lease(&repo, "origin", "main", "main").unwrap_err();
+        assert!(err.to_string().contains("Refusing to force-push"));
+        assert_eq!(
+            remote_repo.refname_to_id("refs/heads/main").unwrap(),
+            other_commit.id()
+        );
+    }
+
+    #[test]
+    fn test_merge_file_contents() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        let merged = merge_file_contents(
+            &repo,
+            "kitty.conf",
+            MergeDriver::Text,
+            b"font_size 12\nfont_family mono\nopacity 1\n",
+            b"font_size 10\nfont_family mono\nopacity 1\n",
+            b"font_size 12\nfont_family mono\nopacity 0.9\n",
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            MergedFile {
+                contents: b"font_size 10\nfont_family mono\nopacity 0.9\n".to_vec(),
+                conflicted: false,
+            }
+        );
+
+        let merged = merge_file_contents(
+            &repo,
+            "kitty.conf",
+            MergeDriver::Text,
+            b"font_size 12\n",
+            b"font_size 10\n",
+            b"font_size 14\n",
+        )
+        .unwrap();
+        assert!(merged.conflicted);
+        assert_eq!(
+            String::from_utf8(merged.contents).unwrap(),
+            "<<<<<<< local\nfont_size 10\n=======\nfont_size 14\n>>>>>>> repository\n"
+        );
+        // Nothing was checked out into the repository
+        assert!(!repo_dir.path().join("kitty.conf").exists());
+
+        // INI files with comments are merged line by line rather than losing the comments
+        let merged = merge_file_contents(
+            &repo,
+            "config.ini",
+            MergeDriver::Ini,
+            b"[user]\nname = Daniel\n\n[core]\neditor = vim\n",
+            b"; Edited locally\n[user]\nname = Daniel\n\n[core]\neditor = vim\n",
+            b"[user]\nname = Daniel\n\n[core]\neditor = nvim\n",
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(merged.contents).unwrap(),
+            "; Edited locally\n[user]\nname = Daniel\n\n[core]\neditor = nvim\n"
+        );
+    }
+
+    #[test]
+    fn test_is_non_fast_forward() {
+        assert!(is_non_fast_forward("non-fast-forward"));
+        assert!(is_non_fast_forward("fetch first"));
+        assert!(!is_non_fast_forward("pre-receive hook declined"));
+    }
+
+    #[test]
+    fn test_check_push_access_no_remote() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        check_push_access(&repo).expect_err("Push access granted without a remote");
+    }
+
+    #[test]
+    fn test_get_identity() {
+        let config_dir = tempdir().unwrap();
+        let mut config = git2::Config::open(&config_dir.path().join("config")).unwrap();
+
+        assert_eq!(
+            get_identity(&config),
+            (
+                "Jointhedots Sync".to_string(),
+                "jtd@danielobr.ie".to_string()
+            )
+        );
+
+        config.set_str("user.name", "Daniel").unwrap();
+        config.set_str("user.email", "daniel@example.com").unwrap();
+        assert_eq!(
+            get_identity(&config),
+            ("Daniel".to_string(), "daniel@example.com".to_string())
+        );
+
+        set_signature_override(None, Some("dotfiles@example.com"));
+        let identity = get_identity(&config);
+        set_signature_override(None, None);
+        assert_eq!(
+            identity,
+            ("Daniel".to_string(), "dotfiles@example.com".to_string())
+        );
+    }
+
     #[test]
     fn test_generate_signature() {
-        let signature = generate_signature().unwrap();
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(&repo_dir).unwrap();
+        repo.config()
+            .unwrap()
+            .set_str("user.name", "Repo Author")
+            .unwrap();
+
+        let signature = generate_signature(&repo).unwrap();
 
-        assert_eq!(signature.email().unwrap(), "jtd@danielobr.ie");
-        assert_eq!(signature.name().unwrap(), "Jointhedots Sync");
+        assert_eq!(signature.name().unwrap(), "Repo Author");
     }
 }
@@ -0,0 +1,301 @@
+use std::error::Error;
+
+use console::style;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::git::remote::{
+    get_access_token, get_git_url_host, get_gitlab_access_token, get_url_host, gitlab_hosts,
+    is_gitlab_host, GITHUB_TOKEN_ENV_VAR, GITLAB_TOKEN_ENV_VAR,
+};
+use crate::utils::http_agent;
+use crate::USER_CONFIG_PATH;
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+const MERGE_REQUEST_DESCRIPTION: &str = "Opened by `jtd sync`";
+
+#[derive(Deserialize, Debug)]
+struct PullRequest {
+    number: u64,
+    html_url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct MergeRequest {
+    iid: u64,
+    web_url: String,
+}
+
+/// Return the path of the repository at the git `url` on its host, e.g. `dob9601/dotfiles` for
+/// `git@github.com:dob9601/dotfiles.git`. None for local paths
+fn parse_repository_path(url: &str) -> Option<&str> {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('/')?.1,
+        // scp-like syntax, e.g. git@github.com:dob9601/dotfiles.git
+        None if get_git_url_host(url).is_some() => url.split_once(':')?.1,
+        None => return None,
+    };
+    let path = path.trim_matches('/').trim_end_matches(".git");
+    Some(path).filter(|path| !path.is_empty())
+}
+
+/// Return the owner and name of the GitHub repository at the git `url`, e.g.
+/// `("dob9601", "dotfiles")` for `git@github.com:dob9601/dotfiles.git`. None if the repository
+/// isn't on GitHub
+fn parse_github_repository(url: &str) -> Option<(&str, &str)> {
+    if get_git_url_host(url)? != "github.com" {
+        return None;
+    }
+    let (owner, name) = parse_repository_path(url)?.split_once('/')?;
+    if owner.is_empty() || name.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some((owner, name))
+}
+
+/// Open a pull request on GitHub from `head_branch` into `base_branch` of the repository at the
+/// git `url`, titled `title`, and return its URL. If a pull request from `head_branch` is already
+/// open, its title is updated instead, as the push to `head_branch` already updated its commits.
+/// Authenticates with the token found by [get_access_token]
+pub fn open_github_pull_request(
+    url: &str,
+    head_branch: &str,
+    base_branch: &str,
+    title: &str,
+) -> Result<String, Box<dyn Error>> {
+    let (owner, name) = parse_github_repository(url)
+        .ok_or_else(|| format!("Can only open pull requests on GitHub, not for {}", url))?;
+    let token = get_access_token("https://github.com/")
+        .ok_or(format!(
+            "Could not find a GitHub token to open a pull request with. Set {} or log in with `gh auth login`",
+            GITHUB_TOKEN_ENV_VAR
+        ))?
+        .token;
+    let pulls_url = format!("{}/repos/{}/{}/pulls", GITHUB_API_URL, owner, name);
+    let agent = http_agent()?;
+
+    let open_pull_requests: Vec<PullRequest> = serde_json::from_str(
+        &api_request(
+            "GitHub",
+            agent
+                .get(&pulls_url)
+                .query("head", &format!("{}:{}", owner, head_branch))
+                .query("base", base_branch)
+                .query("state", "open"),
+            &token,
+            None,
+        )?
+        .into_string()?,
+    )?;
+
+    let pull_request: PullRequest = match open_pull_requests.into_iter().next() {
+        Some(pull_request) => {
+            debug!("Updating pull request #{}", pull_request.number);
+            let request = agent.patch(&format!("{}/{}", pulls_url, pull_request.number));
+            serde_json::from_str(
+                &api_request("GitHub", request, &token, Some(json!({ "title": title })))?
+                    .into_string()?,
+            )?
+        }
+        None => {
+            let body = json!({
+                "title": title,
+                "head": head_branch,
+                "base": base_branch,
+                "body": MERGE_REQUEST_DESCRIPTION,
+            });
+            serde_json::from_str(
+                &api_request("GitHub", agent.post(&pulls_url), &token, Some(body))?
+                    .into_string()?,
+            )?
+        }
+    };
+    Ok(pull_request.html_url)
+}
+
+/// Open a merge request on GitLab from `source_branch` into `target_branch` of the repository at
+/// the git `url`, titled `title`, and return its URL. If a merge request from `source_branch` is
+/// already open, its title is updated instead. The API of the instance at `gitlab_url` is used if
+/// given (e.g. `https://git.example.com` for a self-hosted instance, as passed to `--gitlab-url`),
+/// and otherwise that of the host of `url` if it is `gitlab.com` or one of the [gitlab_hosts] the
+/// user listed. The token is only ever sent over HTTPS
+pub fn open_gitlab_merge_request(
+    url: &str,
+    gitlab_url: Option<&str>,
+    source_branch: &str,
+    target_branch: &str,
+    title: &str,
+) -> Result<String, Box<dyn Error>> {
+    let host = get_git_url_host(url)
+        .ok_or_else(|| format!("Can only open merge requests on GitLab, not for {}", url))?;
+    let base_url = match gitlab_url {
+        Some(gitlab_url) if gitlab_url.starts_with("https://") => {
+            gitlab_url.trim_end_matches('/').to_string()
+        }
+        Some(gitlab_url) => {
+            return Err(format!(
+                "Refusing to send a GitLab token to {}, the GitLab URL must start with https://",
+                gitlab_url
+            )
+            .into())
+        }
+        None if is_gitlab_host(host, &gitlab_hosts()) => format!("https://{}", host),
+        None => {
+            return Err(format!(
+                "{} isn't a known GitLab instance. Pass its URL with --gitlab-url, or add {} to \
+                gitlab_hosts in {}, to open merge requests on it",
+                host, host, USER_CONFIG_PATH
+            )
+            .into())
+        }
+    };
+    let project = parse_repository_path(url)
+        .ok_or_else(|| format!("Could not find the project of {}", url))?;
+    let token = get_gitlab_access_token(get_url_host(&base_url).unwrap_or(host))
+        .ok_or(format!(
+            "Could not find a GitLab token to open a merge request with. Set {} or log in with `glab auth login`",
+            GITLAB_TOKEN_ENV_VAR
+        ))?
+        .token;
+    let merge_requests_url = format!(
+        "{}/api/v4/projects/{}/merge_requests",
+        base_url,
+        project.replace('/', "%2F")
+    );
+    let agent = http_agent()?;
+
+    let open_merge_requests: Vec<MergeRequest> = serde_json::from_str(
+        &api_request(
+            "GitLab",
+            agent
+                .get(&merge_requests_url)
+                .query("source_branch", source_branch)
+                .query("target_branch", target_branch)
+                .query("state", "opened"),
+            &token,
+            None,
+        )?
+        .into_string()?,
+    )?;
+
+    let merge_request: MergeRequest = match open_merge_requests.into_iter().next() {
+        Some(merge_request) => {
+            debug!("Updating merge request !{}", merge_request.iid);
+            let request = agent.put(&format!("{}/{}", merge_requests_url, merge_request.iid));
+            serde_json::from_str(
+                &api_request("GitLab", request, &token, Some(json!({ "title": title })))?
+                    .into_string()?,
+            )?
+        }
+        None => {
+            let body = json!({
+                "title": title,
+                "source_branch": source_branch,
+                "target_branch": target_branch,
+                "description": MERGE_REQUEST_DESCRIPTION,
+            });
+            serde_json::from_str(
+                &api_request(
+                    "GitLab",
+                    agent.post(&merge_requests_url),
+                    &token,
+                    Some(body),
+                )?
+                .into_string()?,
+            )?
+        }
+    };
+    Ok(merge_request.web_url)
+}
+
+/// Send `request`, made with the [http_agent], to the API of `host_name` (e.g. `GitHub`) with
+/// `body` as JSON, authenticated with `token`
+fn api_request(
+    host_name: &str,
+    request: ureq::Request,
+    token: &str,
+    body: Option<serde_json::Value>,
+) -> Result<ureq::Response, Box<dyn Error>> {
+    let request = request
+        .set("Accept", "application/json")
+        .set("Authorization", &format!("Bearer {}", token));
+    let response = match body {
+        Some(body) => request
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string()),
+        None => request.call(),
+    };
+    response.map_err(|err| match err {
+        ureq::Error::Status(code, response) => format!(
+            "{} responded with {}: {}",
+            host_name,
+            code,
+            response.into_string().unwrap_or_default()
+        )
+        .into(),
+        err => format!("Could not reach {}: {}", host_name, err).into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_repository_path() {
+        assert_eq!(
+            parse_repository_path("git@gitlab.com:dob9601/configs/dotfiles.git"),
+            Some("dob9601/configs/dotfiles")
+        );
+        assert_eq!(
+            parse_repository_path("https://git.example.com:8443/dob9601/dotfiles/"),
+            Some("dob9601/dotfiles")
+        );
+        assert_eq!(parse_repository_path("/home/dob9601/dotfiles"), None);
+    }
+
+    #[test]
+    fn test_parse_github_repository() {
+        assert_eq!(
+            parse_github_repository("git@github.com:dob9601/dotfiles.git"),
+            Some(("dob9601", "dotfiles"))
+        );
+        assert_eq!(
+            parse_github_repository("https://github.com/dob9601/dotfiles"),
+            Some(("dob9601", "dotfiles"))
+        );
+        assert_eq!(
+            parse_github_repository("ssh://git@github.com/dob9601/dotfiles.git"),
+            Some(("dob9601", "dotfiles"))
+        );
+        assert_eq!(
+            parse_github_repository("https://gitlab.com/dob9601/dotfiles.git"),
+            None
+        );
+        assert_eq!(parse_github_repository("/home/dob9601/dotfiles"), None);
+    }
+
+    #[test]
+    fn test_open_gitlab_merge_request_unknown_host() {
+        let err = open_gitlab_merge_request(
+            "git@git.example.com:dob9601/dotfiles.git",
+            None,
+            "machine/laptop",
+            "main",
+            "Sync dotfiles",
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("--gitlab-url"));
+
+        let err = open_gitlab_merge_request(
+            "git@git.example.com:dob9601/dotfiles.git",
+            Some("http://git.example.com"),
+            "machine/laptop",
+            "main",
+            "Sync dotfiles",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("must start with https://"));
+    }
+}
@@ -1,8 +1,11 @@
-use std::{error::Error, str::FromStr};
+use std::{env, error::Error, path::PathBuf, process::Command, str::FromStr};
 
 use clap::ArgEnum;
 use strum_macros::{Display, EnumIter};
 
+use crate::structs::UserConfig;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(ArgEnum, Clone, EnumIter, Display, Debug, PartialEq)]
 pub enum ConnectionMethod {
     SSH,
@@ -26,6 +29,9 @@ impl FromStr for ConnectionMethod {
 pub enum RepoHostName {
     GitHub,
     GitLab,
+    Bitbucket,
+    Codeberg,
+    SourceHut,
 }
 
 impl FromStr for RepoHostName {
@@ -35,6 +41,9 @@ impl FromStr for RepoHostName {
         match s.to_lowercase().as_str() {
             "github" => Ok(RepoHostName::GitHub),
             "gitlab" => Ok(RepoHostName::GitLab),
+            "bitbucket" => Ok(RepoHostName::Bitbucket),
+            "codeberg" => Ok(RepoHostName::Codeberg),
+            "sourcehut" => Ok(RepoHostName::SourceHut),
             v => Err(format!("Failed to convert: '{}' is not a known variant.", v).into()),
         }
     }
@@ -43,41 +52,407 @@ impl FromStr for RepoHostName {
 pub struct RepoHost {
     ssh_prefix: &'static str,
     https_prefix: &'static str,
+    /// Prefix the host requires on usernames, e.g. `~` on sourcehut
+    user_prefix: &'static str,
+    suffix: &'static str,
 }
 
 const GITLAB: RepoHost = RepoHost {
     ssh_prefix: "git@gitlab.com:",
     https_prefix: "https://gitlab.com/",
+    user_prefix: "",
+    suffix: ".git",
 };
 
 const GITHUB: RepoHost = RepoHost {
     ssh_prefix: "git@github.com:",
     https_prefix: "https://github.com/",
+    user_prefix: "",
+    suffix: ".git",
+};
+
+const BITBUCKET: RepoHost = RepoHost {
+    ssh_prefix: "git@bitbucket.org:",
+    https_prefix: "https://bitbucket.org/",
+    user_prefix: "",
+    suffix: ".git",
+};
+
+const CODEBERG: RepoHost = RepoHost {
+    ssh_prefix: "git@codeberg.org:",
+    https_prefix: "https://codeberg.org/",
+    user_prefix: "",
+    suffix: ".git",
 };
 
+const SOURCEHUT: RepoHost = RepoHost {
+    ssh_prefix: "git@git.sr.ht:",
+    https_prefix: "https://git.sr.ht/",
+    user_prefix: "~",
+    suffix: "",
+};
+
+/// The prefix of a GitHub gist given as a repository, e.g. `gist:<id>`
+const GIST_PREFIX: &str = "gist:";
+
+const GIST: RepoHost = RepoHost {
+    ssh_prefix: "git@gist.github.com:",
+    https_prefix: "https://gist.github.com/",
+    user_prefix: "",
+    suffix: ".git",
+};
+
+/// Return the git URL of `repository` on the given host, using the given connection method. If
+/// `repository` is already a full git URL it is returned unchanged, so that repositories on any
+/// server can be used. A GitHub gist can be given as `gist:<id>` regardless of the host.
 pub fn get_host_git_url(
     repository: &str,
     host: &RepoHostName,
     method: &ConnectionMethod,
 ) -> Result<String, Box<dyn Error>> {
+    if is_git_url(repository) {
+        return Ok(repository.to_string());
+    }
+    if let Some(gist_id) = repository.strip_prefix(GIST_PREFIX) {
+        if gist_id.is_empty() || !gist_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!(
+                "\"{}\" is not a gist, which are given as gist:<id> (e.g. gist:aa5a315d61ae9438b18d)",
+                repository
+            )
+            .into());
+        }
+        return Ok(match method {
+            ConnectionMethod::SSH => format!("{}{}{}", GIST.ssh_prefix, gist_id, GIST.suffix),
+            ConnectionMethod::HTTPS => format!("{}{}{}", GIST.https_prefix, gist_id, GIST.suffix),
+        });
+    }
+
     let repo_host = match *host {
         RepoHostName::GitHub => GITHUB,
         RepoHostName::GitLab => GITLAB,
+        RepoHostName::Bitbucket => BITBUCKET,
+        RepoHostName::Codeberg => CODEBERG,
+        RepoHostName::SourceHut => SOURCEHUT,
     };
 
+    let repository = format!(
+        "{}{}{}",
+        repo_host.user_prefix,
+        repository.trim_start_matches(repo_host.user_prefix),
+        repo_host.suffix
+    );
+
     match method {
-        ConnectionMethod::SSH => Ok(format!("{}{}{}", repo_host.ssh_prefix, repository, ".git")),
-        ConnectionMethod::HTTPS => Ok(format!(
-            "{}{}{}",
-            repo_host.https_prefix, repository, ".git"
-        )),
+        ConnectionMethod::SSH => Ok(format!("{}{}", repo_host.ssh_prefix, repository)),
+        ConnectionMethod::HTTPS => Ok(format!("{}{}", repo_host.https_prefix, repository)),
+    }
+}
+
+/// Return whether `repository` is a full git URL (e.g. `https://...` or `git@host:...`) as
+/// opposed to a USERNAME/REPONAME pair
+pub fn is_git_url(repository: &str) -> bool {
+    repository.contains("://") || repository.starts_with("git@")
+}
+
+/// Return the path of the local clone `repository` refers to if it is explicitly given as a path,
+/// i.e. it starts with `/`, `./`, `../` or `~` (or is `.` or `..`). Relative paths that don't are
+/// never treated as local, so a directory that happens to be named like a USERNAME/REPONAME pair
+/// can't take the place of the repository on the host
+pub fn local_repository_path(repository: &str) -> Option<PathBuf> {
+    let is_path = ["/", "./", "../", "~"]
+        .iter()
+        .any(|prefix| repository.starts_with(prefix))
+        || repository == "."
+        || repository == "..";
+    if !is_path {
+        return None;
+    }
+    Some(PathBuf::from(shellexpand::tilde(repository).as_ref()))
+}
+
+pub const GITHUB_TOKEN_ENV_VAR: &str = "JTD_GITHUB_TOKEN";
+pub const GITLAB_TOKEN_ENV_VAR: &str = "JTD_GITLAB_TOKEN";
+/// Comma-separated hosts of self-hosted GitLab instances that GitLab tokens may be sent to, in
+/// addition to the `gitlab_hosts` of the user config
+pub const GITLAB_HOSTS_ENV_VAR: &str = "JTD_GITLAB_HOSTS";
+
+/// An access token to authenticate HTTPS requests to a repository host with
+#[derive(Debug, PartialEq)]
+pub struct AccessToken {
+    /// The username to send along with the token. Hosts ignore it or expect a fixed value
+    pub username: &'static str,
+    pub token: String,
+    /// Where the token was found, for debugging
+    pub source: String,
+}
+
+/// Find an access token for the host of the HTTPS `url`: for GitHub (including gists) in
+/// [GITHUB_TOKEN_ENV_VAR] or from `gh auth token`, and for GitLab in [GITLAB_TOKEN_ENV_VAR] or
+/// from `glab config get token`. Tokens are never sent over plain HTTP, and only sent to
+/// self-hosted GitLab instances the user listed themselves (see [gitlab_hosts])
+pub fn get_access_token(url: &str) -> Option<AccessToken> {
+    find_access_token_for(url, &gitlab_hosts(), |env_var| env::var(env_var).ok())
+}
+
+/// Find an access token for the host of `url` as [get_access_token] does, looking up the
+/// environment variables tokens are read from with `get_env_var`
+fn find_access_token_for(
+    url: &str,
+    gitlab_hosts: &[String],
+    get_env_var: impl Fn(&str) -> Option<String>,
+) -> Option<AccessToken> {
+    if !url.starts_with("https://") {
+        return None;
+    }
+    let host = get_url_host(url)?;
+    if host == "github.com" || host == "gist.github.com" {
+        find_access_token(
+            "github.com",
+            "x-access-token",
+            GITHUB_TOKEN_ENV_VAR,
+            get_env_var(GITHUB_TOKEN_ENV_VAR),
+            &["gh", "auth", "token", "--hostname"],
+        )
+    } else if is_gitlab_host(host, gitlab_hosts) {
+        find_gitlab_access_token(host, get_env_var(GITLAB_TOKEN_ENV_VAR))
+    } else {
+        None
+    }
+}
+
+/// Return the hosts of the self-hosted GitLab instances the user trusts with their GitLab token,
+/// from the `gitlab_hosts` of the user config and [GITLAB_HOSTS_ENV_VAR]
+pub fn gitlab_hosts() -> Vec<String> {
+    let mut hosts = UserConfig::get()
+        .map(|user_config| user_config.gitlab_hosts)
+        .unwrap_or_default();
+    if let Ok(env_hosts) = env::var(GITLAB_HOSTS_ENV_VAR) {
+        hosts.extend(
+            env_hosts
+                .split(',')
+                .map(|host| host.trim().to_string())
+                .filter(|host| !host.is_empty()),
+        );
+    }
+    hosts
+}
+
+/// Return whether `host` is `gitlab.com` or one of the self-hosted `gitlab_hosts`, compared
+/// exactly and ignoring case
+pub fn is_gitlab_host(host: &str, gitlab_hosts: &[String]) -> bool {
+    host.eq_ignore_ascii_case("gitlab.com")
+        || gitlab_hosts
+            .iter()
+            .any(|gitlab_host| gitlab_host.eq_ignore_ascii_case(host))
+}
+
+/// Find an access token for the GitLab instance at `host`, which needn't have "gitlab" in its
+/// name, in [GITLAB_TOKEN_ENV_VAR] or from `glab config get token`
+pub fn get_gitlab_access_token(host: &str) -> Option<AccessToken> {
+    find_gitlab_access_token(host, env::var(GITLAB_TOKEN_ENV_VAR).ok())
+}
+
+/// Find an access token for the GitLab instance at `host`, where `env_token` is the value of
+/// [GITLAB_TOKEN_ENV_VAR]
+fn find_gitlab_access_token(host: &str, env_token: Option<String>) -> Option<AccessToken> {
+    find_access_token(
+        host,
+        "oauth2",
+        GITLAB_TOKEN_ENV_VAR,
+        env_token,
+        &["glab", "config", "get", "token", "--host"],
+    )
+}
+
+/// Find an access token for `host` in `env_token`, the value of the environment variable `env_var`,
+/// or otherwise in the output of `cli_command` with the host appended
+fn find_access_token(
+    host: &str,
+    username: &'static str,
+    env_var: &str,
+    env_token: Option<String>,
+    cli_command: &[&str],
+) -> Option<AccessToken> {
+    if let Some(token) = env_token.filter(|token| !token.is_empty()) {
+        return Some(AccessToken {
+            username,
+            token,
+            source: env_var.to_string(),
+        });
+    }
+
+    let output = Command::new(cli_command[0])
+        .args(&cli_command[1..])
+        .arg(host)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let token = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    if token.is_empty() {
+        return None;
+    }
+    Some(AccessToken {
+        username,
+        token,
+        source: format!("`{} {}`", cli_command.join(" "), host),
+    })
+}
+
+/// Return the host of a git `url` over SSH or HTTP(S), e.g. `github.com`
+pub fn get_git_url_host(url: &str) -> Option<&str> {
+    if let Some(rest) = url.strip_prefix("ssh://") {
+        let authority = rest.split('/').next()?;
+        let host = authority.rsplit('@').next()?;
+        return host.split(':').next().filter(|host| !host.is_empty());
+    }
+    if !url.contains("://") {
+        // scp-like syntax, e.g. git@github.com:dob9601/dotfiles.git
+        let (authority, _) = url.split_once(':')?;
+        return authority.rsplit('@').next().filter(|host| !host.is_empty());
+    }
+    get_url_host(url)
+}
+
+/// Return the host and path of the repository at the git `url`, e.g. `github.com/dob9601/dotfiles`
+/// for both `git@github.com:dob9601/dotfiles.git` and `https://github.com/dob9601/dotfiles`, so
+/// that a repository compares equal however it is cloned. None for local paths
+pub fn repository_identity(url: &str) -> Option<String> {
+    let host = get_git_url_host(url)?;
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('/')?.1,
+        // scp-like syntax, e.g. git@github.com:dob9601/dotfiles.git
+        None => url.split_once(':')?.1,
+    };
+    let path = path.trim_matches('/').trim_end_matches(".git");
+    if path.is_empty() {
+        return None;
     }
+    Some(format!("{}/{}", host.to_lowercase(), path))
+}
+
+/// Return the host of an HTTP(S) `url`, without any credentials or port
+pub fn get_url_host(url: &str) -> Option<&str> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let authority = rest.split('/').next()?;
+    let host = authority.rsplit('@').next()?;
+    host.split(':').next().filter(|host| !host.is_empty())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_get_url_host() {
+        assert_eq!(
+            get_url_host("https://github.com/dob9601/dotfiles.git"),
+            Some("github.com")
+        );
+        assert_eq!(
+            get_url_host("https://user@gitlab.example.com:8443/dotfiles.git"),
+            Some("gitlab.example.com")
+        );
+        assert_eq!(get_url_host("git@github.com:dob9601/dotfiles.git"), None);
+    }
+
+    #[test]
+    fn test_get_git_url_host() {
+        assert_eq!(
+            get_git_url_host("git@github.com:dob9601/dotfiles.git"),
+            Some("github.com")
+        );
+        assert_eq!(
+            get_git_url_host("ssh://git@git.sr.ht:22/~dob9601/dotfiles"),
+            Some("git.sr.ht")
+        );
+        assert_eq!(
+            get_git_url_host("https://gitlab.com/dob9601/dotfiles.git"),
+            Some("gitlab.com")
+        );
+        assert_eq!(get_git_url_host("/home/dob9601/dotfiles"), None);
+    }
+
+    #[test]
+    fn test_repository_identity() {
+        for url in [
+            "git@github.com:dob9601/dotfiles.git",
+            "https://github.com/dob9601/dotfiles",
+            "https://GitHub.com/dob9601/dotfiles.git/",
+            "ssh://git@github.com:22/dob9601/dotfiles.git",
+        ] {
+            assert_eq!(
+                repository_identity(url).as_deref(),
+                Some("github.com/dob9601/dotfiles")
+            );
+        }
+        assert_eq!(
+            repository_identity("https://evil.example/git@github.com:dob9601/dotfiles").as_deref(),
+            Some("evil.example/git@github.com:dob9601/dotfiles")
+        );
+        assert_eq!(repository_identity("/home/dob9601/dotfiles"), None);
+        assert_eq!(repository_identity("https://github.com/"), None);
+    }
+
+    #[test]
+    fn test_get_access_token() {
+        let get_env_var =
+            |env_var: &str| (env_var == GITLAB_TOKEN_ENV_VAR).then(|| "glpat-secret".to_string());
+        let token =
+            find_access_token_for("https://gitlab.com/dob9601/dotfiles.git", &[], get_env_var)
+                .unwrap();
+
+        assert_eq!(token.username, "oauth2");
+        assert_eq!(token.token, "glpat-secret");
+        assert_eq!(token.source, GITLAB_TOKEN_ENV_VAR);
+        assert_eq!(
+            find_access_token_for(
+                "https://git.example.com/dob9601/dotfiles.git",
+                &["git.example.com".to_string()],
+                get_env_var
+            ),
+            Some(token)
+        );
+        assert_eq!(
+            find_access_token_for(
+                "https://codeberg.org/dob9601/dotfiles.git",
+                &[],
+                get_env_var
+            ),
+            None
+        );
+        assert_eq!(
+            find_access_token_for("http://gitlab.com/dob9601/dotfiles.git", &[], get_env_var),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_gitlab_host() {
+        let gitlab_hosts = vec!["git.example.com".to_string()];
+        assert!(is_gitlab_host("gitlab.com", &[]));
+        assert!(is_gitlab_host("git.example.com", &gitlab_hosts));
+        assert!(is_gitlab_host("GIT.example.com", &gitlab_hosts));
+        assert!(!is_gitlab_host("gitlab.evil.example", &gitlab_hosts));
+        assert!(!is_gitlab_host("gitlab.com.evil.example", &[]));
+        assert!(!is_gitlab_host("example.com", &gitlab_hosts));
+    }
+
+    #[test]
+    fn test_get_host_git_url_full_url() {
+        let host = RepoHostName::GitHub;
+        let method = ConnectionMethod::SSH;
+
+        for repo in [
+            "https://git.example.com/dob9601/dotfiles.git",
+            "git@git.example.com:dob9601/dotfiles.git",
+        ] {
+            let host_url = get_host_git_url(repo, &host, &method).expect("Failed to get host url");
+            assert_eq!(host_url, repo);
+        }
+    }
+
     #[test]
     fn test_get_host_git_url_ssh_github() {
         let repo = "dob9601/dotfiles";
@@ -104,6 +479,94 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_get_host_git_url_https_codeberg() {
+        let repo = "dob9601/dotfiles";
+        let host = RepoHostName::Codeberg;
+        let method = ConnectionMethod::HTTPS;
+
+        let host_url = get_host_git_url(repo, &host, &method).expect("Failed to get host url");
+        assert_eq!(
+            host_url,
+            String::from("https://codeberg.org/dob9601/dotfiles.git")
+        )
+    }
+
+    #[test]
+    fn test_get_host_git_url_ssh_bitbucket() {
+        let repo = "dob9601/dotfiles";
+        let host = RepoHostName::Bitbucket;
+        let method = ConnectionMethod::SSH;
+
+        let host_url = get_host_git_url(repo, &host, &method).expect("Failed to get host url");
+        assert_eq!(
+            host_url,
+            String::from("git@bitbucket.org:dob9601/dotfiles.git")
+        )
+    }
+
+    #[test]
+    fn test_get_host_git_url_sourcehut() {
+        let host = RepoHostName::SourceHut;
+
+        for repo in ["dob9601/dotfiles", "~dob9601/dotfiles"] {
+            assert_eq!(
+                get_host_git_url(repo, &host, &ConnectionMethod::SSH).unwrap(),
+                String::from("git@git.sr.ht:~dob9601/dotfiles")
+            );
+            assert_eq!(
+                get_host_git_url(repo, &host, &ConnectionMethod::HTTPS).unwrap(),
+                String::from("https://git.sr.ht/~dob9601/dotfiles")
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_host_git_url_gist() {
+        // Gists are on GitHub whichever host is given
+        let host = RepoHostName::GitLab;
+        let repo = "gist:aa5a315d61ae9438b18d";
+
+        assert_eq!(
+            get_host_git_url(repo, &host, &ConnectionMethod::SSH).unwrap(),
+            String::from("git@gist.github.com:aa5a315d61ae9438b18d.git")
+        );
+        assert_eq!(
+            get_host_git_url(repo, &host, &ConnectionMethod::HTTPS).unwrap(),
+            String::from("https://gist.github.com/aa5a315d61ae9438b18d.git")
+        );
+        for repo in ["gist:", "gist:../dotfiles"] {
+            assert!(get_host_git_url(repo, &host, &ConnectionMethod::SSH).is_err());
+        }
+    }
+
+    #[test]
+    fn test_is_git_url() {
+        assert!(is_git_url("https://git.example.com/dob9601/dotfiles.git"));
+        assert!(is_git_url("git@git.example.com:dob9601/dotfiles.git"));
+        assert!(!is_git_url("dob9601/dotfiles"));
+    }
+
+    #[test]
+    fn test_local_repository_path() {
+        assert_eq!(
+            local_repository_path("/home/dob9601/dotfiles"),
+            Some(PathBuf::from("/home/dob9601/dotfiles"))
+        );
+        assert_eq!(
+            local_repository_path("./dotfiles"),
+            Some(PathBuf::from("./dotfiles"))
+        );
+        assert_eq!(local_repository_path("."), Some(PathBuf::from(".")));
+        assert!(local_repository_path("~/dotfiles").is_some());
+        assert_eq!(local_repository_path("dob9601/dotfiles"), None);
+        assert_eq!(
+            local_repository_path("git@github.com:dob9601/dotfiles.git"),
+            None
+        );
+        assert_eq!(local_repository_path("gist:aa5a315d61ae9438b18d"), None);
+    }
+
     #[test]
     fn test_repo_host_name_from_str_github() {
         let hostname = "github";
@@ -124,6 +587,16 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_repo_host_name_from_str_sourcehut() {
+        let hostname = "SourceHut";
+        assert_eq!(
+            <RepoHostName as std::str::FromStr>::from_str(hostname)
+                .expect("Could not convert from str"),
+            RepoHostName::SourceHut
+        )
+    }
+
     #[test]
     fn test_repo_host_from_str_invalid() {
         let hostname = "foobar";
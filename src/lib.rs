@@ -1,23 +1,84 @@
 #[macro_use]
 pub mod log;
 
+pub mod archive;
 pub mod cli;
+pub mod encryption;
+pub mod merge;
+pub mod picker;
+pub mod prompt;
 pub mod structs;
+pub mod template;
 pub mod utils;
 
-pub(crate) const MANIFEST_PATH: &str = "~/.local/share/jointhedots/manifest.yaml";
+/// The data directory used when neither `--data-dir`, `JTD_DATA_DIR` nor `XDG_DATA_HOME` is set
+pub(crate) const DEFAULT_DATA_DIR: &str = "~/.local/share/jointhedots";
+/// The metadata of the installed dotfiles, relative to the data directory
+pub(crate) const MANIFEST_FILE: &str = "manifest.yaml";
+/// The operation log, relative to the data directory
+pub(crate) const OPERATION_LOG_FILE: &str = "jtd.log";
+/// The backups of overwritten files, relative to the data directory
+pub(crate) const BACKUPS_DIR: &str = "backups";
+/// The lock held by the jtd run changing the installed dotfiles, relative to the data directory
+pub(crate) const RUN_LOCK_FILE: &str = "run.lock";
+/// The repositories and install steps the user trusts, relative to the data directory
+pub(crate) const TRUST_FILE: &str = "trust.yaml";
+pub(crate) const CACHE_PATH: &str = "~/.cache/jointhedots";
+pub(crate) const USER_CONFIG_PATH: &str = "~/.config/jointhedots/config.yaml";
+pub(crate) const VARS_PATH: &str = "~/.config/jointhedots/vars.yaml";
+pub(crate) const AGE_IDENTITY_PATH: &str = "~/.config/jointhedots/identity.txt";
 
 pub(crate) mod git {
+    pub mod lfs;
     pub mod operations;
+    pub mod pull_request;
     pub mod remote;
 }
 
 pub mod subcommands {
+    mod completions;
+    mod diff;
+    mod doctor;
+    mod export;
+    mod history;
     mod install;
     mod interactive;
+    mod lock;
+    mod migrate;
+    mod push;
+    mod restore;
+    mod rollback;
+    mod self_update;
+    mod state;
     mod sync;
+    mod trust;
+    mod uninstall;
+    mod update;
+    mod validate;
+    mod vars;
+    mod watch;
+    mod which;
 
+    pub use completions::completions_subcommand_handler;
+    pub use diff::diff_subcommand_handler;
+    pub use doctor::doctor_subcommand_handler;
+    pub use export::{export_subcommand_handler, ExportFormat};
+    pub use history::history_subcommand_handler;
     pub use install::install_subcommand_handler;
-    pub use interactive::interactive_subcommand_handler;
-    pub use sync::sync_subcommand_handler;
+    pub use interactive::{interactive_subcommand_handler, Wizard};
+    pub use lock::lock_subcommand_handler;
+    pub use migrate::migrate_subcommand_handler;
+    pub use push::push_subcommand_handler;
+    pub use restore::restore_subcommand_handler;
+    pub use rollback::rollback_subcommand_handler;
+    pub use self_update::self_update_subcommand_handler;
+    pub use state::state_subcommand_handler;
+    pub use sync::{remind_to_sync, sync_subcommand_handler};
+    pub use trust::trust_subcommand_handler;
+    pub use uninstall::uninstall_subcommand_handler;
+    pub use update::update_subcommand_handler;
+    pub use validate::validate_subcommand_handler;
+    pub use vars::vars_subcommand_handler;
+    pub use watch::watch_subcommand_handler;
+    pub use which::which_subcommand_handler;
 }
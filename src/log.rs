@@ -1,38 +1,281 @@
 #[allow(unused_imports)]
 use console::style;
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ArgEnum;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::utils::operation_log_path;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+static LOGS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+static OPERATION_LOG: AtomicBool = AtomicBool::new(false);
+
+/// How much human-readable output to print. Each level includes the output of the levels below it
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    /// Only warnings and errors
+    Quiet,
+    Normal,
+    /// Also print what jtd is doing behind the scenes, such as the commits it creates
+    Verbose,
+    /// Also print low-level details such as the environment install steps are run with
+    VeryVerbose,
+}
+
+impl Verbosity {
+    /// Return the verbosity selected by passing `-v` `verbose` times, or `-q`
+    pub fn from_flags(verbose: u64, quiet: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::VeryVerbose,
+        }
+    }
+}
+
+/// A function receiving each line of human-readable output, see [set_log_handler]
+pub type LogHandler = Box<dyn Fn(&str) + Send + Sync>;
+
+lazy_static! {
+    static ref LOG_HANDLER: RwLock<Option<LogHandler>> = RwLock::new(None);
+}
+
+pub fn set_output_format(format: OutputFormat) {
+    JSON_OUTPUT.store(format == OutputFormat::Json, Ordering::Relaxed);
+}
+
+pub fn output_format() -> OutputFormat {
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        OutputFormat::Json
+    } else {
+        OutputFormat::Text
+    }
+}
+
+/// Write human-readable output to stderr, keeping stdout free for machine-readable output such as
+/// patches. This is always the case when JSON output is enabled
+pub fn set_logs_to_stderr(enabled: bool) {
+    LOGS_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        2 => Verbosity::Verbose,
+        _ => Verbosity::VeryVerbose,
+    }
+}
+
+/// Pass every line of human-readable output to `handler` instead of printing it, for tools that
+/// embed jtd and want to show or discard its output themselves. `None` restores printing.
+///
+/// # Examples
+///
+/// ```
+/// use jointhedots::log::set_log_handler;
+///
+/// set_log_handler(Some(Box::new(|line| eprintln!("jtd: {}", line))));
+/// ```
+pub fn set_log_handler(handler: Option<LogHandler>) {
+    *LOG_HANDLER.write().unwrap() = handler;
+}
+
+/// Print a line of human-readable output. When JSON output is enabled (or
+/// [set_logs_to_stderr] is set) this is written to stderr instead, so that stdout only contains
+/// the JSON document.
+pub fn print_log<D: Display>(line: D) {
+    print_log_at(Verbosity::Normal, line)
+}
+
+/// Print a line of human-readable output if the verbosity is at least `level`
+pub fn print_log_at<D: Display>(level: Verbosity, line: D) {
+    if verbosity() < level {
+        return;
+    }
+    if let Some(handler) = LOG_HANDLER.read().unwrap().as_ref() {
+        handler(&line.to_string());
+    } else if output_format() == OutputFormat::Json || LOGS_TO_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Print a warning or error, which is shown at every verbosity, and record it in the operation log
+pub fn print_problem<D: Display>(kind: &str, message: String, styled: impl Fn(&str) -> D) {
+    log_operation(kind, json!({ "message": message }));
+    print_log_at(Verbosity::Quiet, styled(&message));
+}
+
+/// Start appending an entry for every operation to `jtd.log` in the data directory (typically
+/// `~/.local/share/jointhedots`), so that unattended runs can be debugged after the fact.
+/// This is enabled by the jtd CLI, but not when jtd is used as a library
+pub fn enable_operation_log() {
+    OPERATION_LOG.store(true, Ordering::Relaxed);
+}
+
+/// Append an entry for `event` to the operation log, if it's enabled. Each entry is a single line
+/// of JSON containing the time, the process ID, the event and `details`. Failing to write the log
+/// never interrupts the operation being logged
+pub fn log_operation(event: &str, details: Value) {
+    if !OPERATION_LOG.load(Ordering::Relaxed) {
+        return;
+    }
+    let _ = append_operation_log(&operation_log_path(), event, details);
+}
+
+fn append_operation_log(path: &Path, event: &str, details: Value) -> Result<(), Box<dyn Error>> {
+    let mut entry = json!({
+        "time": SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        "pid": process::id(),
+        "event": event,
+    });
+    if let (Some(entry), Value::Object(details)) = (entry.as_object_mut(), details) {
+        entry.extend(details);
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry)?;
+    Ok(())
+}
+
+/// An error whose details have already been printed as part of a JSON report, so shouldn't be
+/// printed a second time
+#[derive(Debug)]
+pub struct ReportedError(pub String);
+
+impl Display for ReportedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ReportedError {}
+
+/// Print `value` to stdout as JSON
+pub fn print_json<T: Serialize>(value: &T) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
 
 macro_rules! success {
     ($fmt:expr) => {
-        println!("{}", style(format!("✔ {}", $fmt)).green());
+        $crate::log::print_log(style(format!("✔ {}", $fmt)).green());
     };
     ($fmt:expr $(, $($arg:tt)*)?) => {
-        println!("{}", style(format!(concat!("✔ ", $fmt), $($($arg)*)?)).green());
+        $crate::log::print_log(style(format!(concat!("✔ ", $fmt), $($($arg)*)?)).green());
     };
 }
 
 macro_rules! info {
     ($fmt:expr) => {
-        println!("{}", style(format!("🛈 {}", $fmt)).blue());
+        $crate::log::print_log(style(format!("🛈 {}", $fmt)).blue());
+    };
+    ($fmt:expr $(, $($arg:tt)*)?) => {
+        $crate::log::print_log(style(format!(concat!("🛈 ", $fmt), $($($arg)*)?)).blue());
+    };
+}
+
+/// Log details that are only shown with `-v`
+macro_rules! debug {
+    ($fmt:expr) => {
+        $crate::log::print_log_at($crate::log::Verbosity::Verbose, style(format!("· {}", $fmt)).dim());
     };
     ($fmt:expr $(, $($arg:tt)*)?) => {
-        println!("{}", style(format!(concat!("🛈 ", $fmt), $($($arg)*)?)).blue());
+        $crate::log::print_log_at($crate::log::Verbosity::Verbose, style(format!(concat!("· ", $fmt), $($($arg)*)?)).dim());
     };
 }
 
+/// Log details that are only shown with `-vv`
+macro_rules! trace {
+    ($fmt:expr) => {
+        $crate::log::print_log_at($crate::log::Verbosity::VeryVerbose, style(format!("· {}", $fmt)).dim());
+    };
+    ($fmt:expr $(, $($arg:tt)*)?) => {
+        $crate::log::print_log_at($crate::log::Verbosity::VeryVerbose, style(format!(concat!("· ", $fmt), $($($arg)*)?)).dim());
+    };
+}
+
+// Warnings and errors are still shown with `-q`, and are recorded in the operation log
 macro_rules! warn {
     ($fmt:expr) => {
-        println!("{}", style(format!("⚠ {}", $fmt)).yellow());
+        $crate::log::print_problem("warning", format!("{}", $fmt), |line| style(format!("⚠ {}", line)).yellow());
     };
     ($fmt:expr $(, $($arg:tt)*)?) => {
-        println!("{}", style(format!(concat!("⚠ ", $fmt), $($($arg)*)?)).yellow());
+        $crate::log::print_problem("warning", format!($fmt, $($($arg)*)?), |line| style(format!("⚠ {}", line)).yellow());
     };
 }
 
 macro_rules! error {
     ($fmt:expr) => {
-        println!("{}", style(format!("⚠ {}", $fmt)).red());
+        $crate::log::print_problem("error", format!("{}", $fmt), |line| style(format!("⚠ {}", line)).red());
     };
     ($fmt:expr $(, $($arg:tt)*)?) => {
-        println!("{}", style(format!(concat!("⚠ ", $fmt), $($($arg)*)?)).red());
+        $crate::log::print_problem("error", format!($fmt, $($($arg)*)?), |line| style(format!("⚠ {}", line)).red());
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbosity_from_flags() {
+        assert_eq!(Verbosity::from_flags(0, false), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(1, false), Verbosity::Verbose);
+        assert_eq!(Verbosity::from_flags(3, false), Verbosity::VeryVerbose);
+        assert_eq!(Verbosity::from_flags(0, true), Verbosity::Quiet);
+        assert!(Verbosity::Quiet < Verbosity::Normal);
+    }
+
+    #[test]
+    fn test_append_operation_log() {
+        let log_dir = tempfile::tempdir().unwrap();
+        let log_path = log_dir.path().join("logs").join("jtd.log");
+
+        append_operation_log(&log_path, "installed", json!({ "dotfile": "nvim" })).unwrap();
+        append_operation_log(&log_path, "committed", json!({ "commit": "abc" })).unwrap();
+
+        let contents = fs::read_to_string(&log_path).unwrap();
+        let entries: Vec<Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["event"], "installed");
+        assert_eq!(entries[0]["dotfile"], "nvim");
+        assert_eq!(entries[1]["commit"], "abc");
+        assert_eq!(entries[1]["pid"], process::id());
+    }
+}
@@ -1,20 +1,142 @@
-use clap::Parser;
 use console::style;
-use jointhedots::{cli::JoinTheDots, subcommands};
-use std::process::exit;
+use jointhedots::{
+    cli::{Cli, JoinTheDots},
+    log::{
+        enable_operation_log, log_operation, output_format, print_json, set_verbosity,
+        OutputFormat, ReportedError, Verbosity,
+    },
+    prompt::{set_prompter, TerminalPrompter},
+    structs::{AggregatedDotfileMetadata, OperationReport, UserConfig},
+    subcommands,
+    utils::{set_data_dir, set_debug_git, set_proxy, set_ssh_key, set_wait_for_lock, RunLock},
+};
+use serde_json::json;
+use std::{env, error::Error, ffi::OsString, process::exit};
 
 fn main() {
-    let result = match JoinTheDots::parse() {
-        JoinTheDots::Sync(args) => subcommands::sync_subcommand_handler(args),
-        JoinTheDots::Install(args) => subcommands::install_subcommand_handler(args),
-        JoinTheDots::Interactive(_) => subcommands::interactive_subcommand_handler(),
+    set_prompter(TerminalPrompter);
+    let args: Vec<OsString> = env::args_os().collect();
+    set_data_dir(Cli::find_data_dir(&args));
+    // A broken metadata file is reported by the commands that need it, not while parsing
+    let installed_from = AggregatedDotfileMetadata::get()
+        .ok()
+        .flatten()
+        .and_then(|aggregated_metadata| aggregated_metadata.installed_from);
+    let cli = match UserConfig::get().and_then(|user_config| {
+        Cli::try_parse_with_defaults(args, &user_config, installed_from.as_ref())
+    }) {
+        Ok(cli) => cli,
+        Err(error) => match error.downcast::<clap::Error>() {
+            Ok(clap_error) => clap_error.exit(),
+            Err(error) => exit_with_error(error),
+        },
     };
+    set_verbosity(Verbosity::from_flags(cli.verbose, cli.quiet));
+    set_debug_git(cli.debug_git);
+    set_ssh_key(cli.ssh_key);
+    set_proxy(cli.proxy);
+    set_wait_for_lock(cli.wait);
+    // Completions are generated on every press of tab, so would flood the operation log
+    if !matches!(cli.command, JoinTheDots::Completions(_)) {
+        enable_operation_log();
+    }
+    log_operation(
+        "command",
+        json!({ "args": env::args().collect::<Vec<String>>() }),
+    );
+
+    let command = cli.command;
+    // Only commands that change the installed dotfiles remind to sync, rather than every command
+    // that just reads them
+    let remind_to_sync = matches!(
+        command,
+        JoinTheDots::Install(_)
+            | JoinTheDots::Interactive(_)
+            | JoinTheDots::Update(_)
+            | JoinTheDots::Restore(_)
+            | JoinTheDots::Rollback(_)
+            | JoinTheDots::Uninstall(_)
+    );
+    // Watching only takes the lock while it syncs, as it runs indefinitely
+    let takes_lock = matches!(
+        command,
+        JoinTheDots::Sync(_)
+            | JoinTheDots::Push(_)
+            | JoinTheDots::Lock(_)
+            | JoinTheDots::Install(_)
+            | JoinTheDots::Interactive(_)
+            | JoinTheDots::State(_)
+            | JoinTheDots::Trust(_)
+            | JoinTheDots::Migrate(_)
+            | JoinTheDots::Restore(_)
+            | JoinTheDots::Rollback(_)
+            | JoinTheDots::Uninstall(_)
+            | JoinTheDots::Update(_)
+    );
+    let lock = if takes_lock {
+        RunLock::acquire().map(Some)
+    } else {
+        Ok(None)
+    };
+    let result = lock.and_then(|lock| {
+        let result = run_subcommand(command);
+        drop(lock);
+        result
+    });
+    let result = result.and_then(|_| {
+        if remind_to_sync {
+            subcommands::remind_to_sync()
+        } else {
+            Ok(())
+        }
+    });
+    match &result {
+        Ok(()) => log_operation("command succeeded", json!({})),
+        Err(error) => log_operation("command failed", json!({ "error": error.to_string() })),
+    }
     if let Err(error) = result {
-        println!(
-            "{} {}",
-            style("Error:").red().dim(),
-            error.to_string().replace("\n", "\n       ")
-        );
+        exit_with_error(error);
+    }
+}
+
+fn run_subcommand(command: JoinTheDots) -> Result<(), Box<dyn Error>> {
+    match command {
+        JoinTheDots::Sync(args) => subcommands::sync_subcommand_handler(args),
+        JoinTheDots::Push(args) => subcommands::push_subcommand_handler(args),
+        JoinTheDots::Lock(args) => subcommands::lock_subcommand_handler(args),
+        JoinTheDots::Install(args) => subcommands::install_subcommand_handler(args),
+        JoinTheDots::Interactive(args) => subcommands::interactive_subcommand_handler(args),
+        JoinTheDots::Validate(args) => subcommands::validate_subcommand_handler(args),
+        JoinTheDots::Doctor(args) => subcommands::doctor_subcommand_handler(args),
+        JoinTheDots::Completions(args) => subcommands::completions_subcommand_handler(args),
+        JoinTheDots::Vars(args) => subcommands::vars_subcommand_handler(args),
+        JoinTheDots::State(args) => subcommands::state_subcommand_handler(args),
+        JoinTheDots::Trust(args) => subcommands::trust_subcommand_handler(args),
+        JoinTheDots::Export(args) => subcommands::export_subcommand_handler(args),
+        JoinTheDots::Migrate(args) => subcommands::migrate_subcommand_handler(args),
+        JoinTheDots::Which(args) => subcommands::which_subcommand_handler(args),
+        JoinTheDots::History(args) => subcommands::history_subcommand_handler(args),
+        JoinTheDots::Restore(args) => subcommands::restore_subcommand_handler(args),
+        JoinTheDots::Rollback(args) => subcommands::rollback_subcommand_handler(args),
+        JoinTheDots::Uninstall(args) => subcommands::uninstall_subcommand_handler(args),
+        JoinTheDots::Diff(args) => subcommands::diff_subcommand_handler(args),
+        JoinTheDots::Update(args) => subcommands::update_subcommand_handler(args),
+        JoinTheDots::SelfUpdate(args) => subcommands::self_update_subcommand_handler(args),
+        JoinTheDots::Watch(args) => subcommands::watch_subcommand_handler(args),
+    }
+}
+
+fn exit_with_error(error: Box<dyn Error>) -> ! {
+    if output_format() == OutputFormat::Json {
+        if error.downcast_ref::<ReportedError>().is_none() {
+            let _ = print_json(&OperationReport::from_error(&error.to_string()));
+        }
         exit(1);
     }
+    println!(
+        "{} {}",
+        style("Error:").red().dim(),
+        error.to_string().replace("\n", "\n       ")
+    );
+    exit(1);
 }
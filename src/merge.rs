@@ -0,0 +1,262 @@
+use std::error::Error;
+
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
+use serde_json::{Map, Value};
+
+use crate::structs::MergeDriver;
+
+/// Three-way merge the contents `local` and `remote` of a structured file, which both started out
+/// as `base`, key by key. Keys changed on only one side take that side's value, nested objects are
+/// merged recursively and keys that were changed differently on both sides are an error, as are
+/// contents that can't be parsed in the format of `driver`. Comments in JSON and YAML files aren't
+/// preserved, while INI files with comments are an error.
+///
+/// An empty `base` is treated as an empty object, e.g. when the version last synced isn't known.
+pub fn merge_structured(
+    driver: MergeDriver,
+    base: &[u8],
+    local: &[u8],
+    remote: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let merged = merge_values(
+        "",
+        Some(&parse(driver, base)?),
+        Some(&parse(driver, local)?),
+        Some(&parse(driver, remote)?),
+    )
+    .map_err(|key| format!("{} was changed both locally and in the repository", key))?
+    .unwrap_or_else(|| Value::Object(Map::new()));
+    serialize(driver, &merged, local)
+}
+
+fn parse(driver: MergeDriver, contents: &[u8]) -> Result<Value, Box<dyn Error>> {
+    let contents = std::str::from_utf8(contents)?;
+    if contents.trim().is_empty() {
+        return Ok(Value::Object(Map::new()));
+    }
+    Ok(match driver {
+        MergeDriver::Json => serde_json::from_str(contents)?,
+        MergeDriver::Yaml => serde_yaml::from_str(contents)?,
+        MergeDriver::Ini => parse_ini(contents)?,
+        MergeDriver::Text => return Err("Text files can't be merged key by key".into()),
+    })
+}
+
+/// Parse an INI file into an object of sections, each an object of its keys. Keys before the first
+/// section header are in the section with an empty name. Files the object can't hold all of, i.e.
+/// with comments, keys set more than once or lines that aren't keys, are an error, so that they
+/// are merged line by line instead of losing those lines
+fn parse_ini(contents: &str) -> Result<Value, Box<dyn Error>> {
+    let mut sections = Map::new();
+    let mut section = String::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() {
+            continue;
+        } else if line.starts_with(';') || line.starts_with('#') {
+            return Err("comments can't be kept when merging key by key".into());
+        } else if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            sections
+                .entry(section.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+        } else if let Some((key, value)) = line.split_once('=') {
+            if let Value::Object(keys) = sections
+                .entry(section.clone())
+                .or_insert_with(|| Value::Object(Map::new()))
+            {
+                let key = key.trim();
+                if keys
+                    .insert(key.to_string(), Value::String(value.trim().to_string()))
+                    .is_some()
+                {
+                    return Err(format!("{} is set more than once in [{}]", key, section).into());
+                }
+            }
+        } else {
+            return Err(format!("\"{}\" is not a section header or key", line).into());
+        }
+    }
+    Ok(Value::Object(sections))
+}
+
+/// Serialize `value` in the format of `driver`, matching the indentation and trailing newline of
+/// `like`
+fn serialize(driver: MergeDriver, value: &Value, like: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut contents = match driver {
+        MergeDriver::Json => {
+            let indent = String::from_utf8_lossy(like)
+                .lines()
+                .skip(1)
+                .map(|line| &line[..line.len() - line.trim_start().len()])
+                .find(|indent| !indent.is_empty())
+                .unwrap_or("  ")
+                .to_string();
+            let mut contents = Vec::new();
+            let mut serializer = serde_json::Serializer::with_formatter(
+                &mut contents,
+                PrettyFormatter::with_indent(indent.as_bytes()),
+            );
+            value.serialize(&mut serializer)?;
+            contents
+        }
+        MergeDriver::Yaml => serde_yaml::to_string(value)?
+            .trim_start_matches("---\n")
+            .as_bytes()
+            .to_vec(),
+        MergeDriver::Ini => serialize_ini(value).into_bytes(),
+        MergeDriver::Text => return Err("Text files can't be merged key by key".into()),
+    };
+    if like.ends_with(b"\n") && !contents.ends_with(b"\n") {
+        contents.push(b'\n');
+    }
+    Ok(contents)
+}
+
+fn serialize_ini(value: &Value) -> String {
+    let mut sections = Vec::new();
+    if let Value::Object(map) = value {
+        for (name, keys) in map {
+            let mut lines = Vec::new();
+            if !name.is_empty() {
+                lines.push(format!("[{}]", name));
+            }
+            if let Value::Object(keys) = keys {
+                for (key, value) in keys {
+                    let value = value.as_str().map_or(value.to_string(), str::to_string);
+                    lines.push(format!("{} = {}", key, value));
+                }
+            }
+            sections.push(lines.join("\n"));
+        }
+    }
+    sections.join("\n\n")
+}
+
+/// Three-way merge the value of the key at `path`, where None means the key isn't present. Returns
+/// the path of the first key that was changed differently on both sides if the values conflict
+fn merge_values(
+    path: &str,
+    base: Option<&Value>,
+    local: Option<&Value>,
+    remote: Option<&Value>,
+) -> Result<Option<Value>, String> {
+    if local == remote || remote == base {
+        return Ok(local.cloned());
+    } else if local == base {
+        return Ok(remote.cloned());
+    }
+
+    match (local, remote) {
+        (Some(Value::Object(local_keys)), Some(Value::Object(remote_keys))) => {
+            let no_keys = Map::new();
+            let base_keys = match base {
+                Some(Value::Object(base_keys)) => base_keys,
+                _ => &no_keys,
+            };
+            let mut merged = Map::new();
+            // Keys keep their local order, with keys only added in the repository at the end
+            let keys = local_keys.keys().chain(
+                remote_keys
+                    .keys()
+                    .filter(|key| !local_keys.contains_key(*key)),
+            );
+            for key in keys {
+                let key_path = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                if let Some(value) = merge_values(
+                    &key_path,
+                    base_keys.get(key),
+                    local_keys.get(key),
+                    remote_keys.get(key),
+                )? {
+                    merged.insert(key.to_string(), value);
+                }
+            }
+            Ok(Some(Value::Object(merged)))
+        }
+        _ if path.is_empty() => Err("The file".to_string()),
+        _ => Err(path.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_structured_json() {
+        let merged = merge_structured(
+            MergeDriver::Json,
+            b"{\n    \"editor.fontSize\": 12,\n    \"editor.tabSize\": 4\n}\n",
+            b"{\n    \"editor.fontSize\": 14,\n    \"editor.tabSize\": 4\n}\n",
+            b"{\n    \"editor.fontSize\": 12,\n    \"files.autoSave\": \"off\"\n}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(merged).unwrap(),
+            "{\n    \"editor.fontSize\": 14,\n    \"files.autoSave\": \"off\"\n}\n"
+        );
+
+        let err = merge_structured(
+            MergeDriver::Json,
+            b"{\"editor\": {\"fontSize\": 12}}",
+            b"{\"editor\": {\"fontSize\": 14}}",
+            b"{\"editor\": {\"fontSize\": 10}}",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "editor.fontSize was changed both locally and in the repository"
+        );
+        assert!(merge_structured(MergeDriver::Json, b"", b"{", b"{}").is_err());
+    }
+
+    #[test]
+    fn test_merge_structured_yaml() {
+        let merged = merge_structured(
+            MergeDriver::Yaml,
+            b"",
+            b"font:\n  size: 12\n",
+            b"font:\n  family: mono\n",
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(merged).unwrap(),
+            "font:\n  size: 12\n  family: mono\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_structured_ini() {
+        let merged = merge_structured(
+            MergeDriver::Ini,
+            b"[user]\nname = Daniel\n\n[core]\neditor = vim\n",
+            b"[user]\nname = Daniel\nemail = daniel@example.com\n\n[core]\neditor = vim\n",
+            b"[user]\nname = Daniel\n\n[core]\neditor = nvim\n",
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(merged).unwrap(),
+            "[user]\nname = Daniel\nemail = daniel@example.com\n\n[core]\neditor = nvim\n"
+        );
+
+        // Lines that would be lost are left to the line by line merge
+        for local in [
+            "; Edited locally\n[user]\nname = Daniel\n",
+            "[remote]\nfetch = +refs/heads/*\nfetch = +refs/tags/*\n",
+            "[core]\nbare\n",
+        ] {
+            assert!(merge_structured(
+                MergeDriver::Ini,
+                b"[user]\nname = Daniel\n",
+                local.as_bytes(),
+                b"[user]\nname = Daniel\n",
+            )
+            .is_err());
+        }
+    }
+}
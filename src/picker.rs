@@ -0,0 +1,319 @@
+use std::{
+    error::Error,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use console::Term;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+static USE_TUI: AtomicBool = AtomicBool::new(false);
+
+/// Set whether dotfiles are picked in the full-screen picker (`--tui`) instead of a plain
+/// selection prompt
+pub fn set_use_tui(use_tui: bool) {
+    USE_TUI.store(use_tui, Ordering::Relaxed);
+}
+
+/// Return whether dotfiles should be picked with [pick_dotfiles]. This is only the case if it was
+/// asked for with [set_use_tui] and stdout is a terminal the picker can take over
+pub fn use_tui() -> bool {
+    USE_TUI.load(Ordering::Relaxed) && Term::stdout().is_term()
+}
+
+/// What the preview pane of the picker shows for a dotfile
+#[derive(Debug, PartialEq)]
+pub enum Preview {
+    /// The contents of the dotfile in the repository, as there is no local copy
+    Contents(String),
+    /// A unified diff from the repository version of the dotfile to the local copy
+    Diff(String),
+    /// Why the dotfile can't be previewed
+    Unavailable(String),
+}
+
+/// A dotfile that can be picked, as shown in the picker
+#[derive(Debug)]
+pub struct PickerEntry {
+    pub name: String,
+    pub target: PathBuf,
+    /// The names of the install and uninstall steps the dotfile has, e.g. `post_install`
+    pub hooks: Vec<&'static str>,
+    pub preview: Preview,
+    /// Whether the dotfile is already installed, in which case it is selected to begin with
+    pub installed: bool,
+}
+
+/// What to do after a key press in the picker
+#[derive(Debug, PartialEq)]
+enum PickerAction {
+    Continue,
+    Confirm,
+    Cancel,
+}
+
+/// The state of the picker: which dotfile is highlighted, which are selected and how far the
+/// preview has been scrolled
+struct Picker<'a> {
+    title: &'a str,
+    entries: &'a [PickerEntry],
+    selected: Vec<bool>,
+    list_state: ListState,
+    scroll: u16,
+}
+
+impl<'a> Picker<'a> {
+    fn new(title: &'a str, entries: &'a [PickerEntry]) -> Self {
+        Picker {
+            title,
+            entries,
+            selected: entries.iter().map(|entry| entry.installed).collect(),
+            list_state: ListState::default().with_selected(Some(0)),
+            scroll: 0,
+        }
+    }
+
+    fn highlighted(&self) -> usize {
+        self.list_state.selected().unwrap_or(0)
+    }
+
+    fn selected_indices(&self) -> Vec<usize> {
+        self.selected
+            .iter()
+            .enumerate()
+            .filter(|(_, selected)| **selected)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> PickerAction {
+        let highlighted = self.highlighted();
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return PickerAction::Cancel
+            }
+            KeyCode::Esc | KeyCode::Char('q') => return PickerAction::Cancel,
+            KeyCode::Enter => return PickerAction::Confirm,
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.list_state.select(Some(highlighted.saturating_sub(1)));
+                self.scroll = 0;
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.list_state
+                    .select(Some((highlighted + 1).min(self.entries.len() - 1)));
+                self.scroll = 0;
+            }
+            KeyCode::Char(' ') => self.selected[highlighted] = !self.selected[highlighted],
+            KeyCode::Char('a') => {
+                let select_all = self.selected.iter().any(|selected| !selected);
+                self.selected = vec![select_all; self.entries.len()];
+            }
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(10),
+            _ => {}
+        }
+        PickerAction::Continue
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [body, help] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(body);
+
+        let items = self
+            .entries
+            .iter()
+            .zip(&self.selected)
+            .map(|(entry, selected)| {
+                let mut name_line = Line::from(vec![
+                    Span::raw(if *selected { "[x] " } else { "[ ] " }),
+                    Span::styled(&entry.name, Style::new().add_modifier(Modifier::BOLD)),
+                ]);
+                if entry.installed {
+                    name_line
+                        .push_span(Span::styled(" (installed)", Style::new().fg(Color::Green)));
+                }
+                let mut lines = vec![
+                    name_line,
+                    Line::styled(
+                        format!("    → {}", entry.target.display()),
+                        Style::new().fg(Color::DarkGray),
+                    ),
+                ];
+                if !entry.hooks.is_empty() {
+                    lines.push(Line::styled(
+                        format!("    runs {}", entry.hooks.join(", ")),
+                        Style::new().fg(Color::Yellow),
+                    ));
+                }
+                ListItem::new(lines)
+            })
+            .collect::<Vec<ListItem>>();
+        let list = List::new(items)
+            .block(Block::bordered().title(self.title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        let (preview_title, preview_lines) = match &self.entries[self.highlighted()].preview {
+            Preview::Contents(contents) => (
+                "Contents",
+                contents.lines().map(Line::raw).collect::<Vec<Line>>(),
+            ),
+            Preview::Diff(diff) => ("Diff against the local copy", diff_lines(diff)),
+            Preview::Unavailable(reason) => ("Preview", vec![Line::raw(reason.as_str())]),
+        };
+        let preview = Paragraph::new(preview_lines)
+            .block(Block::bordered().title(preview_title))
+            .scroll((self.scroll, 0));
+        frame.render_widget(preview, preview_area);
+
+        frame.render_widget(
+            Line::styled(
+                format!(
+                    "{} selected · ↑/↓ move · SPACE select · a select all · PgUp/PgDn scroll \
+                    preview · ENTER confirm · ESC cancel",
+                    self.selected_indices().len()
+                ),
+                Style::new().fg(Color::Cyan),
+            ),
+            help,
+        );
+    }
+}
+
+/// Colour the lines of a unified diff
+fn diff_lines(diff: &str) -> Vec<Line<'_>> {
+    diff.lines()
+        .map(|line| {
+            let color = if line.starts_with("+++") || line.starts_with("---") {
+                Color::Reset
+            } else if line.starts_with('+') {
+                Color::Green
+            } else if line.starts_with('-') {
+                Color::Red
+            } else if line.starts_with("@@") {
+                Color::Cyan
+            } else {
+                Color::Reset
+            };
+            Line::styled(line, Style::new().fg(color))
+        })
+        .collect()
+}
+
+/// Let the user pick any number of `entries` in a full-screen picker titled `title`, which shows
+/// where each dotfile is installed, the steps it runs and a preview of it. Returns the indices of
+/// the picked entries, or an error if picking was cancelled
+pub fn pick_dotfiles(title: &str, entries: &[PickerEntry]) -> Result<Vec<usize>, Box<dyn Error>> {
+    if entries.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut terminal = ratatui::try_init()?;
+    let result = run_picker(&mut terminal, Picker::new(title, entries));
+    ratatui::try_restore()?;
+    result
+}
+
+fn run_picker(
+    terminal: &mut DefaultTerminal,
+    mut picker: Picker,
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    loop {
+        terminal.draw(|frame| picker.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match picker.handle_key(key) {
+                PickerAction::Continue => {}
+                PickerAction::Confirm => return Ok(picker.selected_indices()),
+                PickerAction::Cancel => return Err("Dotfile selection was cancelled".into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use super::*;
+
+    fn entries() -> Vec<PickerEntry> {
+        vec![
+            PickerEntry {
+                name: "neovim".to_string(),
+                target: PathBuf::from("~/.config/nvim/init.vim"),
+                hooks: vec!["post_install"],
+                preview: Preview::Diff(
+                    "--- a\n+++ b\n@@ -1 +1 @@\n-set number\n+set nonumber\n".to_string(),
+                ),
+                installed: false,
+            },
+            PickerEntry {
+                name: "zsh".to_string(),
+                target: PathBuf::from("~/.zshrc"),
+                hooks: vec![],
+                preview: Preview::Contents("export EDITOR=nvim\n".to_string()),
+                installed: true,
+            },
+        ]
+    }
+
+    fn press(picker: &mut Picker, code: KeyCode) -> PickerAction {
+        picker.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn test_picker_selection() {
+        let entries = entries();
+        let mut picker = Picker::new("Dotfiles", &entries);
+        // Installed dotfiles are selected to begin with
+        assert_eq!(picker.selected_indices(), vec![1]);
+
+        assert_eq!(press(&mut picker, KeyCode::Down), PickerAction::Continue);
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Char(' '));
+        assert!(picker.selected_indices().is_empty());
+
+        press(&mut picker, KeyCode::Char('a'));
+        assert_eq!(picker.selected_indices(), vec![0, 1]);
+        press(&mut picker, KeyCode::Char('a'));
+        assert!(picker.selected_indices().is_empty());
+
+        assert_eq!(press(&mut picker, KeyCode::Enter), PickerAction::Confirm);
+        assert_eq!(press(&mut picker, KeyCode::Esc), PickerAction::Cancel);
+    }
+
+    #[test]
+    fn test_picker_draw() {
+        let entries = entries();
+        let mut picker = Picker::new("Dotfiles", &entries);
+        press(&mut picker, KeyCode::Char(' '));
+        let mut terminal = Terminal::new(TestBackend::new(100, 12)).unwrap();
+
+        terminal.draw(|frame| picker.draw(frame)).unwrap();
+
+        let screen = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect::<String>();
+        assert!(screen.contains("[x] neovim"));
+        assert!(screen.contains("[x] zsh (installed)"));
+        assert!(screen.contains("runs post_install"));
+        assert!(screen.contains("+set nonumber"));
+        assert!(screen.contains("2 selected"));
+    }
+}
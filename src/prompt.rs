@@ -0,0 +1,209 @@
+use std::error::Error;
+use std::io::{stdin, stdout, Write};
+use std::sync::{Arc, RwLock};
+
+use console::style;
+use dialoguer::{Confirm, Input, MultiSelect, Password, Select};
+use lazy_static::lazy_static;
+
+use crate::utils::get_theme;
+
+/// The questions jtd asks while installing and syncing dotfiles. The jtd CLI answers them with
+/// terminal prompts ([TerminalPrompter]), while tools embedding jtd as a library can answer them
+/// however they like by passing their own implementation to [set_prompter]. By default nothing
+/// is asked ([NoPrompter]).
+pub trait Prompter: Send + Sync {
+    /// Ask a yes/no question, where `default` is the suggested answer
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool, Box<dyn Error>>;
+
+    /// Ask for any number of `items` to be selected, where `defaults` are the items selected to
+    /// begin with, returning the indices of the selected items
+    fn select(
+        &self,
+        prompt: &str,
+        items: &[String],
+        defaults: &[bool],
+    ) -> Result<Vec<usize>, Box<dyn Error>>;
+
+    /// Ask for one of `items` to be chosen, where `default` is the index of the suggested item,
+    /// returning the index of the chosen item
+    fn choose(
+        &self,
+        prompt: &str,
+        items: &[String],
+        default: usize,
+    ) -> Result<usize, Box<dyn Error>>;
+
+    /// Ask for a line of text, where `default` is the suggested answer
+    fn input(&self, prompt: &str, default: Option<&str>) -> Result<String, Box<dyn Error>>;
+
+    /// Ask for a secret such as a password or passphrase
+    fn password(&self, prompt: &str) -> Result<String, Box<dyn Error>>;
+
+    /// Wait until the user has done something outside of jtd, such as resolving merge conflicts
+    fn wait(&self, prompt: &str) -> Result<(), Box<dyn Error>>;
+}
+
+/// Ask questions in the terminal, as the jtd CLI does
+pub struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool, Box<dyn Error>> {
+        Ok(Confirm::with_theme(&get_theme())
+            .with_prompt(prompt)
+            .default(default)
+            .wait_for_newline(true)
+            .interact()?)
+    }
+
+    fn select(
+        &self,
+        prompt: &str,
+        items: &[String],
+        defaults: &[bool],
+    ) -> Result<Vec<usize>, Box<dyn Error>> {
+        Ok(MultiSelect::with_theme(&get_theme())
+            .with_prompt(prompt)
+            .items(items)
+            .defaults(defaults)
+            .interact()?)
+    }
+
+    fn choose(
+        &self,
+        prompt: &str,
+        items: &[String],
+        default: usize,
+    ) -> Result<usize, Box<dyn Error>> {
+        Ok(Select::with_theme(&get_theme())
+            .with_prompt(prompt)
+            .items(items)
+            .default(default)
+            .interact()?)
+    }
+
+    fn input(&self, prompt: &str, default: Option<&str>) -> Result<String, Box<dyn Error>> {
+        let theme = get_theme();
+        let mut input = Input::with_theme(&theme);
+        input.with_prompt(prompt);
+        if let Some(default) = default {
+            input.default(default.to_string());
+        }
+        Ok(input.interact()?)
+    }
+
+    fn password(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+        Ok(Password::with_theme(&get_theme())
+            .with_prompt(prompt)
+            .allow_empty_password(true)
+            .interact()?)
+    }
+
+    fn wait(&self, prompt: &str) -> Result<(), Box<dyn Error>> {
+        print!("{}", style(prompt).blue().italic());
+        let _ = stdout().flush();
+
+        let mut _newline = String::new();
+        stdin().read_line(&mut _newline)?;
+        Ok(())
+    }
+}
+
+/// Ask nothing: every confirmation is declined, every choice is left at its default and anything
+/// requiring input fails. This is the
+/// default, so that jtd never blocks on stdin when used as a library
+pub struct NoPrompter;
+
+impl NoPrompter {
+    fn unanswerable(prompt: &str) -> Box<dyn Error> {
+        format!(
+            "\"{}\" requires input, but no prompter has been set",
+            prompt
+        )
+        .into()
+    }
+}
+
+impl Prompter for NoPrompter {
+    fn confirm(&self, _prompt: &str, _default: bool) -> Result<bool, Box<dyn Error>> {
+        Ok(false)
+    }
+
+    fn select(
+        &self,
+        prompt: &str,
+        _items: &[String],
+        _defaults: &[bool],
+    ) -> Result<Vec<usize>, Box<dyn Error>> {
+        Err(NoPrompter::unanswerable(prompt))
+    }
+
+    fn choose(
+        &self,
+        _prompt: &str,
+        _items: &[String],
+        default: usize,
+    ) -> Result<usize, Box<dyn Error>> {
+        Ok(default)
+    }
+
+    fn input(&self, prompt: &str, _default: Option<&str>) -> Result<String, Box<dyn Error>> {
+        Err(NoPrompter::unanswerable(prompt))
+    }
+
+    fn password(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+        Err(NoPrompter::unanswerable(prompt))
+    }
+
+    fn wait(&self, prompt: &str) -> Result<(), Box<dyn Error>> {
+        Err(NoPrompter::unanswerable(prompt))
+    }
+}
+
+lazy_static! {
+    static ref PROMPTER: RwLock<Arc<dyn Prompter>> = RwLock::new(Arc::new(NoPrompter));
+}
+
+/// Set how the questions jtd asks are answered.
+///
+/// # Examples
+///
+/// ```
+/// use jointhedots::prompt::{set_prompter, TerminalPrompter};
+///
+/// set_prompter(TerminalPrompter);
+/// ```
+pub fn set_prompter<P: Prompter + 'static>(prompter: P) {
+    *PROMPTER.write().unwrap() = Arc::new(prompter);
+}
+
+/// Return the [Prompter] set with [set_prompter]
+pub fn prompter() -> Arc<dyn Prompter> {
+    PROMPTER.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_prompter() {
+        assert!(!NoPrompter.confirm("Overwrite?", true).unwrap());
+        assert!(NoPrompter
+            .input("Username", Some("jtd"))
+            .unwrap_err()
+            .to_string()
+            .contains("\"Username\" requires input"));
+        assert!(NoPrompter
+            .select("Select", &["nvim".to_string()], &[true])
+            .is_err());
+        assert_eq!(
+            NoPrompter
+                .choose("Choose", &["merge".to_string(), "skip".to_string()], 1)
+                .unwrap(),
+            1
+        );
+        assert!(NoPrompter.password("Password").is_err());
+        assert!(NoPrompter.wait("Press ENTER").is_err());
+    }
+}
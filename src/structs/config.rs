@@ -1,46 +1,198 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ArgEnum;
+use git2::FileFavor;
+use indexmap::IndexMap;
 use serde::Deserialize;
 
-const SINGLE_DOTFILE_COMMIT_FORMAT: &str = "Sync {} dotfile";
-const MULTIPLE_DOTFILES_COMMIT_FORMAT: &str = "Sync dotfiles for {}";
+use crate::utils::format_date;
+
+const SINGLE_DOTFILE_COMMIT_FORMAT: &str = "Sync {dotfiles} dotfile";
+const MULTIPLE_DOTFILES_COMMIT_FORMAT: &str = "Sync dotfiles for {dotfiles}";
+const CONVENTIONAL_COMMIT_TYPE: &str = "chore";
+/// The suffix of the copies of overwritten targets kept by `--keep-backup` if the manifest doesn't
+/// set `backup_suffix`
+pub const DEFAULT_BACKUP_SUFFIX: &str = ".jtd-bak";
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub commit_prefix: String,
+    /// The template of the messages of commits made when syncing, in place of the default
+    /// messages and `commit_prefix`. See [render_commit_message] for the placeholders it can use
+    pub commit_message: Option<String>,
+    pub commit_style: CommitStyle,
+    /// The scope of conventional commit messages, e.g. `dotfiles` for `chore(dotfiles): ...`
+    pub commit_scope: Option<String>,
     pub squash_commits: bool,
+    /// Whether to add a `Signed-off-by` trailer to commits made when syncing
+    pub sign_off: bool,
+    /// Trailers to add to commits made when syncing, e.g. `Synced-from: "{hostname}"`. Their values
+    /// can use the `{hostname}` and `{date}` placeholders of [render_commit_message]
+    pub commit_trailers: IndexMap<String, String>,
+    pub age_recipients: Vec<String>,
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    /// The directory of the repository the `file` paths of the dotfiles are relative to, e.g.
+    /// `config/` for dotfiles kept in a larger repository
+    pub root: Option<String>,
+    pub merge_strategy: MergeStrategy,
+    /// The branch of the remote to push sync commits to instead of the branch they are made on,
+    /// e.g. `machine/{hostname}`. Can use the `{hostname}` and `{date}` placeholders of
+    /// [render_commit_message]
+    pub push_branch: Option<String>,
+    /// The URL of the GitLab instance to open merge requests on. Only kept to warn that it is
+    /// ignored, see [Manifest::gitlab_url](crate::structs::Manifest::gitlab_url)
+    pub gitlab_url: Option<String>,
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+    pub shell: Option<String>,
+    /// The suffix to keep a copy of a target under next to it when installing overwrites it, e.g.
+    /// `.jtd-bak` for `kitty.conf.jtd-bak`
+    pub backup_suffix: Option<String>,
+    pub diff_tool: Option<String>,
+}
+
+/// The style of the default messages of commits made when syncing
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitStyle {
+    /// `commit_prefix` followed by e.g. `Sync dotfiles for kitty and nvim`
+    Default,
+    /// A conventional commit such as `chore(dotfiles): sync kitty, nvim`, for repositories that
+    /// lint their commit messages
+    Conventional,
+}
+
+/// How to resolve merge conflicts when syncing
+#[derive(ArgEnum, Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    /// Resolve conflicts manually in the temporary repository
+    Manual,
+    /// Prefer the local content being synced
+    Ours,
+    /// Prefer the content already in the remote repository
+    Theirs,
+}
+
+/// What to do when installing a dotfile over an existing file, instead of asking
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OnConflict {
+    /// Keep the existing file and skip installing the dotfile
+    Keep,
+    /// Replace the existing file with the version in the repository
+    Overwrite,
+    /// Merge the version in the repository into the existing file
+    Merge,
+}
+
+impl MergeStrategy {
+    /// Return the [FileFavor] to merge with, given whether the local content is on the `HEAD` side
+    /// of the merge (rather than being the commit merged into `HEAD`)
+    pub(crate) fn file_favor(&self, local_is_head: bool) -> FileFavor {
+        match (self, local_is_head) {
+            (MergeStrategy::Manual, _) => FileFavor::Normal,
+            (MergeStrategy::Ours, true) | (MergeStrategy::Theirs, false) => FileFavor::Ours,
+            (MergeStrategy::Ours, false) | (MergeStrategy::Theirs, true) => FileFavor::Theirs,
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             commit_prefix: "🔁 ".to_string(),
+            commit_message: None,
+            commit_style: CommitStyle::Default,
+            commit_scope: None,
             squash_commits: true,
+            sign_off: false,
+            commit_trailers: IndexMap::new(),
+            age_recipients: vec![],
+            git_ref: None,
+            root: None,
+            merge_strategy: MergeStrategy::Manual,
+            push_branch: None,
+            gitlab_url: None,
+            author_name: None,
+            author_email: None,
+            shell: None,
+            backup_suffix: None,
+            diff_tool: None,
         }
     }
 }
 
 impl Config {
+    /// Return the `commit_trailers` to add to commits, with their placeholders filled in
+    pub fn commit_trailers(&self) -> Vec<(String, String)> {
+        self.commit_trailers
+            .iter()
+            .map(|(key, value)| (key.clone(), render_commit_message(value, &[])))
+            .collect()
+    }
+
+    /// Return the `push_branch` to push sync commits to, with its placeholders filled in
+    pub fn push_branch(&self) -> Option<String> {
+        self.push_branch
+            .as_ref()
+            .map(|branch| render_commit_message(branch, &[]))
+    }
+
     pub fn generate_commit_message(&self, dotfile_names: Vec<&str>) -> String {
-        let mut commit_message = self.commit_prefix.to_string();
-
-        if dotfile_names.len() == 1 {
-            commit_message.push_str(&SINGLE_DOTFILE_COMMIT_FORMAT.replace("{}", &dotfile_names[0]));
-        } else {
-            commit_message.push_str(
-                &MULTIPLE_DOTFILES_COMMIT_FORMAT
-                    .replace("{}", &dotfile_names.join(", "))
-                    .chars()
-                    .rev()
-                    .collect::<String>()
-                    .replacen(",", "dna ", 1)
-                    .chars()
-                    .rev()
-                    .collect::<String>(),
-            );
+        match (&self.commit_message, self.commit_style) {
+            (Some(template), _) => render_commit_message(template, &dotfile_names),
+            (None, CommitStyle::Conventional) => format!(
+                "{}{}: sync {}",
+                CONVENTIONAL_COMMIT_TYPE,
+                self.commit_scope
+                    .as_ref()
+                    .map_or(String::new(), |scope| format!("({})", scope)),
+                dotfile_names.join(", ")
+            ),
+            (None, CommitStyle::Default) => {
+                let format = if dotfile_names.len() == 1 {
+                    SINGLE_DOTFILE_COMMIT_FORMAT
+                } else {
+                    MULTIPLE_DOTFILES_COMMIT_FORMAT
+                };
+                format!(
+                    "{}{}",
+                    self.commit_prefix,
+                    render_commit_message(format, &dotfile_names)
+                )
+            }
         }
+    }
+}
 
-        commit_message
+/// Fill in the placeholders of a commit message `template` for a commit of `dotfile_names`:
+///
+/// * `{dotfiles}` - The names of the dotfiles, e.g. `kitty, nvim and zsh`
+/// * `{count}` - The number of dotfiles
+/// * `{hostname}` - The hostname of the current machine
+/// * `{date}` - The current date (in UTC) as `YYYY-MM-DD`
+fn render_commit_message(template: &str, dotfile_names: &[&str]) -> String {
+    let dotfiles = match dotfile_names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        _ => dotfile_names.join(""),
+    };
+    let mut message = template
+        .replace("{dotfiles}", &dotfiles)
+        .replace("{count}", &dotfile_names.len().to_string());
+
+    if message.contains("{hostname}") {
+        let hostname = whoami::fallible::hostname().unwrap_or_else(|_| "unknown host".to_string());
+        message = message.replace("{hostname}", &hostname);
+    }
+    if message.contains("{date}") {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        message = message.replace("{date}", &format_date(now as i64));
     }
+    message
 }
 
 #[cfg(test)]
@@ -56,6 +208,15 @@ mod tests {
         assert_eq!("🔁 Sync neovim dotfile", commit_message.as_str());
     }
 
+    #[test]
+    fn test_merge_strategy_file_favor() {
+        assert_eq!(MergeStrategy::Manual.file_favor(true), FileFavor::Normal);
+        assert_eq!(MergeStrategy::Ours.file_favor(true), FileFavor::Ours);
+        assert_eq!(MergeStrategy::Ours.file_favor(false), FileFavor::Theirs);
+        assert_eq!(MergeStrategy::Theirs.file_favor(true), FileFavor::Theirs);
+        assert_eq!(MergeStrategy::Theirs.file_favor(false), FileFavor::Ours);
+    }
+
     #[test]
     fn test_generate_commit_message_multiple_dotfiles() {
         let config = Config::default();
@@ -66,5 +227,60 @@ mod tests {
             "🔁 Sync dotfiles for neovim and kitty",
             commit_message.as_str()
         );
+        assert_eq!(
+            config.generate_commit_message(vec!["neovim", "kitty", "zsh"]),
+            "🔁 Sync dotfiles for neovim, kitty and zsh"
+        );
+    }
+
+    #[test]
+    fn test_generate_commit_message_conventional() {
+        let mut config: Config = serde_yaml::from_str("commit_style: conventional").unwrap();
+
+        assert_eq!(
+            config.generate_commit_message(vec!["kitty", "nvim"]),
+            "chore: sync kitty, nvim"
+        );
+
+        config.commit_scope = Some("dotfiles".to_string());
+        assert_eq!(
+            config.generate_commit_message(vec!["kitty"]),
+            "chore(dotfiles): sync kitty"
+        );
+    }
+
+    #[test]
+    fn test_generate_commit_message_template() {
+        let config = Config {
+            commit_message: Some("dotfiles: update {dotfiles} ({count}) from {hostname}".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.generate_commit_message(vec!["neovim", "kitty"]),
+            format!(
+                "dotfiles: update neovim and kitty (2) from {}",
+                whoami::fallible::hostname().unwrap()
+            )
+        );
+
+        let config = Config {
+            commit_message: Some("Sync on {date}".into()),
+            ..Default::default()
+        };
+        let commit_message = config.generate_commit_message(vec!["neovim"]);
+        assert_eq!(commit_message.len(), "Sync on YYYY-MM-DD".len());
+        assert!(!commit_message.contains('{'));
+    }
+
+    #[test]
+    fn test_push_branch() {
+        assert_eq!(Config::default().push_branch(), None);
+
+        let config: Config = serde_yaml::from_str("push_branch: machine/{hostname}").unwrap();
+        assert_eq!(
+            config.push_branch(),
+            Some(format!("machine/{}", whoami::fallible::hostname().unwrap()))
+        );
     }
 }
@@ -1,30 +1,862 @@
+use crate::encryption::{decrypt, encrypt, identity_path};
+use crate::git::lfs::{smudge_lfs_file, LfsPointer};
 use crate::git::operations::{
-    add_and_commit, checkout_ref, get_commit, get_head_hash, get_repo_dir, normal_merge,
+    add_and_commit, checkout_ref, ensure_ref_available, find_file_in_tree, get_commit, get_head,
+    get_head_hash, get_repo_dir, merge_file_contents, normal_merge, resolve_ref,
+};
+use crate::log::print_log;
+use crate::picker::{PickerEntry, Preview};
+use crate::prompt::prompter;
+use crate::template::{expand_env_vars, render_template, template_variables};
+use crate::utils::{
+    backups_dir, command_vec_succeeds, copy_as_root, ensure_interactive, is_binary,
+    is_non_interactive, metadata_path, print_command_vec, remove_as_root, resolved_path,
+    root_command, run_command_vec, run_filters, write_as_root, write_atomically, DEFAULT_SHELL,
 };
-use crate::utils::run_command_vec;
-use crate::MANIFEST_PATH;
 use console::style;
-use git2::Repository;
-use sha1::{Digest, Sha1};
+use git2::{BranchType, Commit, DiffOptions, ObjectType, Oid, Patch, Repository};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env::consts::OS;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use serde::Deserialize;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
 use std::error::Error;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+use crate::utils::{command_vec_matches_hash, hash_command_vec};
+
+use super::{Config, DotfileMetadata, DotfileStatus};
+
+/// The suffix of the file a merge with conflicts is written to when installing, next to the
+/// target it was meant for
+pub const CONFLICTED_MERGE_SUFFIX: &str = ".jtd-merge";
+
+/// The encryption used to store a dotfile in the repository
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    Age,
+}
+
+/// How the changes to a dotfile are merged when it has changed both locally and in the repository
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeDriver {
+    /// Merge line by line, leaving conflict markers around conflicting lines
+    #[default]
+    Text,
+    /// Merge the keys of the JSON objects in the file
+    Json,
+    /// Merge the keys of the YAML mappings in the file
+    Yaml,
+    /// Merge the keys within each section of the INI file
+    Ini,
+}
+
+/// How to install a dotfile over a target that has changed since it was last synced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InstallMode {
+    /// Refuse to install it, so that the changes can be synced first
+    Safe,
+    /// Overwrite the changes
+    Force,
+    /// Merge the version in the repository into the target, keeping the changes
+    Merge,
+}
+
+/// How a dotfile has changed since it was last synced, on this machine and in the repository
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncState {
+    /// Neither the installed dotfile nor the repository has changed
+    UpToDate,
+    /// Only the installed dotfile has changed, so syncing it fast-forwards the repository
+    LocalOnly,
+    /// Only the repository has changed, so there is nothing to sync and `jtd update` installs the
+    /// new version
+    RemoteOnly,
+    /// Both have changed, so syncing has to merge them
+    Diverged,
+}
+
+impl SyncState {
+    /// Return a short description of this state, e.g. for listing dotfiles before syncing them
+    pub fn describe(&self) -> &'static str {
+        match self {
+            SyncState::UpToDate => "up to date",
+            SyncState::LocalOnly => "local changes only (fast-forward)",
+            SyncState::RemoteOnly => "remote changes only (run jtd update to install them)",
+            SyncState::Diverged => "diverged (local and remote changes)",
+        }
+    }
+}
+
+/// A built-in action that makes a running application pick up its newly installed config
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReloadAction {
+    Tmux,
+    Sway,
+    I3,
+    Kitty,
+    SystemdUser,
+}
+
+impl ReloadAction {
+    /// Return the name of this action as written in the manifest
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReloadAction::Tmux => "tmux",
+            ReloadAction::Sway => "sway",
+            ReloadAction::I3 => "i3",
+            ReloadAction::Kitty => "kitty",
+            ReloadAction::SystemdUser => "systemd-user",
+        }
+    }
+
+    /// Return a command that succeeds only if the application is running, as there is nothing to
+    /// reload otherwise
+    fn running_check(&self) -> &'static str {
+        match self {
+            ReloadAction::Tmux => "tmux list-sessions",
+            ReloadAction::Sway => "swaymsg -t get_version",
+            ReloadAction::I3 => "i3-msg -t get_version",
+            ReloadAction::Kitty => "pgrep -x kitty",
+            ReloadAction::SystemdUser => "systemctl --user show-environment",
+        }
+    }
+
+    /// Return the command that reloads the application. `JTD_TARGET_PATH` is set to the path the
+    /// dotfile was installed to
+    fn command(&self) -> &'static str {
+        match self {
+            ReloadAction::Tmux => "tmux source-file \"$JTD_TARGET_PATH\"",
+            ReloadAction::Sway => "swaymsg reload",
+            ReloadAction::I3 => "i3-msg reload",
+            // kitty reloads its config on SIGUSR1
+            ReloadAction::Kitty => "pkill -USR1 -x kitty",
+            ReloadAction::SystemdUser => "systemctl --user daemon-reload",
+        }
+    }
+}
+
+/// Deserialize `reload` from either a single action or a list of them
+fn deserialize_reload<'de, D>(deserializer: D) -> Result<Vec<ReloadAction>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(ReloadAction),
+        Many(Vec<ReloadAction>),
+    }
+
+    match OneOrMany::deserialize(deserializer) {
+        Ok(OneOrMany::One(action)) => Ok(vec![action]),
+        Ok(OneOrMany::Many(actions)) => Ok(actions),
+        Err(_) => Err(serde::de::Error::custom(
+            "invalid reload, expected one or a list of tmux, sway, i3, kitty or systemd-user",
+        )),
+    }
+}
+
+/// Deserialize a file mode such as `0600` as an octal number. YAML would otherwise read `0600` as
+/// the decimal number 600. Integers, as JSON and TOML manifests have no octal numbers, are taken
+/// as the mode itself, e.g. `384` for `0600`
+fn deserialize_mode<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(ModeVisitor).map(Some)
+}
+
+struct ModeVisitor;
+
+impl<'de> Visitor<'de> for ModeVisitor {
+    type Value = u32;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a file mode such as 0600")
+    }
+
+    fn visit_str<E: de::Error>(self, mode: &str) -> Result<u32, E> {
+        u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .ok_or_else(|| E::custom(format!("invalid mode {}, expected e.g. 0600", mode)))
+    }
+
+    fn visit_u64<E: de::Error>(self, mode: u64) -> Result<u32, E> {
+        if mode <= 0o7777 {
+            Ok(mode as u32)
+        } else {
+            Err(E::custom(format!(
+                "invalid mode {:o}, expected e.g. 0600",
+                mode
+            )))
+        }
+    }
+
+    fn visit_i64<E: de::Error>(self, mode: i64) -> Result<u32, E> {
+        if mode >= 0 {
+            self.visit_u64(mode as u64)
+        } else {
+            Err(E::custom(format!(
+                "invalid mode {}, expected e.g. 0600",
+                mode
+            )))
+        }
+    }
+}
+
+/// Label the side of the unified diff `patch` whose file doesn't exist as `/dev/null`, with the
+/// `new file mode` or `deleted file mode` line `git diff` gives added and deleted files, so that
+/// `git apply` creates or deletes the file rather than leaving it empty
+fn label_missing_file(patch: &str, old_exists: bool, new_exists: bool) -> String {
+    let mode_line = match (old_exists, new_exists) {
+        (false, true) => "new file mode 100644",
+        (true, false) => "deleted file mode 100644",
+        _ => return patch.to_string(),
+    };
+
+    let mut labelled = String::new();
+    let mut in_header = true;
+    for line in patch.split_inclusive('\n') {
+        in_header &= !line.starts_with("@@");
+        if !in_header {
+            labelled.push_str(line);
+        } else if let Some(index) = line.strip_prefix("index ") {
+            // The missing side is the empty blob, which git shows as all zeroes
+            let hashes = index.trim_end().trim_end_matches(" 100644");
+            let hashes = match hashes.split_once("..") {
+                Some((_, new_hash)) if !old_exists => format!("0000000..{}", new_hash),
+                Some((old_hash, _)) => format!("{}..0000000", old_hash),
+                None => hashes.to_string(),
+            };
+            labelled.push_str(&format!("{}\nindex {}\n", mode_line, hashes));
+        } else if line.starts_with("--- ") && !old_exists {
+            labelled.push_str("--- /dev/null\n");
+        } else if line.starts_with("+++ ") && !new_exists {
+            labelled.push_str("+++ /dev/null\n");
+        } else if let Some(files) = line.strip_prefix("Binary files ") {
+            let files = match files.split_once(" and ") {
+                Some((_, new_file)) if !old_exists => format!("/dev/null and {}", new_file),
+                Some((old_file, _)) => format!("{} and /dev/null differ\n", old_file),
+                None => files.to_string(),
+            };
+            labelled.push_str(&format!("{}\nBinary files {}", mode_line, files));
+        } else {
+            labelled.push_str(line);
+        }
+    }
+    labelled
+}
+
+/// Apply the hunks of the diff from `old` to `new` that `keep` returns true for to `old`. `keep` is
+/// called with the text of each hunk as it appears in a unified diff
+fn apply_hunks<F>(old: &[u8], new: &[u8], mut keep: F) -> Result<Vec<u8>, Box<dyn Error>>
+where
+    F: FnMut(&str) -> Result<bool, Box<dyn Error>>,
+{
+    let old_lines = old
+        .split_inclusive(|byte| *byte == b'\n')
+        .collect::<Vec<_>>();
+    let patch = Patch::from_buffers(old, None, new, None, Some(&mut DiffOptions::new()))?;
+
+    let mut contents = Vec::new();
+    let mut old_pos = 0;
+    for hunk_idx in 0..patch.num_hunks() {
+        let (hunk, line_count) = patch.hunk(hunk_idx)?;
+        let mut text = String::from_utf8_lossy(hunk.header()).to_string();
+        let mut new_contents = Vec::new();
+        for line_idx in 0..line_count {
+            let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+            // The other origins mark missing newlines at the end of the file
+            if let ' ' | '+' | '-' = line.origin() {
+                text.push(line.origin());
+                text.push_str(&String::from_utf8_lossy(line.content()));
+                if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            }
+            if let ' ' | '+' = line.origin() {
+                new_contents.extend_from_slice(line.content());
+            }
+        }
+
+        // A hunk that removes nothing starts after its start line rather than on it
+        let start = if hunk.old_lines() == 0 {
+            hunk.old_start()
+        } else {
+            hunk.old_start() - 1
+        } as usize;
+        let end = start + hunk.old_lines() as usize;
+        contents.extend(old_lines[old_pos..start].concat());
+        if keep(&text)? {
+            contents.extend(new_contents);
+        } else {
+            contents.extend(old_lines[start..end].concat());
+        }
+        old_pos = end;
+    }
+    contents.extend(old_lines[old_pos..].concat());
+    Ok(contents)
+}
 
-use crate::utils::hash_command_vec;
+/// Show the user each hunk of the diff from `old` to `new`, the contents of `file` in the
+/// repository and the local copy of it, and return `old` with the hunks they pick to sync applied
+fn pick_hunks(file: &str, old: &[u8], new: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    ensure_interactive(&format!(
+        "Picking the changes to {} to sync needs input",
+        file
+    ))?;
+    if is_binary(old) || is_binary(new) {
+        let sync = prompter().confirm(
+            &format!("{} is binary, sync the changes to it?", file),
+            true,
+        )?;
+        return Ok(if sync { new.to_vec() } else { old.to_vec() });
+    }
 
-use super::{Config, DotfileMetadata};
+    let choices = [
+        "Sync this hunk",
+        "Leave this hunk out",
+        "Sync this and all remaining hunks",
+        "Leave this and all remaining hunks out",
+    ]
+    .map(str::to_string);
+    let mut rest = None;
+    apply_hunks(old, new, |text| {
+        if let Some(sync_rest) = rest {
+            return Ok(sync_rest);
+        }
+        print_log(style(format!("Changes to {}", file)).bold());
+        for line in text.lines() {
+            if line.starts_with("@@") {
+                print_log(style(line).cyan());
+            } else if line.starts_with('+') {
+                print_log(style(line).green());
+            } else if line.starts_with('-') {
+                print_log(style(line).red());
+            } else {
+                print_log(line);
+            }
+        }
+        let choice = prompter().choose("Sync this hunk?", &choices, 0)?;
+        if choice >= 2 {
+            rest = Some(choice == 2);
+        }
+        Ok(choice == 0 || choice == 2)
+    })
+}
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct Dotfile {
     pub file: String,
     pub target: PathBuf,
+
+    /// Per-OS overrides for `target`, keyed by the name of the OS as given by
+    /// [std::env::consts::OS] (e.g. `linux`, `macos` or `windows`)
+    #[serde(default)]
+    pub targets: HashMap<String, PathBuf>,
+
+    /// Whether to render `file` as a handlebars template before installing it
+    #[serde(default)]
+    pub template: bool,
+
+    /// Whether to replace `${VAR}` placeholders in `file` with the values of environment variables
+    /// when installing it. The copy in the repository keeps the placeholders
+    #[serde(default)]
+    pub expand_env: bool,
+
+    /// How `file` is encrypted in the repository, if at all. Encrypted dotfiles are decrypted on
+    /// install and re-encrypted on sync
+    #[serde(default)]
+    pub encrypted: Option<Encryption>,
+
+    /// How to merge `file` when it has changed both locally and in the repository. Structured
+    /// formats are merged key by key, falling back to merging line by line if the same key was
+    /// changed on both sides
+    #[serde(default)]
+    pub merge: MergeDriver,
+
+    /// Commands the local copy of this dotfile is piped through, in order, before it is copied into
+    /// the repository when syncing, e.g. to scrub tokens or machine-specific paths from it
+    #[serde(default)]
+    pub sync_filter: Option<Vec<String>>,
+
+    /// Commands this dotfile is piped through, in order, before it is installed, e.g. to put back
+    /// what `sync_filter` scrubbed
+    #[serde(default)]
+    pub install_filter: Option<Vec<String>>,
+
+    /// The branch, tag or commit to install this dotfile from instead of `HEAD`
+    #[serde(default, rename = "ref")]
+    pub git_ref: Option<String>,
+
+    /// The permissions to apply to the target after installing it, e.g. `0600`
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    pub mode: Option<u32>,
+
+    /// Whether the target is only writable by root, e.g. `/etc/keyd/default.conf`. Writing the
+    /// target and setting its permissions are then escalated with sudo (or the command in
+    /// `JTD_ROOT_COMMAND`) rather than jtd having to be run as root
+    #[serde(default)]
+    pub requires_root: bool,
+
     pub pre_install: Option<Vec<String>>,
     pub post_install: Option<Vec<String>>,
+
+    /// Built-in actions to run after this dotfile is installed so that running applications pick
+    /// up the new version, e.g. `tmux`
+    #[serde(default, deserialize_with = "deserialize_reload")]
+    pub reload: Vec<ReloadAction>,
+
+    /// Environment variables to run the install and uninstall steps with, in addition to
+    /// `JTD_DOTFILE_NAME`, `JTD_TARGET_PATH` and `JTD_REPO_DIR`
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// The number of seconds each install and uninstall step may run for before it is killed and
+    /// the dotfile fails to install
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+
+    /// The shell to run the install and uninstall steps with, `sh` if unspecified
+    #[serde(default)]
+    pub shell: Option<String>,
+
+    /// The suffix to keep a copy of the target under next to it when installing overwrites it, in
+    /// addition to the backup in the backups directory
+    #[serde(default)]
+    pub backup_suffix: Option<String>,
+
+    /// Commands that must all succeed for this dotfile to be installed or synced, e.g.
+    /// `command -v sway`. The dotfile is skipped if any of them fail
+    #[serde(default)]
+    pub only_if: Option<Vec<String>>,
+
+    /// Whether the `only_if` conditions and the `sync_filter` and `install_filter` of this dotfile,
+    /// which come from the repository, may be run. Set by
+    /// [Manifest::load_trust](super::Manifest::load_trust), or once the user agrees to run them
+    #[serde(skip)]
+    pub(crate) commands_trusted: bool,
+
+    /// The age identity to decrypt this dotfile with, and encrypt it to if the manifest lists no
+    /// `age_recipients`, in place of the local one given by [identity_path]
+    #[serde(skip)]
+    pub(crate) age_identity: Option<PathBuf>,
+
+    /// Commands to run before and after this dotfile is removed by `jtd uninstall`
+    pub pre_uninstall: Option<Vec<String>>,
+    pub post_uninstall: Option<Vec<String>>,
 }
 
 impl Dotfile {
+    /// Return the target path of this dotfile on the given OS, with `~` expanded. Falls back to
+    /// `target` if there is no override for the OS.
+    pub fn target_path_for_os(&self, os: &str) -> PathBuf {
+        let target = self.targets.get(os).unwrap_or(&self.target);
+        PathBuf::from(shellexpand::tilde(&target.to_string_lossy()).as_ref())
+    }
+
+    /// Return the target path of this dotfile on the current OS, with `~` expanded
+    pub fn target_path(&self) -> PathBuf {
+        self.target_path_for_os(OS)
+    }
+
+    /// Return the shell to run the install steps of this dotfile with
+    fn shell(&self) -> &str {
+        self.shell.as_deref().unwrap_or(DEFAULT_SHELL)
+    }
+
+    /// Return how long each install step of this dotfile may run for, if limited
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+
+    /// Return the contents this dotfile would be installed with, decrypting it if `encrypted` is
+    /// set, rendering it as a template if `template` is set and then piping it through
+    /// `install_filter`
+    fn read_source(&self, source_path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.render_source(fs::read(source_path)?)
+    }
+
+    /// Decrypt and render `contents`, the contents of `file` in the repository, as
+    /// [Dotfile::read_source] does
+    fn render_source(&self, mut contents: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+        if let Some(Encryption::Age) = self.encrypted {
+            contents = decrypt(&contents, &self.age_identity())
+                .map_err(|err| format!("{} ({})", err, self.file))?;
+        }
+
+        if self.template {
+            let template = String::from_utf8(contents)
+                .map_err(|_| format!("Template {} is not valid UTF-8", self.file))?;
+            let rendered = render_template(&template, &template_variables()?)
+                .map_err(|err| format!("{} in {}", err, self.file))?;
+            contents = rendered.into_bytes();
+        }
+
+        if self.expand_env {
+            let unexpanded = String::from_utf8(contents)
+                .map_err(|_| format!("{} is not valid UTF-8, so can't expand_env", self.file))?;
+            contents = expand_env_vars(&unexpanded)
+                .map_err(|err| format!("{} in {}", err, self.file))?
+                .into_bytes();
+        }
+
+        if let Some(install_filter) = &self.install_filter {
+            self.ensure_filters_trusted("install_filter")?;
+            contents = run_filters(install_filter, self.shell(), &self.filter_env(), contents)
+                .map_err(|err| format!("{} ({})", err, self.file))?;
+        }
+
+        Ok(contents)
+    }
+
+    /// Return whether this dotfile is installed exactly as it is stored in the repository
+    fn installed_as_is(&self) -> bool {
+        !self.template
+            && !self.expand_env
+            && self.encrypted.is_none()
+            && self.install_filter.is_none()
+    }
+
+    /// Return how this dotfile is stored in the repository if only jtd can install it from there,
+    /// e.g. `a template`
+    pub fn rendered_form(&self) -> Option<&'static str> {
+        if self.template {
+            Some("a template")
+        } else if self.expand_env {
+            Some("written with environment variable placeholders")
+        } else if self.encrypted.is_some() {
+            Some("encrypted")
+        } else {
+            None
+        }
+    }
+
+    /// Return an error rather than run the filter `filter_name` of this dotfile if its commands
+    /// haven't been trusted
+    fn ensure_filters_trusted(&self, filter_name: &str) -> Result<(), Box<dyn Error>> {
+        if self.commands_trusted {
+            return Ok(());
+        }
+        Err(format!(
+            "The {} of {} isn't trusted, pass \"--trust\" to run it",
+            filter_name, self.file
+        )
+        .into())
+    }
+
+    /// Return the environment variables to run the `sync_filter` and `install_filter` of this
+    /// dotfile with
+    fn filter_env(&self) -> HashMap<String, String> {
+        let mut env = HashMap::from([(
+            "JTD_TARGET_PATH".to_string(),
+            self.target_path().to_string_lossy().to_string(),
+        )]);
+        env.extend(self.env.clone());
+        env
+    }
+
+    /// Return the contents the local copy of this dotfile at `local_path` would be committed with
+    /// when syncing, before being encrypted, i.e. piped through `sync_filter`
+    fn read_local(&self, local_path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        let contents = fs::read(local_path)?;
+        match &self.sync_filter {
+            Some(sync_filter) => {
+                self.ensure_filters_trusted("sync_filter")?;
+                run_filters(sync_filter, self.shell(), &self.filter_env(), contents)
+                    .map_err(|err| format!("{} ({})", err, self.file).into())
+            }
+            None => Ok(contents),
+        }
+    }
+
+    /// Return the contents of this dotfile in the repository (rendered and decrypted as it would be
+    /// on install) and the contents of the local copy. Either is empty if the file doesn't exist
+    fn diff_contents(&self, repo: &Repository) -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+        let source_path = get_repo_dir(repo).join(&self.file);
+        let repo_contents = if source_path.exists() {
+            self.read_source(&source_path)?
+        } else {
+            vec![]
+        };
+        let target_path = self.target_path();
+        let local_contents = if target_path.exists() {
+            fs::read(&target_path)?
+        } else {
+            vec![]
+        };
+        Ok((repo_contents, local_contents))
+    }
+
+    /// Return a unified diff from the version of this dotfile in the repository to the copy
+    /// installed on this machine, or `None` if they are the same.
+    ///
+    /// If `as_patch` is set, both sides of the diff are labelled with the path of the file in the
+    /// repository so that the diff can be applied to it with `git apply`. A side that doesn't exist
+    /// is labelled `/dev/null`. If either side is binary, the diff only says that the files
+    /// differ, as git does
+    pub(crate) fn diff(
+        &self,
+        repo: &Repository,
+        as_patch: bool,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let (repo_contents, local_contents) = self.diff_contents(repo)?;
+        let repo_exists = get_repo_dir(repo).join(&self.file).exists();
+        let local_exists = self.target_path().exists();
+        let target_path = if as_patch {
+            PathBuf::from(&self.file)
+        } else {
+            self.target_path()
+        };
+
+        if repo_contents == local_contents && repo_exists == local_exists {
+            return Ok(None);
+        }
+        let diff = if is_binary(&repo_contents) || is_binary(&local_contents) {
+            format!(
+                "diff --git a/{file} b/{target}\nBinary files a/{file} and b/{target} differ\n",
+                file = self.file,
+                target = target_path.to_string_lossy()
+            )
+        } else {
+            let mut patch = Patch::from_buffers(
+                &repo_contents,
+                Some(Path::new(&self.file)),
+                &local_contents,
+                Some(&target_path),
+                Some(&mut DiffOptions::new()),
+            )?;
+            String::from_utf8_lossy(&patch.to_buf()?).to_string()
+        };
+        Ok(Some(label_missing_file(&diff, repo_exists, local_exists)))
+    }
+
+    /// Show the differences between the repository and local versions of this dotfile with an
+    /// external diff tool such as `delta` or `difftastic`, returning whether they differ. The tool
+    /// is run through the shell with the path of the repository version and then the local
+    /// version appended, so it may include arguments
+    pub(crate) fn diff_with_tool(
+        &self,
+        repo: &Repository,
+        dotfile_name: &str,
+        tool: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let (repo_contents, local_contents) = self.diff_contents(repo)?;
+        let target_path = self.target_path();
+
+        if repo_contents == local_contents {
+            return Ok(false);
+        }
+
+        // Keep the file name so that tools can pick syntax highlighting from the extension
+        let source_dir = tempfile::tempdir()?;
+        let source_path = source_dir.path().join(
+            Path::new(&self.file)
+                .file_name()
+                .ok_or_else(|| format!("Invalid file name {}", self.file))?,
+        );
+        fs::write(&source_path, repo_contents)?;
+        let local_path = if target_path.exists() {
+            target_path
+        } else {
+            PathBuf::from("/dev/null")
+        };
+
+        print_log(
+            style(format!(
+                "{} ({})",
+                dotfile_name,
+                self.target_path().to_string_lossy()
+            ))
+            .bold(),
+        );
+        let tool_command = match tool {
+            "difftastic" => "difft",
+            tool => tool,
+        };
+        let status = Command::new(self.shell())
+            .arg("-c")
+            .arg(format!("{} \"$@\"", tool_command))
+            .arg(tool_command)
+            .arg(&source_path)
+            .arg(&local_path)
+            .status()
+            .map_err(|err| format!("Could not run {}: {}", self.shell(), err))?;
+
+        // Diff tools commonly exit with 1 when the files differ, so only treat a missing command
+        // as an error
+        if status.code() == Some(127) {
+            return Err(format!("Could not run diff tool \"{}\"", tool).into());
+        }
+        Ok(true)
+    }
+
+    /// Describe this dotfile for the dotfile picker. The preview is the diff against the local copy
+    /// if there is one, or the contents of the dotfile in the repository otherwise
+    pub(crate) fn picker_entry(
+        &self,
+        dotfile_name: &str,
+        repo: &Repository,
+        installed: bool,
+    ) -> PickerEntry {
+        let hooks = [
+            ("pre_install", &self.pre_install),
+            ("post_install", &self.post_install),
+            ("pre_uninstall", &self.pre_uninstall),
+            ("post_uninstall", &self.post_uninstall),
+        ]
+        .iter()
+        .filter(|(_, steps)| steps.as_ref().is_some_and(|steps| !steps.is_empty()))
+        .map(|(name, _)| *name)
+        .collect();
+
+        let preview = if self.encrypted.is_some() {
+            // Decrypting it could require a passphrase
+            Preview::Unavailable("This dotfile is encrypted in the repository".to_string())
+        } else if self.target_path().exists() {
+            match self.diff(repo, false) {
+                Ok(Some(diff)) => Preview::Diff(diff),
+                Ok(None) => Preview::Unavailable(
+                    "The local copy is the same as the version in the repository".to_string(),
+                ),
+                Err(err) => Preview::Unavailable(format!("Could not diff this dotfile: {}", err)),
+            }
+        } else {
+            match self.read_source(&get_repo_dir(repo).join(&self.file)) {
+                Ok(contents) if is_binary(&contents) => {
+                    Preview::Unavailable("This dotfile is a binary file".to_string())
+                }
+                Ok(contents) => Preview::Contents(String::from_utf8_lossy(&contents).to_string()),
+                Err(err) => Preview::Unavailable(format!("Could not read this dotfile: {}", err)),
+            }
+        };
+
+        PickerEntry {
+            name: dotfile_name.to_string(),
+            target: self.target_path(),
+            hooks,
+            preview,
+            installed,
+        }
+    }
+
+    /// Copy the local copy of this dotfile into the repository, piping it through `sync_filter`
+    /// and then encrypting it if `encrypted` is set. If `pick_hunks` is set, only the hunks of the
+    /// changes the user picks are copied. Returns false, without copying anything, if there are
+    /// no changes left to copy
+    fn write_to_repo(
+        &self,
+        local_path: &Path,
+        repo_path: &Path,
+        config: &Config,
+        pick_hunks: bool,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut contents = self.read_local(local_path)?;
+        if pick_hunks {
+            let repo_contents = match (fs::read(repo_path), self.encrypted) {
+                (Ok(repo_contents), Some(Encryption::Age)) => {
+                    decrypt(&repo_contents, &self.age_identity())
+                        .map_err(|err| format!("{} ({})", err, self.file))?
+                }
+                (Ok(repo_contents), None) => repo_contents,
+                (Err(_), _) => vec![],
+            };
+            contents = self::pick_hunks(&self.file, &repo_contents, &contents)?;
+            if contents == repo_contents {
+                return Ok(false);
+            }
+        }
+        match self.encrypted {
+            Some(Encryption::Age) => fs::write(
+                repo_path,
+                encrypt(&contents, &config.age_recipients, &self.age_identity())?,
+            )?,
+            None => fs::write(repo_path, contents)?,
+        }
+        Ok(true)
+    }
+
+    /// Commit the local copy of this dotfile on top of `parent_commit`, which must be checked out,
+    /// on a new branch named `branch_name`. Returns the new commit, or None if no changes were
+    /// picked
+    #[allow(clippy::too_many_arguments)]
+    fn commit_on_parent(
+        &self,
+        repo: &Repository,
+        dotfile_name: &str,
+        parent_commit: &Commit,
+        branch_name: &str,
+        local_path: &Path,
+        repo_path: &Path,
+        config: &Config,
+        pick_hunks: bool,
+    ) -> Result<Option<Oid>, Box<dyn Error>> {
+        if !self.write_to_repo(local_path, repo_path, config, pick_hunks)? {
+            return Ok(None);
+        }
+
+        repo.branch(branch_name, parent_commit, true)?;
+        checkout_ref(repo, branch_name)?;
+        add_and_commit(
+            repo,
+            Some(vec![Path::new(&self.file)]),
+            &config.generate_commit_message(vec![dotfile_name]),
+            Some(vec![parent_commit]),
+            Some("HEAD"),
+        )?;
+        Ok(Some(get_head(repo)?.id()))
+    }
+
+    /// Apply `mode` to the installed target, if set
+    #[cfg(unix)]
+    fn apply_mode(&self, target_path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(mode) = self.mode {
+            fs::set_permissions(target_path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply_mode(&self, _target_path: &Path) -> Result<(), Box<dyn Error>> {
+        if self.mode.is_some() {
+            warn!(
+                "Ignoring mode of {} as modes are only supported on unix",
+                &self.file
+            );
+        }
+        Ok(())
+    }
+
+    /// Return the actual permissions of the installed target if `mode` is set and they differ
+    /// from it
+    #[cfg(unix)]
+    pub fn mode_mismatch(&self) -> Result<Option<u32>, Box<dyn Error>> {
+        if let Some(mode) = self.mode {
+            let actual_mode = fs::metadata(self.target_path())?.permissions().mode() & 0o7777;
+            if actual_mode != mode {
+                return Ok(Some(actual_mode));
+            }
+        }
+        Ok(None)
+    }
+
+    #[cfg(not(unix))]
+    pub fn mode_mismatch(&self) -> Result<Option<u32>, Box<dyn Error>> {
+        Ok(None)
+    }
+
     fn hash_pre_install(&self) -> String {
         if let Some(pre_install) = &self.pre_install {
             hash_command_vec(pre_install)
@@ -41,24 +873,205 @@ impl Dotfile {
         }
     }
 
+    /// Return whether `hash`, as recorded in the metadata of this dotfile, is the hash of its
+    /// pre-install steps
+    fn pre_install_matches(&self, hash: &str) -> bool {
+        match &self.pre_install {
+            Some(pre_install) => command_vec_matches_hash(pre_install, hash),
+            None => hash.is_empty(),
+        }
+    }
+
+    /// Return whether `hash`, as recorded in the metadata of this dotfile, is the hash of its
+    /// post-install steps
+    fn post_install_matches(&self, hash: &str) -> bool {
+        match &self.post_install {
+            Some(post_install) => command_vec_matches_hash(post_install, hash),
+            None => hash.is_empty(),
+        }
+    }
+
+    /// Return the hashes of the pre-install and post-install steps, which are empty for steps that
+    /// don't exist
+    pub(crate) fn install_step_hashes(&self) -> Vec<String> {
+        vec![self.hash_pre_install(), self.hash_post_install()]
+    }
+
+    /// Return both the pre-install and post-install steps, in order
+    fn install_steps(&self) -> Vec<String> {
+        self.pre_install
+            .iter()
+            .chain(self.post_install.iter())
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Return a hash of both the pre-install and post-install steps, used to remember which steps
+    /// the user declined to run
+    pub(crate) fn hash_install_steps(&self) -> String {
+        hash_command_vec(&self.install_steps())
+    }
+
+    /// Return whether `hash` is the hash of both the pre-install and post-install steps as given by
+    /// [Dotfile::hash_install_steps], or as recorded by older versions of jtd
+    pub(crate) fn install_steps_match(&self, hash: &str) -> bool {
+        command_vec_matches_hash(&self.install_steps(), hash)
+    }
+
+    /// Return the hashes of the commands of this dotfile that run on every install and sync, i.e.
+    /// the `only_if` conditions, `sync_filter` and `install_filter`, which are empty for commands
+    /// that don't exist
+    pub(crate) fn command_hashes(&self) -> Vec<String> {
+        [&self.only_if, &self.sync_filter, &self.install_filter]
+            .iter()
+            .map(|commands| {
+                commands
+                    .as_ref()
+                    .map_or_else(String::new, |commands| hash_command_vec(commands))
+            })
+            .collect()
+    }
+
+    /// Return whether this dotfile has `only_if` conditions or filters that haven't been trusted,
+    /// so must not be run
+    pub(crate) fn has_untrusted_commands(&self) -> bool {
+        !self.commands_trusted
+            && (self.only_if.is_some()
+                || self.sync_filter.is_some()
+                || self.install_filter.is_some())
+    }
+
+    /// Return whether this dotfile has a `sync_filter` or `install_filter` that hasn't been trusted,
+    /// so can't be diffed
+    pub(crate) fn has_untrusted_filters(&self) -> bool {
+        !self.commands_trusted && (self.sync_filter.is_some() || self.install_filter.is_some())
+    }
+
+    /// Describe the commands of this dotfile that [Dotfile::command_hashes] covers, e.g.
+    /// `only_if conditions and install_filter`
+    pub(crate) fn described_commands(&self) -> String {
+        let names: Vec<&str> = [
+            ("only_if conditions", &self.only_if),
+            ("sync_filter", &self.sync_filter),
+            ("install_filter", &self.install_filter),
+        ]
+        .iter()
+        .filter(|(_, commands)| commands.is_some())
+        .map(|(name, _)| *name)
+        .collect();
+        match names.split_last() {
+            Some((last, [])) => last.to_string(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+            None => "commands".to_string(),
+        }
+    }
+
+    /// Return the path of the age identity this dotfile is decrypted with
+    fn age_identity(&self) -> PathBuf {
+        self.age_identity.clone().unwrap_or_else(identity_path)
+    }
+
+    /// Return a copy of this dotfile whose `only_if` conditions and filters may be run
+    pub(crate) fn trusted(&self) -> Dotfile {
+        Dotfile {
+            commands_trusted: true,
+            ..self.clone()
+        }
+    }
+
+    /// Return whether this dotfile may be written to its target, asking first if it
+    /// `requires_root`. The prompt shows the absolute path the file would really be written to, as
+    /// the manifest chooses the target. Root writes are allowed without asking if `trust` is set
+    /// (`--trust`), and refused when running non-interactively without it
+    pub(crate) fn confirm_root_write(
+        &self,
+        dotfile_name: &str,
+        trust: bool,
+    ) -> Result<bool, Box<dyn Error>> {
+        if !self.requires_root || trust {
+            return Ok(true);
+        }
+        let target_path = resolved_path(&self.target_path());
+        if is_non_interactive() {
+            info!(
+                "Running non-interactively, skipping {} as it is written to {} as root. Pass \
+                \"--trust\" to write it",
+                dotfile_name,
+                target_path.to_string_lossy()
+            );
+            return Ok(false);
+        }
+        let write = prompter().confirm(
+            &format!(
+                "{} is written to {} as root with \"{}\", continue?",
+                dotfile_name,
+                target_path.to_string_lossy(),
+                root_command()
+            ),
+            false,
+        )?;
+        if !write {
+            info!("Skipping {}", dotfile_name);
+        }
+        Ok(write)
+    }
+
     /// Return whether this dotfile has run stages, i.e. pre_install or post_install is not `None`
     /// and the hash of the pre/post install stages are different to the one in the metadata
     pub fn has_unexecuted_run_stages(&self, maybe_metadata: &Option<&DotfileMetadata>) -> bool {
         if let Some(metadata) = maybe_metadata {
             // If metadata is available, don't return true if the steps have already
             // been executed
-            (self.pre_install.is_some() && metadata.pre_install_hash != self.hash_pre_install())
+            (self.pre_install.is_some() && !self.pre_install_matches(&metadata.pre_install_hash))
                 || (self.post_install.is_some()
-                    && metadata.post_install_hash != self.hash_post_install())
+                    && !self.post_install_matches(&metadata.post_install_hash))
         } else {
             // Otherwise just depend on the presence of the steps
             self.pre_install.is_some() || self.post_install.is_some()
         }
     }
 
+    /// Return the environment variables to run the install steps of this dotfile with
+    fn hook_env(&self, dotfile_name: &str, repo_dir: &Path) -> HashMap<String, String> {
+        let mut env = HashMap::from([
+            ("JTD_DOTFILE_NAME".to_string(), dotfile_name.to_string()),
+            (
+                "JTD_TARGET_PATH".to_string(),
+                self.target_path().to_string_lossy().to_string(),
+            ),
+            (
+                "JTD_REPO_DIR".to_string(),
+                repo_dir.to_string_lossy().to_string(),
+            ),
+        ]);
+        env.extend(self.env.clone());
+        env
+    }
+
+    /// Return whether every `only_if` condition of this dotfile succeeds on this machine.
+    /// Conditions that haven't been trusted are never run and count as unmet
+    pub(crate) fn conditions_met(
+        &self,
+        dotfile_name: &str,
+        repo_dir: &Path,
+    ) -> Result<bool, Box<dyn Error>> {
+        match &self.only_if {
+            Some(_) if !self.commands_trusted => Ok(false),
+            Some(conditions) => command_vec_succeeds(
+                conditions,
+                self.shell(),
+                &self.hook_env(dotfile_name, repo_dir),
+            ),
+            None => Ok(true),
+        }
+    }
+
     fn run_pre_install(
         &self,
         metadata: &Option<DotfileMetadata>,
+        env: &HashMap<String, String>,
+        dry_run: bool,
     ) -> Result<String, Box<dyn Error>> {
         let mut hash = String::new();
 
@@ -66,16 +1079,22 @@ impl Dotfile {
             let mut skip_pre_install = false;
 
             if let Some(metadata) = metadata {
-                if self.hash_pre_install() == metadata.pre_install_hash {
+                if self.pre_install_matches(&metadata.pre_install_hash) {
                     info!("{}", style("Skipping pre install steps as they have been run in a previous install").blue());
                     skip_pre_install = true;
                 }
             }
+            // Recorded even if the steps are skipped, so that they are still skipped next time
+            hash = self.hash_pre_install();
 
             if !skip_pre_install {
-                success!("Running pre-install steps");
-                run_command_vec(pre_install)?;
-                hash = self.hash_pre_install();
+                if dry_run {
+                    info!("Would run pre-install steps:");
+                    print_command_vec(pre_install);
+                } else {
+                    success!("Running pre-install steps");
+                    run_command_vec(pre_install, self.shell(), env, self.timeout())?;
+                }
             }
         }
         Ok(hash)
@@ -84,6 +1103,8 @@ impl Dotfile {
     fn run_post_install(
         &self,
         metadata: &Option<DotfileMetadata>,
+        env: &HashMap<String, String>,
+        dry_run: bool,
     ) -> Result<String, Box<dyn Error>> {
         let mut hash = String::new();
 
@@ -91,52 +1112,259 @@ impl Dotfile {
             let mut skip_post_install = false;
 
             if let Some(metadata) = metadata {
-                if self.hash_post_install() == metadata.post_install_hash {
+                if self.post_install_matches(&metadata.post_install_hash) {
                     success!(
                         "Skipping post install steps as they have been run in a previous install"
                     );
                     skip_post_install = true;
                 }
             }
+            hash = self.hash_post_install();
 
             if !skip_post_install {
-                success!("Running post-install steps");
-                run_command_vec(post_install)?;
-                hash = self.hash_post_install();
+                if dry_run {
+                    info!("Would run post-install steps:");
+                    print_command_vec(post_install);
+                } else {
+                    success!("Running post-install steps");
+                    run_command_vec(post_install, self.shell(), env, self.timeout())?;
+                }
             }
         }
         Ok(hash)
     }
 
-    fn install_dotfile(&self, repo_dir: &Path) -> Result<(), Box<dyn Error>> {
-        let mut origin_path = repo_dir.to_path_buf();
-        origin_path.push(&self.file);
-
-        let unexpanded_target_path = &self.target.to_string_lossy();
-
-        let target_path_str = shellexpand::tilde(unexpanded_target_path);
-
-        let target_path = Path::new(target_path_str.as_ref());
-
-        if let Some(parent) = target_path.parent() {
-            fs::create_dir_all(parent)
-                .map_err(|_| "Unable to create parent directories".to_string())?;
+    /// Run the `reload` actions of this dotfile. Applications that aren't running are skipped, and
+    /// failing to reload one is only a warning as the dotfile has already been installed
+    fn run_reload(
+        &self,
+        env: &HashMap<String, String>,
+        dry_run: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        for action in &self.reload {
+            if dry_run {
+                info!("Would reload {}", action.name());
+                continue;
+            }
+            if !command_vec_succeeds(&[action.running_check().to_string()], DEFAULT_SHELL, env)? {
+                debug!("Not reloading {} as it isn't running", action.name());
+                continue;
+            }
+            if command_vec_succeeds(&[action.command().to_string()], DEFAULT_SHELL, env)? {
+                success!("Reloaded {}", action.name());
+            } else {
+                warn!(
+                    "Could not reload {}, restart it to pick up the changes",
+                    action.name()
+                );
+            }
         }
-        fs::copy(origin_path, target_path).expect("Failed to copy target file");
-
-        success!(
-            "Installed config file {} to location {}",
-            &self.file,
-            target_path.to_str().expect("Invalid unicode in path")
-        );
-
         Ok(())
     }
 
+    /// Copy the existing target of this dotfile into `backups_dir/<dotfile_name>/<timestamp>` if it
+    /// differs from `new_contents`, returning the path of the backup if one was made
+    fn backup_target(
+        &self,
+        dotfile_name: &str,
+        backups_dir: &Path,
+        new_contents: &[u8],
+    ) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        let target_path = self.target_path();
+        match fs::read(&target_path) {
+            Ok(contents) if contents != new_contents => {
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+                let backup_path = backups_dir.join(dotfile_name).join(timestamp.to_string());
+
+                fs::create_dir_all(backup_path.parent().unwrap())?;
+                fs::copy(&target_path, &backup_path)?;
+                Ok(Some(backup_path))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Return the path to keep a copy of the target at when installing overwrites it, if
+    /// `backup_suffix` is set
+    fn keep_backup_path(&self) -> Option<PathBuf> {
+        let suffix = self.backup_suffix.as_ref()?;
+        let mut keep_path = self.target_path().into_os_string();
+        keep_path.push(suffix);
+        Some(PathBuf::from(keep_path))
+    }
+
+    /// Copy this dotfile from the repository to its target, returning the path of the backup of
+    /// the file it replaced, if any. If `merge_base` is given, the existing target is three-way
+    /// merged with the version in the repository instead, with `merge_base` as the version both
+    /// started out as
+    fn install_dotfile(
+        &self,
+        dotfile_name: &str,
+        repo: &Repository,
+        merge_base: Option<&[u8]>,
+        dry_run: bool,
+    ) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        let repo_dir = get_repo_dir(repo);
+        let mut origin_path = repo_dir.to_path_buf();
+        origin_path.push(&self.file);
+
+        let target_path_buf = self.target_path();
+        let target_path = target_path_buf.as_path();
+
+        // Files tracked with Git LFS are checked out as pointers, as libgit2 doesn't support LFS
+        let is_lfs_pointer = fs::read(&origin_path)
+            .map(|contents| LfsPointer::parse(&contents).is_some())
+            .unwrap_or(false);
+
+        if dry_run {
+            if is_lfs_pointer {
+                info!("Would fetch {} from Git LFS", &self.file);
+            }
+            if target_path.exists() {
+                info!(
+                    "Would back up the existing {} to {}",
+                    target_path.to_string_lossy(),
+                    backups_dir().join(dotfile_name).to_string_lossy()
+                );
+            }
+            if let Some(keep_path) = self.keep_backup_path().filter(|_| target_path.exists()) {
+                info!(
+                    "Would keep the existing {} as {}",
+                    target_path.to_string_lossy(),
+                    keep_path.to_string_lossy()
+                );
+            }
+            info!(
+                "Would {} config file {} to location {}{}",
+                if merge_base.is_some() && target_path.exists() {
+                    "merge"
+                } else if self.template || self.expand_env {
+                    "render"
+                } else {
+                    "install"
+                },
+                &self.file,
+                target_path.to_string_lossy(),
+                if self.requires_root { " as root" } else { "" }
+            );
+            if let Some(mode) = self.mode {
+                info!(
+                    "Would set the mode of {} to {:04o}",
+                    target_path.to_string_lossy(),
+                    mode
+                );
+            }
+            return Ok(None);
+        }
+
+        if is_lfs_pointer {
+            smudge_lfs_file(repo_dir, &self.file)?;
+        }
+
+        let mut contents = self.read_source(&origin_path)?;
+        if let (Some(merge_base), Ok(local_contents)) = (merge_base, fs::read(target_path)) {
+            let file_name = Path::new(&self.file)
+                .file_name()
+                .map_or(self.file.clone(), |name| name.to_string_lossy().to_string());
+            let merged = merge_file_contents(
+                repo,
+                &file_name,
+                self.merge,
+                merge_base,
+                &local_contents,
+                &contents,
+            )?;
+            if merged.conflicted {
+                // Conflict markers would break the live file, so leave it alone
+                let merge_path = PathBuf::from(format!(
+                    "{}{}",
+                    target_path.to_string_lossy(),
+                    CONFLICTED_MERGE_SUFFIX
+                ));
+                if self.requires_root {
+                    write_as_root(&root_command(), &merge_path, &merged.contents, None)?;
+                } else {
+                    // The merge contains the local file, so is only as readable as it
+                    let permissions = fs::metadata(target_path)?.permissions();
+                    write_atomically(&merge_path, &merged.contents, Some(permissions))?;
+                }
+                return Err(format!(
+                    "The local changes to {} conflict with the repository. It was left as it is, \
+                    and the merge was written to {} for you to resolve the conflict markers in and \
+                    move into place",
+                    target_path.to_string_lossy(),
+                    merge_path.to_string_lossy()
+                )
+                .into());
+            }
+            success!(
+                "Merged the local changes to {} with the repository",
+                target_path.to_string_lossy()
+            );
+            contents = merged.contents;
+        }
+
+        let backup_path = self.backup_target(dotfile_name, &backups_dir(), &contents)?;
+        if let Some(ref backup_path) = backup_path {
+            info!(
+                "Backed up the existing {} to {}",
+                target_path.to_string_lossy(),
+                backup_path.to_string_lossy()
+            );
+            if let Some(keep_path) = self.keep_backup_path() {
+                if self.requires_root {
+                    copy_as_root(&root_command(), target_path, &keep_path)?;
+                } else {
+                    fs::copy(target_path, &keep_path)?;
+                }
+                info!(
+                    "Kept the existing {} as {}",
+                    target_path.to_string_lossy(),
+                    keep_path.to_string_lossy()
+                );
+            }
+        }
+
+        if self.requires_root {
+            info!("Writing {} as root", target_path.to_string_lossy());
+            write_as_root(&root_command(), target_path, &contents, self.mode)?;
+        } else {
+            if let Some(parent) = target_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|_| "Unable to create parent directories".to_string())?;
+            }
+            // Files installed as they are keep the permissions they have in the repository (e.g. for
+            // scripts), while rendered files keep those of the file they replace
+            let permissions = if self.installed_as_is() {
+                Some(fs::metadata(&origin_path)?.permissions())
+            } else {
+                fs::metadata(target_path)
+                    .ok()
+                    .map(|metadata| metadata.permissions())
+            };
+            // The file is written with its mode rather than given it afterwards, so that it is
+            // never readable by others in between
+            #[cfg(unix)]
+            let permissions = self.mode.map(fs::Permissions::from_mode).or(permissions);
+            write_atomically(target_path, &contents, permissions)?;
+            self.apply_mode(target_path)?;
+        }
+
+        success!(
+            "Installed config file {} to location {}",
+            &self.file,
+            target_path.to_str().expect("Invalid unicode in path")
+        );
+
+        Ok(backup_path)
+    }
+
     /// Return whether this dotfile has changed since it was last synchronised
     ///
-    /// This is performed by loading the current dotfile on the system, loading the dotfile as of
-    /// the specified commit and comparing them byte by byte.
+    /// This is performed by hashing the current dotfile on the system and comparing it to the hash
+    /// of the blob of the dotfile in the tree of the specified commit, so nothing is checked out.
+    /// Encrypted dotfiles are decrypted and templated dotfiles are rendered from the blob before
+    /// being compared.
     ///
     /// # Arguments
     ///
@@ -152,139 +1380,531 @@ impl Dotfile {
         repo: &Repository,
         metadata: &DotfileMetadata,
     ) -> Result<bool, Box<dyn Error>> {
-        let head_ref = repo.head()?;
-        let head_ref_name = head_ref.name().unwrap();
-
-        let unexpanded_target_path = &self.target.to_string_lossy();
-        let local_dotfile_path = shellexpand::tilde(unexpanded_target_path).to_string();
-        let dotfile_contents = fs::read_to_string(local_dotfile_path)?;
-        let local_dotfile_hash = Sha1::digest(dotfile_contents.as_bytes());
-
-        checkout_ref(&repo, &metadata.commit_hash)?;
+        let local_contents = fs::read(self.target_path())?;
+
+        ensure_ref_available(repo, &metadata.commit_hash)?;
+        let commit = get_commit(repo, &metadata.commit_hash)?;
+        let (blob_id, contents) = find_file_in_tree(repo, &commit.tree()?, Path::new(&self.file))?
+            .ok_or_else(|| {
+                format!(
+                    "{} does not exist in commit {}",
+                    self.file, metadata.commit_hash
+                )
+            })?;
+
+        if let Some(pointer) = LfsPointer::parse(&contents) {
+            return Ok(!pointer.matches(&local_contents));
+        }
+        if self.sync_filter.is_some() {
+            // Compare what syncing would commit with what was committed
+            let committed = match self.encrypted {
+                Some(Encryption::Age) => decrypt(&contents, &self.age_identity())?,
+                None => contents,
+            };
+            return Ok(self.read_local(&self.target_path())? != committed);
+        }
+        if self.installed_as_is() {
+            // The file is installed as is, so its blob hash can be compared
+            return Ok(Oid::hash_object(ObjectType::Blob, &local_contents)? != blob_id);
+        }
 
-        let repo_dir = get_repo_dir(&repo);
-        let repo_dotfile_path = &repo_dir.join(&self.file);
-        let dotfile_contents = fs::read_to_string(repo_dotfile_path)?;
-        let repo_dotfile_hash = Sha1::digest(dotfile_contents.as_bytes());
+        let repo_contents = self.render_source(contents)?;
+        Ok(Sha256::digest(&local_contents) != Sha256::digest(&repo_contents))
+    }
 
-        if local_dotfile_hash != repo_dotfile_hash {
-            checkout_ref(&repo, &head_ref_name)?;
+    /// Return whether this dotfile should be reinstalled, i.e. its file differs between the commit
+    /// it was installed from and the commit it would be installed from now (`HEAD`, or `ref` if
+    /// pinned), or it has install steps that haven't been run
+    pub(crate) fn needs_update(
+        &self,
+        repo: &Repository,
+        metadata: &DotfileMetadata,
+    ) -> Result<bool, Box<dyn Error>> {
+        if self.has_unexecuted_run_stages(&Some(metadata)) {
             return Ok(true);
-        } else {
-            checkout_ref(&repo, &head_ref_name)?;
-            Ok(false)
         }
+        self.changed_in_repo(repo, metadata)
+    }
+
+    /// Return the commit this dotfile would be installed from now: `HEAD`, or `ref` if pinned
+    fn target_commit<'a>(&self, repo: &'a Repository) -> Result<Commit<'a>, Box<dyn Error>> {
+        match &self.git_ref {
+            Some(git_ref) => {
+                ensure_ref_available(repo, git_ref)?;
+                Ok(get_commit(repo, &resolve_ref(repo, git_ref)?)?)
+            }
+            None => Ok(get_head(repo)?),
+        }
+    }
+
+    /// Return whether the file of this dotfile differs between the commit it was installed from
+    /// according to `metadata` and the commit it would be installed from now (`HEAD`, or `ref` if
+    /// pinned)
+    fn changed_in_repo(
+        &self,
+        repo: &Repository,
+        metadata: &DotfileMetadata,
+    ) -> Result<bool, Box<dyn Error>> {
+        let target_commit = self.target_commit(repo)?;
+        ensure_ref_available(repo, &metadata.commit_hash)?;
+        let installed_commit = match get_commit(repo, &metadata.commit_hash) {
+            Ok(commit) => commit,
+            Err(_) => return Ok(true),
+        };
+
+        let file_path = Path::new(&self.file);
+        let installed_blob =
+            find_file_in_tree(repo, &installed_commit.tree()?, file_path)?.map(|(id, _)| id);
+        let target_blob =
+            find_file_in_tree(repo, &target_commit.tree()?, file_path)?.map(|(id, _)| id);
+        Ok(installed_blob != target_blob)
+    }
+
+    /// Classify how this dotfile has changed since the commit it was last synced/installed from,
+    /// by comparing that commit with both the installed dotfile ([Dotfile::has_changed]) and the
+    /// dotfile it would be installed from now (at `HEAD`, or `ref` if pinned)
+    pub fn sync_state(
+        &self,
+        repo: &Repository,
+        metadata: &DotfileMetadata,
+    ) -> Result<SyncState, Box<dyn Error>> {
+        let local_changed = self.has_changed(repo, metadata)?;
+
+        let file_path = Path::new(&self.file);
+        let synced_blob = find_file_in_tree(
+            repo,
+            &get_commit(repo, &metadata.commit_hash)?.tree()?,
+            file_path,
+        )?
+        .map(|(id, _)| id);
+        let target_blob = find_file_in_tree(repo, &self.target_commit(repo)?.tree()?, file_path)?
+            .map(|(id, _)| id);
+        let remote_changed = synced_blob != target_blob;
+
+        Ok(match (local_changed, remote_changed) {
+            (false, false) => SyncState::UpToDate,
+            (true, false) => SyncState::LocalOnly,
+            (false, true) => SyncState::RemoteOnly,
+            (true, true) => SyncState::Diverged,
+        })
     }
 
     /// Install the dotfile to the specified location.
     ///
-    /// Refuse to do so if a local dotfile exists that has changes since the last sync, unless
-    /// `force` is true.
+    /// If a local dotfile exists that has changes since the last sync, refuse to do so unless
+    /// `mode` says otherwise. If the dotfile has also changed in the repository since, the changes
+    /// are three-way merged instead, with the version last synced as the base. If an existing local
+    /// dotfile is overwritten, it is first backed up into
+    /// the backups directory in the data directory and the path of the backup is recorded in the returned
+    /// metadata.
     ///
     /// # Arguments
     ///
     /// * `repo` - The repository object
+    /// * `dotfile_name` - The name of this dotfile in the manifest
     /// * `maybe_metadata` - Optionally, this dotfiles metadata. If not passed, a naive install will
-    /// be performed, meaning:
+    ///   be performed, meaning:
     ///   * No idempotency checks will be performed for pre/post steps
     ///   * No check can be made as to whether the dotfile has changed since last sync so it will
-    ///   be overwritten no matter what
+    ///     be overwritten no matter what
     /// * `skip_install_steps` - Whether to skip pre/post install steps
-    /// * `force` - Whether to force the install, even if the local dotfile has changed since the
-    /// last sync
+    /// * `mode` - Whether to refuse to install, overwrite or merge if the local dotfile has
+    ///   changed since the last sync
+    /// * `dry_run` - Whether to only print the file copies and install steps that would happen,
+    ///   without performing them
+    ///
+    /// If this dotfile is pinned to a `ref`, it is installed from that ref rather than `HEAD`.
     pub fn install(
         &self,
         repo: &Repository,
+        dotfile_name: &str,
         maybe_metadata: Option<DotfileMetadata>,
         skip_install_steps: bool,
-        force: bool,
+        mut mode: InstallMode,
+        dry_run: bool,
     ) -> Result<DotfileMetadata, Box<dyn Error>> {
-        let commit_hash = get_head_hash(&repo)?;
-        if !force {
+        // There are no local changes to lose if the dotfile isn't on this machine, e.g. because
+        // its metadata was imported from another one
+        if mode == InstallMode::Safe && self.target_path().exists() {
             if let Some(ref metadata) = maybe_metadata {
-                if self.has_changed(&repo, &metadata)? {
-                    return Err("Refusing to install dotfile. Changes have been made since last sync. \
-                            either run \"jtd sync\" for this dotfile or call install again with the \
-                            \"--force\" flag".into());
+                if self.has_changed(repo, metadata)? {
+                    if !self.changed_in_repo(repo, metadata)? {
+                        return Err("Refusing to install dotfile. Changes have been made since last sync. \
+                                either run \"jtd sync\" for this dotfile or call install again with the \
+                                \"--force\" flag".into());
+                    }
+                    info!(
+                        "{} has changed both locally and in the repository since it was last \
+                        synced, merging the changes",
+                        dotfile_name
+                    );
+                    mode = InstallMode::Merge;
                 }
             }
         }
 
+        if let Some(git_ref) = &self.git_ref {
+            let head_ref = repo.head()?;
+            let head_ref_name = head_ref.name().unwrap();
+
+            info!("Installing {} from {}", dotfile_name, git_ref);
+            ensure_ref_available(repo, git_ref)?;
+            checkout_ref(repo, &resolve_ref(repo, git_ref)?)?;
+            let result = self.install_from_head(
+                repo,
+                dotfile_name,
+                maybe_metadata,
+                skip_install_steps,
+                mode == InstallMode::Merge,
+                dry_run,
+            );
+            checkout_ref(repo, head_ref_name)?;
+            result
+        } else {
+            self.install_from_head(
+                repo,
+                dotfile_name,
+                maybe_metadata,
+                skip_install_steps,
+                mode == InstallMode::Merge,
+                dry_run,
+            )
+        }
+    }
+
+    fn install_from_head(
+        &self,
+        repo: &Repository,
+        dotfile_name: &str,
+        maybe_metadata: Option<DotfileMetadata>,
+        skip_install_steps: bool,
+        merge: bool,
+        dry_run: bool,
+    ) -> Result<DotfileMetadata, Box<dyn Error>> {
+        let commit_hash = get_head_hash(repo)?;
+        let repo_dir = get_repo_dir(repo);
+        let env = self.hook_env(dotfile_name, repo_dir);
+        let target_existed = self.target_path().exists();
+
         let pre_install_hash = if !skip_install_steps {
-            self.run_pre_install(&maybe_metadata)?
+            self.run_pre_install(&maybe_metadata, &env, dry_run)
+                .map_err(|err| format!("{}. The dotfile was not installed", err))?
         } else {
             String::new()
         };
 
-        let repo_dir = get_repo_dir(&repo);
-        self.install_dotfile(repo_dir)?;
+        let merge_base = if merge {
+            Some(self.installed_contents(repo, maybe_metadata.as_ref())?)
+        } else {
+            None
+        };
+        let backup_path =
+            self.install_dotfile(dotfile_name, repo, merge_base.as_deref(), dry_run)?;
 
         let post_install_hash = if !skip_install_steps {
-            self.run_post_install(&maybe_metadata)?
+            match self.run_post_install(&maybe_metadata, &env, dry_run) {
+                Ok(hash) => hash,
+                Err(err) => {
+                    self.undo_install(target_existed, backup_path.as_deref())?;
+                    return Err(format!("{}. The installed file has been rolled back", err).into());
+                }
+            }
         } else {
             String::new()
         };
 
-        let new_metadata = DotfileMetadata::new(&commit_hash, pre_install_hash, post_install_hash);
+        self.run_reload(&env, dry_run)?;
+
+        let mut new_metadata =
+            DotfileMetadata::new(&commit_hash, pre_install_hash, post_install_hash);
+        new_metadata.backup_path =
+            backup_path.or_else(|| maybe_metadata.and_then(|metadata| metadata.backup_path));
+        new_metadata.target_path = Some(self.target_path());
+        new_metadata.requires_root = self.requires_root;
+        new_metadata.pre_uninstall = self.pre_uninstall.clone().unwrap_or_default();
+        new_metadata.post_uninstall = self.post_uninstall.clone().unwrap_or_default();
+        new_metadata.shell = self.shell.clone();
+        new_metadata.env = self.env.clone();
+        new_metadata.timeout_secs = self.timeout_secs;
+
+        Ok(new_metadata)
+    }
+
+    /// Return the contents this dotfile was installed with according to `metadata`, as the base to
+    /// merge local changes to it with. Empty if they aren't known, e.g. as it was never installed
+    fn installed_contents(
+        &self,
+        repo: &Repository,
+        metadata: Option<&DotfileMetadata>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => return Ok(vec![]),
+        };
+        ensure_ref_available(repo, &metadata.commit_hash)?;
+        let commit = match get_commit(repo, &metadata.commit_hash) {
+            Ok(commit) => commit,
+            Err(_) => return Ok(vec![]),
+        };
+        match find_file_in_tree(repo, &commit.tree()?, Path::new(&self.file))? {
+            Some((_, contents)) if LfsPointer::parse(&contents).is_none() => {
+                self.render_source(contents)
+            }
+            _ => Ok(vec![]),
+        }
+    }
 
+    /// Install this dotfile as it was at `git_ref` in place of the installed version described by
+    /// `metadata`, without moving the branch of the repository. The install steps aren't run again
+    /// for an older version of the same dotfile. The returned metadata records `git_ref` so that
+    /// the restored version isn't overwritten by `jtd update`
+    pub fn restore(
+        &self,
+        repo: &Repository,
+        dotfile_name: &str,
+        metadata: DotfileMetadata,
+        git_ref: &str,
+        force: bool,
+        dry_run: bool,
+    ) -> Result<DotfileMetadata, Box<dyn Error>> {
+        let mut pinned = self.clone();
+        pinned.git_ref = Some(git_ref.to_string());
+
+        let mut new_metadata = pinned.install(
+            repo,
+            dotfile_name,
+            Some(metadata.clone()),
+            true,
+            if force {
+                InstallMode::Force
+            } else {
+                InstallMode::Safe
+            },
+            dry_run,
+        )?;
+        new_metadata.pre_install_hash = metadata.pre_install_hash;
+        new_metadata.post_install_hash = metadata.post_install_hash;
+        new_metadata.declined_steps_hash = metadata.declined_steps_hash;
+        new_metadata.restored_ref = Some(git_ref.to_string());
         Ok(new_metadata)
     }
 
+    /// Undo the copy made by [Dotfile::install_dotfile], restoring the file it replaced from
+    /// `backup_path` or removing it if there was no file at the target beforehand
+    fn undo_install(
+        &self,
+        target_existed: bool,
+        backup_path: Option<&Path>,
+    ) -> Result<(), Box<dyn Error>> {
+        let target_path = self.target_path();
+        if let Some(backup_path) = backup_path {
+            if self.requires_root {
+                write_as_root(&root_command(), &target_path, &fs::read(backup_path)?, None)?;
+            } else {
+                fs::copy(backup_path, &target_path)?;
+            }
+        } else if !target_existed && target_path.exists() {
+            if self.requires_root {
+                remove_as_root(&root_command(), &target_path)?;
+            } else {
+                fs::remove_file(&target_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return why local changes to this dotfile can't be synced back into the repository, if they
+    /// can't
+    fn unsyncable_reason(&self) -> Option<String> {
+        if let Some(git_ref) = &self.git_ref {
+            Some(format!(
+                "it is pinned to {}, syncing it would move it off the pin",
+                git_ref
+            ))
+        } else if self.template {
+            Some(format!(
+                "it is rendered from a template, make your changes to {} in the repository instead",
+                &self.file
+            ))
+        } else if self.expand_env {
+            Some(format!(
+                "its environment variables are expanded on install, make your changes to {} in the repository instead",
+                &self.file
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Return why this dotfile can't be synced if it is stored with Git LFS at `HEAD`, as jtd can
+    /// only commit its contents directly, which would replace the LFS pointer
+    fn lfs_reason(&self, repo: &Repository) -> Option<String> {
+        let (_, contents) = find_file_in_tree(
+            repo,
+            &get_head(repo).ok()?.tree().ok()?,
+            Path::new(&self.file),
+        )
+        .ok()??;
+        LfsPointer::parse(&contents)?;
+        Some(format!(
+            "it is stored with Git LFS, commit your changes to {} with git instead",
+            &self.file
+        ))
+    }
+
+    /// Sync the local copy of this dotfile back into the repository, committing the changes.
+    ///
+    /// If metadata is available, the changes are committed on top of the commit the dotfile was
+    /// last synced/installed from and merged into `HEAD`. Otherwise the dotfile in the repository
+    /// is simply overwritten.
+    ///
+    /// If `pick_hunks` is true, the user is shown each hunk of the changes and only the ones they
+    /// pick are synced, leaving the rest in the local copy only.
+    ///
+    /// If `dry_run` is true, the commit that would be created is printed and the repository is
+    /// left untouched.
+    ///
+    /// # Returns
+    ///
+    /// The updated metadata for this dotfile, along with whether it was synced or unchanged
     pub fn sync(
         &self,
         repo: &Repository,
         dotfile_name: &str,
         config: &Config,
         metadata: Option<&DotfileMetadata>,
-    ) -> Result<DotfileMetadata, Box<dyn Error>> {
-        let mut target_path_buf = get_repo_dir(&repo).to_owned();
+        pick_hunks: bool,
+        dry_run: bool,
+    ) -> Result<(DotfileMetadata, DotfileStatus), Box<dyn Error>> {
+        let mut target_path_buf = get_repo_dir(repo).to_owned();
         target_path_buf.push(&self.file);
         let target_path = target_path_buf.as_path();
 
-        let origin_path_unexpanded = &self.target.to_string_lossy();
-        let origin_path_str = shellexpand::tilde(origin_path_unexpanded);
-        let origin_path = Path::new(origin_path_str.as_ref());
+        let origin_path_buf = self.target_path();
+        let origin_path = origin_path_buf.as_path();
 
         if let Some(metadata) = metadata {
             let mut new_metadata = metadata.clone();
 
-            if self.has_changed(&repo, &metadata)? {
+            let has_changed = self.has_changed(repo, metadata)?;
+
+            let reason = self
+                .unsyncable_reason()
+                .or_else(|| {
+                    metadata.restored_ref.as_ref().map(|restored_ref| {
+                        format!(
+                            "it was restored to {}, syncing it would revert the repository",
+                            restored_ref
+                        )
+                    })
+                })
+                .or_else(|| {
+                    metadata.rolled_back.then(|| {
+                        "it was rolled back, syncing it would revert the repository".to_string()
+                    })
+                })
+                .or_else(|| self.lfs_reason(repo));
+            if let (true, Some(reason)) = (has_changed, reason) {
+                warn!("Skipping syncing {} as {}", dotfile_name, reason);
+                return Ok((new_metadata, DotfileStatus::Skipped));
+            } else if has_changed && dry_run {
+                info!(
+                    "Would commit {} to the repository on top of commit {} and merge it into HEAD",
+                    origin_path.to_string_lossy(),
+                    metadata.commit_hash
+                );
+            } else if has_changed {
                 let parent_commit = get_commit(repo, &metadata.commit_hash).map_err(
                     |_| format!("Could not find last sync'd commit for {}, manifest is corrupt. Try fresh-installing \
-                                this dotfile or manually correcting the commit hash in {}", dotfile_name, MANIFEST_PATH))?;
+                                this dotfile or manually correcting the commit hash in {}", dotfile_name, metadata_path().display()))?;
 
                 let head_ref = repo.head()?;
                 let head_ref_name = head_ref.name().unwrap();
                 let merge_target_commit = repo.reference_to_annotated_commit(&head_ref)?;
 
-                checkout_ref(&repo, &parent_commit.id().to_string())?;
-                fs::copy(origin_path, target_path)?;
-
+                // HEAD is put back and the branch deleted however committing went, so that a
+                // failing sync_filter doesn't leave the user's own clone on a detached HEAD
                 let new_branch_name = format!("merge-{}-dotfile", dotfile_name);
-                let _new_branch = repo.branch(&new_branch_name, &parent_commit, true)?;
-                checkout_ref(&repo, &new_branch_name)?;
-
-                let _new_commit = add_and_commit(
+                checkout_ref(repo, &parent_commit.id().to_string())?;
+                let result = self.commit_on_parent(
                     repo,
-                    Some(vec![Path::new(&self.file)]),
-                    &config.generate_commit_message(vec![dotfile_name]),
-                    Some(vec![&parent_commit]),
-                    Some("HEAD"),
-                )?;
-
-                let new_commit = repo.reference_to_annotated_commit(&repo.head()?)?;
-                checkout_ref(&repo, &head_ref_name)?;
+                    dotfile_name,
+                    &parent_commit,
+                    &new_branch_name,
+                    origin_path,
+                    target_path,
+                    config,
+                    pick_hunks,
+                );
+                checkout_ref(repo, head_ref_name)?;
+                if let Ok(mut branch) = repo.find_branch(&new_branch_name, BranchType::Local) {
+                    branch.delete()?;
+                }
 
-                let merge_commit = normal_merge(repo, &merge_target_commit, &new_commit)
-                    .map_err(|err| format!("Could not merge commits: {}", err))?;
+                let new_commit = match result? {
+                    Some(new_commit) => repo.find_annotated_commit(new_commit)?,
+                    None => {
+                        info!(
+                            "Skipping syncing {} as no changes were picked",
+                            dotfile_name
+                        );
+                        return Ok((new_metadata, DotfileStatus::Skipped));
+                    }
+                };
+
+                let merge_commit = normal_merge(
+                    repo,
+                    &merge_target_commit,
+                    &new_commit,
+                    config.merge_strategy.file_favor(false),
+                    &[(Path::new(&self.file), self.merge)],
+                )
+                .map_err(|err| format!("Could not merge commits: {}", err))?;
 
                 new_metadata.commit_hash = merge_commit.id().to_string();
             } else {
                 info!("Skipping syncing {} as no changes made", dotfile_name);
+                return Ok((new_metadata, DotfileStatus::Unchanged));
             }
-            Ok(new_metadata)
+            Ok((new_metadata, DotfileStatus::Synced))
+        } else if let Some(reason) = self.unsyncable_reason() {
+            warn!("Skipping syncing {} as {}", dotfile_name, reason);
+            Ok((
+                DotfileMetadata::new(
+                    &get_head_hash(repo)?,
+                    self.hash_pre_install(),
+                    self.hash_post_install(),
+                ),
+                DotfileStatus::Skipped,
+            ))
+        } else if dry_run {
+            info!(
+                "Would copy {} into the repository as {} and commit it",
+                origin_path.to_string_lossy(),
+                &self.file
+            );
+            Ok((
+                DotfileMetadata::new(
+                    &get_head_hash(repo)?,
+                    self.hash_pre_install(),
+                    self.hash_post_install(),
+                ),
+                DotfileStatus::Synced,
+            ))
+        } else if !self.write_to_repo(origin_path, target_path, config, pick_hunks)? {
+            info!(
+                "Skipping syncing {} as no changes were picked",
+                dotfile_name
+            );
+            Ok((
+                DotfileMetadata::new(
+                    &get_head_hash(repo)?,
+                    self.hash_pre_install(),
+                    self.hash_post_install(),
+                ),
+                DotfileStatus::Skipped,
+            ))
         } else {
-            fs::copy(origin_path, target_path)?;
             let new_commit = add_and_commit(
                 repo,
                 Some(vec![Path::new(&self.file)]),
@@ -292,24 +1912,180 @@ impl Dotfile {
                 None,
                 Some("HEAD"),
             )?;
-            Ok(DotfileMetadata::new(
-                &new_commit.id().to_string(),
-                self.hash_pre_install(),
-                self.hash_post_install(),
+            Ok((
+                DotfileMetadata::new(
+                    &new_commit.id().to_string(),
+                    self.hash_pre_install(),
+                    self.hash_post_install(),
+                ),
+                DotfileStatus::Synced,
             ))
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::unnecessary_to_owned)]
 mod tests {
     use std::{fs::File, io::Write, path::PathBuf};
     use tempfile::tempdir;
 
     use crate::git::operations::get_head;
+    use age::secrecy::ExposeSecret;
 
     use super::*;
 
+    #[test]
+    fn test_apply_hunks() {
+        let old = (1..=20)
+            .map(|n| format!("line {}\n", n))
+            .collect::<String>();
+        let new = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 19\n", "line 19\nsecret token\n");
+
+        let mut hunks = vec![];
+        let picked = apply_hunks(old.as_bytes(), new.as_bytes(), |text| {
+            hunks.push(text.to_string());
+            Ok(!text.contains("secret"))
+        })
+        .unwrap();
+        assert_eq!(hunks.len(), 2);
+        assert!(hunks[0].starts_with("@@ -1,5 +1,5 @@"));
+        assert!(hunks[0].contains("-line 2\n+line two\n"));
+        assert_eq!(
+            String::from_utf8(picked).unwrap(),
+            old.replace("line 2\n", "line two\n")
+        );
+
+        let picked = apply_hunks(b"", b"set number\n", |_| Ok(true)).unwrap();
+        assert_eq!(picked, b"set number\n");
+        let picked = apply_hunks(b"a\nb", b"a\nc", |_| Ok(false)).unwrap();
+        assert_eq!(picked, b"a\nb");
+        let picked = apply_hunks(b"a\nb", b"a\nc", |_| Ok(true)).unwrap();
+        assert_eq!(picked, b"a\nc");
+    }
+
+    #[test]
+    fn test_target_path_for_os() {
+        let dotfile = Dotfile {
+            file: "kitty.conf".to_string(),
+            target: PathBuf::from("/home/user/.config/kitty/kitty.conf"),
+            targets: HashMap::from([(
+                "macos".to_string(),
+                PathBuf::from("/Users/user/Library/Preferences/kitty/kitty.conf"),
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            dotfile.target_path_for_os("linux"),
+            PathBuf::from("/home/user/.config/kitty/kitty.conf")
+        );
+        assert_eq!(
+            dotfile.target_path_for_os("macos"),
+            PathBuf::from("/Users/user/Library/Preferences/kitty/kitty.conf")
+        );
+    }
+
+    #[test]
+    fn test_deserialize_mode() {
+        let dotfile: Dotfile =
+            serde_yaml::from_str("file: config\ntarget: ~/.ssh/config\nmode: 0600").unwrap();
+        assert_eq!(dotfile.mode, Some(0o600));
+
+        let dotfile: Dotfile =
+            serde_yaml::from_str("file: script\ntarget: ~/script\nmode: \"755\"").unwrap();
+        assert_eq!(dotfile.mode, Some(0o755));
+
+        assert!(
+            serde_yaml::from_str::<Dotfile>("file: config\ntarget: ~/config\nmode: 0800").is_err()
+        );
+
+        let value: serde_yaml::Value =
+            serde_yaml::from_str("file: config\ntarget: ~/.ssh/config\nmode: 0600").unwrap();
+        let dotfile: Dotfile = serde_yaml::from_value(value).unwrap();
+        assert_eq!(dotfile.mode, Some(0o600));
+
+        // JSON and TOML have no octal numbers, so their integers are the mode itself
+        let value: serde_yaml::Value =
+            serde_json::from_str(r#"{"file": "config", "target": "~/config", "mode": 384}"#)
+                .unwrap();
+        let dotfile: Dotfile = serde_yaml::from_value(value).unwrap();
+        assert_eq!(dotfile.mode, Some(0o600));
+        let dotfile: Dotfile =
+            toml::from_str("file = \"script\"\ntarget = \"~/script\"\nmode = 0o755").unwrap();
+        assert_eq!(dotfile.mode, Some(0o755));
+        assert!(serde_json::from_str::<Dotfile>(
+            r#"{"file": "config", "target": "~/config", "mode": 65536}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_deserialize_reload() {
+        let dotfile: Dotfile =
+            serde_yaml::from_str("file: tmux.conf\ntarget: ~/.tmux.conf\nreload: tmux").unwrap();
+        assert_eq!(dotfile.reload, vec![ReloadAction::Tmux]);
+
+        let dotfile: Dotfile = serde_yaml::from_str(
+            "file: sway\ntarget: ~/.config/sway/config\nreload: [sway, systemd-user]",
+        )
+        .unwrap();
+        assert_eq!(
+            dotfile.reload,
+            vec![ReloadAction::Sway, ReloadAction::SystemdUser]
+        );
+
+        let dotfile: Dotfile = serde_yaml::from_str("file: config\ntarget: ~/config").unwrap();
+        assert!(dotfile.reload.is_empty());
+
+        assert!(
+            serde_yaml::from_str::<Dotfile>("file: config\ntarget: ~/config\nreload: emacs")
+                .is_err()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_install_mode() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        // Create file in repo
+        let filepath = repo_dir.path().to_owned().join("dotfile");
+        File::create(&filepath).expect("Could not create file in repo");
+        let _commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: target_path.clone(),
+            mode: Some(0o600),
+            ..Default::default()
+        };
+
+        dotfile
+            .install(&repo, "dotfile", None, true, InstallMode::Force, false)
+            .expect("Failed to install dotfile");
+
+        let mode = fs::metadata(&target_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o600);
+        assert_eq!(dotfile.mode_mismatch().unwrap(), None);
+
+        fs::set_permissions(&target_path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(dotfile.mode_mismatch().unwrap(), Some(0o644));
+    }
+
     #[test]
     fn test_hash_empty_pre_install() {
         let dotfile = Dotfile {
@@ -317,6 +2093,7 @@ mod tests {
             target: PathBuf::new(),
             pre_install: None,
             post_install: None,
+            ..Default::default()
         };
 
         assert_eq!("", dotfile.hash_pre_install());
@@ -333,12 +2110,15 @@ mod tests {
                 "cat".to_string(),
             ]),
             post_install: None,
+            ..Default::default()
         };
 
         assert_eq!(
-            "1ef98a8d0946d6512ca5da8242eb7a52a506de54",
+            "2e7205ffea78602250e084f08a9252b8b78815610576513968343238d4f5ef2b",
             dotfile.hash_pre_install()
         );
+        // Hashes recorded by older versions of jtd are SHA-1
+        assert!(dotfile.pre_install_matches("1ef98a8d0946d6512ca5da8242eb7a52a506de54"));
     }
 
     #[test]
@@ -348,6 +2128,7 @@ mod tests {
             target: PathBuf::new(),
             pre_install: None,
             post_install: None,
+            ..Default::default()
         };
 
         assert_eq!("", dotfile.hash_post_install());
@@ -364,12 +2145,39 @@ mod tests {
                 "ls".to_string(),
                 "cat".to_string(),
             ]),
+            ..Default::default()
         };
 
         assert_eq!(
-            "1ef98a8d0946d6512ca5da8242eb7a52a506de54",
+            "2e7205ffea78602250e084f08a9252b8b78815610576513968343238d4f5ef2b",
             dotfile.hash_post_install()
         );
+        // Hashes recorded by older versions of jtd are SHA-1
+        assert!(dotfile.post_install_matches("1ef98a8d0946d6512ca5da8242eb7a52a506de54"));
+    }
+
+    #[test]
+    fn test_conditions_met() {
+        let mut dotfile = Dotfile {
+            file: "".to_string(),
+            target: PathBuf::from("/tmp/sway-config"),
+            ..Default::default()
+        };
+        assert!(dotfile.conditions_met("sway", Path::new("/tmp")).unwrap());
+
+        dotfile.only_if = Some(vec![
+            "[ \"$JTD_DOTFILE_NAME\" = sway ]".to_string(),
+            "command -v sh".to_string(),
+        ]);
+        // Untrusted conditions aren't run
+        assert!(dotfile.has_untrusted_commands());
+        assert!(!dotfile.conditions_met("sway", Path::new("/tmp")).unwrap());
+        let mut dotfile = dotfile.trusted();
+        assert!(!dotfile.has_untrusted_commands());
+        assert!(dotfile.conditions_met("sway", Path::new("/tmp")).unwrap());
+
+        dotfile.only_if = Some(vec!["command -v jtd-missing-command".to_string()]);
+        assert!(!dotfile.conditions_met("sway", Path::new("/tmp")).unwrap());
     }
 
     #[test]
@@ -379,6 +2187,7 @@ mod tests {
             target: PathBuf::new(),
             pre_install: None,
             post_install: None,
+            ..Default::default()
         };
 
         assert_eq!(false, dotfile.has_unexecuted_run_stages(&None));
@@ -391,12 +2200,14 @@ mod tests {
             target: PathBuf::new(),
             pre_install: None,
             post_install: None,
+            ..Default::default()
         };
 
         let metadata = DotfileMetadata {
             commit_hash: "".to_string(),
             pre_install_hash: "".to_string(),
             post_install_hash: "".to_string(),
+            ..Default::default()
         };
 
         assert_eq!(false, dotfile.has_unexecuted_run_stages(&Some(&metadata)));
@@ -417,103 +2228,1012 @@ mod tests {
                 "ls".to_string(),
                 "cat".to_string(),
             ]),
+            ..Default::default()
         };
 
         let metadata = DotfileMetadata {
             commit_hash: "".to_string(),
             pre_install_hash: "".to_string(),
             post_install_hash: "".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(true, dotfile.has_unexecuted_run_stages(&Some(&metadata)));
+    }
+
+    #[test]
+    fn test_has_unexecuted_run_stages_with_metadata_with_install_steps_false() {
+        let dotfile = Dotfile {
+            file: "".to_string(),
+            target: PathBuf::new(),
+            pre_install: Some(vec![
+                "echo".to_string(),
+                "ls".to_string(),
+                "cat".to_string(),
+            ]),
+            post_install: Some(vec![
+                "echo".to_string(),
+                "ls".to_string(),
+                "cat".to_string(),
+            ]),
+            ..Default::default()
+        };
+
+        let metadata = DotfileMetadata {
+            commit_hash: "".to_string(),
+            pre_install_hash: "1ef98a8d0946d6512ca5da8242eb7a52a506de54".to_string(),
+            post_install_hash: "1ef98a8d0946d6512ca5da8242eb7a52a506de54".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(false, dotfile.has_unexecuted_run_stages(&Some(&metadata)));
+    }
+
+    #[test]
+    fn test_has_changed_false() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        // Create file in repo
+        let repo_filepath = repo_dir.path().to_owned().join("dotfile");
+        File::create(repo_filepath.to_owned()).expect("Could not create file in repo");
+
+        // Create dotfile "on the local system"
+        let local_filepath = dotfile_dir.path().to_owned().join("dotfile");
+        File::create(local_filepath.to_owned()).expect("Could not create file in tempdir");
+
+        let commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: dotfile_dir.path().join("dotfile"),
+            pre_install: None,
+            post_install: None,
+            ..Default::default()
+        };
+
+        let metadata = DotfileMetadata {
+            commit_hash: commit.id().to_string(),
+            pre_install_hash: "".to_string(),
+            post_install_hash: "".to_string(),
+            ..Default::default()
+        };
+
+        assert!(!dotfile.has_changed(&repo, &metadata).unwrap());
+    }
+
+    #[test]
+    fn test_has_changed_does_not_check_out() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let repo_filepath = repo_dir.path().join("dotfile");
+        let local_filepath = dotfile_dir.path().join("dotfile");
+
+        fs::write(&repo_filepath, "first version").unwrap();
+        let first_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "first",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+        fs::write(&repo_filepath, "second version").unwrap();
+        let head_commit = repo.head().unwrap().peel_to_commit().unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "second",
+            Some(vec![&head_commit]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let metadata = DotfileMetadata {
+            commit_hash: first_commit.id().to_string(),
+            ..Default::default()
+        };
+        for template in [false, true] {
+            let dotfile = Dotfile {
+                file: "dotfile".to_string(),
+                target: local_filepath.clone(),
+                template,
+                ..Default::default()
+            };
+
+            fs::write(&local_filepath, "first version").unwrap();
+            assert!(!dotfile.has_changed(&repo, &metadata).unwrap());
+            fs::write(&local_filepath, "local changes").unwrap();
+            assert!(dotfile.has_changed(&repo, &metadata).unwrap());
+        }
+
+        // The working tree is left on the latest commit
+        assert_eq!(
+            fs::read_to_string(&repo_filepath).unwrap(),
+            "second version"
+        );
+        assert_eq!(repo.head().unwrap().name(), Some("refs/heads/master"));
+    }
+
+    #[test]
+    fn test_has_changed_true() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        // Create file in repo
+        let filepath = repo_dir.path().to_owned().join("dotfile");
+        File::create(filepath.to_owned()).expect("Could not create file in repo");
+
+        // Create dotfile "on the local system" with different contents
+        let filepath = dotfile_dir.path().to_owned().join("dotfile");
+        let mut dotfile_file =
+            File::create(filepath.to_owned()).expect("Could not create file in tempdir");
+        dotfile_file
+            .write_all("This file has changes".as_bytes())
+            .unwrap();
+
+        let commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: dotfile_dir.path().join("dotfile"),
+            pre_install: None,
+            post_install: None,
+            ..Default::default()
+        };
+
+        let metadata = DotfileMetadata {
+            commit_hash: commit.id().to_string(),
+            pre_install_hash: "".to_string(),
+            post_install_hash: "".to_string(),
+            ..Default::default()
+        };
+
+        assert!(dotfile.has_changed(&repo, &metadata).unwrap());
+    }
+
+    #[test]
+    fn test_has_changed_lfs_pointer() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let wallpaper = b"not really a png";
+
+        fs::write(
+            repo_dir.path().join("wallpaper.png"),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+                hex::encode(sha2::Sha256::digest(wallpaper)),
+                wallpaper.len()
+            ),
+        )
+        .unwrap();
+        let commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("wallpaper.png")]),
+            "Add wallpaper",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "wallpaper.png".to_string(),
+            target: dotfile_dir.path().join("wallpaper.png"),
+            ..Default::default()
+        };
+        let metadata = DotfileMetadata {
+            commit_hash: commit.id().to_string(),
+            ..Default::default()
+        };
+
+        fs::write(dotfile.target_path(), wallpaper).unwrap();
+        assert!(!dotfile.has_changed(&repo, &metadata).unwrap());
+        fs::write(dotfile.target_path(), b"another wallpaper").unwrap();
+        assert!(dotfile.has_changed(&repo, &metadata).unwrap());
+        assert!(dotfile.lfs_reason(&repo).is_some());
+    }
+
+    #[test]
+    fn test_diff() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        fs::write(
+            repo_dir.path().join("dotfile"),
+            "font_size 10\ntheme dark\n",
+        )
+        .unwrap();
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: dotfile_dir.path().join("dotfile"),
+            ..Default::default()
+        };
+
+        let diff = dotfile
+            .diff(&repo, false)
+            .unwrap()
+            .expect("Missing dotfile has no diff");
+        assert!(diff.contains("-font_size 10\n-theme dark\n"));
+        let patch = dotfile.diff(&repo, true).unwrap().unwrap();
+        assert!(patch.contains("\ndeleted file mode 100644\n"));
+        assert!(patch.contains("\n--- a/dotfile\n+++ /dev/null\n"));
+
+        fs::write(
+            dotfile_dir.path().join("dotfile"),
+            "font_size 12\ntheme dark\n",
+        )
+        .unwrap();
+        let diff = dotfile
+            .diff(&repo, false)
+            .unwrap()
+            .expect("Changed dotfile has no diff");
+        assert!(diff.starts_with("diff --git a/dotfile"));
+        assert!(diff.contains("-font_size 10\n+font_size 12\n theme dark\n"));
+
+        let patch = dotfile.diff(&repo, true).unwrap().unwrap();
+        assert!(patch.starts_with("diff --git a/dotfile b/dotfile\n"));
+        assert!(patch.contains("+++ b/dotfile\n"));
+
+        fs::write(
+            dotfile_dir.path().join("dotfile"),
+            "font_size 10\ntheme dark\n",
+        )
+        .unwrap();
+        assert_eq!(dotfile.diff(&repo, false).unwrap(), None);
+    }
+
+    #[test]
+    fn test_binary_dotfile() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        // A compiled terminfo entry
+        let terminfo = b"\x1a\x01\x30\x00\x1d\x00\xff\xfe";
+        fs::write(repo_dir.path().join("terminfo"), terminfo).unwrap();
+        let commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("terminfo")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let dotfile = Dotfile {
+            file: "terminfo".to_string(),
+            target: dotfile_dir.path().join("xterm-kitty"),
+            ..Default::default()
+        };
+        let metadata = DotfileMetadata {
+            commit_hash: commit.id().to_string(),
+            ..Default::default()
+        };
+
+        fs::write(dotfile.target_path(), terminfo).unwrap();
+        assert!(!dotfile.has_changed(&repo, &metadata).unwrap());
+        assert_eq!(dotfile.diff(&repo, false).unwrap(), None);
+
+        fs::write(dotfile.target_path(), b"\x1a\x01\x31\x00").unwrap();
+        assert!(dotfile.has_changed(&repo, &metadata).unwrap());
+        let diff = dotfile.diff(&repo, false).unwrap().unwrap();
+        assert!(diff.ends_with("differ\n"));
+        assert!(diff.contains("Binary files a/terminfo and b/"));
+    }
+
+    #[test]
+    fn test_diff_with_tool() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        fs::write(repo_dir.path().join("dotfile.conf"), "theme dark\n").unwrap();
+        fs::write(dotfile_dir.path().join("dotfile.conf"), "theme light\n").unwrap();
+
+        let dotfile = Dotfile {
+            file: "dotfile.conf".to_string(),
+            target: dotfile_dir.path().join("dotfile.conf"),
+            ..Default::default()
+        };
+
+        let output_path = dotfile_dir.path().join("output");
+        let tool = format!(
+            "sh -c 'basename \"$1\" > {}' -",
+            output_path.to_string_lossy()
+        );
+        assert!(dotfile.diff_with_tool(&repo, "dotfile", &tool).unwrap());
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "dotfile.conf\n");
+
+        // diff exits with 1 when the files differ, which isn't an error
+        assert!(dotfile.diff_with_tool(&repo, "dotfile", "diff -u").unwrap());
+        assert!(dotfile
+            .diff_with_tool(&repo, "dotfile", "jtd-missing-diff-tool")
+            .is_err());
+
+        fs::write(dotfile_dir.path().join("dotfile.conf"), "theme dark\n").unwrap();
+        assert!(!dotfile
+            .diff_with_tool(&repo, "dotfile", "jtd-missing-diff-tool")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_needs_update() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        fs::write(repo_dir.path().join("dotfile"), "first version").unwrap();
+        let first_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "Add dotfile",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        fs::write(repo_dir.path().join("other"), "unrelated").unwrap();
+        let second_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("other")]),
+            "Add unrelated file",
+            Some(vec![&first_commit]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: PathBuf::from("/tmp/jtd-needs-update"),
+            ..Default::default()
+        };
+        let metadata = DotfileMetadata {
+            commit_hash: first_commit.id().to_string(),
+            ..Default::default()
+        };
+        assert!(!dotfile.needs_update(&repo, &metadata).unwrap());
+
+        fs::write(repo_dir.path().join("dotfile"), "second version").unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "Update dotfile",
+            Some(vec![&second_commit]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+        assert!(dotfile.needs_update(&repo, &metadata).unwrap());
+
+        let dotfile = Dotfile {
+            post_install: Some(vec!["echo installed".to_string()]),
+            git_ref: Some(first_commit.id().to_string()),
+            ..dotfile
+        };
+        assert!(dotfile.needs_update(&repo, &metadata).unwrap());
+    }
+
+    #[test]
+    fn test_sync_state() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let local_filepath = dotfile_dir.path().join("dotfile");
+
+        fs::write(repo_dir.path().join("dotfile"), "first version").unwrap();
+        let first_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "Add dotfile",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: local_filepath.clone(),
+            ..Default::default()
+        };
+        let metadata = DotfileMetadata {
+            commit_hash: first_commit.id().to_string(),
+            ..Default::default()
+        };
+
+        fs::write(&local_filepath, "first version").unwrap();
+        assert_eq!(
+            dotfile.sync_state(&repo, &metadata).unwrap(),
+            SyncState::UpToDate
+        );
+        fs::write(&local_filepath, "local version").unwrap();
+        assert_eq!(
+            dotfile.sync_state(&repo, &metadata).unwrap(),
+            SyncState::LocalOnly
+        );
+
+        fs::write(repo_dir.path().join("dotfile"), "remote version").unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "Update dotfile",
+            Some(vec![&first_commit]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+        assert_eq!(
+            dotfile.sync_state(&repo, &metadata).unwrap(),
+            SyncState::Diverged
+        );
+        fs::write(&local_filepath, "first version").unwrap();
+        assert_eq!(
+            dotfile.sync_state(&repo, &metadata).unwrap(),
+            SyncState::RemoteOnly
+        );
+
+        // Pinned to the synced commit, the change at HEAD won't be installed
+        let dotfile = Dotfile {
+            git_ref: Some(first_commit.id().to_string()),
+            ..dotfile
+        };
+        assert_eq!(
+            dotfile.sync_state(&repo, &metadata).unwrap(),
+            SyncState::UpToDate
+        );
+    }
+
+    #[test]
+    fn test_install_no_metadata() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        // Create file in repo
+        let filepath = repo_dir.path().to_owned().join("dotfile");
+        File::create(filepath.to_owned()).expect("Could not create file in repo");
+
+        let _commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: target_path.clone(),
+            pre_install: None,
+            post_install: None,
+            ..Default::default()
+        };
+
+        dotfile
+            .install(&repo, "dotfile", None, true, InstallMode::Force, false)
+            .expect("Failed to install dotfile");
+
+        assert!(Path::exists(&target_path));
+    }
+
+    #[test]
+    fn test_install_rolls_back_failed_post_install() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        fs::write(repo_dir.path().join("dotfile"), "new").unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: target_path.clone(),
+            post_install: Some(vec!["false".to_string()]),
+            ..Default::default()
+        };
+
+        let err = dotfile
+            .install(&repo, "dotfile", None, false, InstallMode::Force, false)
+            .expect_err("Install succeeded despite a failing post-install step");
+        assert!(err.to_string().contains("Step #0 \"false\" failed"));
+        assert!(!target_path.exists());
+    }
+
+    #[test]
+    fn test_install_commands() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        let target_touch_pre_install = dotfile_dir.path().join("pre_install");
+        let target_touch_post_install = dotfile_dir.path().join("post_install");
+
+        // Create file in repo
+        let filepath = repo_dir.path().to_owned().join("dotfile");
+        File::create(filepath.to_owned()).expect("Could not create file in repo");
+
+        let _commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: target_path.clone(),
+            pre_install: Some(vec![format!(
+                "touch {}",
+                target_touch_pre_install.to_string_lossy()
+            )]),
+            post_install: Some(vec![format!(
+                "touch {}",
+                target_touch_post_install.to_string_lossy()
+            )]),
+            post_uninstall: Some(vec!["echo uninstalled".to_string()]),
+            ..Default::default()
+        };
+
+        let metadata = dotfile
+            .install(&repo, "dotfile", None, false, InstallMode::Force, false)
+            .expect("Failed to install dotfile");
+
+        assert!(Path::exists(&target_path));
+        assert!(Path::exists(&target_touch_pre_install));
+        assert!(Path::exists(&target_touch_post_install));
+        assert!(metadata.pre_uninstall.is_empty());
+        assert_eq!(metadata.post_uninstall, vec!["echo uninstalled"]);
+    }
+
+    #[test]
+    fn test_install_dry_run() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+        let target_touch_pre_install = dotfile_dir.path().join("pre_install");
+
+        // Create file in repo
+        let filepath = repo_dir.path().to_owned().join("dotfile");
+        File::create(&filepath).expect("Could not create file in repo");
+
+        let _commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: target_path.clone(),
+            pre_install: Some(vec![format!(
+                "touch {}",
+                target_touch_pre_install.to_string_lossy()
+            )]),
+            post_install: None,
+            ..Default::default()
+        };
+
+        dotfile
+            .install(&repo, "dotfile", None, false, InstallMode::Force, true)
+            .expect("Failed to install dotfile");
+
+        assert!(!Path::exists(&target_path));
+        assert!(!Path::exists(&target_touch_pre_install));
+    }
+
+    #[test]
+    fn test_install_template() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        // Create templated file in repo
+        let filepath = repo_dir.path().to_owned().join("dotfile");
+        let mut file = File::create(&filepath).expect("Could not create file in repo");
+        file.write_all(b"os = {{ os }}")
+            .expect("Could not write to file in repo");
+
+        let _commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: target_path.clone(),
+            template: true,
+            ..Default::default()
+        };
+
+        let metadata = dotfile
+            .install(&repo, "dotfile", None, false, InstallMode::Force, false)
+            .expect("Failed to install dotfile");
+
+        assert_eq!(
+            fs::read_to_string(&target_path).unwrap(),
+            format!("os = {}", OS)
+        );
+        assert!(!dotfile.has_changed(&repo, &metadata).unwrap());
+    }
+
+    #[test]
+    fn test_backup_target() {
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let backups_dir = tempdir().expect("Could not create temporary backups dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: target_path.clone(),
+            ..Default::default()
+        };
+
+        // Nothing to back up if the target doesn't exist
+        assert_eq!(
+            dotfile
+                .backup_target("dotfile", backups_dir.path(), b"new contents")
+                .unwrap(),
+            None
+        );
+
+        // Nothing to back up if the target is unchanged
+        fs::write(&target_path, "old contents").unwrap();
+        assert_eq!(
+            dotfile
+                .backup_target("dotfile", backups_dir.path(), b"old contents")
+                .unwrap(),
+            None
+        );
+
+        let backup_path = dotfile
+            .backup_target("dotfile", backups_dir.path(), b"new contents")
+            .unwrap()
+            .expect("Target was not backed up");
+
+        assert!(backup_path.starts_with(backups_dir.path().join("dotfile")));
+        assert_eq!(fs::read_to_string(backup_path).unwrap(), "old contents");
+    }
+
+    #[test]
+    fn test_install_keep_backup() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("kitty.conf");
+        let keep_path = dotfile_dir.path().join("kitty.conf.jtd-bak");
+
+        fs::write(repo_dir.path().join("kitty.conf"), "font_size 12").unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![Path::new("kitty.conf")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let dotfile = Dotfile {
+            file: "kitty.conf".to_string(),
+            target: target_path.clone(),
+            backup_suffix: Some(".jtd-bak".to_string()),
+            ..Default::default()
+        };
+
+        // Nothing is overwritten on the first install
+        dotfile
+            .install(&repo, "kitty", None, true, InstallMode::Force, false)
+            .expect("Failed to install dotfile");
+        assert!(!keep_path.exists());
+
+        fs::write(&target_path, "font_size 10").unwrap();
+        dotfile
+            .install(&repo, "kitty", None, true, InstallMode::Force, false)
+            .expect("Failed to install dotfile");
+
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "font_size 12");
+        assert_eq!(fs::read_to_string(&keep_path).unwrap(), "font_size 10");
+    }
+
+    #[test]
+    fn test_install_pinned_ref() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        // Create a tagged commit followed by a newer one
+        let filepath = repo_dir.path().to_owned().join("dotfile");
+        fs::write(&filepath, "pinned").expect("Could not write file in repo");
+        let pinned_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+        repo.tag_lightweight("v1.0.0", pinned_commit.as_object(), false)
+            .expect("Failed to tag commit");
+
+        fs::write(&filepath, "latest").expect("Could not write file in repo");
+        let latest_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![&pinned_commit]),
+            Some("HEAD"),
+        )
+        .expect("Failed to commit to repository");
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: target_path.clone(),
+            git_ref: Some("v1.0.0".to_string()),
+            ..Default::default()
+        };
+
+        let metadata = dotfile
+            .install(&repo, "dotfile", None, true, InstallMode::Force, false)
+            .expect("Failed to install dotfile");
+
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "pinned");
+        assert_eq!(metadata.commit_hash, pinned_commit.id().to_string());
+        assert_eq!(
+            get_head_hash(&repo).unwrap(),
+            latest_commit.id().to_string()
+        );
+    }
+
+    #[test]
+    fn test_restore() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        let filepath = repo_dir.path().join("dotfile");
+        fs::write(&filepath, "last week").unwrap();
+        let old_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+        fs::write(&filepath, "today").unwrap();
+        let latest_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![&old_commit]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: target_path.clone(),
+            post_install: Some(vec!["echo installed".to_string()]),
+            ..Default::default()
         };
+        let mut metadata = dotfile
+            .install(&repo, "dotfile", None, true, InstallMode::Force, false)
+            .unwrap();
+        metadata.post_install_hash = dotfile.hash_post_install();
+
+        let restored = dotfile
+            .restore(
+                &repo,
+                "dotfile",
+                metadata,
+                &old_commit.id().to_string(),
+                false,
+                false,
+            )
+            .unwrap();
 
-        assert_eq!(true, dotfile.has_unexecuted_run_stages(&Some(&metadata)));
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "last week");
+        assert_eq!(restored.commit_hash, old_commit.id().to_string());
+        assert_eq!(restored.restored_ref, Some(old_commit.id().to_string()));
+        assert_eq!(restored.post_install_hash, dotfile.hash_post_install());
+        assert!(!dotfile.has_changed(&repo, &restored).unwrap());
+        assert_eq!(
+            get_head_hash(&repo).unwrap(),
+            latest_commit.id().to_string()
+        );
     }
 
     #[test]
-    fn test_has_unexecuted_run_stages_with_metadata_with_install_steps_false() {
+    fn test_install_merge() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("kitty.conf");
+
+        let filepath = repo_dir.path().join("kitty.conf");
+        fs::write(&filepath, "font_size 12\nfont_family mono\nopacity 1\n").unwrap();
+        let base_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("kitty.conf")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
         let dotfile = Dotfile {
-            file: "".to_string(),
-            target: PathBuf::new(),
-            pre_install: Some(vec![
-                "echo".to_string(),
-                "ls".to_string(),
-                "cat".to_string(),
-            ]),
-            post_install: Some(vec![
-                "echo".to_string(),
-                "ls".to_string(),
-                "cat".to_string(),
-            ]),
+            file: "kitty.conf".to_string(),
+            target: target_path.clone(),
+            ..Default::default()
         };
+        let metadata = dotfile
+            .install(&repo, "kitty", None, true, InstallMode::Force, false)
+            .unwrap();
 
-        let metadata = DotfileMetadata {
-            commit_hash: "".to_string(),
-            pre_install_hash: "1ef98a8d0946d6512ca5da8242eb7a52a506de54".to_string(),
-            post_install_hash: "1ef98a8d0946d6512ca5da8242eb7a52a506de54".to_string(),
-        };
+        fs::write(&target_path, "font_size 10\nfont_family mono\nopacity 1\n").unwrap();
+        fs::write(&filepath, "font_size 12\nfont_family mono\nopacity 0.9\n").unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![Path::new("kitty.conf")]),
+            "commit message",
+            Some(vec![&base_commit]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let metadata = dotfile
+            .install(
+                &repo,
+                "kitty",
+                Some(metadata),
+                true,
+                InstallMode::Merge,
+                false,
+            )
+            .unwrap();
 
-        assert_eq!(false, dotfile.has_unexecuted_run_stages(&Some(&metadata)));
+        assert_eq!(
+            fs::read_to_string(&target_path).unwrap(),
+            "font_size 10\nfont_family mono\nopacity 0.9\n"
+        );
+        assert_eq!(metadata.commit_hash, get_head_hash(&repo).unwrap());
+        // The local change is still there to be synced
+        assert!(dotfile.has_changed(&repo, &metadata).unwrap());
     }
 
     #[test]
-    fn test_has_changed_false() {
+    fn test_install_merges_diverged_changes() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
-        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
         let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("kitty.conf");
 
-        // Create file in repo
-        let repo_filepath = repo_dir.path().to_owned().join("dotfile");
-        File::create(repo_filepath.to_owned()).expect("Could not create file in repo");
-
-        // Create dotfile "on the local system"
-        let local_filepath = dotfile_dir.path().to_owned().join("dotfile");
-        File::create(local_filepath.to_owned()).expect("Could not create file in tempdir");
-
-        let commit = add_and_commit(
+        let filepath = repo_dir.path().join("kitty.conf");
+        fs::write(&filepath, "font_size 12\n").unwrap();
+        let base_commit = add_and_commit(
             &repo,
-            Some(vec![&repo_filepath]),
+            Some(vec![Path::new("kitty.conf")]),
             "commit message",
             Some(vec![]),
             Some("HEAD"),
         )
-        .expect("Failed to commit to repository");
+        .unwrap();
 
         let dotfile = Dotfile {
-            file: "dotfile".to_string(),
-            target: dotfile_dir.path().join("dotfile"),
-            pre_install: None,
-            post_install: None,
-        };
-
-        let metadata = DotfileMetadata {
-            commit_hash: commit.id().to_string(),
-            pre_install_hash: "".to_string(),
-            post_install_hash: "".to_string(),
+            file: "kitty.conf".to_string(),
+            target: target_path.clone(),
+            ..Default::default()
         };
-
-        assert!(!dotfile.has_changed(&repo, &metadata).unwrap());
+        let metadata = dotfile
+            .install(&repo, "kitty", None, true, InstallMode::Force, false)
+            .unwrap();
+        fs::write(&target_path, "font_size 10\n").unwrap();
+
+        // Only the local file has changed, so there is nothing to merge
+        assert!(dotfile
+            .install(
+                &repo,
+                "kitty",
+                Some(metadata.clone()),
+                true,
+                InstallMode::Safe,
+                false
+            )
+            .is_err());
+
+        fs::write(&filepath, "font_size 14\n").unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![Path::new("kitty.conf")]),
+            "commit message",
+            Some(vec![&base_commit]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let err = dotfile
+            .install(
+                &repo,
+                "kitty",
+                Some(metadata),
+                true,
+                InstallMode::Safe,
+                false,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("kitty.conf.jtd-merge"));
+        // The conflicting merge is kept next to the target, which is left alone
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "font_size 10\n");
+        assert_eq!(
+            fs::read_to_string(dotfile_dir.path().join("kitty.conf.jtd-merge")).unwrap(),
+            "<<<<<<< local\nfont_size 10\n=======\nfont_size 14\n>>>>>>> repository\n"
+        );
     }
 
     #[test]
-    fn test_has_changed_true() {
+    fn test_abort_install_if_local_changes() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
-        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
         let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
 
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
         // Create file in repo
         let filepath = repo_dir.path().to_owned().join("dotfile");
         File::create(filepath.to_owned()).expect("Could not create file in repo");
 
-        // Create dotfile "on the local system" with different contents
-        let filepath = dotfile_dir.path().to_owned().join("dotfile");
-        let mut dotfile_file =
-            File::create(filepath.to_owned()).expect("Could not create file in tempdir");
-        dotfile_file
-            .write_all("This file has changes".as_bytes())
-            .unwrap();
+        // Create dotfile "on the local system"
+        let local_filepath = dotfile_dir.path().to_owned().join("dotfile");
+        let mut file =
+            File::create(local_filepath.to_owned()).expect("Could not create file in tempdir");
+        file.write_all(b"These are local changes on the system")
+            .expect("Failed to write to dotfile");
 
-        let commit = add_and_commit(
+        let _commit = add_and_commit(
             &repo,
-            Some(vec![&filepath]),
+            Some(vec![Path::new("dotfile")]),
             "commit message",
             Some(vec![]),
             Some("HEAD"),
@@ -522,22 +3242,33 @@ mod tests {
 
         let dotfile = Dotfile {
             file: "dotfile".to_string(),
-            target: dotfile_dir.path().join("dotfile"),
+            target: target_path.clone(),
             pre_install: None,
             post_install: None,
+            ..Default::default()
         };
 
         let metadata = DotfileMetadata {
-            commit_hash: commit.id().to_string(),
+            commit_hash: _commit.id().to_string(),
             pre_install_hash: "".to_string(),
             post_install_hash: "".to_string(),
+            ..Default::default()
         };
 
-        assert!(dotfile.has_changed(&repo, &metadata).unwrap());
+        assert!(dotfile
+            .install(
+                &repo,
+                "dotfile",
+                Some(metadata),
+                true,
+                InstallMode::Safe,
+                false
+            )
+            .is_err());
     }
 
     #[test]
-    fn test_install_no_metadata() {
+    fn test_sync_naive() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
         let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
 
@@ -547,78 +3278,97 @@ mod tests {
         // Create file in repo
         let filepath = repo_dir.path().to_owned().join("dotfile");
         File::create(filepath.to_owned()).expect("Could not create file in repo");
-
         let _commit = add_and_commit(
             &repo,
-            Some(vec![&filepath]),
+            Some(vec![Path::new("dotfile")]),
             "commit message",
             Some(vec![]),
             Some("HEAD"),
         )
         .expect("Failed to commit to repository");
 
+        // Create dotfile "on the local system"
+        let local_filepath = dotfile_dir.path().to_owned().join("dotfile");
+        let mut file =
+            File::create(local_filepath.to_owned()).expect("Could not create file in tempdir");
+        file.write_all(b"These are local changes on the system")
+            .expect("Failed to write to dotfile");
+
         let dotfile = Dotfile {
             file: "dotfile".to_string(),
             target: target_path.clone(),
             pre_install: None,
             post_install: None,
+            ..Default::default()
         };
 
-        dotfile
-            .install(&repo, None, true, true)
-            .expect("Failed to install dotfile");
+        let config = Config::default();
 
-        assert!(Path::exists(&target_path));
+        dotfile
+            .sync(&repo, "dotfile", &config, None, false, false)
+            .expect("Failed to sync dotfile");
+        assert_eq!(
+            fs::read_to_string(filepath).unwrap(),
+            "These are local changes on the system"
+        );
     }
 
     #[test]
-    fn test_install_commands() {
+    fn test_sync_and_install_encrypted() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
         let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
 
         let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
         let target_path = dotfile_dir.path().join("dotfile");
 
-        let target_touch_pre_install = dotfile_dir.path().join("pre_install");
-        let target_touch_post_install = dotfile_dir.path().join("post_install");
+        // Create age identity
+        let identity = age::x25519::Identity::generate();
+        let identity_path = dotfile_dir.path().join("identity.txt");
+        fs::write(&identity_path, identity.to_string().expose_secret())
+            .expect("Could not write identity");
 
         // Create file in repo
         let filepath = repo_dir.path().to_owned().join("dotfile");
-        File::create(filepath.to_owned()).expect("Could not create file in repo");
-
+        File::create(&filepath).expect("Could not create file in repo");
         let _commit = add_and_commit(
             &repo,
-            Some(vec![&filepath]),
+            Some(vec![Path::new("dotfile")]),
             "commit message",
             Some(vec![]),
             Some("HEAD"),
         )
         .expect("Failed to commit to repository");
 
+        // Create dotfile "on the local system"
+        let mut file = File::create(&target_path).expect("Could not create file in tempdir");
+        file.write_all(b"token = hunter2")
+            .expect("Failed to write to dotfile");
+
         let dotfile = Dotfile {
             file: "dotfile".to_string(),
             target: target_path.clone(),
-            pre_install: Some(vec![format!(
-                "touch {}",
-                target_touch_pre_install.to_string_lossy()
-            )]),
-            post_install: Some(vec![format!(
-                "touch {}",
-                target_touch_post_install.to_string_lossy()
-            )]),
+            encrypted: Some(Encryption::Age),
+            age_identity: Some(identity_path),
+            ..Default::default()
         };
 
         dotfile
-            .install(&repo, None, false, true)
+            .sync(&repo, "dotfile", &Config::default(), None, false, false)
+            .expect("Failed to sync dotfile");
+
+        let repo_contents = fs::read_to_string(filepath).unwrap();
+        assert!(!repo_contents.contains("hunter2"));
+
+        fs::remove_file(&target_path).unwrap();
+        dotfile
+            .install(&repo, "dotfile", None, true, InstallMode::Force, false)
             .expect("Failed to install dotfile");
 
-        assert!(Path::exists(&target_path));
-        assert!(Path::exists(&target_touch_pre_install));
-        assert!(Path::exists(&target_touch_post_install));
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "token = hunter2");
     }
 
     #[test]
-    fn test_abort_install_if_local_changes() {
+    fn test_sync_naive_dry_run() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
         let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
 
@@ -627,42 +3377,40 @@ mod tests {
 
         // Create file in repo
         let filepath = repo_dir.path().to_owned().join("dotfile");
-        File::create(filepath.to_owned()).expect("Could not create file in repo");
-
-        // Create dotfile "on the local system"
-        let local_filepath = dotfile_dir.path().to_owned().join("dotfile");
-        let mut file =
-            File::create(local_filepath.to_owned()).expect("Could not create file in tempdir");
-        file.write_all(b"These are local changes on the system")
-            .expect("Failed to write to dotfile");
-
-        let _commit = add_and_commit(
+        File::create(&filepath).expect("Could not create file in repo");
+        let commit = add_and_commit(
             &repo,
-            Some(vec![&filepath]),
+            Some(vec![Path::new("dotfile")]),
             "commit message",
             Some(vec![]),
             Some("HEAD"),
         )
         .expect("Failed to commit to repository");
 
+        // Create dotfile "on the local system"
+        let mut file = File::create(&target_path).expect("Could not create file in tempdir");
+        file.write_all(b"These are local changes on the system")
+            .expect("Failed to write to dotfile");
+
         let dotfile = Dotfile {
             file: "dotfile".to_string(),
             target: target_path.clone(),
             pre_install: None,
             post_install: None,
+            ..Default::default()
         };
 
-        let metadata = DotfileMetadata {
-            commit_hash: _commit.id().to_string(),
-            pre_install_hash: "".to_string(),
-            post_install_hash: "".to_string(),
-        };
+        let config = Config::default();
 
-        assert!(dotfile.install(&repo, Some(metadata), true, false).is_err());
+        dotfile
+            .sync(&repo, "dotfile", &config, None, false, true)
+            .expect("Failed to sync dotfile");
+        assert_eq!(fs::read_to_string(filepath).unwrap(), "");
+        assert_eq!(commit.id(), get_head(&repo).unwrap().id());
     }
 
     #[test]
-    fn test_sync_naive() {
+    fn test_sync_with_metadata() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
         let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
 
@@ -674,7 +3422,7 @@ mod tests {
         File::create(filepath.to_owned()).expect("Could not create file in repo");
         let _commit = add_and_commit(
             &repo,
-            Some(vec![&filepath]),
+            Some(vec![Path::new("dotfile")]),
             "commit message",
             Some(vec![]),
             Some("HEAD"),
@@ -693,12 +3441,20 @@ mod tests {
             target: target_path.clone(),
             pre_install: None,
             post_install: None,
+            ..Default::default()
+        };
+
+        let metadata = DotfileMetadata {
+            commit_hash: _commit.id().to_string(),
+            pre_install_hash: "".to_string(),
+            post_install_hash: "".to_string(),
+            ..Default::default()
         };
 
         let config = Config::default();
 
         dotfile
-            .sync(&repo, "dotfile", &config, None)
+            .sync(&repo, "dotfile", &config, Some(&metadata), false, false)
             .expect("Failed to sync dotfile");
         assert_eq!(
             fs::read_to_string(filepath).unwrap(),
@@ -707,54 +3463,174 @@ mod tests {
     }
 
     #[test]
-    fn test_sync_with_metadata() {
+    fn test_sync_and_install_filters() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
         let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
 
+        let dotfile = Dotfile {
+            file: "gitconfig".to_string(),
+            target: dotfile_dir.path().join("gitconfig"),
+            sync_filter: Some(vec!["sed 's/token = .*/token = REDACTED/'".to_string()]),
+            install_filter: Some(vec!["sed \"s/REDACTED/$TOKEN/\"".to_string()]),
+            env: HashMap::from([("TOKEN".to_string(), "secret".to_string())]),
+            ..Default::default()
+        };
+        fs::write(dotfile.target_path(), "[github]\n  token = secret\n").unwrap();
+        add_and_commit(&repo, None, "Initial commit", Some(vec![]), Some("HEAD")).unwrap();
+
+        // Filters from the repository are never run until they are trusted
+        assert!(dotfile.has_untrusted_filters());
+        let err = dotfile
+            .sync(&repo, "gitconfig", &Config::default(), None, false, false)
+            .unwrap_err();
+        assert!(err.to_string().contains("isn't trusted"));
+        assert!(!repo_dir.path().join("gitconfig").exists());
+
+        let dotfile = dotfile.trusted();
+        let (metadata, status) = dotfile
+            .sync(&repo, "gitconfig", &Config::default(), None, false, false)
+            .unwrap();
+        assert_eq!(status, DotfileStatus::Synced);
+        assert_eq!(
+            fs::read_to_string(repo_dir.path().join("gitconfig")).unwrap(),
+            "[github]\n  token = REDACTED\n"
+        );
+        assert!(!dotfile.has_changed(&repo, &metadata).unwrap());
+
+        fs::remove_file(dotfile.target_path()).unwrap();
+        dotfile
+            .install(
+                &repo,
+                "gitconfig",
+                Some(metadata),
+                true,
+                InstallMode::Safe,
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(dotfile.target_path()).unwrap(),
+            "[github]\n  token = secret\n"
+        );
+    }
+
+    #[test]
+    fn test_sync_failing_filter_restores_head() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
         let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
-        let target_path = dotfile_dir.path().join("dotfile");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
 
-        // Create file in repo
-        let filepath = repo_dir.path().to_owned().join("dotfile");
-        File::create(filepath.to_owned()).expect("Could not create file in repo");
-        let _commit = add_and_commit(
+        fs::write(repo_dir.path().join("dotfile"), "synced contents\n").unwrap();
+        let synced_commit = add_and_commit(
             &repo,
-            Some(vec![&filepath]),
-            "commit message",
+            Some(vec![Path::new("dotfile")]),
+            "Add dotfile",
             Some(vec![]),
             Some("HEAD"),
         )
-        .expect("Failed to commit to repository");
-
-        // Create dotfile "on the local system"
-        let local_filepath = dotfile_dir.path().to_owned().join("dotfile");
-        let mut file =
-            File::create(local_filepath.to_owned()).expect("Could not create file in tempdir");
-        file.write_all(b"These are local changes on the system")
-            .expect("Failed to write to dotfile");
+        .unwrap();
+        fs::write(repo_dir.path().join("other"), "other contents\n").unwrap();
+        let head_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("other")]),
+            "Add other file",
+            None,
+            Some("HEAD"),
+        )
+        .unwrap();
+        let head_ref_name = repo.head().unwrap().name().unwrap().to_string();
 
         let dotfile = Dotfile {
             file: "dotfile".to_string(),
-            target: target_path.clone(),
-            pre_install: None,
-            post_install: None,
+            target: dotfile_dir.path().join("dotfile"),
+            sync_filter: Some(vec!["exit 1".to_string()]),
+            ..Default::default()
+        }
+        .trusted();
+        fs::write(dotfile.target_path(), "local changes\n").unwrap();
+        let metadata = DotfileMetadata {
+            commit_hash: synced_commit.id().to_string(),
+            ..Default::default()
         };
 
+        assert!(dotfile
+            .sync(
+                &repo,
+                "dotfile",
+                &Config::default(),
+                Some(&metadata),
+                false,
+                false
+            )
+            .is_err());
+        let head = repo.head().unwrap();
+        assert_eq!(head.name(), Some(head_ref_name.as_str()));
+        assert_eq!(head.target(), Some(head_commit.id()));
+        assert!(repo
+            .find_branch("merge-dotfile-dotfile", BranchType::Local)
+            .is_err());
+    }
+
+    #[test]
+    fn test_sync_skips_rolled_back() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+
+        fs::write(repo_dir.path().join("dotfile"), "installed contents\n").unwrap();
+        let commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "Add dotfile",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let dotfile = Dotfile {
+            file: "dotfile".to_string(),
+            target: dotfile_dir.path().join("dotfile"),
+            ..Default::default()
+        };
+        // The file the install replaced, as restored by jtd rollback
+        fs::write(dotfile.target_path(), "contents before install\n").unwrap();
         let metadata = DotfileMetadata {
-            commit_hash: _commit.id().to_string(),
-            pre_install_hash: "".to_string(),
-            post_install_hash: "".to_string(),
+            commit_hash: commit.id().to_string(),
+            rolled_back: true,
+            ..Default::default()
         };
 
-        let config = Config::default();
-
-        dotfile
-            .sync(&repo, "dotfile", &config, Some(&metadata))
-            .expect("Failed to sync dotfile");
+        let (new_metadata, status) = dotfile
+            .sync(
+                &repo,
+                "dotfile",
+                &Config::default(),
+                Some(&metadata),
+                false,
+                false,
+            )
+            .unwrap();
+        assert_eq!(status, DotfileStatus::Skipped);
+        assert!(new_metadata.rolled_back);
+        assert_eq!(get_head(&repo).unwrap().id(), commit.id());
         assert_eq!(
-            fs::read_to_string(filepath).unwrap(),
-            "These are local changes on the system"
+            fs::read_to_string(repo_dir.path().join("dotfile")).unwrap(),
+            "installed contents\n"
         );
+
+        // Installing the dotfile again goes back to syncing it
+        let new_metadata = dotfile
+            .install(
+                &repo,
+                "dotfile",
+                Some(new_metadata),
+                true,
+                InstallMode::Force,
+                false,
+            )
+            .unwrap();
+        assert!(!new_metadata.rolled_back);
     }
 
     #[test]
@@ -770,7 +3646,7 @@ mod tests {
         File::create(filepath.to_owned()).expect("Could not create file in repo");
         let _commit = add_and_commit(
             &repo,
-            Some(vec![&filepath]),
+            Some(vec![Path::new("dotfile")]),
             "commit message",
             Some(vec![]),
             Some("HEAD"),
@@ -786,19 +3662,22 @@ mod tests {
             target: target_path.clone(),
             pre_install: None,
             post_install: None,
+            ..Default::default()
         };
 
         let metadata = DotfileMetadata {
             commit_hash: _commit.id().to_string(),
             pre_install_hash: "".to_string(),
             post_install_hash: "".to_string(),
+            ..Default::default()
         };
 
         let config = Config::default();
 
-        dotfile
-            .sync(&repo, "dotfile", &config, Some(&metadata))
+        let (_, status) = dotfile
+            .sync(&repo, "dotfile", &config, Some(&metadata), false, false)
             .expect("Failed to sync dotfile");
+        assert_eq!(status, DotfileStatus::Unchanged);
 
         // Check that the head commit of the repo is still the initial commit - i.e. no changes
         // have been committed
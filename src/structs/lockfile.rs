@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the lockfile, which is kept next to the manifest it locks
+pub const LOCKFILE: &str = "jtd.lock";
+
+/// Struct representing a `jtd.lock` file, generated by `jtd lock`. Records the exact commit each
+/// dotfile of the manifest next to it was installed from, so that other machines can install the
+/// same versions
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Lockfile {
+    #[serde(default)]
+    pub dotfiles: BTreeMap<String, LockedDotfile>,
+}
+
+/// The version of a single dotfile recorded in a [Lockfile]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LockedDotfile {
+    /// The hash of the commit the dotfile was installed from
+    pub commit: String,
+}
+
+impl Lockfile {
+    /// Read the lockfile in `manifest_dir`, returning None if there isn't one
+    pub fn read(manifest_dir: &Path) -> Result<Option<Lockfile>, Box<dyn Error>> {
+        let path = manifest_dir.join(LOCKFILE);
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(Some(serde_yaml::from_str(&contents).map_err(|err| {
+                format!(
+                    "Could not parse lockfile. Check {} for issues: {}",
+                    path.to_string_lossy(),
+                    err
+                )
+            })?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Write this lockfile to `manifest_dir`
+    pub fn write(&self, manifest_dir: &Path) -> Result<(), Box<dyn Error>> {
+        let contents = format!(
+            "# jointhedots lockfile, generated by \"jtd lock\". Install the locked versions with \
+            \"jtd install --locked\"\n{}",
+            serde_yaml::to_string(self)?
+        );
+        fs::write(manifest_dir.join(LOCKFILE), contents)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_lockfile_roundtrip() {
+        let dir = tempdir().unwrap();
+        assert_eq!(Lockfile::read(dir.path()).unwrap(), None);
+
+        let lockfile = Lockfile {
+            dotfiles: BTreeMap::from([(
+                "kitty".to_string(),
+                LockedDotfile {
+                    commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+                },
+            )]),
+        };
+        lockfile.write(dir.path()).unwrap();
+        assert_eq!(Lockfile::read(dir.path()).unwrap(), Some(lockfile));
+
+        fs::write(dir.path().join(LOCKFILE), "dotfiles: [").unwrap();
+        assert!(Lockfile::read(dir.path()).is_err());
+    }
+}
@@ -1,46 +1,277 @@
 use console::style;
-use dialoguer::{Confirm, MultiSelect};
 use git2::{Oid, Repository};
+use indexmap::IndexMap;
 use serde::Deserialize;
+use serde_json::json;
+use serde_yaml::{Mapping, Value};
 use std::{
     collections::HashMap,
     error::Error,
-    fs::File,
+    fs,
     path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    git::operations::{add_and_commit, get_repo_dir, push},
-    utils::get_theme,
+    git::operations::{
+        add_and_commit, ensure_ref_available, force_push_with_lease, get_head_branch, get_repo_dir,
+        integrate_remote_changes, is_in_head_history, mark_unpushed, push, rebase_onto_remote,
+        set_commit_trailers, set_signature_override, NonFastForwardError,
+    },
+    log::{log_operation, print_log},
+    picker::{pick_dotfiles, use_tui, PickerEntry},
+    prompt::prompter,
+    utils::{ensure_interactive, is_non_interactive, print_command_vec, trust_path},
 };
 
-use super::{AggregatedDotfileMetadata, Config, Dotfile};
+use super::{
+    AggregatedDotfileMetadata, Config, Dotfile, DotfileMetadata, DotfileReport, DotfileStatus,
+    InstallMode, LockedDotfile, Lockfile, MergeStrategy, OnConflict, RepositoryOrigin, SyncState,
+    TrustStore, UserConfig, DEFAULT_BACKUP_SUFFIX, LOCKFILE, UNKNOWN_REPOSITORY,
+};
+
+/// How many times to try pushing when the remote keeps changing before the push lands
+const PUSH_ATTEMPTS: usize = 3;
 
-/// Represents an aggregation of [Dotfile]s, as found in the `jtd.yaml` file. This is done via a
-/// mapping of `dotfile_name` to [Dotfile]
+/// Represents an aggregation of [Dotfile]s, as found in the `jtd.yaml` (or `jtd.toml`/`jtd.json`)
+/// file. This is done via a
+/// mapping of `dotfile_name` to [Dotfile], which keeps the order the dotfiles are listed in
 #[derive(Deserialize, Debug, Clone)]
 pub struct Manifest {
     #[serde(default, rename = ".config")]
     config: Config,
 
+    /// Named groups of dotfiles, which can be passed in place of the dotfiles they contain
+    #[serde(default, rename = ".groups")]
+    groups: HashMap<String, Vec<String>>,
+
     #[serde(flatten)]
-    data: HashMap<String, Dotfile>,
+    data: IndexMap<String, Dotfile>,
+
+    /// The repository and manifest this manifest was read from, which the metadata of its
+    /// installed dotfiles is stored under. Unknown if None
+    #[serde(skip)]
+    origin: Option<RepositoryOrigin>,
+
+    /// What to do when installing a dotfile over an existing file (`--on-conflict`). The user is
+    /// asked if None
+    #[serde(skip)]
+    on_conflict: Option<OnConflict>,
+
+    /// Whether the user picks which hunks of the changes to each dotfile to sync
+    /// (`--interactive-hunks`)
+    #[serde(skip)]
+    pick_hunks: bool,
+
+    /// The lockfile the dotfiles are installed from (`--locked`), if any
+    #[serde(skip)]
+    lockfile: Option<Lockfile>,
 }
 
 impl Manifest {
     pub fn get(path: &Path) -> Result<Manifest, Box<dyn Error>> {
-        let config: Manifest = serde_yaml::from_reader(File::open(path).map_err(|_| {
-            format!(
-                "Could not find manifest {} in repository.",
-                path.file_name()
-                    .map(|v| v.to_string_lossy())
-                    .unwrap_or_else(|| "N/A".into())
-            )
-        })?)
-        .map_err(|err| format!("Could not parse manifest: {}", err))?;
-        Ok(config)
+        let mut value = read_manifest_value(path)?;
+        UserConfig::get()?.commit.apply_to(&mut value)?;
+        let config: Manifest = serde_yaml::from_value(value)
+            .map_err(|err| format!("Could not parse manifest: {}", err))?;
+        Ok(config.with_config_defaults())
+    }
+
+    /// Apply the `ref` and `shell` in the config, if any, to every dotfile that doesn't set its own
+    /// and resolve the `file` of every dotfile relative to the `root` in the config
+    fn with_config_defaults(mut self) -> Self {
+        if let Some(root) = self.config.root.clone() {
+            self.resolve_files(&root);
+        }
+        for dotfile in self.data.values_mut() {
+            if let Some(git_ref) = &self.config.git_ref {
+                dotfile.git_ref.get_or_insert_with(|| git_ref.to_string());
+            }
+            if let Some(shell) = &self.config.shell {
+                dotfile.shell.get_or_insert_with(|| shell.to_string());
+            }
+            if let Some(backup_suffix) = &self.config.backup_suffix {
+                dotfile
+                    .backup_suffix
+                    .get_or_insert_with(|| backup_suffix.to_string());
+            }
+        }
+        self
+    }
+
+    /// Set the repository and manifest this manifest was read from. If the manifest is in a
+    /// subdirectory of the repository and doesn't set its own `root`, the `file` of every dotfile
+    /// is resolved relative to that subdirectory
+    pub fn set_origin(&mut self, origin: RepositoryOrigin) {
+        if let (None, Some(subdir)) = (&self.config.root, &origin.subdir) {
+            self.resolve_files(subdir);
+        }
+        self.origin = Some(origin);
+    }
+
+    /// Mark the dotfiles whose `only_if` conditions may be run without asking: all of them if
+    /// `trust` is set (`--trust`) or the repository is in the trust store, and otherwise those whose
+    /// conditions the user has trusted before
+    pub fn load_trust(&mut self, trust: bool) -> Result<(), Box<dyn Error>> {
+        let trust_store = TrustStore::read(&trust_path())?;
+        let trust = trust || trust_store.trusts_repository(self.repository());
+        for dotfile in self.data.values_mut() {
+            dotfile.commands_trusted =
+                trust || trust_store.trusts_hashes(&dotfile.command_hashes());
+        }
+        Ok(())
+    }
+
+    /// Make the `file` of every dotfile, given relative to the directory `root` of the
+    /// repository, relative to the top of the repository instead
+    fn resolve_files(&mut self, root: &str) {
+        let root = root.trim_start_matches("./").trim_matches('/');
+        if root.is_empty() || root == "." {
+            return;
+        }
+        for dotfile in self.data.values_mut() {
+            dotfile.file = format!("{}/{}", root, dotfile.file.trim_start_matches("./"));
+        }
+    }
+
+    /// Return the repository the metadata of the dotfiles in this manifest is stored under
+    fn repository(&self) -> &str {
+        self.origin
+            .as_ref()
+            .map_or(UNKNOWN_REPOSITORY, |origin| origin.repository.as_str())
+    }
+
+    /// Override the `merge_strategy` in the config of this manifest
+    pub fn set_merge_strategy(&mut self, strategy: MergeStrategy) {
+        self.config.merge_strategy = strategy;
+    }
+
+    /// Override the `push_branch` in the config of this manifest
+    pub fn set_push_branch(&mut self, branch: String) {
+        self.config.push_branch = Some(branch);
+    }
+
+    /// Keep a copy of every target overwritten by installing next to it (`--keep-backup`), with the
+    /// `backup_suffix` of its dotfile or [DEFAULT_BACKUP_SUFFIX]
+    pub fn keep_backups(&mut self) {
+        for dotfile in self.data.values_mut() {
+            dotfile
+                .backup_suffix
+                .get_or_insert_with(|| DEFAULT_BACKUP_SUFFIX.to_string());
+        }
+    }
+
+    /// Set what to do when installing a dotfile over an existing file, instead of asking
+    pub fn set_on_conflict(&mut self, on_conflict: OnConflict) {
+        self.on_conflict = Some(on_conflict);
+    }
+
+    /// Let the user pick which hunks of the changes to each dotfile to sync, like `git add -p`,
+    /// instead of syncing all of them
+    pub fn pick_hunks(&mut self) {
+        self.pick_hunks = true;
+    }
+
+    /// Return the lockfile of this manifest, recording the commit each of its dotfiles installed on
+    /// this machine was installed from. Dotfiles that aren't installed here keep the commit they
+    /// are locked to in `existing`, if any, so that machines with different dotfiles installed can
+    /// share the lockfile.
+    ///
+    /// Every locked commit must be reachable from `HEAD`, as other machines couldn't install from
+    /// it otherwise
+    pub fn lock(
+        &self,
+        repo: &Repository,
+        aggregated_metadata: &AggregatedDotfileMetadata,
+        existing: Option<Lockfile>,
+    ) -> Result<Lockfile, Box<dyn Error>> {
+        let mut existing = existing.unwrap_or_default();
+        let mut lockfile = Lockfile::default();
+        for dotfile_name in self.data.keys() {
+            let locked = match aggregated_metadata.dotfile(self.repository(), dotfile_name) {
+                Some(metadata) => {
+                    if !is_in_head_history(repo, &metadata.commit_hash)? {
+                        return Err(format!(
+                            "{} was installed from commit {}, which isn't in the history of the \
+                             repository. Push it or reinstall the dotfile first",
+                            dotfile_name, metadata.commit_hash
+                        )
+                        .into());
+                    }
+                    LockedDotfile {
+                        commit: metadata.commit_hash.to_string(),
+                    }
+                }
+                None => match existing.dotfiles.remove(dotfile_name) {
+                    Some(locked) => locked,
+                    None => continue,
+                },
+            };
+            lockfile.dotfiles.insert(dotfile_name.to_string(), locked);
+        }
+        Ok(lockfile)
+    }
+
+    /// Install every dotfile from the commit it is locked to in `lockfile` (`--locked`), instead of
+    /// its `ref` or `HEAD`. Dotfiles that aren't locked fail to install. Fails if any locked commit
+    /// isn't in the history of the repository, fetching the full history of shallow clones first
+    pub fn set_lockfile(
+        &mut self,
+        repo: &Repository,
+        lockfile: Lockfile,
+    ) -> Result<(), Box<dyn Error>> {
+        for (dotfile_name, locked) in &lockfile.dotfiles {
+            if let Some(dotfile) = self.data.get_mut(dotfile_name) {
+                ensure_ref_available(repo, &locked.commit)?;
+                if !is_in_head_history(repo, &locked.commit)? {
+                    return Err(format!(
+                        "{} is locked to commit {}, which isn't in the history of the repository",
+                        dotfile_name, locked.commit
+                    )
+                    .into());
+                }
+                dotfile.git_ref = Some(locked.commit.to_string());
+            }
+        }
+        self.lockfile = Some(lockfile);
+        Ok(())
+    }
+
+    /// Write `lockfile` next to this manifest in `manifest_dir` and commit it
+    pub fn commit_lockfile(
+        &self,
+        repo: &Repository,
+        manifest_dir: &Path,
+        lockfile: &Lockfile,
+    ) -> Result<(), Box<dyn Error>> {
+        lockfile.write(manifest_dir)?;
+        let lockfile_path = manifest_dir
+            .join(LOCKFILE)
+            .strip_prefix(get_repo_dir(repo))?
+            .to_path_buf();
+        set_signature_override(
+            self.config.author_name.as_deref(),
+            self.config.author_email.as_deref(),
+        );
+        set_commit_trailers(self.config.commit_trailers(), self.config.sign_off);
+        add_and_commit(
+            repo,
+            Some(vec![&lockfile_path]),
+            &format!("Update {}", LOCKFILE),
+            None,
+            Some("HEAD"),
+        )?;
+        Ok(())
+    }
+
+    /// Return the [Dotfile] with the given name, if it exists in this manifest
+    pub fn get_dotfile(&self, dotfile_name: &str) -> Option<&Dotfile> {
+        self.data.get(dotfile_name)
     }
 
+    /// Install the target dotfiles from this manifest, returning a [DotfileReport] for each of
+    /// them. A dotfile that fails to install is reported as [DotfileStatus::Failed] and the rest
+    /// are still installed.
     pub fn install(
         &self,
         repo: &Repository,
@@ -48,103 +279,534 @@ impl Manifest {
         target_dotfiles: Vec<String>,
         force_install: bool,
         trust: bool,
-    ) -> Result<(), Box<dyn Error>> {
-        let theme = get_theme();
+        dry_run: bool,
+    ) -> Result<Vec<DotfileReport>, Box<dyn Error>> {
+        let dotfiles = self.get_target_dotfiles(repo, target_dotfiles, install_all)?;
+        self.install_dotfiles(repo, dotfiles, vec![], force_install, trust, dry_run, true)
+    }
 
-        let mut skip_install_commands = false;
+    /// Reinstall the installed dotfiles whose files or install steps have changed in the
+    /// repository since they were installed, returning a [DotfileReport] for each installed
+    /// dotfile in this manifest. Unchanged dotfiles are reported as [DotfileStatus::Unchanged].
+    ///
+    /// If `target_dotfiles` is empty, every installed dotfile is checked. Dotfiles with local
+    /// changes aren't overwritten unless `force_install` is set.
+    pub fn update(
+        &self,
+        repo: &Repository,
+        target_dotfiles: Vec<String>,
+        force_install: bool,
+        trust: bool,
+        dry_run: bool,
+    ) -> Result<Vec<DotfileReport>, Box<dyn Error>> {
+        let aggregated_metadata = AggregatedDotfileMetadata::get_or_create()?;
+        let target_dotfiles = self.expand_groups(target_dotfiles);
 
-        let dotfiles = self.get_target_dotfiles(target_dotfiles, install_all);
-        let mut aggregated_metadata = AggregatedDotfileMetadata::get_or_create()?;
+        let dotfile_names: Vec<&String> = self
+            .data
+            .keys()
+            .filter(|dotfile_name| {
+                aggregated_metadata
+                    .dotfile(self.repository(), dotfile_name)
+                    .is_some()
+            })
+            .filter(|dotfile_name| {
+                target_dotfiles.is_empty() || target_dotfiles.contains(dotfile_name)
+            })
+            .collect();
+        for target_dotfile in target_dotfiles.iter() {
+            if aggregated_metadata
+                .dotfile(self.repository(), target_dotfile)
+                .is_none()
+            {
+                return Err(format!("Dotfile {} is not installed", target_dotfile).into());
+            }
+        }
 
-        if !trust
-            && self.has_unexecuted_run_stages(
-                Some(dotfiles.iter().map(|(v, _)| v.as_str()).collect()),
-                &aggregated_metadata,
-            )
-        {
-            warn!(
-                "Some of the dotfiles being installed contain pre_install and/or post_install \
-                steps. If you do not trust this manifest, you can skip running them."
-            );
-            skip_install_commands = Confirm::with_theme(&theme)
-                .with_prompt("Skip running pre/post install?")
-                .default(false)
-                .wait_for_newline(true)
-                .interact()
-                .unwrap();
+        let mut changed = vec![];
+        let mut reports = vec![];
+        for dotfile_name in dotfile_names {
+            let dotfile = &self.data[dotfile_name];
+            let metadata = aggregated_metadata
+                .dotfile(self.repository(), dotfile_name)
+                .ok_or_else(|| format!("Dotfile {} is not installed", dotfile_name))?;
+            if let Some(restored_ref) = &metadata.restored_ref {
+                info!(
+                    "Skipping {} as it was restored to {}. Install it to go back to the latest \
+                    version",
+                    dotfile_name, restored_ref
+                );
+                reports.push(DotfileReport::new(
+                    dotfile_name,
+                    DotfileStatus::Skipped,
+                    Some(metadata.commit_hash.to_string()),
+                ));
+            } else if metadata.rolled_back {
+                info!(
+                    "Skipping {} as it was rolled back. Install it to go back to the latest version",
+                    dotfile_name
+                );
+                reports.push(DotfileReport::new(
+                    dotfile_name,
+                    DotfileStatus::Skipped,
+                    Some(metadata.commit_hash.to_string()),
+                ));
+            } else if dotfile.needs_update(repo, metadata)? {
+                changed.push((dotfile_name, dotfile));
+            } else {
+                info!("Skipping {} as it is up to date", dotfile_name);
+                reports.push(DotfileReport::new(
+                    dotfile_name,
+                    DotfileStatus::Unchanged,
+                    Some(metadata.commit_hash.to_string()),
+                ));
+            }
         }
 
-        let repo_dir = get_repo_dir(&repo);
+        if changed.is_empty() {
+            success!("All dotfiles are up to date");
+            return Ok(reports);
+        }
+        self.install_dotfiles(repo, changed, reports, force_install, trust, dry_run, false)
+    }
+
+    /// Install each of `dotfiles`, adding a report for each of them to `reports`. If
+    /// `confirm_overwrite` is set, the user is asked before overwriting any existing file
+    #[allow(clippy::too_many_arguments)]
+    fn install_dotfiles(
+        &self,
+        repo: &Repository,
+        dotfiles: Vec<(&String, &Dotfile)>,
+        mut reports: Vec<DotfileReport>,
+        force_install: bool,
+        trust: bool,
+        dry_run: bool,
+        confirm_overwrite: bool,
+    ) -> Result<Vec<DotfileReport>, Box<dyn Error>> {
+        let mut aggregated_metadata = AggregatedDotfileMetadata::get_or_create()?;
+        let mut trust_store = TrustStore::read(&trust_path())?;
+        let trusted_store = trust_store.clone();
+        let trust = trust || trust_store.trusts_repository(self.repository());
+
+        let repo_dir = get_repo_dir(repo);
+        let installed_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
 
         for (dotfile_name, dotfile) in dotfiles {
+            if let Some(lockfile) = &self.lockfile {
+                if !lockfile.dotfiles.contains_key(dotfile_name) {
+                    error!(
+                        "Failed to install {}: it isn't locked in {}, lock it with \"jtd lock\"",
+                        dotfile_name, LOCKFILE
+                    );
+                    reports.push(DotfileReport::new(
+                        dotfile_name,
+                        DotfileStatus::Failed,
+                        None,
+                    ));
+                    continue;
+                }
+            }
+
+            let maybe_metadata = aggregated_metadata
+                .dotfile(self.repository(), dotfile_name)
+                .cloned();
+
+            // The commands from the repository are trusted (or not) before any of them are run
+            let ask_commands = dotfile.has_untrusted_commands();
+            let ask_steps = dotfile.has_unexecuted_run_stages(&maybe_metadata.as_ref())
+                && !trust_store.trusts_hashes(&dotfile.install_step_hashes());
+            let declined = !trust
+                && !dry_run
+                && (ask_commands || ask_steps)
+                && self.decline_commands(
+                    dotfile_name,
+                    dotfile,
+                    ask_commands,
+                    ask_steps,
+                    &maybe_metadata,
+                    &mut trust_store,
+                )?;
+            let skip_install_commands = declined && ask_steps;
+            let trusted_dotfile;
+            let dotfile = if ask_commands && !declined && (trust || !dry_run) {
+                trusted_dotfile = dotfile.trusted();
+                &trusted_dotfile
+            } else {
+                dotfile
+            };
+
+            if dotfile.has_untrusted_commands() {
+                info!(
+                    "Skipping installing {} as its {} aren't trusted. Pass \"--trust\" to run them",
+                    dotfile_name,
+                    dotfile.described_commands()
+                );
+                reports.push(DotfileReport::new(
+                    dotfile_name,
+                    DotfileStatus::Skipped,
+                    None,
+                ));
+                continue;
+            }
+            if !dotfile.conditions_met(dotfile_name, repo_dir)? {
+                info!(
+                    "Skipping installing {} as its only_if conditions were not met",
+                    dotfile_name
+                );
+                reports.push(DotfileReport::new(
+                    dotfile_name,
+                    DotfileStatus::Skipped,
+                    None,
+                ));
+                continue;
+            }
+
             let mut origin_path_buf = PathBuf::from(&repo_dir);
             origin_path_buf.push(&dotfile.file);
 
-            if dotfile.target.exists() && !force_install {
-                let force = Confirm::with_theme(&theme)
-                    .with_prompt(format!(
-                        "Dotfile \"{}\" already exists on disk. Overwrite?",
-                        dotfile_name
-                    ))
-                    .default(false)
-                    .interact()
-                    .unwrap();
-                if !force {
+            let target_exists = confirm_overwrite && dotfile.target_path().exists();
+
+            let on_conflict = if !target_exists || force_install {
+                None
+            } else if self.on_conflict.is_some() {
+                self.on_conflict
+            } else if dry_run {
+                info!(
+                    "Dotfile \"{}\" already exists on disk, you would be asked what to do with it",
+                    dotfile_name
+                );
+                None
+            } else if is_non_interactive() {
+                None
+            } else {
+                Some(Manifest::choose_on_conflict(repo, dotfile_name, dotfile)?)
+            };
+            let mode = match on_conflict {
+                Some(OnConflict::Keep) => {
+                    info!(
+                        "Keeping the existing {}",
+                        dotfile.target_path().to_string_lossy()
+                    );
+                    reports.push(DotfileReport::new(
+                        dotfile_name,
+                        DotfileStatus::Skipped,
+                        None,
+                    ));
                     continue;
                 }
+                Some(OnConflict::Overwrite) => InstallMode::Force,
+                Some(OnConflict::Merge) => InstallMode::Merge,
+                None if force_install => InstallMode::Force,
+                None => InstallMode::Safe,
+            };
+            if !dry_run && !dotfile.confirm_root_write(dotfile_name, trust)? {
+                reports.push(DotfileReport::new(
+                    dotfile_name,
+                    DotfileStatus::Skipped,
+                    None,
+                ));
+                continue;
             }
 
-            println!("Commencing install for {}", dotfile_name);
+            print_log(format!("Commencing install for {}", dotfile_name));
 
-            let maybe_metadata = aggregated_metadata
-                .data
-                .get(dotfile_name)
-                .map(|d| (*d).clone());
+            let mut metadata = match dotfile.install(
+                repo,
+                dotfile_name,
+                maybe_metadata,
+                skip_install_commands,
+                mode,
+                dry_run,
+            ) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    error!("Failed to install {}: {}", dotfile_name, err);
+                    reports.push(DotfileReport::new(
+                        dotfile_name,
+                        DotfileStatus::Failed,
+                        None,
+                    ));
+                    continue;
+                }
+            };
+            metadata.installed_at = Some(installed_at);
+            metadata.jtd_version = Some(env!("CARGO_PKG_VERSION").to_string());
+            metadata.repo_url = self
+                .origin
+                .as_ref()
+                .map(|origin| origin.repository.to_string());
+            metadata.declined_steps_hash = if skip_install_commands {
+                Some(dotfile.hash_install_steps())
+            } else {
+                None
+            };
 
-            let metadata =
-                dotfile.install(&repo, maybe_metadata, skip_install_commands, force_install)?;
+            if !dry_run {
+                log_operation(
+                    "installed",
+                    json!({
+                        "dotfile": dotfile_name,
+                        "target": dotfile.target_path(),
+                        "commit": metadata.commit_hash,
+                        "skipped_install_steps": skip_install_commands,
+                    }),
+                );
+            }
+            let status = if dry_run {
+                DotfileStatus::WouldInstall
+            } else {
+                DotfileStatus::Installed
+            };
+            reports.push(DotfileReport::new(
+                dotfile_name,
+                status,
+                Some(metadata.commit_hash.to_string()),
+            ));
+            aggregated_metadata.insert_dotfile(self.origin.as_ref(), dotfile_name, metadata);
+        }
 
-            aggregated_metadata
-                .data
-                .insert(dotfile_name.to_string(), metadata);
+        if dry_run {
+            info!("Dry run complete, no changes were made");
+            return Ok(reports);
         }
 
         aggregated_metadata.save()?;
-        Ok(())
+        if trust_store != trusted_store {
+            trust_store.write(&trust_path())?;
+        }
+        Ok(reports)
+    }
+
+    /// Ask what to do with the existing target of `dotfile` when installing it, showing the diff
+    /// against the version in the repository as many times as asked for
+    fn choose_on_conflict(
+        repo: &Repository,
+        dotfile_name: &str,
+        dotfile: &Dotfile,
+    ) -> Result<OnConflict, Box<dyn Error>> {
+        loop {
+            let choice = prompter().choose(
+                &format!(
+                    "Dotfile \"{}\" already exists on disk. What should be done with it?",
+                    dotfile_name
+                ),
+                &[
+                    "Keep the local file",
+                    "Use the version in the repository",
+                    "View the diff",
+                    "Merge them",
+                ]
+                .map(str::to_string),
+                0,
+            )?;
+            match choice {
+                0 => return Ok(OnConflict::Keep),
+                1 => return Ok(OnConflict::Overwrite),
+                2 => {
+                    if let Some(diff) = dotfile.diff(repo, false)? {
+                        print_log(diff);
+                    } else {
+                        info!("The local file is the same as the version in the repository");
+                    }
+                }
+                _ => return Ok(OnConflict::Merge),
+            }
+        }
+    }
+
+    /// Show the commands of `dotfile` that come from the repository and ask whether to run them,
+    /// returning whether they should be skipped: its `only_if` conditions if `ask_commands` is set
+    /// and its install steps if `ask_steps` is set. Install steps the user declined in a previous
+    /// install are skipped without asking again unless they have changed since. Commands the user
+    /// agrees to run (or the whole repository, if the user chooses to always trust it) are added to
+    /// `trust_store`.
+    fn decline_commands(
+        &self,
+        dotfile_name: &str,
+        dotfile: &Dotfile,
+        ask_commands: bool,
+        ask_steps: bool,
+        maybe_metadata: &Option<DotfileMetadata>,
+        trust_store: &mut TrustStore,
+    ) -> Result<bool, Box<dyn Error>> {
+        if let (false, Some(metadata)) = (ask_commands, maybe_metadata) {
+            let declined = metadata
+                .declined_steps_hash
+                .as_deref()
+                .is_some_and(|hash| dotfile.install_steps_match(hash));
+            if declined {
+                info!(
+                    "Skipping the install steps of {} as they were declined in a previous \
+                    install. Pass \"--trust\" to run them",
+                    dotfile_name
+                );
+                return Ok(true);
+            }
+        }
+        let described = match (ask_commands, ask_steps) {
+            (true, true) => format!("{} and install steps", dotfile.described_commands()),
+            (true, false) => dotfile.described_commands(),
+            _ => "install steps".to_string(),
+        };
+        if is_non_interactive() {
+            info!(
+                "Running non-interactively, skipping the {} of {}. Pass \"--trust\" to run them",
+                described, dotfile_name
+            );
+            return Ok(true);
+        }
+
+        warn!(
+            "{} contains {}. If you do not trust this manifest, you can skip running them.",
+            dotfile_name, described
+        );
+        if ask_commands {
+            for (name, commands) in [
+                ("only_if conditions", &dotfile.only_if),
+                ("sync_filter", &dotfile.sync_filter),
+                ("install_filter", &dotfile.install_filter),
+            ] {
+                if let Some(commands) = commands {
+                    print_log(format!("{}:", name));
+                    print_command_vec(commands);
+                }
+            }
+            info!(
+                "{} is skipped if its {} aren't run",
+                dotfile_name,
+                dotfile.described_commands()
+            );
+        }
+        if ask_steps {
+            for (stage, steps) in [
+                ("pre-install", &dotfile.pre_install),
+                ("post-install", &dotfile.post_install),
+            ] {
+                if let Some(steps) = steps {
+                    print_log(format!("{} steps:", stage));
+                    print_command_vec(steps);
+                }
+            }
+        }
+
+        let mut choices = vec!["Run them".to_string(), "Skip them".to_string()];
+        if let Some(origin) = &self.origin {
+            choices.push(format!(
+                "Run them and always trust {} from now on",
+                origin.repository
+            ));
+        }
+        let choice = prompter().choose(
+            &format!("Run the {} of {}?", described, dotfile_name),
+            &choices,
+            0,
+        )?;
+        match choice {
+            0 => {
+                let mut hashes = vec![];
+                if ask_commands {
+                    hashes.extend(dotfile.command_hashes());
+                }
+                if ask_steps {
+                    hashes.extend(dotfile.install_step_hashes());
+                }
+                trust_store
+                    .hashes
+                    .extend(hashes.into_iter().filter(|hash| !hash.is_empty()));
+            }
+            1 => return Ok(true),
+            _ => {
+                trust_store
+                    .repositories
+                    .insert(self.repository().to_string());
+            }
+        }
+        Ok(false)
+    }
+
+    /// Return the names of the dotfiles and groups in this manifest, as they can be passed to
+    /// subcommands
+    pub fn names(&self) -> Vec<&String> {
+        self.data.keys().chain(self.groups.keys()).collect()
+    }
+
+    /// Replace any group names in `names` with the names of the dotfiles in that group, keeping
+    /// the order the names were given in and dropping duplicates
+    fn expand_groups(&self, names: Vec<String>) -> Vec<String> {
+        let mut expanded: Vec<String> = vec![];
+        for name in names {
+            let members = match self.groups.get(&name) {
+                Some(members) if !self.data.contains_key(&name) => members.clone(),
+                _ => vec![name],
+            };
+            for member in members {
+                if !expanded.contains(&member) {
+                    expanded.push(member);
+                }
+            }
+        }
+        expanded
     }
 
     fn get_target_dotfiles(
         &self,
+        repo: &Repository,
         target_dotfiles: Vec<String>,
         all: bool,
-    ) -> Vec<(&String, &Dotfile)> {
-        let theme = get_theme();
-
+    ) -> Result<Vec<(&String, &Dotfile)>, Box<dyn Error>> {
         if all {
-            self.data.iter().collect()
+            Ok(self.data.iter().collect())
         } else if !target_dotfiles.is_empty() {
-            self.data
+            let target_dotfiles = self.expand_groups(target_dotfiles);
+            Ok(self
+                .data
                 .iter()
                 .filter(|(dotfile_name, _)| target_dotfiles.contains(dotfile_name))
-                .collect()
+                .collect())
         } else {
-            let dotfile_names = &self
-                .clone()
-                .into_iter()
-                .map(|pair| pair.0)
-                .collect::<Vec<String>>();
-            let selected = MultiSelect::with_theme(&theme)
-                .with_prompt("Select the dotfiles you wish to install. Use \"SPACE\" to select and \"ENTER\" to proceed.")
-                .items(dotfile_names)
-                .interact()
-                .unwrap();
+            ensure_interactive(
+                "No dotfiles were specified (pass their names or \"--all\") so they must be selected",
+            )?;
 
-            self.data
+            // Already installed dotfiles are selected to begin with, as installing or syncing the
+            // dotfiles already managed by jtd is the most common case
+            let aggregated_metadata = AggregatedDotfileMetadata::get()?.unwrap_or_default();
+            let installed = self
+                .data
+                .keys()
+                .map(|dotfile_name| {
+                    aggregated_metadata
+                        .dotfile(self.repository(), dotfile_name)
+                        .is_some()
+                })
+                .collect::<Vec<bool>>();
+
+            let selected = if use_tui() {
+                let entries = self
+                    .data
+                    .iter()
+                    .zip(&installed)
+                    .map(|((dotfile_name, dotfile), installed)| {
+                        dotfile.picker_entry(dotfile_name, repo, *installed)
+                    })
+                    .collect::<Vec<PickerEntry>>();
+                pick_dotfiles("Dotfiles", &entries)?
+            } else {
+                let dotfile_names = &self.data.keys().cloned().collect::<Vec<String>>();
+                prompter().select(
+                    "Select the dotfiles you wish to install. Use \"SPACE\" to select and \"ENTER\" to proceed.",
+                    dotfile_names,
+                    &installed,
+                )?
+            };
+
+            Ok(self
+                .data
                 .iter()
                 .enumerate()
                 .filter(|(index, (_, _))| selected.contains(index))
                 .map(|(_, (name, dotfile))| (name, dotfile))
-                .collect()
+                .collect())
         }
     }
 
@@ -162,10 +824,15 @@ impl Manifest {
             .iter()
             .filter(|(dotfile_name, _)| dotfile_names.contains(&dotfile_name.as_str()))
             .any(|(dotfile_name, dotfile)| {
-                dotfile.has_unexecuted_run_stages(&metadata.data.get(dotfile_name))
+                dotfile
+                    .has_unexecuted_run_stages(&metadata.dotfile(self.repository(), dotfile_name))
             })
     }
 
+    /// Sync the target dotfiles from this manifest back into the repository and push the changes
+    /// to the `push_remote` remote (if `None`, they are only committed), returning a
+    /// [DotfileReport] for each of them
+    #[allow(clippy::too_many_arguments)]
     pub fn sync(
         &self,
         repo: &Repository,
@@ -174,46 +841,123 @@ impl Manifest {
         commit_msg: Option<&str>,
         aggregated_metadata: Option<AggregatedDotfileMetadata>,
         use_naive_sync: bool,
-    ) -> Result<(), Box<dyn Error>> {
-        let theme = get_theme();
-
-        let dotfiles = self.get_target_dotfiles(target_dotfiles, sync_all);
+        dry_run: bool,
+        push_remote: Option<&str>,
+    ) -> Result<Vec<DotfileReport>, Box<dyn Error>> {
+        let dotfiles = self.get_target_dotfiles(repo, target_dotfiles, sync_all)?;
+        set_signature_override(
+            self.config.author_name.as_deref(),
+            self.config.author_email.as_deref(),
+        );
+        set_commit_trailers(self.config.commit_trailers(), self.config.sign_off);
         let mut commit_hashes = vec![];
+        let mut statuses = vec![];
 
-        if aggregated_metadata.is_none() && !use_naive_sync {
-            println!(
-                "{}",
+        if aggregated_metadata.is_none() && !use_naive_sync && dry_run {
+            info!("Could not find any metadata on the currently installed dotfiles, you would be prompted to use naive sync");
+        } else if aggregated_metadata.is_none() && !use_naive_sync {
+            print_log(
                 style(
                     "Could not find any metadata on the currently installed dotfiles. Proceed with naive sync and overwrite remote files?"
                 )
                 .yellow()
             );
-            if !Confirm::with_theme(&theme)
-                .with_prompt("Use naive sync?")
-                .default(false)
-                .wait_for_newline(true)
-                .interact()
-                .unwrap()
-            {
+            if !is_non_interactive() && !prompter().confirm("Use naive sync?", false)? {
                 return Err("Aborting due to lack of dotfile metadata".into());
             }
         }
 
         let mut aggregated_metadata = aggregated_metadata.unwrap_or_default();
+        let resolutions =
+            self.resolve_diverged_dotfiles(repo, &dotfiles, &aggregated_metadata, dry_run)?;
 
+        let mut trust_store = TrustStore::read(&trust_path())?;
+        let trusted_store = trust_store.clone();
+        let repo_dir = get_repo_dir(repo);
         for (dotfile_name, dotfile) in dotfiles.iter() {
-            println!("Syncing {}", dotfile_name);
-            let new_metadata = dotfile.sync(
+            let ask_commands = dotfile.has_untrusted_commands() && !dry_run;
+            let declined = ask_commands
+                && self.decline_commands(
+                    dotfile_name,
+                    dotfile,
+                    true,
+                    false,
+                    &None,
+                    &mut trust_store,
+                )?;
+            let trusted_dotfile;
+            let dotfile = if ask_commands && !declined {
+                trusted_dotfile = dotfile.trusted();
+                &trusted_dotfile
+            } else {
+                *dotfile
+            };
+
+            if dotfile.has_untrusted_commands() {
+                info!(
+                    "Skipping syncing {} as its {} aren't trusted",
+                    dotfile_name,
+                    dotfile.described_commands()
+                );
+                statuses.push((dotfile_name.as_str(), DotfileStatus::Skipped));
+                continue;
+            }
+            if !dotfile.conditions_met(dotfile_name, repo_dir)? {
+                info!(
+                    "Skipping syncing {} as its only_if conditions were not met",
+                    dotfile_name
+                );
+                statuses.push((dotfile_name.as_str(), DotfileStatus::Skipped));
+                continue;
+            }
+
+            let mut config = self.config.clone();
+            match resolutions.get(dotfile_name.as_str()) {
+                Some(None) => {
+                    info!("Skipping syncing {} as it has diverged", dotfile_name);
+                    statuses.push((dotfile_name.as_str(), DotfileStatus::Skipped));
+                    continue;
+                }
+                Some(Some(strategy)) => config.merge_strategy = *strategy,
+                None => {}
+            }
+
+            print_log(format!("Syncing {}", dotfile_name));
+            let (new_metadata, status) = dotfile.sync(
                 repo,
                 dotfile_name,
-                &self.config,
-                aggregated_metadata.data.get(dotfile_name.as_str()),
+                &config,
+                aggregated_metadata.dotfile(self.repository(), dotfile_name),
+                self.pick_hunks,
+                dry_run,
             )?;
+            let status = match status {
+                DotfileStatus::Synced if dry_run => DotfileStatus::WouldSync,
+                status => status,
+            };
 
-            commit_hashes.push(new_metadata.commit_hash.to_owned());
-            aggregated_metadata
-                .data
-                .insert((*dotfile_name).to_string(), new_metadata);
+            statuses.push((dotfile_name.as_str(), status));
+            // Only the commits made by this sync should be squashed
+            if status == DotfileStatus::Synced {
+                commit_hashes.push(new_metadata.commit_hash.to_owned());
+            }
+            aggregated_metadata.insert_dotfile(self.origin.as_ref(), dotfile_name, new_metadata);
+        }
+        if trust_store != trusted_store {
+            trust_store.write(&trust_path())?;
+        }
+
+        if dry_run {
+            if self.config.squash_commits {
+                info!("Would squash the sync commits into a single commit");
+            }
+            if let Some(branch) = self.config.push_branch() {
+                info!("Would push the changes to {} on the remote repo", branch);
+            } else {
+                info!("Would push the changes to the remote repo");
+            }
+            info!("Dry run complete, no changes were made");
+            return Ok(self.generate_sync_reports(statuses, &aggregated_metadata));
         }
 
         if self.config.squash_commits {
@@ -244,12 +988,9 @@ impl Manifest {
                 let commit_hash = add_and_commit(repo, None, &commit_msg, None, Some("HEAD"))?
                     .id()
                     .to_string();
-                for (dotfile_name, metadata) in aggregated_metadata.data.iter_mut() {
-                    if dotfiles
-                        .iter()
-                        .map(|(name, _dotfile)| name)
-                        .any(|s| s == &dotfile_name)
-                        || sync_all
+                for (dotfile_name, _) in dotfiles.iter() {
+                    if let Some(metadata) =
+                        aggregated_metadata.dotfile_mut(self.repository(), dotfile_name)
                     {
                         metadata.commit_hash = commit_hash.to_owned();
                     }
@@ -259,19 +1000,516 @@ impl Manifest {
             info!("Not squashing commits");
         }
 
-        push(repo)?;
-
-        success!("Successfully synced changes!");
+        if let Some(remote_name) = push_remote {
+            let rewritten = self.push_to(repo, remote_name)?;
+            aggregated_metadata.rewrite_commit_hashes(&rewritten);
+            success!("Successfully synced changes!");
+        } else {
+            mark_unpushed(repo)?;
+            success!("Committed changes without pushing them");
+        }
 
+        aggregated_metadata.last_synced_at =
+            Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
         aggregated_metadata.save()?;
+        Ok(self.generate_sync_reports(statuses, &aggregated_metadata))
+    }
+
+    /// Classify each of the target `dotfiles` that has been synced before with
+    /// [Dotfile::sync_state] and print the result, then ask how each diverged dotfile should be
+    /// synced instead of merging it outright.
+    ///
+    /// # Returns
+    ///
+    /// The merge strategy chosen for each diverged dotfile, or None if it should be skipped.
+    /// Dotfiles without an entry are synced with the configured merge strategy
+    fn resolve_diverged_dotfiles(
+        &self,
+        repo: &Repository,
+        dotfiles: &[(&String, &Dotfile)],
+        aggregated_metadata: &AggregatedDotfileMetadata,
+        dry_run: bool,
+    ) -> Result<HashMap<String, Option<MergeStrategy>>, Box<dyn Error>> {
+        let states = dotfiles
+            .iter()
+            .filter_map(|(dotfile_name, dotfile)| {
+                let metadata = aggregated_metadata.dotfile(self.repository(), dotfile_name)?;
+                // Dotfiles that can't be classified, e.g. as they aren't installed, fail or are
+                // skipped when synced with a clearer error
+                let state = dotfile.sync_state(repo, metadata).ok()?;
+                Some((dotfile_name.as_str(), state))
+            })
+            .collect::<Vec<(&str, SyncState)>>();
+
+        if states
+            .iter()
+            .all(|(_, state)| *state == SyncState::UpToDate)
+        {
+            return Ok(HashMap::new());
+        }
+        for (dotfile_name, state) in states.iter() {
+            print_log(format!("{}: {}", dotfile_name, state.describe()));
+        }
+
+        let mut resolutions = HashMap::new();
+        let diverged = states
+            .iter()
+            .filter(|(_, state)| *state == SyncState::Diverged)
+            .map(|(dotfile_name, _)| *dotfile_name);
+        for dotfile_name in diverged {
+            if dry_run || is_non_interactive() {
+                info!(
+                    "{} has diverged, its local and remote changes will be merged",
+                    dotfile_name
+                );
+                continue;
+            }
+
+            let choices = [
+                (
+                    format!(
+                        "Merge the local and remote changes ({} merge strategy)",
+                        format!("{:?}", self.config.merge_strategy).to_lowercase()
+                    ),
+                    Some(self.config.merge_strategy),
+                ),
+                (
+                    "Merge, keeping the local version of conflicting changes".to_string(),
+                    Some(MergeStrategy::Ours),
+                ),
+                (
+                    "Merge, keeping the remote version of conflicting changes".to_string(),
+                    Some(MergeStrategy::Theirs),
+                ),
+                (format!("Skip syncing {}", dotfile_name), None),
+            ];
+            let items = choices
+                .iter()
+                .map(|(item, _)| item.clone())
+                .collect::<Vec<String>>();
+            let choice = prompter().choose(
+                &format!(
+                    "{} has changed both locally and in the repository since it was last synced. How should it be synced?",
+                    dotfile_name
+                ),
+                &items,
+                0,
+            )?;
+            resolutions.insert(dotfile_name.to_string(), choices[choice].1);
+        }
+        Ok(resolutions)
+    }
+
+    /// Merge any new commits from the `origin` remote into the current branch of `repo` and push
+    /// it, updating the metadata of the installed dotfiles if their commits had to be replayed
+    pub fn push(&self, repo: &Repository) -> Result<(), Box<dyn Error>> {
+        let rewritten = self.push_to(repo, "origin")?;
+        if let Some(mut aggregated_metadata) = AggregatedDotfileMetadata::get()? {
+            if aggregated_metadata.rewrite_commit_hashes(&rewritten) {
+                aggregated_metadata.save()?;
+            }
+        }
         Ok(())
     }
+
+    /// Merge any new commits from the `remote_name` remote into the current branch of `repo` and
+    /// push it there, or to the `push_branch` in the config of this manifest if it is set.
+    ///
+    /// If the remote changes again before the push lands, the commits that weren't pushed are
+    /// replayed on top of it and the push is retried. The hashes of the replayed commits are
+    /// returned, mapped to those of the commits they were replayed as.
+    pub fn push_to(
+        &self,
+        repo: &Repository,
+        remote_name: &str,
+    ) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        let branch = get_head_branch(repo)?;
+        let remote_branch = self.push_branch(repo)?;
+        integrate_remote_changes(
+            repo,
+            remote_name,
+            &branch,
+            &remote_branch,
+            self.config.merge_strategy,
+        )?;
+
+        let mut rewritten = HashMap::new();
+        for attempt in 1..=PUSH_ATTEMPTS {
+            match push(repo, remote_name, &branch, &remote_branch) {
+                Err(err) if err.is::<NonFastForwardError>() && attempt < PUSH_ATTEMPTS => {
+                    warn!("The remote repo changed while pushing, replaying the sync commits on top of it");
+                }
+                result => return result.map(|_| rewritten),
+            }
+            match rebase_onto_remote(repo, remote_name, &branch, &remote_branch) {
+                Ok(replayed) => {
+                    // Commits replayed in an earlier attempt may have been replayed again
+                    for new_hash in rewritten.values_mut() {
+                        if let Some(replayed_hash) = replayed.get(new_hash) {
+                            *new_hash = replayed_hash.to_string();
+                        }
+                    }
+                    rewritten.extend(replayed);
+                }
+                Err(err) => {
+                    warn!(
+                        "Could not replay the sync commits ({}), merging instead",
+                        err
+                    );
+                    integrate_remote_changes(
+                        repo,
+                        remote_name,
+                        &branch,
+                        &remote_branch,
+                        self.config.merge_strategy,
+                    )?;
+                }
+            }
+        }
+        unreachable!("the last push attempt always returns")
+    }
+
+    /// Force-push the current branch of `repo` to the `origin` remote (or to the `push_branch` in
+    /// the config of this manifest), replacing its history as long as it hasn't changed since it
+    /// was last fetched
+    pub fn force_push(&self, repo: &Repository) -> Result<(), Box<dyn Error>> {
+        let branch = get_head_branch(repo)?;
+        force_push_with_lease(repo, "origin", &branch, &self.push_branch(repo)?)
+    }
+
+    /// Return the branch of the remote that [Manifest::push_to] pushes the current branch of `repo`
+    /// to
+    pub fn push_branch(&self, repo: &Repository) -> Result<String, Box<dyn Error>> {
+        match self.config.push_branch() {
+            Some(branch) => Ok(branch),
+            None => get_head_branch(repo),
+        }
+    }
+
+    /// Return the URL of the GitLab instance set in the config of this manifest, if any. It is
+    /// never used, as a manifest cloned from elsewhere mustn't choose where tokens are sent
+    pub fn gitlab_url(&self) -> Option<&str> {
+        self.config.gitlab_url.as_deref()
+    }
+
+    /// Return whether the commits made when syncing are squashed into one, i.e. whether a commit
+    /// message passed to [Manifest::sync] is used
+    pub fn squashes_commits(&self) -> bool {
+        self.config.squash_commits
+    }
+
+    /// Return the message of the commit made when syncing `dotfile_names`, if no other message is
+    /// given
+    pub fn default_commit_message(&self, dotfile_names: Vec<&str>) -> String {
+        self.config.generate_commit_message(dotfile_names)
+    }
+
+    /// Return the diff tool set in the config of this manifest, if any. It is never run, as a
+    /// manifest cloned from elsewhere mustn't choose the commands jtd runs
+    pub fn diff_tool(&self) -> Option<&str> {
+        self.config.diff_tool.as_deref()
+    }
+
+    /// Return the dotfiles to diff, in the order they are listed in the manifest. If `target_dotfiles` is empty and `all` isn't
+    /// set, every dotfile in the manifest that is installed according to `aggregated_metadata` is
+    /// returned
+    fn get_diff_targets(
+        &self,
+        target_dotfiles: Vec<String>,
+        all: bool,
+        aggregated_metadata: &AggregatedDotfileMetadata,
+    ) -> Result<Vec<(String, &Dotfile)>, Box<dyn Error>> {
+        let mut dotfile_names: Vec<String> = if all {
+            self.data.keys().cloned().collect()
+        } else if target_dotfiles.is_empty() {
+            self.data
+                .keys()
+                .filter(|dotfile_name| {
+                    aggregated_metadata
+                        .dotfile(self.repository(), dotfile_name)
+                        .is_some()
+                })
+                .cloned()
+                .collect()
+        } else {
+            self.expand_groups(target_dotfiles)
+        };
+        // Unknown dotfiles are sorted first, and reported as such below
+        dotfile_names.sort_by_key(|dotfile_name| self.data.get_index_of(dotfile_name));
+
+        dotfile_names
+            .into_iter()
+            .map(|dotfile_name| match self.data.get(&dotfile_name) {
+                Some(dotfile) => Ok((dotfile_name, dotfile)),
+                None => Err(format!("Dotfile {} not found in manifest", dotfile_name).into()),
+            })
+            .collect()
+    }
+
+    /// Return the unified diffs between the repository and local versions of the target dotfiles,
+    /// in the order they are listed in the manifest. Dotfiles without changes are left out.
+    ///
+    /// If `target_dotfiles` is empty and `all` isn't set, every dotfile in the manifest that is
+    /// installed according to `aggregated_metadata` is diffed.
+    ///
+    /// If `as_patch` is set, the diffs can be applied to the repository with `git apply` or
+    /// `patch -p1`. Templated, encrypted and `expand_env` dotfiles are left out in this case, as
+    /// their local copies don't match the files in the repository.
+    pub fn diff(
+        &self,
+        repo: &Repository,
+        target_dotfiles: Vec<String>,
+        all: bool,
+        aggregated_metadata: &AggregatedDotfileMetadata,
+        as_patch: bool,
+    ) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let mut diffs = vec![];
+        for (dotfile_name, dotfile) in
+            self.get_diff_targets(target_dotfiles, all, aggregated_metadata)?
+        {
+            if dotfile.has_untrusted_filters() {
+                warn!(
+                    "Leaving {} out as its filters aren't trusted. They are trusted once you \
+                    agree to run them when installing it",
+                    dotfile_name
+                );
+                continue;
+            }
+            if let Some(form) = dotfile.rendered_form().filter(|_| as_patch) {
+                warn!(
+                    "Leaving {} out of the patch as it is {} in the repository",
+                    dotfile_name, form
+                );
+                continue;
+            }
+            if let Some(diff) = dotfile.diff(repo, as_patch)? {
+                diffs.push((dotfile_name, diff));
+            }
+        }
+        Ok(diffs)
+    }
+
+    /// Show the differences between the repository and local versions of the target dotfiles with
+    /// an external diff `tool`, selecting the dotfiles the same way as [Manifest::diff]. Returns
+    /// the number of dotfiles that differ
+    pub fn diff_with_tool(
+        &self,
+        repo: &Repository,
+        target_dotfiles: Vec<String>,
+        all: bool,
+        aggregated_metadata: &AggregatedDotfileMetadata,
+        tool: &str,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut changed = 0;
+        for (dotfile_name, dotfile) in
+            self.get_diff_targets(target_dotfiles, all, aggregated_metadata)?
+        {
+            if dotfile.has_untrusted_filters() {
+                warn!(
+                    "Leaving {} out as its filters aren't trusted. They are trusted once you \
+                    agree to run them when installing it",
+                    dotfile_name
+                );
+                continue;
+            }
+            if dotfile.diff_with_tool(repo, &dotfile_name, tool)? {
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    fn generate_sync_reports(
+        &self,
+        statuses: Vec<(&str, DotfileStatus)>,
+        aggregated_metadata: &AggregatedDotfileMetadata,
+    ) -> Vec<DotfileReport> {
+        statuses
+            .into_iter()
+            .map(|(dotfile_name, status)| {
+                DotfileReport::new(
+                    dotfile_name,
+                    status,
+                    aggregated_metadata
+                        .dotfile(self.repository(), dotfile_name)
+                        .map(|metadata| metadata.commit_hash.to_string()),
+                )
+            })
+            .collect()
+    }
+}
+
+/// The manifest used when none is passed with `--manifest`
+pub const DEFAULT_MANIFEST: &str = "jtd.yaml";
+
+/// The manifests looked for, relative to the root of the repository, when the default manifest
+/// doesn't exist
+pub const MANIFEST_CANDIDATES: [&str; 7] = [
+    "jtd.yaml",
+    "jtd.yml",
+    ".jtd.yaml",
+    ".jtd.yml",
+    ".config/jtd.yaml",
+    "jtd.toml",
+    "jtd.json",
+];
+
+/// Return the path of the manifest to use in `repo_dir`, relative to it. This is `manifest`
+/// itself, unless it is the default manifest and doesn't exist, in which case the repository is
+/// searched for [MANIFEST_CANDIDATES] and the only one found is used.
+pub fn find_manifest(repo_dir: &Path, manifest: &str) -> Result<String, Box<dyn Error>> {
+    if manifest != DEFAULT_MANIFEST || repo_dir.join(manifest).is_file() {
+        return Ok(manifest.to_string());
+    }
+
+    let found: Vec<&str> = MANIFEST_CANDIDATES
+        .iter()
+        .copied()
+        .filter(|candidate| repo_dir.join(candidate).is_file())
+        .collect();
+    match found.as_slice() {
+        [manifest] => {
+            info!("Using the manifest {} found in the repository", manifest);
+            Ok(manifest.to_string())
+        }
+        [] => Err(format!(
+            "Could not find a manifest in the repository. Looked for {}",
+            MANIFEST_CANDIDATES.join(", ")
+        )
+        .into()),
+        found => Err(format!(
+            "Found several manifests in the repository ({}). Pass the one to use with \"--manifest\"",
+            found.join(", ")
+        )
+        .into()),
+    }
+}
+
+/// Read the manifest at `path` as YAML, merging in the manifest it extends (if any).
+///
+/// A manifest can set `.extends` to the path of another manifest, relative to its own directory.
+/// Manifests outside of the repository the manifest at `path` is in (or its directory, if it isn't
+/// in one) can't be extended, so that a cloned manifest can't read other files on the machine.
+/// The base manifest is loaded first (following its own `.extends`) and then each top-level key of
+/// the extending manifest is applied on top of it:
+///
+/// * Dotfiles, `.config` and `.groups` are merged key by key, so setting `target` on an inherited
+///   dotfile keeps its `file` and hooks. Lists such as `post_install` are replaced, not appended to
+/// * Setting a dotfile (or any other key) to `null` removes it from the base manifest
+pub(crate) fn read_manifest_value(path: &Path) -> Result<Value, Box<dyn Error>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let root = Repository::discover(dir)
+        .ok()
+        .and_then(|repo| repo.workdir().map(Path::to_path_buf))
+        .unwrap_or_else(|| dir.to_path_buf());
+    let root = root.canonicalize().unwrap_or(root);
+    read_manifest_value_extending(path, &root, &mut vec![])
+}
+
+fn read_manifest_value_extending(
+    path: &Path,
+    root: &Path,
+    visited: &mut Vec<PathBuf>,
+) -> Result<Value, Box<dyn Error>> {
+    let manifest_name = path
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_else(|| "N/A".to_string());
+    let canonical_path = path
+        .canonicalize()
+        .map_err(|_| format!("Could not find manifest {} in repository.", manifest_name))?;
+    if !canonical_path.starts_with(root) {
+        return Err(format!(
+            "Manifest {} is outside of the repository, so it can't be used",
+            path.to_string_lossy()
+        )
+        .into());
+    }
+    if visited.contains(&canonical_path) {
+        return Err(format!("Manifest {} extends itself", manifest_name).into());
+    }
+    visited.push(canonical_path);
+
+    let contents = fs::read_to_string(path)
+        .map_err(|_| format!("Could not find manifest {} in repository.", manifest_name))?;
+    let mut manifest = parse_manifest_value(path, &contents)
+        .map_err(|err| format!("Could not parse manifest: {}", err))?;
+
+    let mapping = match manifest.as_mapping_mut() {
+        Some(mapping) => mapping,
+        None => return Ok(manifest),
+    };
+    let base_name = match mapping.remove(&Value::from(".extends")) {
+        Some(Value::String(base_name)) => base_name,
+        Some(other) => {
+            return Err(format!(
+                "Could not parse manifest: .extends must be a path, found {:?}",
+                other
+            )
+            .into())
+        }
+        None => return Ok(manifest),
+    };
+
+    let base_path = path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(base_name);
+    let mut base = read_manifest_value_extending(&base_path, root, visited)?;
+    match base.as_mapping_mut() {
+        Some(base_mapping) => {
+            merge_mappings(base_mapping, mapping, true);
+            Ok(base)
+        }
+        None => Err(format!(
+            "Could not parse manifest: base manifest {} is not a mapping",
+            base_path.to_string_lossy()
+        )
+        .into()),
+    }
+}
+
+/// Parse the contents of a manifest into a YAML [Value], picking the format from the extension of
+/// `path`. Manifests ending in `.toml` or `.json` are parsed as TOML or JSON, anything else is
+/// parsed as YAML
+fn parse_manifest_value(path: &Path, contents: &str) -> Result<Value, Box<dyn Error>> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => Ok(serde_yaml::to_value(toml::from_str::<toml::Value>(
+            contents,
+        )?)?),
+        Some("json") => Ok(serde_yaml::to_value(serde_json::from_str::<
+            serde_json::Value,
+        >(contents)?)?),
+        _ => Ok(serde_yaml::from_str(contents)?),
+    }
+}
+
+/// Apply the keys of `overrides` on top of `base`. Keys set to `null` are removed. If `recurse`
+/// is set, values that are mappings in both are merged one level deeper instead of replaced
+fn merge_mappings(base: &mut Mapping, overrides: &Mapping, recurse: bool) {
+    for (key, value) in overrides {
+        if value.is_null() {
+            base.remove(key);
+            continue;
+        }
+        match (base.get_mut(key), value) {
+            (Some(Value::Mapping(base_value)), Value::Mapping(override_value)) if recurse => {
+                merge_mappings(base_value, override_value, false)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
 }
 
 impl IntoIterator for Manifest {
     type Item = (String, Dotfile);
 
-    type IntoIter = std::collections::hash_map::IntoIter<String, Dotfile>;
+    type IntoIter = indexmap::map::IntoIter<String, Dotfile>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.data.into_iter()
@@ -279,9 +1517,10 @@ impl IntoIterator for Manifest {
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_to_owned)]
 mod tests {
     use std::{
-        fs::{read_to_string, File},
+        fs::{self, read_to_string, File},
         io::Write,
         path::{Path, PathBuf},
     };
@@ -301,7 +1540,7 @@ kitty:
 
         let path = tempdir.path().join(Path::new("manifest.yaml"));
         let mut manifest_file = File::create(path.to_owned()).unwrap();
-        manifest_file.write(SAMPLE_MANIFEST.as_bytes()).unwrap();
+        manifest_file.write_all(SAMPLE_MANIFEST.as_bytes()).unwrap();
 
         let manifest = Manifest::get(&path).unwrap();
 
@@ -310,11 +1549,311 @@ kitty:
             target: PathBuf::from("~/some/path/here"),
             pre_install: None,
             post_install: None,
+            ..Default::default()
         };
 
         assert_eq!(manifest.data["kitty"], kitty_dotfile);
     }
 
+    #[test]
+    fn test_manifest_get_dotfile() {
+        let manifest: Manifest = serde_yaml::from_str(SAMPLE_MANIFEST).unwrap();
+
+        assert_eq!(
+            manifest
+                .get_dotfile("kitty")
+                .map(|dotfile| dotfile.file.as_str()),
+            Some("dotfile")
+        );
+        assert!(manifest.get_dotfile("nvim").is_none());
+    }
+
+    #[test]
+    fn test_manifest_keeps_order() {
+        let tempdir = tempdir().unwrap();
+        let manifests = [
+            (
+                "jtd.yaml",
+                "zsh:\n  file: zshrc\n  target: ~/.zshrc\nkitty:\n  file: kitty.conf\n  target: ~/kitty.conf\nalacritty:\n  file: alacritty.yml\n  target: ~/alacritty.yml\n",
+            ),
+            (
+                "jtd.toml",
+                "[zsh]\nfile = \"zshrc\"\ntarget = \"~/.zshrc\"\n[kitty]\nfile = \"kitty.conf\"\ntarget = \"~/kitty.conf\"\n[alacritty]\nfile = \"alacritty.yml\"\ntarget = \"~/alacritty.yml\"\n",
+            ),
+            (
+                "jtd.json",
+                r#"{"zsh": {"file": "zshrc", "target": "~/.zshrc"}, "kitty": {"file": "kitty.conf", "target": "~/kitty.conf"}, "alacritty": {"file": "alacritty.yml", "target": "~/alacritty.yml"}}"#,
+            ),
+        ];
+
+        for (manifest_name, contents) in manifests.iter() {
+            let path = tempdir.path().join(manifest_name);
+            fs::write(&path, contents).unwrap();
+            let manifest = Manifest::get(&path).unwrap();
+
+            let dotfile_names: Vec<String> = manifest.into_iter().map(|(name, _)| name).collect();
+            assert_eq!(dotfile_names, vec!["zsh", "kitty", "alacritty"]);
+        }
+    }
+
+    #[test]
+    fn test_manifest_get_toml_and_json() {
+        let tempdir = tempdir().unwrap();
+        let toml_path = tempdir.path().join("jtd.toml");
+        fs::write(
+            &toml_path,
+            r#"
+[".config"]
+squash_commits = false
+
+[kitty]
+file = "kitty.conf"
+target = "~/.config/kitty/kitty.conf"
+mode = "0600"
+post_install = ["echo installed"]
+"#,
+        )
+        .unwrap();
+        let json_path = tempdir.path().join("jtd.json");
+        fs::write(
+            &json_path,
+            r#"{
+    ".config": {"squash_commits": false},
+    "kitty": {
+        "file": "kitty.conf",
+        "target": "~/.config/kitty/kitty.conf",
+        "mode": "0600",
+        "post_install": ["echo installed"]
+    }
+}"#,
+        )
+        .unwrap();
+
+        for path in [toml_path, json_path] {
+            let manifest = Manifest::get(&path).unwrap();
+            assert!(!manifest.config.squash_commits);
+            assert_eq!(
+                manifest.data["kitty"],
+                Dotfile {
+                    file: "kitty.conf".to_string(),
+                    target: PathBuf::from("~/.config/kitty/kitty.conf"),
+                    mode: Some(0o600),
+                    post_install: Some(vec!["echo installed".to_string()]),
+                    ..Default::default()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_manifest_get_extends() {
+        let tempdir = tempdir().unwrap();
+        Repository::init(tempdir.path()).unwrap();
+        fs::create_dir(tempdir.path().join("hosts")).unwrap();
+        fs::write(
+            tempdir.path().join("base.yaml"),
+            r"
+.config:
+  squash_commits: false
+kitty:
+  file: kitty.conf
+  target: ~/.config/kitty/kitty.conf
+  post_install:
+    - echo installed
+sway:
+  file: sway.conf
+  target: ~/.config/sway/config
+",
+        )
+        .unwrap();
+        let path = tempdir.path().join("hosts/laptop.yaml");
+        fs::write(
+            &path,
+            r"
+.extends: ../base.yaml
+.config:
+  commit_prefix: 'laptop: '
+kitty:
+  target: ~/kitty.conf
+sway: null
+",
+        )
+        .unwrap();
+
+        let manifest = Manifest::get(&path).unwrap();
+
+        assert_eq!(manifest.data.len(), 1);
+        let kitty = manifest.get_dotfile("kitty").unwrap();
+        assert_eq!(kitty.file, "kitty.conf");
+        assert_eq!(kitty.target, PathBuf::from("~/kitty.conf"));
+        assert_eq!(kitty.post_install, Some(vec!["echo installed".to_string()]));
+        assert!(!manifest.config.squash_commits);
+        assert_eq!(manifest.config.commit_prefix, "laptop: ");
+    }
+
+    #[test]
+    fn test_manifest_get_extends_outside_repository() {
+        let outside = tempdir().unwrap();
+        fs::write(
+            outside.path().join("secrets.yaml"),
+            "token:\n  file: token\n  target: ~/token\n",
+        )
+        .unwrap();
+        let tempdir = tempdir().unwrap();
+        Repository::init(tempdir.path()).unwrap();
+        let path = tempdir.path().join("jtd.yaml");
+
+        for extends in [
+            outside
+                .path()
+                .join("secrets.yaml")
+                .to_string_lossy()
+                .to_string(),
+            format!(
+                "../{}/secrets.yaml",
+                outside.path().file_name().unwrap().to_string_lossy()
+            ),
+        ] {
+            fs::write(&path, format!(".extends: {}\n", extends)).unwrap();
+            let err = Manifest::get(&path).unwrap_err();
+            assert!(err.to_string().contains("is outside of the repository"));
+        }
+    }
+
+    #[test]
+    fn test_manifest_get_extends_cycle() {
+        let tempdir = tempdir().unwrap();
+        fs::write(tempdir.path().join("a.yaml"), ".extends: b.yaml\n").unwrap();
+        fs::write(tempdir.path().join("b.yaml"), ".extends: a.yaml\n").unwrap();
+
+        let err = Manifest::get(&tempdir.path().join("a.yaml")).unwrap_err();
+        assert_eq!(err.to_string(), "Manifest a.yaml extends itself");
+    }
+
+    #[test]
+    fn test_manifest_root_and_subdir() {
+        let tempdir = tempdir().unwrap();
+        let path = tempdir.path().join("jtd.yaml");
+        fs::write(
+            &path,
+            ".config:\n  root: config/\nkitty:\n  file: kitty.conf\n  target: ~/.config/kitty/kitty.conf\n",
+        )
+        .unwrap();
+        let manifest = Manifest::get(&path).unwrap();
+        assert_eq!(
+            manifest.get_dotfile("kitty").unwrap().file,
+            "config/kitty.conf"
+        );
+
+        fs::write(
+            &path,
+            "kitty:\n  file: ./kitty.conf\n  target: ~/.config/kitty/kitty.conf\n",
+        )
+        .unwrap();
+        let mut manifest = Manifest::get(&path).unwrap();
+        let origin = RepositoryOrigin::new("dob9601/dotfiles", "dob9601/dotfiles", "jtd.yaml")
+            .with_subdir(Some("./home/dotfiles/"));
+        assert_eq!(
+            origin.manifest_dir(Path::new("/repo")),
+            Path::new("/repo/home/dotfiles")
+        );
+        manifest.set_origin(origin);
+        assert_eq!(
+            manifest.get_dotfile("kitty").unwrap().file,
+            "home/dotfiles/kitty.conf"
+        );
+    }
+
+    #[test]
+    fn test_manifest_get_target_dotfiles_groups() {
+        let repo_dir = tempdir().unwrap();
+        let repo = Repository::init(repo_dir.path()).unwrap();
+        let manifest: Manifest = serde_yaml::from_str(
+            r"
+.groups:
+  shell-tools:
+    - kitty
+    - zsh
+kitty:
+  file: kitty.conf
+  target: ~/.config/kitty/kitty.conf
+zsh:
+  file: zshrc
+  target: ~/.zshrc
+nvim:
+  file: init.lua
+  target: ~/.config/nvim/init.lua
+",
+        )
+        .unwrap();
+
+        assert!(manifest.get_dotfile("shell-tools").is_none());
+        assert_eq!(
+            manifest.expand_groups(vec!["zsh".to_string(), "shell-tools".to_string()]),
+            vec!["zsh".to_string(), "kitty".to_string()]
+        );
+
+        let target_names: Vec<&String> = manifest
+            .get_target_dotfiles(&repo, vec!["shell-tools".to_string()], false)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(target_names, vec!["kitty", "zsh"]);
+    }
+
+    #[test]
+    fn test_find_manifest() {
+        let repo_dir = tempdir().unwrap();
+        assert!(find_manifest(repo_dir.path(), DEFAULT_MANIFEST).is_err());
+
+        fs::create_dir(repo_dir.path().join(".config")).unwrap();
+        File::create(repo_dir.path().join(".config/jtd.yaml")).unwrap();
+        assert_eq!(
+            find_manifest(repo_dir.path(), DEFAULT_MANIFEST).unwrap(),
+            ".config/jtd.yaml"
+        );
+        // Explicitly passed manifests are used as they are
+        assert_eq!(
+            find_manifest(repo_dir.path(), "hosts/laptop.yaml").unwrap(),
+            "hosts/laptop.yaml"
+        );
+
+        File::create(repo_dir.path().join("jtd.yml")).unwrap();
+        let err = find_manifest(repo_dir.path(), DEFAULT_MANIFEST).unwrap_err();
+        assert!(err.to_string().contains("jtd.yml, .config/jtd.yaml"));
+
+        File::create(repo_dir.path().join("jtd.yaml")).unwrap();
+        assert_eq!(
+            find_manifest(repo_dir.path(), DEFAULT_MANIFEST).unwrap(),
+            "jtd.yaml"
+        );
+    }
+
+    #[test]
+    fn test_decline_commands_remembered() {
+        let dotfile = Dotfile {
+            post_install: Some(vec!["echo installed".to_string()]),
+            ..Default::default()
+        };
+        let metadata = DotfileMetadata {
+            declined_steps_hash: Some(dotfile.hash_install_steps()),
+            ..Default::default()
+        };
+
+        let manifest: Manifest = serde_yaml::from_str(SAMPLE_MANIFEST).unwrap();
+        assert!(manifest
+            .decline_commands(
+                "kitty",
+                &dotfile,
+                false,
+                true,
+                &Some(metadata),
+                &mut TrustStore::default()
+            )
+            .unwrap());
+    }
+
     #[test]
     fn test_manifest_install() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
@@ -341,11 +1880,165 @@ kitty:
         .unwrap();
 
         manifest
-            .install(&repo, true, vec![], true, false)
+            .install(&repo, true, vec![], true, false, false)
             .expect("Failed to install manifest");
         assert!(Path::exists(&target_path));
     }
 
+    #[test]
+    fn test_manifest_install_on_conflict_keep() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        let filepath = repo_dir.path().join("dotfile");
+        fs::write(&filepath, "repository").unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![&filepath]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap();
+        fs::write(&target_path, "local").unwrap();
+
+        let mut manifest: Manifest = serde_yaml::from_str(
+            &SAMPLE_MANIFEST.replace("~/some/path/here", &target_path.to_string_lossy()),
+        )
+        .unwrap();
+        manifest.set_on_conflict(OnConflict::Keep);
+
+        let reports = manifest
+            .install(&repo, true, vec![], false, false, false)
+            .expect("Failed to install manifest");
+        assert!(reports
+            .iter()
+            .all(|report| report.status == DotfileStatus::Skipped));
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "local");
+    }
+
+    #[test]
+    fn test_manifest_lock() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let commit = add_and_commit(&repo, None, "commit message", Some(vec![]), Some("HEAD"))
+            .unwrap()
+            .id()
+            .to_string();
+
+        let manifest: Manifest = serde_yaml::from_str(
+            "kitty:\n  file: kitty.conf\n  target: ~/kitty.conf\nnvim:\n  file: init.lua\n  target: ~/init.lua\n",
+        )
+        .unwrap();
+        let mut aggregated_metadata = AggregatedDotfileMetadata::new();
+        aggregated_metadata.insert_dotfile(
+            None,
+            "kitty",
+            DotfileMetadata::new(&commit, String::new(), String::new()),
+        );
+        // Locked on another machine that has nvim installed
+        let existing = Lockfile {
+            dotfiles: [("nvim", "0123456"), ("removed", "0123456")]
+                .iter()
+                .map(|(name, commit)| {
+                    (
+                        name.to_string(),
+                        LockedDotfile {
+                            commit: commit.to_string(),
+                        },
+                    )
+                })
+                .collect(),
+        };
+
+        let lockfile = manifest
+            .lock(&repo, &aggregated_metadata, Some(existing))
+            .unwrap();
+        assert_eq!(
+            lockfile.dotfiles.keys().collect::<Vec<_>>(),
+            vec!["kitty", "nvim"]
+        );
+        assert_eq!(lockfile.dotfiles["kitty"].commit, commit);
+        assert_eq!(lockfile.dotfiles["nvim"].commit, "0123456");
+
+        aggregated_metadata.insert_dotfile(
+            None,
+            "nvim",
+            DotfileMetadata::new(
+                "0123456789abcdef0123456789abcdef01234567",
+                String::new(),
+                String::new(),
+            ),
+        );
+        assert!(manifest.lock(&repo, &aggregated_metadata, None).is_err());
+    }
+
+    #[test]
+    fn test_manifest_install_locked() {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        let repo = Repository::init(&repo_dir).expect("Could not initialise repository");
+        let dotfile_dir = tempdir().expect("Could not create temporary dotfile dir");
+        let target_path = dotfile_dir.path().join("dotfile");
+
+        let filepath = repo_dir.path().join("dotfile");
+        fs::write(&filepath, "locked").unwrap();
+        let locked_commit = add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            Some(vec![]),
+            Some("HEAD"),
+        )
+        .unwrap()
+        .id()
+        .to_string();
+        fs::write(&filepath, "latest").unwrap();
+        add_and_commit(
+            &repo,
+            Some(vec![Path::new("dotfile")]),
+            "commit message",
+            None,
+            Some("HEAD"),
+        )
+        .unwrap();
+
+        let sample_manifest =
+            SAMPLE_MANIFEST.replace("~/some/path/here", &target_path.to_string_lossy());
+        let lockfile = |commit: &str| Lockfile {
+            dotfiles: std::collections::BTreeMap::from([(
+                "kitty".to_string(),
+                LockedDotfile {
+                    commit: commit.to_string(),
+                },
+            )]),
+        };
+
+        let mut manifest: Manifest = serde_yaml::from_str(&sample_manifest).unwrap();
+        assert!(manifest
+            .set_lockfile(&repo, lockfile("0123456789abcdef0123456789abcdef01234567"))
+            .is_err());
+
+        manifest
+            .set_lockfile(&repo, lockfile(&locked_commit))
+            .unwrap();
+        let reports = manifest
+            .install(&repo, true, vec![], false, false, false)
+            .expect("Failed to install manifest");
+        assert_eq!(reports[0].status, DotfileStatus::Installed);
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "locked");
+
+        // Dotfiles missing from the lockfile aren't installed from HEAD instead
+        let mut manifest: Manifest = serde_yaml::from_str(&sample_manifest).unwrap();
+        manifest.set_lockfile(&repo, Lockfile::default()).unwrap();
+        let reports = manifest
+            .install(&repo, true, vec![], true, false, false)
+            .expect("Failed to install manifest");
+        assert_eq!(reports[0].status, DotfileStatus::Failed);
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "locked");
+    }
+
     #[test]
     fn test_manifest_sync() {
         let repo_dir = tempdir().expect("Could not create temporary repo dir");
@@ -378,7 +2071,7 @@ kitty:
         .unwrap();
 
         let err = manifest
-            .sync(&repo, true, vec![], None, None, true)
+            .sync(&repo, true, vec![], None, None, true, false, Some("origin"))
             .unwrap_err();
 
         // FIXME: This is a very dodgy test, maybe setup a mock repo for pushing to?
@@ -1,20 +1,210 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{error::Error, fs::File};
 
+use console::style;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
-use crate::MANIFEST_PATH;
+use crate::git::remote::{get_git_url_host, local_repository_path};
+use crate::log::log_operation;
+use crate::structs::DEFAULT_MANIFEST;
+use crate::utils::metadata_path;
+use crate::utils::{
+    print_command_vec, remove_as_root, root_command, run_command_vec, DEFAULT_SHELL,
+};
 
-/// Struct representing a `manifest.yaml` file, typically found in ~/.local/share/jointhedots.
-/// Represents an aggregation of the metadata of all of the dotfiles in a Manifest via a mapping of
-/// `dotfile_name` to [DotfileMetadata]
+/// The key of the dotfiles whose repository wasn't recorded when they were installed, by versions
+/// of jtd that only supported installing from a single repository. They are treated as installed
+/// from whichever repository is used with them, until they are reinstalled
+pub const UNKNOWN_REPOSITORY: &str = "";
+
+/// Struct representing a `manifest.yaml` file, found in the data directory (typically
+/// ~/.local/share/jointhedots).
+/// Represents an aggregation of the metadata of all of the installed dotfiles via a mapping of
+/// repository to [RepositoryMetadata], which in turn maps `dotfile_name` to [DotfileMetadata]. This
+/// allows dotfiles from several repositories (e.g. personal and work dotfiles) to be installed on
+/// the same machine, even if they share names
 #[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(from = "StoredAggregatedDotfileMetadata")]
 pub struct AggregatedDotfileMetadata {
+    /// The installed dotfiles, keyed by the repository they were installed from
+    #[serde(
+        default,
+        rename = ".repositories",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub repositories: BTreeMap<String, RepositoryMetadata>,
+
+    /// The UNIX timestamp of the last `jtd sync` that committed changes
+    #[serde(
+        default,
+        rename = ".last_synced_at",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_synced_at: Option<u64>,
+
+    /// The repository and manifest the last `jtd sync` was run with, so that the dotfiles can be
+    /// synced again when reminding the user to do so
+    #[serde(
+        default,
+        rename = ".last_synced_from",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_synced_from: Option<RepositoryOrigin>,
+
+    /// The repository and manifest dotfiles were last installed from, used by `jtd sync` and
+    /// `jtd diff` when no repository is passed
+    #[serde(
+        default,
+        rename = ".installed_from",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub installed_from: Option<RepositoryOrigin>,
+}
+
+/// The layout of `manifest.yaml` as read from disk, which may still contain dotfiles stored at the
+/// top level by older versions of jtd
+#[derive(Deserialize)]
+struct StoredAggregatedDotfileMetadata {
+    #[serde(default, rename = ".repositories")]
+    repositories: BTreeMap<String, RepositoryMetadata>,
+
+    #[serde(default, rename = ".last_synced_at")]
+    last_synced_at: Option<u64>,
+
+    #[serde(default, rename = ".last_synced_from")]
+    last_synced_from: Option<RepositoryOrigin>,
+
+    #[serde(default, rename = ".installed_from")]
+    installed_from: Option<RepositoryOrigin>,
+
     #[serde(flatten)]
-    pub data: HashMap<String, DotfileMetadata>,
+    unscoped: HashMap<String, DotfileMetadata>,
+}
+
+impl From<StoredAggregatedDotfileMetadata> for AggregatedDotfileMetadata {
+    fn from(stored: StoredAggregatedDotfileMetadata) -> Self {
+        let mut repositories = stored.repositories;
+        if !stored.unscoped.is_empty() {
+            repositories
+                .entry(UNKNOWN_REPOSITORY.to_string())
+                .or_default()
+                .dotfiles
+                .extend(stored.unscoped);
+        }
+        AggregatedDotfileMetadata {
+            repositories,
+            last_synced_at: stored.last_synced_at,
+            last_synced_from: stored.last_synced_from,
+            installed_from: stored.installed_from,
+        }
+    }
+}
+
+/// The metadata of the dotfiles installed from a single repository
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepositoryMetadata {
+    /// The host the repository is on, e.g. `github.com`. None for local clones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    /// The manifest the dotfiles were last installed with
+    #[serde(default = "default_manifest")]
+    pub manifest: String,
+
+    /// The subdirectory of the repository the manifest is in, see [RepositoryOrigin::subdir]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdir: Option<String>,
+
+    #[serde(default)]
+    pub dotfiles: HashMap<String, DotfileMetadata>,
+}
+
+impl Default for RepositoryMetadata {
+    fn default() -> Self {
+        RepositoryMetadata {
+            host: None,
+            manifest: default_manifest(),
+            subdir: None,
+            dotfiles: HashMap::new(),
+        }
+    }
+}
+
+fn default_manifest() -> String {
+    DEFAULT_MANIFEST.to_string()
+}
+
+/// A repository and the names of the dotfiles to operate on in it
+pub type RepositoryTargets = (RepositoryOrigin, Vec<String>);
+
+/// The repository and manifest a subcommand was run with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RepositoryOrigin {
+    /// The URL of the repository, or the absolute path of a local clone
+    pub repository: String,
+
+    /// The host the repository is on, e.g. `github.com`. None for local clones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    pub manifest: String,
+
+    /// The subdirectory of the repository the manifest is in (relative to which `manifest` is
+    /// given), for dotfiles kept in a larger repository. The `file` paths of the dotfiles are
+    /// relative to it too, unless the manifest sets its own `root`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdir: Option<String>,
+
+    /// The user's own clone of the repository the subcommand was run in place of the cached one
+    /// (`--repo-dir`), if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo_dir: Option<PathBuf>,
+}
+
+impl RepositoryOrigin {
+    /// Create the origin of a subcommand run with `repository`, which resolved to `url`. Local
+    /// clones are stored by their absolute path as they are used in place
+    pub fn new(repository: &str, url: &str, manifest: &str) -> Self {
+        match local_repository_path(repository).and_then(|path| path.canonicalize().ok()) {
+            Some(path) if path.is_dir() => RepositoryOrigin {
+                repository: path.to_string_lossy().to_string(),
+                host: None,
+                manifest: manifest.to_string(),
+                subdir: None,
+                repo_dir: None,
+            },
+            _ => RepositoryOrigin {
+                repository: url.to_string(),
+                host: get_git_url_host(url).map(str::to_string),
+                manifest: manifest.to_string(),
+                subdir: None,
+                repo_dir: None,
+            },
+        }
+    }
+
+    /// Set the subdirectory of the repository the manifest is in, see [RepositoryOrigin::subdir]
+    pub fn with_subdir(mut self, subdir: Option<&str>) -> Self {
+        self.subdir = subdir
+            .map(|subdir| subdir.trim_start_matches("./").trim_matches('/'))
+            .filter(|subdir| !subdir.is_empty() && *subdir != ".")
+            .map(str::to_string);
+        self
+    }
+
+    /// Return the directory of `repo_dir` (the directory of a clone of the repository) the
+    /// manifest is in
+    pub fn manifest_dir(&self, repo_dir: &Path) -> PathBuf {
+        match &self.subdir {
+            Some(subdir) => repo_dir.join(subdir),
+            None => repo_dir.to_path_buf(),
+        }
+    }
 }
 
 impl AggregatedDotfileMetadata {
@@ -32,15 +222,15 @@ impl AggregatedDotfileMetadata {
     /// let manifest = AggregatedDotfileMetadata::get().unwrap();
     /// ```
     pub fn get() -> Result<Option<AggregatedDotfileMetadata>, Box<dyn Error>> {
-        let path = shellexpand::tilde(MANIFEST_PATH);
-        let reader = File::open(path.as_ref()).ok();
+        let path = metadata_path();
+        let reader = File::open(&path).ok();
 
         if let Some(file) = reader {
             let config: AggregatedDotfileMetadata =
                 serde_yaml::from_reader(file).map_err(|_| {
                     format!(
                         "Could not parse manifest. Check {} for issues",
-                        MANIFEST_PATH
+                        path.display()
                     )
                 })?;
             Ok(Some(config))
@@ -63,21 +253,312 @@ impl AggregatedDotfileMetadata {
     }
 
     pub fn save(&self) -> Result<(), Box<dyn Error>> {
-        let data_path = shellexpand::tilde(MANIFEST_PATH);
+        let data_path = metadata_path();
         fs::create_dir_all(
-            Path::new(data_path.as_ref())
+            data_path
                 .parent()
                 .ok_or("Could not access manifest directory")?,
         )?;
 
-        let mut output_manifest_file = File::create(data_path.to_string())?;
+        let mut output_manifest_file = File::create(&data_path)?;
         output_manifest_file.write_all("# jointhedots installation manifest. Automatically generated, DO NOT EDIT (unless you know what you're doing)\n".as_bytes())?;
         Ok(serde_yaml::to_writer(output_manifest_file, &self)?)
     }
+
+    /// Prepare this metadata to be imported on another machine with
+    /// [AggregatedDotfileMetadata::import], leaving out the paths of backups as they only exist on
+    /// this machine
+    pub fn export(mut self) -> Self {
+        for repository_metadata in self.repositories.values_mut() {
+            for metadata in repository_metadata.dotfiles.values_mut() {
+                metadata.backup_path = None;
+            }
+        }
+        self
+    }
+
+    /// Adopt the installed dotfiles exported on another machine with
+    /// [AggregatedDotfileMetadata::export], including the hashes of the install steps that were
+    /// run there. Dotfiles installed on this machine from the same repository are replaced, the
+    /// rest are kept. Returns the number of dotfiles imported
+    pub fn import(&mut self, imported: AggregatedDotfileMetadata) -> usize {
+        let mut imported_count = 0;
+        for (repository, imported_repository) in imported.repositories {
+            let repository_metadata = self.repositories.entry(repository).or_default();
+            repository_metadata.host = imported_repository.host;
+            repository_metadata.manifest = imported_repository.manifest;
+            repository_metadata.subdir = imported_repository.subdir;
+            for (dotfile_name, mut metadata) in imported_repository.dotfiles {
+                metadata.backup_path = None;
+                repository_metadata.dotfiles.insert(dotfile_name, metadata);
+                imported_count += 1;
+            }
+        }
+        if self.installed_from.is_none() {
+            self.installed_from = imported.installed_from;
+        }
+        if self.last_synced_from.is_none() {
+            self.last_synced_from = imported.last_synced_from;
+        }
+        imported_count
+    }
+
+    /// Return the repository the dotfile `dotfile_name` installed from `repository` is stored
+    /// under: `repository` itself, or [UNKNOWN_REPOSITORY] if it was installed before
+    /// repositories were recorded
+    fn scope<'a>(&self, repository: &'a str, dotfile_name: &str) -> &'a str {
+        match self.repositories.get(repository) {
+            Some(metadata) if metadata.dotfiles.contains_key(dotfile_name) => repository,
+            _ => UNKNOWN_REPOSITORY,
+        }
+    }
+
+    /// Return the metadata of the dotfile `dotfile_name` installed from `repository`
+    pub fn dotfile(&self, repository: &str, dotfile_name: &str) -> Option<&DotfileMetadata> {
+        self.repositories
+            .get(self.scope(repository, dotfile_name))?
+            .dotfiles
+            .get(dotfile_name)
+    }
+
+    /// Return the metadata of the dotfile `dotfile_name` installed from `repository`, mutably
+    pub fn dotfile_mut(
+        &mut self,
+        repository: &str,
+        dotfile_name: &str,
+    ) -> Option<&mut DotfileMetadata> {
+        let repository = self.scope(repository, dotfile_name);
+        self.repositories
+            .get_mut(repository)?
+            .dotfiles
+            .get_mut(dotfile_name)
+    }
+
+    /// Replace the commit hashes of the dotfiles that are keys of `rewritten` with their values,
+    /// e.g. after the commits were rebased. Returns whether any were replaced
+    pub fn rewrite_commit_hashes(&mut self, rewritten: &HashMap<String, String>) -> bool {
+        let mut changed = false;
+        for repository_metadata in self.repositories.values_mut() {
+            for metadata in repository_metadata.dotfiles.values_mut() {
+                if let Some(new_hash) = rewritten.get(&metadata.commit_hash) {
+                    metadata.commit_hash = new_hash.to_string();
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Record `metadata` as that of the dotfile `dotfile_name` installed from `origin`, or from
+    /// an unknown repository if None
+    pub fn insert_dotfile(
+        &mut self,
+        origin: Option<&RepositoryOrigin>,
+        dotfile_name: &str,
+        metadata: DotfileMetadata,
+    ) {
+        let repository = origin.map_or(UNKNOWN_REPOSITORY, |origin| origin.repository.as_str());
+        if repository != UNKNOWN_REPOSITORY {
+            self.remove_dotfile(UNKNOWN_REPOSITORY, dotfile_name);
+        }
+
+        let repository_metadata = self.repositories.entry(repository.to_string()).or_default();
+        if let Some(origin) = origin {
+            repository_metadata.host = origin.host.clone();
+            repository_metadata.manifest = origin.manifest.clone();
+            repository_metadata.subdir = origin.subdir.clone();
+        }
+        repository_metadata
+            .dotfiles
+            .insert(dotfile_name.to_string(), metadata);
+    }
+
+    /// Remove the dotfile `dotfile_name` installed from `repository`, returning its metadata
+    pub fn remove_dotfile(
+        &mut self,
+        repository: &str,
+        dotfile_name: &str,
+    ) -> Option<DotfileMetadata> {
+        let repository = self.scope(repository, dotfile_name);
+        let repository_metadata = self.repositories.get_mut(repository)?;
+        let metadata = repository_metadata.dotfiles.remove(dotfile_name);
+        if repository_metadata.dotfiles.is_empty() {
+            self.repositories.remove(repository);
+        }
+        metadata
+    }
+
+    /// Return the names of the dotfiles installed from `repository`, sorted alphabetically
+    pub fn installed_dotfiles(&self, repository: &str) -> Vec<&String> {
+        let mut dotfile_names: Vec<&String> = [repository, UNKNOWN_REPOSITORY]
+            .iter()
+            .filter_map(|repository| self.repositories.get(*repository))
+            .flat_map(|metadata| metadata.dotfiles.keys())
+            .collect();
+        dotfile_names.sort();
+        dotfile_names.dedup();
+        dotfile_names
+    }
+
+    /// Return every installed dotfile as (repository, dotfile name, metadata), sorted by
+    /// repository and then name
+    pub fn dotfiles(&self) -> Vec<(&String, &String, &DotfileMetadata)> {
+        let mut dotfiles: Vec<(&String, &String, &DotfileMetadata)> = self
+            .repositories
+            .iter()
+            .flat_map(|(repository, repository_metadata)| {
+                repository_metadata
+                    .dotfiles
+                    .iter()
+                    .map(move |(dotfile_name, metadata)| (repository, dotfile_name, metadata))
+            })
+            .collect();
+        dotfiles.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        dotfiles
+    }
+
+    /// Return the repositories dotfiles have been installed from, sorted by repository
+    pub fn known_repositories(&self) -> Vec<RepositoryOrigin> {
+        self.repositories
+            .iter()
+            .filter(|(repository, _)| repository.as_str() != UNKNOWN_REPOSITORY)
+            .map(|(repository, metadata)| RepositoryOrigin {
+                repository: repository.to_string(),
+                host: metadata.host.clone(),
+                manifest: metadata.manifest.clone(),
+                subdir: metadata.subdir.clone(),
+                repo_dir: None,
+            })
+            .collect()
+    }
+
+    /// Return each known repository with the names of the dotfiles installed from it, narrowed
+    /// down to `target_dotfiles` unless it is empty. Repositories none of the target dotfiles are
+    /// installed from are left out
+    pub fn installed_by_repository(
+        &self,
+        target_dotfiles: &[String],
+    ) -> Result<Vec<RepositoryTargets>, Box<dyn Error>> {
+        let installed: Vec<RepositoryTargets> = self
+            .known_repositories()
+            .into_iter()
+            .map(|origin| {
+                let dotfile_names = self
+                    .installed_dotfiles(&origin.repository)
+                    .into_iter()
+                    .filter(|dotfile_name| {
+                        target_dotfiles.is_empty() || target_dotfiles.contains(dotfile_name)
+                    })
+                    .cloned()
+                    .collect();
+                (origin, dotfile_names)
+            })
+            .filter(|(_, dotfile_names): &RepositoryTargets| !dotfile_names.is_empty())
+            .collect();
+
+        if installed.is_empty() {
+            return Err("No dotfiles have been installed from a known repository".into());
+        }
+        Ok(installed)
+    }
+
+    /// Return whether no dotfiles are installed
+    pub fn is_empty(&self) -> bool {
+        self.repositories
+            .values()
+            .all(|metadata| metadata.dotfiles.is_empty())
+    }
+
+    /// Return the repository the installed dotfile `dotfile_name` is from. If it is installed from
+    /// several repositories, `from` must match one of them (see [repository_matches])
+    pub fn locate_dotfile(
+        &self,
+        dotfile_name: &str,
+        from: Option<&str>,
+    ) -> Result<String, Box<dyn Error>> {
+        let repositories: Vec<&String> = self
+            .repositories
+            .iter()
+            .filter(|(_, metadata)| metadata.dotfiles.contains_key(dotfile_name))
+            .map(|(repository, _)| repository)
+            .filter(|repository| from.is_none_or(|from| repository_matches(repository, from)))
+            .collect();
+
+        match repositories.as_slice() {
+            [repository] => Ok(repository.to_string()),
+            [] => match from {
+                Some(from) => {
+                    Err(format!("Dotfile {} is not installed from {}", dotfile_name, from).into())
+                }
+                None => Err(format!("Dotfile {} is not installed", dotfile_name).into()),
+            },
+            repositories => Err(format!(
+                "Dotfile {} is installed from several repositories ({}). Pass \"--from\" to choose one",
+                dotfile_name,
+                repositories
+                    .iter()
+                    .map(|repository| repository.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )
+            .into()),
+        }
+    }
+
+    /// Return the dotfiles installed by the most recent `jtd install` as (repository, dotfile
+    /// name), sorted alphabetically by name
+    pub fn last_installed(&self) -> Vec<(String, String)> {
+        let dotfiles = self.dotfiles();
+        let last_installed_at = dotfiles
+            .iter()
+            .filter_map(|(_, _, metadata)| metadata.installed_at)
+            .max();
+
+        let mut last_installed: Vec<(String, String)> = dotfiles
+            .into_iter()
+            .filter(|(_, _, metadata)| {
+                last_installed_at.is_some() && metadata.installed_at == last_installed_at
+            })
+            .map(|(repository, dotfile_name, _)| (repository.to_string(), dotfile_name.to_string()))
+            .collect();
+        last_installed.sort_by(|a, b| (&a.1, &a.0).cmp(&(&b.1, &b.0)));
+        last_installed
+    }
+
+    /// Return the number of seconds between `now` and the last sync, or the last install if the
+    /// dotfiles have never been synced. Returns None if nothing is installed
+    pub fn secs_since_sync(&self, now: u64) -> Option<u64> {
+        if self.is_empty() {
+            return None;
+        }
+        let last_synced_at = self.last_synced_at.or_else(|| {
+            self.dotfiles()
+                .into_iter()
+                .filter_map(|(_, _, metadata)| metadata.installed_at)
+                .max()
+        })?;
+        Some(now.saturating_sub(last_synced_at))
+    }
+}
+
+/// Return whether the `recorded` repository of installed dotfiles is the repository `given` by
+/// the user, which may be a local path, a URL or just the end of one (e.g. `dob9601/dotfiles`)
+pub fn repository_matches(recorded: &str, given: &str) -> bool {
+    if let Some(path) = local_repository_path(given).and_then(|path| path.canonicalize().ok()) {
+        if path.is_dir() {
+            return recorded == path.to_string_lossy();
+        }
+    }
+    let recorded = recorded.trim_end_matches('/').trim_end_matches(".git");
+    let given = given.trim_end_matches('/').trim_end_matches(".git");
+    recorded == given
+        || recorded
+            .strip_suffix(given)
+            .is_some_and(|prefix| prefix.ends_with('/') || prefix.ends_with(':'))
 }
 
 /// Represent the metadata of an installed dotfile
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct DotfileMetadata {
     /// The hash of the commit this dotfile was installed from
     pub commit_hash: String,
@@ -89,6 +570,69 @@ pub struct DotfileMetadata {
     /// The sha1 hash of the post-install steps. Used to figure out whether post-install should be
     /// run again on subsequent installations
     pub post_install_hash: String,
+
+    /// The path of the backup of the local file that was overwritten by the last install of this
+    /// dotfile, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_path: Option<PathBuf>,
+
+    /// The branch, tag or commit this dotfile was restored to by `jtd restore`, if it has been
+    /// since it was last installed. Restored dotfiles are left alone by `jtd update` and `jtd sync`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restored_ref: Option<String>,
+
+    /// Whether this dotfile was rolled back to the file it replaced by `jtd rollback` since it was
+    /// last installed. Rolled back dotfiles are left alone by `jtd update` and `jtd sync`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rolled_back: bool,
+
+    /// The path this dotfile was installed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_path: Option<PathBuf>,
+
+    /// Whether the target is only writable by root, in which case it is restored and removed with
+    /// sudo
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub requires_root: bool,
+
+    /// The UNIX timestamp of the `jtd install` this dotfile was last installed by. Shared by every
+    /// dotfile installed in the same operation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_at: Option<u64>,
+
+    /// The version of jtd this dotfile was last installed by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jtd_version: Option<String>,
+
+    /// The URL (or path, for local clones) of the repository this dotfile was last installed from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo_url: Option<String>,
+
+    /// The hash of the install steps the user declined to run in the last install, if they did.
+    /// Used to avoid asking about the same steps again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub declined_steps_hash: Option<String>,
+
+    /// The pre-uninstall steps of this dotfile as of its last install. Stored here so that the
+    /// dotfile can be uninstalled without access to the repository
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_uninstall: Vec<String>,
+
+    /// The post-uninstall steps of this dotfile as of its last install
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_uninstall: Vec<String>,
+
+    /// The environment variables declared by this dotfile, which the uninstall steps are run with
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+
+    /// The number of seconds each uninstall step may run for, if limited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+
+    /// The shell the uninstall steps are run with, if not the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
 }
 
 impl DotfileMetadata {
@@ -98,6 +642,332 @@ impl DotfileMetadata {
             commit_hash: commit_hash.to_string(),
             pre_install_hash,
             post_install_hash,
+            ..Default::default()
+        }
+    }
+
+    /// Return whether this dotfile has pre_uninstall or post_uninstall steps
+    pub fn has_uninstall_steps(&self) -> bool {
+        !self.pre_uninstall.is_empty() || !self.post_uninstall.is_empty()
+    }
+
+    /// Remove the installed dotfile, running its pre/post uninstall steps around the removal
+    /// unless `skip_uninstall_steps` is true. If `dry_run` is true, only print what would happen.
+    pub fn uninstall(
+        &self,
+        dotfile_name: &str,
+        skip_uninstall_steps: bool,
+        dry_run: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut env = HashMap::from([("JTD_DOTFILE_NAME".to_string(), dotfile_name.to_string())]);
+        if let Some(target_path) = &self.target_path {
+            env.insert(
+                "JTD_TARGET_PATH".to_string(),
+                target_path.to_string_lossy().to_string(),
+            );
+        }
+        env.extend(self.env.clone());
+
+        let run_steps = |stage: &str, steps: &[String]| -> Result<(), Box<dyn Error>> {
+            if steps.is_empty() || skip_uninstall_steps {
+                return Ok(());
+            }
+            if dry_run {
+                info!("Would run {} steps:", stage);
+                print_command_vec(steps);
+            } else {
+                success!("Running {} steps", stage);
+                run_command_vec(
+                    steps,
+                    self.shell.as_deref().unwrap_or(DEFAULT_SHELL),
+                    &env,
+                    self.timeout_secs.map(Duration::from_secs),
+                )?;
+            }
+            Ok(())
+        };
+
+        run_steps("pre-uninstall", &self.pre_uninstall)?;
+
+        match &self.target_path {
+            Some(target_path) if dry_run => {
+                info!("Would remove {}", target_path.to_string_lossy());
+            }
+            Some(target_path) if target_path.exists() => {
+                if self.requires_root {
+                    remove_as_root(&root_command(), target_path)?;
+                } else {
+                    fs::remove_file(target_path)?;
+                }
+                log_operation(
+                    "uninstalled",
+                    json!({ "dotfile": dotfile_name, "target": target_path }),
+                );
+                success!("Removed {}", target_path.to_string_lossy());
+            }
+            Some(target_path) => {
+                info!("{} has already been removed", target_path.to_string_lossy());
+            }
+            None => {
+                warn!(
+                    "Could not determine where {} was installed, it will need to be removed manually",
+                    dotfile_name
+                );
+            }
+        }
+
+        run_steps("post-uninstall", &self.post_uninstall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_installed() {
+        let mut aggregated_metadata = AggregatedDotfileMetadata::new();
+        for (dotfile_name, installed_at) in [
+            ("nvim", Some(100)),
+            ("kitty", Some(200)),
+            ("fish", Some(200)),
+            ("tmux", None),
+        ] {
+            aggregated_metadata.insert_dotfile(
+                None,
+                dotfile_name,
+                DotfileMetadata {
+                    installed_at,
+                    ..Default::default()
+                },
+            );
+        }
+
+        assert_eq!(
+            aggregated_metadata.last_installed(),
+            vec![
+                (UNKNOWN_REPOSITORY.to_string(), "fish".to_string()),
+                (UNKNOWN_REPOSITORY.to_string(), "kitty".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dotfile_metadata_provenance() {
+        // Metadata written before the version and repository were recorded
+        let metadata: DotfileMetadata = serde_yaml::from_str(
+            "commit_hash: abc123\npre_install_hash: ''\npost_install_hash: ''\ninstalled_at: 100\n",
+        )
+        .unwrap();
+        assert_eq!(metadata.installed_at, Some(100));
+        assert_eq!(metadata.jtd_version, None);
+        assert_eq!(metadata.repo_url, None);
+
+        let metadata = DotfileMetadata {
+            jtd_version: Some("0.1.0".to_string()),
+            repo_url: Some("https://github.com/dob9601/dotfiles.git".to_string()),
+            ..metadata
+        };
+        let serialized = serde_yaml::to_string(&metadata).unwrap();
+        assert!(serialized.contains("jtd_version: 0.1.0"));
+        let deserialized: DotfileMetadata = serde_yaml::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.repo_url, metadata.repo_url);
+    }
+
+    #[test]
+    fn test_uninstall() {
+        let dotfile_dir = tempfile::tempdir().unwrap();
+        let target_path = dotfile_dir.path().join("dotfile");
+        let post_uninstall_path = dotfile_dir.path().join("post_uninstall");
+        File::create(&target_path).unwrap();
+
+        let metadata = DotfileMetadata {
+            target_path: Some(target_path.clone()),
+            post_uninstall: vec![format!("touch {}", post_uninstall_path.to_string_lossy())],
+            ..Default::default()
+        };
+
+        metadata.uninstall("dotfile", false, true).unwrap();
+        assert!(target_path.exists());
+        assert!(!post_uninstall_path.exists());
+
+        metadata.uninstall("dotfile", false, false).unwrap();
+        assert!(!target_path.exists());
+        assert!(post_uninstall_path.exists());
+    }
+
+    #[test]
+    fn test_secs_since_sync() {
+        let mut aggregated_metadata = AggregatedDotfileMetadata::new();
+        assert_eq!(aggregated_metadata.secs_since_sync(1000), None);
+
+        aggregated_metadata.insert_dotfile(
+            None,
+            "nvim",
+            DotfileMetadata {
+                installed_at: Some(100),
+                ..Default::default()
+            },
+        );
+        assert_eq!(aggregated_metadata.secs_since_sync(1000), Some(900));
+
+        aggregated_metadata.last_synced_at = Some(400);
+        assert_eq!(aggregated_metadata.secs_since_sync(1000), Some(600));
+
+        let serialized = serde_yaml::to_string(&aggregated_metadata).unwrap();
+        let deserialized: AggregatedDotfileMetadata = serde_yaml::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.last_synced_at, Some(400));
+        assert_eq!(deserialized.dotfiles().len(), 1);
+    }
+
+    fn origin(repository: &str) -> RepositoryOrigin {
+        RepositoryOrigin {
+            repository: repository.to_string(),
+            host: Some("github.com".to_string()),
+            manifest: "jtd.yaml".to_string(),
+            subdir: None,
+            repo_dir: None,
+        }
+    }
+
+    fn installed_from(commit_hash: &str) -> DotfileMetadata {
+        DotfileMetadata {
+            commit_hash: commit_hash.to_string(),
+            ..Default::default()
         }
     }
+
+    #[test]
+    fn test_multiple_repositories() {
+        let personal = origin("git@github.com:dob9601/dotfiles.git");
+        let work = origin("git@github.com:work/dotfiles.git");
+
+        let mut aggregated_metadata = AggregatedDotfileMetadata::new();
+        aggregated_metadata.insert_dotfile(Some(&personal), "git", installed_from("1"));
+        aggregated_metadata.insert_dotfile(Some(&personal), "nvim", installed_from("2"));
+        aggregated_metadata.insert_dotfile(Some(&work), "git", installed_from("3"));
+
+        assert_eq!(
+            aggregated_metadata
+                .dotfile(&work.repository, "git")
+                .unwrap()
+                .commit_hash,
+            "3"
+        );
+        assert!(aggregated_metadata
+            .dotfile(&work.repository, "nvim")
+            .is_none());
+        assert_eq!(
+            aggregated_metadata.installed_dotfiles(&personal.repository),
+            vec!["git", "nvim"]
+        );
+
+        assert!(aggregated_metadata.locate_dotfile("git", None).is_err());
+        assert_eq!(
+            aggregated_metadata
+                .locate_dotfile("git", Some("work/dotfiles"))
+                .unwrap(),
+            work.repository
+        );
+        assert_eq!(
+            aggregated_metadata.locate_dotfile("nvim", None).unwrap(),
+            personal.repository
+        );
+
+        let targets = aggregated_metadata
+            .installed_by_repository(&["git".to_string()])
+            .unwrap();
+        assert_eq!(targets.len(), 2);
+        assert!(targets
+            .iter()
+            .all(|(_, dotfile_names)| dotfile_names == &["git"]));
+
+        aggregated_metadata.remove_dotfile(&work.repository, "git");
+        assert_eq!(aggregated_metadata.known_repositories(), vec![personal]);
+    }
+
+    #[test]
+    fn test_export_import() {
+        let personal = origin("git@github.com:dob9601/dotfiles.git");
+        let work = origin("git@github.com:work/dotfiles.git");
+
+        let mut exported = AggregatedDotfileMetadata::new();
+        let mut git_metadata = installed_from("1");
+        git_metadata.backup_path = Some(PathBuf::from("/home/user/.local/share/backups/git"));
+        git_metadata.post_install_hash = "abc".to_string();
+        exported.insert_dotfile(Some(&personal), "git", git_metadata);
+        exported.insert_dotfile(Some(&personal), "nvim", installed_from("2"));
+        exported.installed_from = Some(personal.clone());
+        let exported: AggregatedDotfileMetadata =
+            serde_yaml::from_str(&serde_yaml::to_string(&exported.export()).unwrap()).unwrap();
+
+        let mut aggregated_metadata = AggregatedDotfileMetadata::new();
+        aggregated_metadata.insert_dotfile(Some(&personal), "git", installed_from("0"));
+        aggregated_metadata.insert_dotfile(Some(&work), "git", installed_from("3"));
+
+        assert_eq!(aggregated_metadata.import(exported), 2);
+        let git_metadata = aggregated_metadata
+            .dotfile(&personal.repository, "git")
+            .unwrap();
+        assert_eq!(git_metadata.commit_hash, "1");
+        assert_eq!(git_metadata.post_install_hash, "abc");
+        assert!(git_metadata.backup_path.is_none());
+        assert_eq!(
+            aggregated_metadata.installed_dotfiles(&personal.repository),
+            vec!["git", "nvim"]
+        );
+        assert_eq!(
+            aggregated_metadata
+                .dotfile(&work.repository, "git")
+                .unwrap()
+                .commit_hash,
+            "3"
+        );
+        assert_eq!(aggregated_metadata.installed_from, Some(personal));
+    }
+
+    #[test]
+    fn test_read_unscoped_metadata() {
+        let mut aggregated_metadata: AggregatedDotfileMetadata = serde_yaml::from_str(
+            "nvim:\n  commit_hash: abc\n  pre_install_hash: \"\"\n  post_install_hash: \"\"\n\
+            \".last_synced_at\": 400\n",
+        )
+        .unwrap();
+        let personal = origin("git@github.com:dob9601/dotfiles.git");
+
+        assert_eq!(aggregated_metadata.last_synced_at, Some(400));
+        assert!(aggregated_metadata.known_repositories().is_empty());
+        // Dotfiles installed before repositories were recorded belong to any repository
+        assert_eq!(
+            aggregated_metadata
+                .dotfile(&personal.repository, "nvim")
+                .unwrap()
+                .commit_hash,
+            "abc"
+        );
+
+        aggregated_metadata.insert_dotfile(Some(&personal), "nvim", installed_from("def"));
+        assert_eq!(aggregated_metadata.known_repositories(), vec![personal]);
+        assert_eq!(aggregated_metadata.dotfiles().len(), 1);
+    }
+
+    #[test]
+    fn test_repository_matches() {
+        let recorded = "git@github.com:dob9601/dotfiles.git";
+        assert!(repository_matches(recorded, recorded));
+        assert!(repository_matches(recorded, "dob9601/dotfiles"));
+        assert!(repository_matches(
+            "https://github.com/dob9601/dotfiles.git",
+            "dob9601/dotfiles"
+        ));
+        assert!(!repository_matches(recorded, "9601/dotfiles"));
+        assert!(!repository_matches(recorded, "work/dotfiles"));
+    }
+
+    #[test]
+    fn test_last_installed_no_installs() {
+        let aggregated_metadata = AggregatedDotfileMetadata::new();
+
+        assert!(aggregated_metadata.last_installed().is_empty());
+    }
 }
@@ -1,10 +1,25 @@
 mod config;
 mod dotfile;
+mod lockfile;
 mod manifest;
 mod metadata;
+mod report;
+mod trust;
+mod user_config;
+mod validation;
+mod vars;
 
-pub use config::Config;
-pub use dotfile::Dotfile;
-pub use manifest::Manifest;
+pub use config::{CommitStyle, Config, MergeStrategy, OnConflict, DEFAULT_BACKUP_SUFFIX};
+pub use dotfile::{Dotfile, Encryption, InstallMode, MergeDriver, ReloadAction, SyncState};
+pub use lockfile::{LockedDotfile, Lockfile, LOCKFILE};
+pub use manifest::{find_manifest, Manifest, DEFAULT_MANIFEST, MANIFEST_CANDIDATES};
 
-pub use metadata::{AggregatedDotfileMetadata, DotfileMetadata};
+pub use metadata::{
+    repository_matches, AggregatedDotfileMetadata, DotfileMetadata, RepositoryMetadata,
+    RepositoryOrigin, RepositoryTargets, UNKNOWN_REPOSITORY,
+};
+pub use report::{DotfileReport, DotfileStatus, OperationReport};
+pub use trust::TrustStore;
+pub use user_config::{CommitDefaults, UserConfig};
+pub use validation::{validate_manifest, ManifestProblem, ProblemSeverity};
+pub use vars::MachineVariables;
@@ -0,0 +1,133 @@
+use serde::Serialize;
+
+/// The outcome of an operation on a single dotfile
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DotfileStatus {
+    Installed,
+    Synced,
+    /// The dotfile would have been installed, had this not been a dry run
+    WouldInstall,
+    /// The dotfile would have been synced, had this not been a dry run
+    WouldSync,
+    Unchanged,
+    Skipped,
+    Failed,
+}
+
+/// Represents the result of installing or syncing a single dotfile
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DotfileReport {
+    pub name: String,
+    pub status: DotfileStatus,
+
+    /// The commit the dotfile was installed from or synced to, if any
+    pub commit_hash: Option<String>,
+}
+
+impl DotfileReport {
+    pub fn new(name: &str, status: DotfileStatus, commit_hash: Option<String>) -> Self {
+        DotfileReport {
+            name: name.to_string(),
+            status,
+            commit_hash,
+        }
+    }
+}
+
+/// Represents the result of a whole subcommand, used for machine-readable output
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OperationReport {
+    pub success: bool,
+    pub dry_run: bool,
+    pub dotfiles: Vec<DotfileReport>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl OperationReport {
+    pub fn new(dotfiles: Vec<DotfileReport>, dry_run: bool) -> Self {
+        OperationReport {
+            success: !dotfiles
+                .iter()
+                .any(|dotfile| dotfile.status == DotfileStatus::Failed),
+            dry_run,
+            dotfiles,
+            error: None,
+        }
+    }
+
+    pub fn from_error(error: &str) -> Self {
+        OperationReport {
+            success: false,
+            dry_run: false,
+            dotfiles: vec![],
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_report_serialize() {
+        let report = OperationReport::new(
+            vec![DotfileReport::new(
+                "kitty",
+                DotfileStatus::Installed,
+                Some("b51a85b8eeee922159d23463ffc057ab25fbaf9b".to_string()),
+            )],
+            false,
+        );
+
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            "{\"success\":true,\"dry_run\":false,\"dotfiles\":[{\"name\":\"kitty\",\"status\":\
+            \"installed\",\"commit_hash\":\"b51a85b8eeee922159d23463ffc057ab25fbaf9b\"}]}"
+        );
+    }
+
+    #[test]
+    fn test_operation_report_with_failures() {
+        let report = OperationReport::new(
+            vec![
+                DotfileReport::new("kitty", DotfileStatus::Installed, None),
+                DotfileReport::new("nvim", DotfileStatus::Failed, None),
+            ],
+            false,
+        );
+
+        assert!(!report.success);
+    }
+
+    #[test]
+    fn test_dry_run_report_serialize() {
+        let report = OperationReport::new(
+            vec![DotfileReport::new(
+                "kitty",
+                DotfileStatus::WouldInstall,
+                None,
+            )],
+            true,
+        );
+
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            "{\"success\":true,\"dry_run\":true,\"dotfiles\":[{\"name\":\"kitty\",\"status\":\
+            \"would_install\",\"commit_hash\":null}]}"
+        );
+    }
+
+    #[test]
+    fn test_operation_report_from_error() {
+        let report = OperationReport::from_error("Could not clone repo");
+
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            "{\"success\":false,\"dry_run\":false,\"dotfiles\":[],\"error\":\"Could not clone repo\"}"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::repository_matches;
+use crate::git::remote::repository_identity;
+
+/// Struct representing the trust store, `trust.yaml` in the data directory. Records the
+/// repositories whose install steps are always run and the hashes of install steps that the user
+/// agreed to run, so that installing them again doesn't ask about them again
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct TrustStore {
+    /// The URLs (or local paths) of the trusted repositories
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub repositories: BTreeSet<String>,
+
+    /// The hashes of trusted pre-install or post-install steps, as recorded in the metadata of the
+    /// dotfiles they were run for
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub hashes: BTreeSet<String>,
+}
+
+impl TrustStore {
+    /// Read the trust store at `path`, returning an empty one if there isn't one yet
+    pub fn read(path: &Path) -> Result<TrustStore, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_yaml::from_str(&contents).map_err(|err| {
+                format!(
+                    "Could not parse trust store. Check {} for issues: {}",
+                    path.display(),
+                    err
+                )
+            })?),
+            Err(_) => Ok(TrustStore::default()),
+        }
+    }
+
+    /// Write this trust store to `path`
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = format!(
+            "# jointhedots trust store. Manage it with \"jtd trust\"\n{}",
+            serde_yaml::to_string(self)?
+        );
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Return whether the install steps of `repository` (as recorded in the metadata of the
+    /// dotfiles installed from it) are trusted. Repositories on a host are compared by host and
+    /// path (see [repository_identity]), so whether they are cloned over SSH or HTTPS doesn't
+    /// matter, and local clones by their path
+    pub fn trusts_repository(&self, repository: &str) -> bool {
+        match repository_identity(repository) {
+            Some(identity) => self
+                .repositories
+                .iter()
+                .any(|trusted| repository_identity(trusted).as_ref() == Some(&identity)),
+            None => !repository.is_empty() && self.repositories.contains(repository),
+        }
+    }
+
+    /// Return whether all of `hashes` are trusted. Empty hashes, of steps that don't exist, are
+    /// ignored
+    pub fn trusts_hashes(&self, hashes: &[String]) -> bool {
+        hashes
+            .iter()
+            .filter(|hash| !hash.is_empty())
+            .all(|hash| self.hashes.contains(hash))
+    }
+
+    /// Remove the repositories matching `repository`, which may be given as in
+    /// [repository_matches], returning whether any were trusted
+    pub fn remove_repository(&mut self, repository: &str) -> bool {
+        let count = self.repositories.len();
+        self.repositories
+            .retain(|trusted| !repository_matches(trusted, repository));
+        self.repositories.len() != count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_trust_store() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trust.yaml");
+        assert_eq!(TrustStore::read(&path).unwrap(), TrustStore::default());
+
+        let mut trust_store = TrustStore::default();
+        trust_store
+            .repositories
+            .insert("git@github.com:dob9601/dotfiles.git".to_string());
+        trust_store.hashes.insert("abc123".to_string());
+        trust_store.write(&path).unwrap();
+        let mut trust_store = TrustStore::read(&path).unwrap();
+
+        assert!(trust_store.trusts_repository("git@github.com:dob9601/dotfiles.git"));
+        assert!(trust_store.trusts_repository("https://github.com/dob9601/dotfiles.git"));
+        assert!(!trust_store.trusts_repository("git@github.com:someone/dotfiles.git"));
+        assert!(
+            !trust_store.trusts_repository("https://evil.example/git@github.com:dob9601/dotfiles")
+        );
+        assert!(!trust_store.trusts_repository("https://github.com/fork/dob9601/dotfiles.git"));
+        assert!(!trust_store.trusts_repository(""));
+        assert!(trust_store.trusts_hashes(&["abc123".to_string(), "".to_string()]));
+        assert!(!trust_store.trusts_hashes(&["abc123".to_string(), "def456".to_string()]));
+
+        assert!(trust_store.remove_repository("dob9601/dotfiles"));
+        assert!(!trust_store.remove_repository("dob9601/dotfiles"));
+        assert!(trust_store.repositories.is_empty());
+    }
+}
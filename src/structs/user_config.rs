@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use crate::USER_CONFIG_PATH;
+
+/// Struct representing a `config.yaml` file, typically found in ~/.config/jointhedots. Holds the
+/// defaults of this machine for the arguments of every subcommand, so that e.g. `jtd sync` needs
+/// no arguments on a configured machine
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserConfig {
+    /// The repository to use when none is passed
+    pub repository: Option<String>,
+
+    /// The host to source the repository from, as passed to `--source`
+    pub source: Option<String>,
+
+    /// The connection method to use, as passed to `--method`
+    pub method: Option<String>,
+
+    /// The manifest to use in the repository, as passed to `--manifest`
+    pub manifest: Option<String>,
+
+    /// Whether to run install steps without asking, as if `--trust` was always passed
+    pub trust: bool,
+
+    /// The external tool to show differences with, as passed to `jtd diff --tool`
+    pub diff_tool: Option<String>,
+
+    /// The hosts of self-hosted GitLab instances to send the GitLab access token to, e.g.
+    /// `git.example.com`. Tokens are only sent to `gitlab.com` otherwise
+    pub gitlab_hosts: Vec<String>,
+
+    /// Defaults for the commit settings in the `.config` block of the manifest. Settings in the
+    /// manifest take precedence
+    pub commit: CommitDefaults,
+}
+
+/// The commit settings of the manifest config that can be given defaults in [UserConfig]
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommitDefaults {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_style: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub squash_commits: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sign_off: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_trailers: Option<IndexMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author_email: Option<String>,
+}
+
+impl UserConfig {
+    /// Get the user configuration of this machine, or an empty configuration if no config file
+    /// exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use jointhedots::structs::UserConfig;
+    ///
+    /// let user_config = UserConfig::get().unwrap();
+    /// ```
+    pub fn get() -> Result<UserConfig, Box<dyn Error>> {
+        UserConfig::read(Path::new(shellexpand::tilde(USER_CONFIG_PATH).as_ref()))
+    }
+
+    fn read(path: &Path) -> Result<UserConfig, Box<dyn Error>> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_yaml::from_reader(file).map_err(|err| {
+                format!(
+                    "Could not parse config file. Check {} for issues: {}",
+                    path.to_string_lossy(),
+                    err
+                )
+            })?),
+            Err(_) => Ok(UserConfig::default()),
+        }
+    }
+}
+
+impl CommitDefaults {
+    /// Add these defaults to the `.config` block of the raw `manifest`, for every setting the
+    /// manifest doesn't set itself
+    pub(crate) fn apply_to(&self, manifest: &mut Value) -> Result<(), Box<dyn Error>> {
+        let defaults = match serde_yaml::to_value(self)? {
+            Value::Mapping(defaults) if !defaults.is_empty() => defaults,
+            _ => return Ok(()),
+        };
+        let manifest = match manifest {
+            Value::Mapping(manifest) => manifest,
+            _ => return Ok(()),
+        };
+
+        let config_key = Value::String(".config".to_string());
+        if !manifest.contains_key(&config_key) {
+            manifest.insert(config_key.clone(), Value::Mapping(Mapping::new()));
+        }
+        if let Some(Value::Mapping(config)) = manifest.get_mut(&config_key) {
+            for (key, value) in defaults {
+                if !config.contains_key(&key) {
+                    config.insert(key, value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_read_user_config() {
+        let dir = tempdir().expect("Could not create temporary dir");
+        let path = dir.path().join("config.yaml");
+        assert_eq!(UserConfig::read(&path).unwrap(), UserConfig::default());
+
+        fs::write(
+            &path,
+            "repository: dob9601/dotfiles\nmethod: https\ntrust: true\ndiff_tool: delta\ngitlab_hosts: [git.example.com]\ncommit:\n  squash_commits: true\n",
+        )
+        .unwrap();
+        let user_config = UserConfig::read(&path).unwrap();
+        assert_eq!(user_config.repository.as_deref(), Some("dob9601/dotfiles"));
+        assert_eq!(user_config.method.as_deref(), Some("https"));
+        assert!(user_config.trust);
+        assert_eq!(user_config.diff_tool.as_deref(), Some("delta"));
+        assert_eq!(user_config.gitlab_hosts, ["git.example.com"]);
+        assert_eq!(user_config.commit.squash_commits, Some(true));
+
+        fs::write(&path, "respository: dob9601/dotfiles\n").unwrap();
+        assert!(UserConfig::read(&path).is_err());
+    }
+
+    #[test]
+    fn test_apply_commit_defaults() {
+        let defaults = CommitDefaults {
+            commit_prefix: Some("🔧 ".to_string()),
+            author_name: Some("Laptop".to_string()),
+            ..Default::default()
+        };
+        let mut manifest: Value =
+            serde_yaml::from_str(".config:\n  commit_prefix: \"🚀 \"\nnvim:\n  file: nvim\n")
+                .unwrap();
+
+        defaults.apply_to(&mut manifest).unwrap();
+
+        let config = &manifest[".config"];
+        assert_eq!(config["commit_prefix"], Value::from("🚀 "));
+        assert_eq!(config["author_name"], Value::from("Laptop"));
+        assert_eq!(config.get("squash_commits"), None);
+    }
+}
@@ -0,0 +1,539 @@
+use std::{collections::HashMap, error::Error, fmt, path::Path};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use serde_yaml::Value;
+
+use super::{manifest::read_manifest_value, Config, Dotfile};
+
+/// The operating systems dotfiles may specify target overrides for
+const SUPPORTED_OSES: &[&str] = &["linux", "macos", "windows"];
+
+lazy_static! {
+    static ref SUSPICIOUS_COMMAND_PATTERNS: Vec<(Regex, &'static str)> = vec![
+        (
+            Regex::new(r"\b(curl|wget)\b.*\|\s*(ba|z|fi|da)?sh\b").unwrap(),
+            "pipes a download straight into a shell"
+        ),
+        (
+            Regex::new(r"\brm\s+-[a-zA-Z]*r[a-zA-Z]*\s+(/|~|\$HOME)/?\*?(\s|$)").unwrap(),
+            "recursively deletes a root or home directory"
+        ),
+        (
+            Regex::new(r"\bsudo\b").unwrap(),
+            "runs with elevated privileges"
+        ),
+        (
+            Regex::new(r"\bchmod\s+(-R\s+)?777\b").unwrap(),
+            "makes files world-writable"
+        ),
+        (
+            Regex::new(r"\b(mkfs(\.\w+)?|dd\s+if=)").unwrap(),
+            "writes directly to a block device"
+        ),
+        (
+            Regex::new(r"\bbase64\s+(-d|--decode)\b").unwrap(),
+            "decodes an obfuscated payload"
+        ),
+    ];
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemSeverity {
+    Error,
+    Warning,
+}
+
+/// A single issue found while validating a manifest
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ManifestProblem {
+    pub severity: ProblemSeverity,
+
+    /// The name of the dotfile the problem was found in, or `None` if it applies to the whole
+    /// manifest
+    pub dotfile: Option<String>,
+
+    pub message: String,
+}
+
+impl ManifestProblem {
+    fn error(dotfile: Option<&str>, message: String) -> Self {
+        ManifestProblem {
+            severity: ProblemSeverity::Error,
+            dotfile: dotfile.map(|name| name.to_string()),
+            message,
+        }
+    }
+
+    fn warning(dotfile: Option<&str>, message: String) -> Self {
+        ManifestProblem {
+            severity: ProblemSeverity::Warning,
+            dotfile: dotfile.map(|name| name.to_string()),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ManifestProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            ProblemSeverity::Error => "error",
+            ProblemSeverity::Warning => "warning",
+        };
+        match &self.dotfile {
+            Some(dotfile) => write!(f, "[{}] {}: {}", severity, dotfile, self.message),
+            None => write!(f, "[{}] {}", severity, self.message),
+        }
+    }
+}
+
+/// Validate the manifest located at `manifest_path`, returning every problem found rather than
+/// stopping at the first one. Manifests using `.extends` are validated after being merged with
+/// their base manifest.
+///
+/// # Arguments
+///
+/// * `manifest_path` - The path to the manifest file
+/// * `repo_dir` - The root of the repository the manifest belongs to. Used to check that the
+///   files referenced by each dotfile exist
+///
+/// # Returns
+///
+/// A vector of the problems found in the manifest. An empty vector means the manifest is valid.
+pub fn validate_manifest(
+    manifest_path: &Path,
+    repo_dir: &Path,
+) -> Result<Vec<ManifestProblem>, Box<dyn Error>> {
+    let manifest = read_manifest_value(manifest_path)?;
+
+    let mapping = manifest
+        .as_mapping()
+        .ok_or("Manifest must be a mapping of dotfile names to dotfiles")?;
+
+    let mut problems = vec![];
+    let mut targets: HashMap<String, Vec<String>> = HashMap::new();
+    let mut target_dotfiles: Vec<(String, Dotfile)> = vec![];
+    let mut groups: Option<&Value> = None;
+    let files_dir = match mapping
+        .get(&Value::from(".config"))
+        .and_then(|config| config.get("root"))
+        .and_then(Value::as_str)
+    {
+        Some(root) => repo_dir.join(root),
+        None => repo_dir.to_path_buf(),
+    };
+
+    for (key, value) in mapping {
+        let dotfile_name = match key.as_str() {
+            Some(name) => name,
+            None => {
+                problems.push(ManifestProblem::error(
+                    None,
+                    format!("Dotfile names must be strings, found {:?}", key),
+                ));
+                continue;
+            }
+        };
+
+        if dotfile_name == ".config" {
+            problems.extend(validate_config(value));
+            continue;
+        }
+
+        if dotfile_name == ".groups" {
+            groups = Some(value);
+            continue;
+        }
+
+        let dotfile = match validate_dotfile(dotfile_name, value, &mut problems) {
+            Some(dotfile) => dotfile,
+            None => continue,
+        };
+
+        if !files_dir.join(&dotfile.file).exists() {
+            problems.push(ManifestProblem::error(
+                Some(dotfile_name),
+                format!("File \"{}\" does not exist in the repository", dotfile.file),
+            ));
+        }
+
+        for os in dotfile.targets.keys() {
+            if !SUPPORTED_OSES.contains(&os.as_str()) {
+                problems.push(ManifestProblem::warning(
+                    Some(dotfile_name),
+                    format!(
+                        "Unknown OS \"{}\" in targets, expected one of {}",
+                        os,
+                        SUPPORTED_OSES.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        for (stage, commands) in [
+            ("pre_install", &dotfile.pre_install),
+            ("post_install", &dotfile.post_install),
+            ("only_if", &dotfile.only_if),
+            ("pre_uninstall", &dotfile.pre_uninstall),
+            ("post_uninstall", &dotfile.post_uninstall),
+        ] {
+            for command in commands.iter().flatten() {
+                for (pattern, reason) in SUSPICIOUS_COMMAND_PATTERNS.iter() {
+                    if pattern.is_match(command) {
+                        problems.push(ManifestProblem::warning(
+                            Some(dotfile_name),
+                            format!("{} step \"{}\" {}", stage, command, reason),
+                        ));
+                    }
+                }
+            }
+        }
+
+        target_dotfiles.push((dotfile_name.to_string(), dotfile));
+    }
+
+    // Targets can differ between OSes, so check for duplicates on each of them
+    for os in SUPPORTED_OSES {
+        let mut os_targets: HashMap<String, Vec<String>> = HashMap::new();
+        for (dotfile_name, dotfile) in target_dotfiles.iter() {
+            os_targets
+                .entry(dotfile.target_path_for_os(os).to_string_lossy().to_string())
+                .or_default()
+                .push(dotfile_name.to_string());
+        }
+        for (target, mut dotfile_names) in os_targets {
+            if dotfile_names.len() > 1 {
+                dotfile_names.sort();
+                targets.insert(target, dotfile_names);
+            }
+        }
+    }
+
+    let mut duplicate_targets: Vec<(String, Vec<String>)> = targets.into_iter().collect();
+    duplicate_targets.sort();
+    for (target, dotfile_names) in duplicate_targets {
+        problems.push(ManifestProblem::error(
+            None,
+            format!(
+                "Dotfiles {} all install to the same target {}",
+                dotfile_names.join(", "),
+                target
+            ),
+        ));
+    }
+
+    if let Some(groups) = groups {
+        problems.extend(validate_groups(groups, mapping));
+    }
+
+    Ok(problems)
+}
+
+fn validate_groups(value: &Value, mapping: &serde_yaml::Mapping) -> Vec<ManifestProblem> {
+    let mut problems = vec![];
+    let groups: HashMap<String, Vec<String>> = match serde_yaml::from_value(value.clone()) {
+        Ok(groups) => groups,
+        Err(err) => {
+            problems.push(ManifestProblem::error(Some(".groups"), err.to_string()));
+            return problems;
+        }
+    };
+
+    let mut group_names: Vec<&String> = groups.keys().collect();
+    group_names.sort();
+    for group_name in group_names {
+        if mapping.contains_key(&Value::from(group_name.as_str())) {
+            problems.push(ManifestProblem::warning(
+                Some(".groups"),
+                format!(
+                    "Group \"{}\" has the same name as a dotfile and will be ignored",
+                    group_name
+                ),
+            ));
+        }
+        for member in &groups[group_name] {
+            if member.starts_with('.') || !mapping.contains_key(&Value::from(member.as_str())) {
+                problems.push(ManifestProblem::error(
+                    Some(".groups"),
+                    format!(
+                        "Group \"{}\" contains unknown dotfile \"{}\"",
+                        group_name, member
+                    ),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+fn validate_config(value: &Value) -> Vec<ManifestProblem> {
+    let mut problems = vec![];
+    let result: Result<Config, _> = serde_ignored::deserialize(value.clone(), |path| {
+        problems.push(ManifestProblem::error(
+            Some(".config"),
+            format!("Unknown configuration key \"{}\"", path),
+        ))
+    });
+
+    if let Err(err) = result {
+        problems.push(ManifestProblem::error(Some(".config"), err.to_string()));
+    }
+    problems
+}
+
+fn validate_dotfile(
+    dotfile_name: &str,
+    value: &Value,
+    problems: &mut Vec<ManifestProblem>,
+) -> Option<Dotfile> {
+    let mapping = match value.as_mapping() {
+        Some(mapping) => mapping,
+        None => {
+            problems.push(ManifestProblem::error(
+                Some(dotfile_name),
+                "Dotfile must be a mapping of keys to values".to_string(),
+            ));
+            return None;
+        }
+    };
+
+    let mut missing_required_key = false;
+    for required_key in ["file", "target"] {
+        if !mapping.contains_key(&Value::from(required_key)) {
+            problems.push(ManifestProblem::error(
+                Some(dotfile_name),
+                format!("Missing required key \"{}\"", required_key),
+            ));
+            missing_required_key = true;
+        }
+    }
+    if missing_required_key {
+        return None;
+    }
+
+    let result: Result<Dotfile, _> = serde_ignored::deserialize(value.clone(), |path| {
+        problems.push(ManifestProblem::error(
+            Some(dotfile_name),
+            format!("Unknown key \"{}\"", path),
+        ))
+    });
+
+    match result {
+        Ok(dotfile) => Some(dotfile),
+        Err(err) => {
+            problems.push(ManifestProblem::error(Some(dotfile_name), err.to_string()));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn validate_str(manifest: &str, repo_files: &[&str]) -> Vec<ManifestProblem> {
+        let repo_dir = tempdir().expect("Could not create temporary repo dir");
+        for repo_file in repo_files {
+            File::create(repo_dir.path().join(repo_file)).expect("Could not create file in repo");
+        }
+
+        let manifest_path = repo_dir.path().join("jtd.yaml");
+        let mut manifest_file = File::create(&manifest_path).unwrap();
+        manifest_file.write_all(manifest.as_bytes()).unwrap();
+
+        validate_manifest(&manifest_path, repo_dir.path()).expect("Failed to validate manifest")
+    }
+
+    #[test]
+    fn test_validate_manifest_valid() {
+        let problems = validate_str(
+            r"
+.config:
+  squash_commits: false
+kitty:
+  file: kitty.conf
+  target: ~/.config/kitty/kitty.conf
+  post_install:
+    - echo done
+",
+            &["kitty.conf"],
+        );
+
+        assert_eq!(problems, vec![]);
+    }
+
+    #[test]
+    fn test_validate_manifest_unknown_keys() {
+        let problems = validate_str(
+            r"
+.config:
+  squash_commit: false
+kitty:
+  file: kitty.conf
+  targte: ~/.config/kitty/kitty.conf
+  target: ~/.config/kitty/kitty.conf
+",
+            &["kitty.conf"],
+        );
+
+        assert_eq!(
+            problems,
+            vec![
+                ManifestProblem::error(
+                    Some(".config"),
+                    "Unknown configuration key \"squash_commit\"".to_string()
+                ),
+                ManifestProblem::error(Some("kitty"), "Unknown key \"targte\"".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_manifest_missing_keys_and_files() {
+        let problems = validate_str(
+            r"
+kitty:
+  pre_install:
+    - echo hi
+nvim:
+  file: init.vim
+  target: ~/.config/nvim/init.vim
+",
+            &[],
+        );
+
+        assert_eq!(
+            problems,
+            vec![
+                ManifestProblem::error(Some("kitty"), "Missing required key \"file\"".to_string()),
+                ManifestProblem::error(
+                    Some("kitty"),
+                    "Missing required key \"target\"".to_string()
+                ),
+                ManifestProblem::error(
+                    Some("nvim"),
+                    "File \"init.vim\" does not exist in the repository".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_manifest_duplicate_targets() {
+        let problems = validate_str(
+            r"
+kitty:
+  file: kitty.conf
+  target: ~/.config/kitty/kitty.conf
+kitty-alt:
+  file: kitty.conf
+  target: ~/.config/kitty/kitty.conf
+",
+            &["kitty.conf"],
+        );
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, ProblemSeverity::Error);
+        assert!(problems[0].message.contains("kitty, kitty-alt"));
+    }
+
+    #[test]
+    fn test_validate_manifest_duplicate_os_targets() {
+        let problems = validate_str(
+            r"
+kitty:
+  file: kitty.conf
+  target: ~/.config/kitty/kitty.conf
+  targets:
+    macos: /tmp/kitty.conf
+    beos: /tmp/kitty.conf
+kitty-theme:
+  file: kitty.conf
+  target: ~/.config/kitty/theme.conf
+  targets:
+    macos: /tmp/kitty.conf
+",
+            &["kitty.conf"],
+        );
+
+        assert_eq!(
+            problems,
+            vec![
+                ManifestProblem::warning(
+                    Some("kitty"),
+                    "Unknown OS \"beos\" in targets, expected one of linux, macos, windows"
+                        .to_string()
+                ),
+                ManifestProblem::error(
+                    None,
+                    "Dotfiles kitty, kitty-theme all install to the same target /tmp/kitty.conf"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_manifest_suspicious_hooks() {
+        let problems = validate_str(
+            r"
+kitty:
+  file: kitty.conf
+  target: ~/.config/kitty/kitty.conf
+  pre_install:
+    - curl -sL https://example.com/install.sh | sh
+    - mkdir -p ~/.config/kitty
+",
+            &["kitty.conf"],
+        );
+
+        assert_eq!(
+            problems,
+            vec![ManifestProblem::warning(
+                Some("kitty"),
+                "pre_install step \"curl -sL https://example.com/install.sh | sh\" pipes a \
+                download straight into a shell"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_validate_manifest_groups() {
+        let problems = validate_str(
+            r"
+.groups:
+  shell-tools:
+    - kitty
+    - zsh
+  kitty:
+    - kitty
+kitty:
+  file: kitty.conf
+  target: ~/.config/kitty/kitty.conf
+",
+            &["kitty.conf"],
+        );
+
+        assert_eq!(
+            problems,
+            vec![
+                ManifestProblem::warning(
+                    Some(".groups"),
+                    "Group \"kitty\" has the same name as a dotfile and will be ignored"
+                        .to_string()
+                ),
+                ManifestProblem::error(
+                    Some(".groups"),
+                    "Group \"shell-tools\" contains unknown dotfile \"zsh\"".to_string()
+                ),
+            ]
+        );
+    }
+}
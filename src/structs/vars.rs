@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::VARS_PATH;
+
+/// Struct representing a `vars.yaml` file, typically found in ~/.config/jointhedots. Holds
+/// machine-local variables that are exposed to templated dotfiles via a mapping of variable name
+/// to value
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(transparent)]
+pub struct MachineVariables {
+    pub data: BTreeMap<String, String>,
+}
+
+impl MachineVariables {
+    /// Get the machine-local variables for this machine, or an empty set of variables if no vars
+    /// file exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use jointhedots::structs::MachineVariables;
+    ///
+    /// let vars = MachineVariables::get().unwrap();
+    /// ```
+    pub fn get() -> Result<MachineVariables, Box<dyn Error>> {
+        MachineVariables::read(Path::new(shellexpand::tilde(VARS_PATH).as_ref()))
+    }
+
+    fn read(path: &Path) -> Result<MachineVariables, Box<dyn Error>> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_yaml::from_reader(file).map_err(|err| {
+                format!(
+                    "Could not parse vars file. Check {} for issues: {}",
+                    path.to_string_lossy(),
+                    err
+                )
+            })?),
+            Err(_) => Ok(MachineVariables::default()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        self.write(Path::new(shellexpand::tilde(VARS_PATH).as_ref()))
+    }
+
+    fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(path.parent().ok_or("Could not access vars directory")?)?;
+
+        let mut output_file = File::create(path)?;
+        output_file.write_all(
+            "# jointhedots machine-local template variables. Manage with \"jtd vars\"\n".as_bytes(),
+        )?;
+        Ok(serde_yaml::to_writer(output_file, &self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_read_missing_file() {
+        let dir = tempdir().expect("Could not create temporary dir");
+
+        let vars = MachineVariables::read(&dir.path().join("vars.yaml")).unwrap();
+
+        assert_eq!(vars, MachineVariables::default());
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let dir = tempdir().expect("Could not create temporary dir");
+        let path = dir.path().join("jointhedots").join("vars.yaml");
+
+        let vars = MachineVariables {
+            data: BTreeMap::from([
+                ("email".to_string(), "user@example.com".to_string()),
+                ("font_size".to_string(), "12".to_string()),
+            ]),
+        };
+        vars.write(&path).unwrap();
+
+        assert_eq!(MachineVariables::read(&path).unwrap(), vars);
+    }
+
+    #[test]
+    fn test_read_non_string_values() {
+        let dir = tempdir().expect("Could not create temporary dir");
+        let path = dir.path().join("vars.yaml");
+        fs::write(&path, "font_size: 12\ndark_mode: true\n").unwrap();
+
+        let vars = MachineVariables::read(&path).unwrap();
+
+        assert_eq!(vars.data["font_size"], "12");
+        assert_eq!(vars.data["dark_mode"], "true");
+    }
+}
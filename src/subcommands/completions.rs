@@ -0,0 +1,258 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::Path;
+
+use clap::{Command, CommandFactory};
+use clap_complete::{generate, Shell};
+
+use crate::cli::{default_repository, looks_like_repository, Cli, CompletionsSubcommandArgs};
+use crate::git::operations::get_cache_dir;
+use crate::git::remote::{get_host_git_url, ConnectionMethod, RepoHostName};
+use crate::structs::{
+    find_manifest, AggregatedDotfileMetadata, Manifest, RepositoryOrigin, UserConfig,
+    DEFAULT_MANIFEST,
+};
+use crate::utils::set_data_dir;
+
+/// The subcommands that take the names of dotfiles, which are completed dynamically
+const DOTFILE_SUBCOMMANDS: [&str; 9] = [
+    "install",
+    "update",
+    "sync",
+    "diff",
+    "uninstall",
+    "rollback",
+    "which",
+    "history",
+    "restore",
+];
+
+/// Completes the names of dotfiles in place of files, with `_jtd` as the fallback
+const BASH_HOOK: &str = r#"
+_jtd_dotfiles() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    if [[ "${cur}" != -* ]]; then
+        local dotfiles
+        dotfiles="$(jtd completions --dotfiles -- "${COMP_WORDS[@]:0:COMP_CWORD}" 2>/dev/null)"
+        if [[ -n "${dotfiles}" ]]; then
+            COMPREPLY=($(compgen -W "${dotfiles}" -- "${cur}"))
+            return 0
+        fi
+    fi
+    _jtd "$@"
+}
+
+complete -F _jtd_dotfiles -o bashdefault -o default jtd
+"#;
+
+/// Replaces the generated `_jtd` (renamed to `_jtd_static`), so that later completions still go
+/// through it once the completion function has been autoloaded
+const ZSH_HOOK: &str = r#"
+_jtd() {
+    if [[ "${PREFIX}" != -* ]]; then
+        local -a dotfiles
+        dotfiles=(${(f)"$(jtd completions --dotfiles -- ${words[1,CURRENT-1]} 2>/dev/null)"})
+        compadd -a dotfiles
+    fi
+    _jtd_static "$@"
+}
+
+"#;
+
+pub fn completions_subcommand_handler(
+    args: CompletionsSubcommandArgs,
+) -> Result<(), Box<dyn Error>> {
+    if args.dotfiles {
+        let mut stdout = io::stdout();
+        for dotfile_name in complete_dotfiles(&args.words)? {
+            writeln!(stdout, "{}", dotfile_name)?;
+        }
+        return Ok(());
+    }
+
+    let shell = args.shell.ok_or("A shell is required")?;
+    let mut command = Cli::command();
+    let mut script = Vec::new();
+    generate(shell, &mut command, "jtd", &mut script);
+    let script = String::from_utf8(script)?;
+
+    let script = match shell {
+        Shell::Bash => script + BASH_HOOK,
+        Shell::Zsh => {
+            let script = script.replacen("\n_jtd() {", "\n_jtd_static() {", 1);
+            match script.rfind("_jtd \"$@\"") {
+                Some(position) => {
+                    format!("{}{}{}", &script[..position], ZSH_HOOK, &script[position..])
+                }
+                None => script,
+            }
+        }
+        Shell::Fish => format!(
+            "{}complete -c jtd -n \"__fish_seen_subcommand_from {}\" -a \"(jtd completions \
+            --dotfiles -- (commandline -opc) 2>/dev/null)\"\n",
+            script,
+            DOTFILE_SUBCOMMANDS.join(" ")
+        ),
+        _ => script,
+    };
+    print!("{}", script);
+    Ok(())
+}
+
+/// Return the names of the dotfiles (and groups) to complete for the command line `words`, which
+/// doesn't include the word being completed. These are the dotfiles installed from the repository
+/// given in `words` (or the default repository of the subcommand), along with those in its
+/// manifest if it has been cloned into the cache. Every installed dotfile is completed if there is
+/// no repository, and nothing is completed where a dotfile isn't expected
+fn complete_dotfiles(words: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    if let Some(data_dir) = Cli::find_data_dir(words) {
+        set_data_dir(Some(data_dir));
+    }
+    let command = Cli::command();
+    let (position, subcommand) = match words
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find_map(|(position, word)| Some((position, command.find_subcommand(word)?)))
+    {
+        Some((position, subcommand)) if DOTFILE_SUBCOMMANDS.contains(&subcommand.get_name()) => {
+            (position, subcommand)
+        }
+        _ => return Ok(vec![]),
+    };
+
+    let mut positionals = vec![];
+    let mut takes_value = false;
+    for word in &words[position + 1..] {
+        if takes_value {
+            takes_value = false;
+        } else if word.starts_with('-') {
+            takes_value = option_takes_value(&command, subcommand, word);
+        } else {
+            positionals.push(word.as_str());
+        }
+    }
+    if takes_value {
+        return Ok(vec![]);
+    }
+
+    let user_config = UserConfig::get()?;
+    let aggregated_metadata = AggregatedDotfileMetadata::get()
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let repository = positionals
+        .first()
+        .filter(|word| looks_like_repository(word))
+        .copied()
+        .or_else(|| {
+            default_repository(
+                subcommand.get_name(),
+                &user_config,
+                aggregated_metadata.installed_from.as_ref(),
+            )
+        });
+
+    let mut dotfile_names = match repository {
+        Some(repository) => {
+            repository_dotfile_names(repository, &user_config, &aggregated_metadata)?
+        }
+        None => aggregated_metadata
+            .dotfiles()
+            .into_iter()
+            .map(|(_, dotfile_name, _)| dotfile_name.to_string())
+            .collect(),
+    };
+    dotfile_names.sort();
+    dotfile_names.dedup();
+    dotfile_names.retain(|dotfile_name| !positionals.contains(&dotfile_name.as_str()));
+    Ok(dotfile_names)
+}
+
+/// Return the names of the dotfiles installed from `repository`, along with the dotfiles and groups
+/// in its manifest if it is a local clone or has been cloned into the cache
+fn repository_dotfile_names(
+    repository: &str,
+    user_config: &UserConfig,
+    aggregated_metadata: &AggregatedDotfileMetadata,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let source = match &user_config.source {
+        Some(source) => source.parse()?,
+        None => RepoHostName::GitHub,
+    };
+    let method = match &user_config.method {
+        Some(method) => method.parse()?,
+        None => ConnectionMethod::SSH,
+    };
+    let url = get_host_git_url(repository, &source, &method)?;
+    let origin = RepositoryOrigin::new(
+        repository,
+        &url,
+        user_config.manifest.as_deref().unwrap_or(DEFAULT_MANIFEST),
+    );
+    // Dotfiles installed before use the manifest they were installed with
+    let origin = aggregated_metadata
+        .known_repositories()
+        .into_iter()
+        .find(|known| known.repository == origin.repository)
+        .unwrap_or(origin);
+
+    let mut dotfile_names: Vec<String> = aggregated_metadata
+        .installed_dotfiles(&origin.repository)
+        .into_iter()
+        .cloned()
+        .collect();
+
+    let repo_dir = match Path::new(&origin.repository) {
+        path if path.is_dir() => path.to_path_buf(),
+        _ => get_cache_dir(&url),
+    };
+    if let Some(manifest) = read_manifest(&origin.manifest_dir(&repo_dir), &origin.manifest) {
+        dotfile_names.extend(manifest.names().into_iter().cloned());
+    }
+    Ok(dotfile_names)
+}
+
+/// Read the manifest `manifest` in `manifest_dir`, if there is one
+fn read_manifest(manifest_dir: &Path, manifest: &str) -> Option<Manifest> {
+    let manifest = find_manifest(manifest_dir, manifest).ok()?;
+    Manifest::get(&manifest_dir.join(manifest)).ok()
+}
+
+/// Return whether the option `word` of `subcommand` (or a global option of `command`) is followed
+/// by a value, e.g. `--manifest jtd.yaml`
+fn option_takes_value<'a>(command: &Command<'a>, subcommand: &Command<'a>, word: &str) -> bool {
+    if word.contains('=') {
+        return false;
+    }
+    let long = word.strip_prefix("--");
+    let short = word
+        .strip_prefix('-')
+        .filter(|short| short.chars().count() == 1)
+        .and_then(|short| short.chars().next());
+    command
+        .get_arguments()
+        .chain(subcommand.get_arguments())
+        .filter(|arg| {
+            (long.is_some() && arg.get_long() == long)
+                || (short.is_some() && arg.get_short() == short)
+        })
+        .any(|arg| arg.is_takes_value_set())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_option_takes_value() {
+        let command = Cli::command();
+        let sync = command.find_subcommand("sync").unwrap();
+
+        assert!(option_takes_value(&command, sync, "--manifest"));
+        assert!(option_takes_value(&command, sync, "-n"));
+        assert!(option_takes_value(&command, sync, "--data-dir"));
+        assert!(!option_takes_value(&command, sync, "--manifest=jtd.yaml"));
+        assert!(!option_takes_value(&command, sync, "--all"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::error::Error;
+
+use console::style;
+use tempfile::tempdir;
+
+use crate::cli::DiffSubcommandArgs;
+use crate::git::operations::{get_repo_dir, open_or_clone_repo, open_user_checkout};
+use crate::git::remote::get_host_git_url;
+use crate::log::set_logs_to_stderr;
+use crate::structs::{find_manifest, AggregatedDotfileMetadata, Manifest, RepositoryOrigin};
+use crate::utils::{print_paged, set_offline};
+use crate::USER_CONFIG_PATH;
+
+pub fn diff_subcommand_handler(args: DiffSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    set_offline(args.offline);
+    set_logs_to_stderr(args.patch);
+
+    let aggregated_metadata = AggregatedDotfileMetadata::get_or_create()?;
+    let sources = if args.all_repos {
+        aggregated_metadata.installed_by_repository(&args.target_dotfiles)?
+    } else {
+        let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+        vec![(
+            RepositoryOrigin::new(&args.repository, &url, &args.manifest)
+                .with_subdir(args.subdir.as_deref()),
+            args.target_dotfiles.clone(),
+        )]
+    };
+
+    let mut diffs = vec![];
+    let mut changed = 0;
+    for (mut origin, target_dotfiles) in sources {
+        let target_dir = tempdir()?;
+        let repo = match &args.repo_dir {
+            Some(repo_dir) => {
+                open_user_checkout(repo_dir, &origin.repository, args.branch.as_deref())?
+            }
+            None => open_or_clone_repo(
+                &origin.repository,
+                &origin.repository,
+                target_dir.path(),
+                args.branch.as_deref(),
+                Some(1),
+                !args.no_cache,
+            )?,
+        };
+
+        let repo_dir = get_repo_dir(&repo);
+        let manifest_dir = origin.manifest_dir(repo_dir);
+        origin.manifest = find_manifest(&manifest_dir, &origin.manifest)?;
+
+        let mut manifest = Manifest::get(&manifest_dir.join(&origin.manifest))?;
+        manifest.set_origin(origin);
+        manifest.load_trust(false)?;
+
+        let tool = if args.patch {
+            None
+        } else {
+            args.tool.as_deref()
+        };
+        if let (None, Some(manifest_tool)) = (tool, manifest.diff_tool()) {
+            warn!(
+                "Not running the diff_tool \"{}\" set in the manifest. Pass it with \"--tool\" or \
+                set diff_tool in {} to use it",
+                manifest_tool, USER_CONFIG_PATH
+            );
+        }
+        if let Some(tool) = tool {
+            changed += manifest.diff_with_tool(
+                &repo,
+                target_dotfiles,
+                args.all,
+                &aggregated_metadata,
+                tool,
+            )?;
+        } else {
+            diffs.extend(manifest.diff(
+                &repo,
+                target_dotfiles,
+                args.all,
+                &aggregated_metadata,
+                args.patch,
+            )?);
+        }
+    }
+
+    if diffs.is_empty() {
+        // Differences shown with a diff tool have already been shown
+        if changed == 0 {
+            info!("No differences found");
+        }
+        return Ok(());
+    }
+
+    if args.patch {
+        for (_, diff) in diffs {
+            print!("{}", diff);
+        }
+        return Ok(());
+    }
+
+    let mut output = String::new();
+    for (_, diff) in diffs {
+        for line in diff.lines() {
+            output.push_str(&colorize_diff_line(line));
+            output.push('\n');
+        }
+    }
+    if args.no_pager {
+        print!("{}", output);
+        Ok(())
+    } else {
+        print_paged(&output)
+    }
+}
+
+/// Colour a line of a unified diff the way `git diff` does
+fn colorize_diff_line(line: &str) -> String {
+    if line.starts_with("diff ")
+        || line.starts_with("index ")
+        || line.starts_with("--- ")
+        || line.starts_with("+++ ")
+    {
+        style(line).bold().to_string()
+    } else if line.starts_with("@@") {
+        style(line).cyan().to_string()
+    } else if line.starts_with('+') {
+        style(line).green().to_string()
+    } else if line.starts_with('-') {
+        style(line).red().to_string()
+    } else {
+        line.to_string()
+    }
+}
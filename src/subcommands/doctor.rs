@@ -0,0 +1,237 @@
+use std::error::Error;
+
+use console::style;
+use serde::Serialize;
+use tempfile::tempdir;
+
+use crate::cli::DoctorSubcommandArgs;
+use crate::git::operations::{check_push_access, get_commit, get_repo_dir, open_or_clone_repo};
+use crate::git::remote::get_host_git_url;
+use crate::log::{output_format, print_json, set_output_format, OutputFormat, ReportedError};
+use crate::structs::{
+    find_manifest, validate_manifest, AggregatedDotfileMetadata, Manifest, ProblemSeverity,
+    RepositoryOrigin,
+};
+use crate::utils::metadata_path;
+
+#[derive(Serialize)]
+struct DoctorFailure {
+    problem: String,
+    fix: String,
+}
+
+#[derive(Serialize)]
+struct DoctorReport<'a> {
+    success: bool,
+    failures: &'a [DoctorFailure],
+}
+
+fn report_failure(failures: &mut Vec<DoctorFailure>, problem: String, fix: String) {
+    error!("{}", problem);
+    info!("Fix: {}", fix);
+    failures.push(DoctorFailure { problem, fix });
+}
+
+fn finish(failures: Vec<DoctorFailure>) -> Result<(), Box<dyn Error>> {
+    let error_message = format!("{} check(s) failed", failures.len());
+
+    if output_format() == OutputFormat::Json {
+        print_json(&DoctorReport {
+            success: failures.is_empty(),
+            failures: &failures,
+        })?;
+        if !failures.is_empty() {
+            return Err(ReportedError(error_message).into());
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(error_message.into());
+    }
+
+    success!("All checks passed!");
+    Ok(())
+}
+
+pub fn doctor_subcommand_handler(args: DoctorSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    set_output_format(args.output);
+    let mut failures = vec![];
+
+    let aggregated_metadata = match AggregatedDotfileMetadata::get() {
+        Ok(Some(metadata)) => {
+            success!("Metadata in {} is readable", metadata_path().display());
+            Some(metadata)
+        }
+        Ok(None) => {
+            warn!(
+                "No metadata found in {}, skipping checks on installed dotfiles",
+                metadata_path().display()
+            );
+            None
+        }
+        Err(err) => {
+            report_failure(
+                &mut failures,
+                err.to_string(),
+                format!(
+                    "Correct the syntax of {} by hand, or delete it and re-run \"jtd install\"",
+                    metadata_path().display()
+                ),
+            );
+            None
+        }
+    };
+
+    let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+    let target_dir = tempdir()?;
+
+    // Always clone afresh so that the checks reflect the current state of the remote
+    let cloned = open_or_clone_repo(&args.repository, &url, target_dir.path(), None, None, false);
+    let repo = match cloned {
+        Ok(repo) => repo,
+        Err(err) => {
+            report_failure(
+                &mut failures,
+                err.to_string(),
+                format!(
+                    "Check that {} exists and that your credentials have access to it, or try \
+                    a different connection method with \"--method\"",
+                    url
+                ),
+            );
+            return finish(failures);
+        }
+    };
+
+    if let Err(err) = check_push_access(&repo) {
+        report_failure(
+            &mut failures,
+            err.to_string(),
+            "Make sure your SSH key is loaded into ssh-agent or passed with \"--ssh-key\" (or \
+            your HTTPS credentials are correct) and has write access to the repository"
+                .to_string(),
+        );
+    } else {
+        success!("Git credentials can push to {}", url);
+    }
+
+    let repo_dir = get_repo_dir(&repo).to_path_buf();
+    let manifest_path = match find_manifest(&repo_dir, &args.manifest) {
+        Ok(manifest_name) => repo_dir.join(manifest_name),
+        Err(err) => {
+            report_failure(
+                &mut failures,
+                err.to_string(),
+                "Add a manifest to the root of the repository, or pass the one to use with \
+                \"--manifest\""
+                    .to_string(),
+            );
+            return finish(failures);
+        }
+    };
+    let origin = RepositoryOrigin::new(&args.repository, &url, &args.manifest);
+    let manifest = match Manifest::get(&manifest_path) {
+        Ok(manifest) => {
+            success!("Manifest {} is readable", args.manifest);
+            Some(manifest)
+        }
+        Err(err) => {
+            report_failure(
+                &mut failures,
+                err.to_string(),
+                format!(
+                    "Run \"jtd validate {}\" for a detailed list of problems with the manifest",
+                    args.repository
+                ),
+            );
+            None
+        }
+    };
+
+    if manifest.is_some() {
+        let error_count = validate_manifest(&manifest_path, &repo_dir)?
+            .iter()
+            .filter(|problem| problem.severity == ProblemSeverity::Error)
+            .count();
+        if error_count > 0 {
+            report_failure(
+                &mut failures,
+                format!("Manifest {} has {} problem(s)", args.manifest, error_count),
+                format!(
+                    "Run \"jtd validate {}\" for a detailed list of problems with the manifest",
+                    args.repository
+                ),
+            );
+        }
+    }
+
+    if let (Some(aggregated_metadata), Some(manifest)) = (aggregated_metadata, manifest) {
+        for dotfile_name in aggregated_metadata.installed_dotfiles(&origin.repository) {
+            let metadata = match aggregated_metadata.dotfile(&origin.repository, dotfile_name) {
+                Some(metadata) => metadata,
+                None => continue,
+            };
+            let reinstall_fix = format!(
+                "Reinstall the dotfile with \"jtd install {} {} --force\"",
+                args.repository, dotfile_name
+            );
+
+            let dotfile = match manifest.get_dotfile(dotfile_name) {
+                Some(dotfile) => dotfile,
+                None => {
+                    report_failure(
+                        &mut failures,
+                        format!(
+                            "{} is installed but no longer present in the manifest",
+                            dotfile_name
+                        ),
+                        format!(
+                            "Add {} back to the manifest or remove its entry from {}",
+                            dotfile_name,
+                            metadata_path().display()
+                        ),
+                    );
+                    continue;
+                }
+            };
+
+            if get_commit(&repo, &metadata.commit_hash).is_err() {
+                report_failure(
+                    &mut failures,
+                    format!(
+                        "Commit {} recorded for {} no longer exists in the remote repo",
+                        metadata.commit_hash, dotfile_name
+                    ),
+                    reinstall_fix.to_string(),
+                );
+            }
+
+            let target_path = dotfile.target_path();
+            if !target_path.exists() {
+                report_failure(
+                    &mut failures,
+                    format!(
+                        "Target {} for {} does not exist",
+                        target_path.to_string_lossy(),
+                        dotfile_name
+                    ),
+                    reinstall_fix.to_string(),
+                );
+            } else if let Some(actual_mode) = dotfile.mode_mismatch()? {
+                report_failure(
+                    &mut failures,
+                    format!(
+                        "Target {} for {} has mode {:04o}, expected {:04o}",
+                        target_path.to_string_lossy(),
+                        dotfile_name,
+                        actual_mode,
+                        dotfile.mode.unwrap_or_default()
+                    ),
+                    reinstall_fix,
+                );
+            }
+        }
+    }
+
+    finish(failures)
+}
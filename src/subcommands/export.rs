@@ -0,0 +1,267 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use console::style;
+use serde_yaml::{Mapping, Value};
+
+use crate::cli::ExportSubcommandArgs;
+use crate::structs::{find_manifest, Dotfile, Manifest};
+
+/// The dotfile managers a manifest can be exported to
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// A GNU Stow directory with a package per dotfile
+    Stow,
+    /// A dotbot `install.conf.yaml`
+    Dotbot,
+}
+
+pub fn export_subcommand_handler(args: ExportSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    let repo_dir = args.repository;
+    if !repo_dir.is_dir() {
+        return Err(format!(
+            "{} is not a directory. Export from a local clone of the repository",
+            repo_dir.display()
+        )
+        .into());
+    }
+    let manifest = Manifest::get(&repo_dir.join(find_manifest(&repo_dir, &args.manifest)?))?;
+    let dotfiles = exportable_dotfiles(manifest, args.format);
+
+    match args.format {
+        ExportFormat::Dotbot => {
+            let output = args
+                .output
+                .unwrap_or_else(|| repo_dir.join("install.conf.yaml"));
+            fs::write(&output, serde_yaml::to_string(&dotbot_config(&dotfiles))?)?;
+            success!(
+                "Exported {} dotfiles to {}",
+                dotfiles.len(),
+                output.display()
+            );
+            info!(
+                "Link them with \"dotbot -d {} -c {}\"",
+                repo_dir.display(),
+                output.display()
+            );
+        }
+        ExportFormat::Stow => {
+            let output = args.output.unwrap_or_else(|| repo_dir.join("stow"));
+            let packages = export_stow(&dotfiles, &repo_dir, &output)?;
+            success!(
+                "Exported {} dotfiles to {}",
+                packages.len(),
+                output.display()
+            );
+            if !packages.is_empty() {
+                info!(
+                    "Link them with \"stow -d {} -t ~ {}\"",
+                    output.display(),
+                    packages.join(" ")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Return the dotfiles in `manifest` that can be exported to `format`, warning about those that
+/// can't and about the parts of the others that will be lost
+fn exportable_dotfiles(manifest: Manifest, format: ExportFormat) -> Vec<(String, Dotfile)> {
+    manifest
+        .into_iter()
+        .filter(|(dotfile_name, dotfile)| {
+            if let Some(form) = dotfile.rendered_form() {
+                warn!(
+                    "Skipping {} as it is {} in the repository, which only jtd can install",
+                    dotfile_name, form
+                );
+                return false;
+            }
+            if format == ExportFormat::Stow && home_relative_target(dotfile).is_none() {
+                warn!(
+                    "Skipping {} as stow can only link dotfiles into the home directory",
+                    dotfile_name
+                );
+                return false;
+            }
+
+            let mut lost = vec![];
+            if format == ExportFormat::Stow
+                && (dotfile.pre_install.is_some() || dotfile.post_install.is_some())
+            {
+                lost.push("install steps");
+            }
+            if format == ExportFormat::Stow && dotfile.only_if.is_some() {
+                lost.push("only_if");
+            }
+            if dotfile.pre_uninstall.is_some() || dotfile.post_uninstall.is_some() {
+                lost.push("uninstall steps");
+            }
+            if dotfile.mode.is_some() {
+                lost.push("mode");
+            }
+            if dotfile.git_ref.is_some() {
+                lost.push("ref");
+            }
+            if dotfile.requires_root {
+                lost.push("requires_root");
+            }
+            if !lost.is_empty() {
+                warn!(
+                    "The {} of {} can't be exported and will be left out",
+                    lost.join(", "),
+                    dotfile_name
+                );
+            }
+            true
+        })
+        .collect()
+}
+
+/// Return the target of `dotfile` on this OS relative to the home directory, if it is in it
+fn home_relative_target(dotfile: &Dotfile) -> Option<PathBuf> {
+    let home = PathBuf::from(shellexpand::tilde("~").as_ref());
+    dotfile
+        .target_path()
+        .strip_prefix(home)
+        .ok()
+        .map(Path::to_path_buf)
+}
+
+/// Build a dotbot configuration that links each of `dotfiles` to its target, running the install
+/// steps of all of them before and after linking. Dotfiles with `only_if` commands are only linked
+/// if the commands succeed
+fn dotbot_config(dotfiles: &[(String, Dotfile)]) -> Value {
+    let mut link_defaults = Mapping::new();
+    link_defaults.insert("create".into(), true.into());
+    link_defaults.insert("relink".into(), true.into());
+    let mut defaults = Mapping::new();
+    defaults.insert("link".into(), Value::Mapping(link_defaults));
+    let mut directive = Mapping::new();
+    directive.insert("defaults".into(), Value::Mapping(defaults));
+    let mut directives = vec![Value::Mapping(directive)];
+
+    let steps = |select: fn(&Dotfile) -> &Option<Vec<String>>| -> Vec<Value> {
+        dotfiles
+            .iter()
+            .flat_map(|(_, dotfile)| select(dotfile).iter().flatten())
+            .map(|step| step.as_str().into())
+            .collect()
+    };
+    let shell_directive = |steps: Vec<Value>| {
+        let mut directive = Mapping::new();
+        directive.insert("shell".into(), Value::Sequence(steps));
+        Value::Mapping(directive)
+    };
+
+    let pre_install = steps(|dotfile| &dotfile.pre_install);
+    if !pre_install.is_empty() {
+        directives.push(shell_directive(pre_install));
+    }
+
+    let mut links = Mapping::new();
+    for (_, dotfile) in dotfiles {
+        let target = match home_relative_target(dotfile) {
+            Some(relative_target) => format!("~/{}", relative_target.display()),
+            None => dotfile.target_path().display().to_string(),
+        };
+        let source = match &dotfile.only_if {
+            Some(only_if) => {
+                let mut link = Mapping::new();
+                link.insert("path".into(), dotfile.file.as_str().into());
+                link.insert("if".into(), only_if.join(" && ").into());
+                Value::Mapping(link)
+            }
+            None => dotfile.file.as_str().into(),
+        };
+        links.insert(target.into(), source);
+    }
+    let mut directive = Mapping::new();
+    directive.insert("link".into(), Value::Mapping(links));
+    directives.push(Value::Mapping(directive));
+
+    let post_install = steps(|dotfile| &dotfile.post_install);
+    if !post_install.is_empty() {
+        directives.push(shell_directive(post_install));
+    }
+
+    Value::Sequence(directives)
+}
+
+/// Copy each of `dotfiles` from `repo_dir` into a stow package named after it in `output_dir`, at
+/// its target relative to the home directory. Returns the names of the packages
+fn export_stow(
+    dotfiles: &[(String, Dotfile)],
+    repo_dir: &Path,
+    output_dir: &Path,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut packages = vec![];
+    for (dotfile_name, dotfile) in dotfiles {
+        let relative_target = match home_relative_target(dotfile) {
+            Some(relative_target) => relative_target,
+            None => continue,
+        };
+        let package_path = output_dir.join(dotfile_name).join(relative_target);
+        fs::create_dir_all(package_path.parent().unwrap())?;
+        fs::copy(repo_dir.join(&dotfile.file), &package_path)
+            .map_err(|err| format!("Could not export {}: {}", dotfile_name, err))?;
+        packages.push(dotfile_name.clone());
+    }
+    Ok(packages)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn write_manifest(repo_dir: &Path) -> Manifest {
+        fs::write(
+            repo_dir.join("jtd.yaml"),
+            "nvim:\n  file: init.vim\n  target: ~/.config/nvim/init.vim\n  post_install:\n    \
+            - nvim +PlugInstall +qa\nsway:\n  file: sway.conf\n  target: ~/.config/sway/config\n  \
+            only_if:\n    - command -v sway\ngitconfig:\n  file: gitconfig.hbs\n  target: \
+            ~/.gitconfig\n  template: true\nkeyd:\n  file: keyd.conf\n  target: \
+            /etc/keyd/default.conf\n  requires_root: true\n",
+        )
+        .unwrap();
+        for file in ["init.vim", "sway.conf", "gitconfig.hbs", "keyd.conf"].iter() {
+            fs::write(repo_dir.join(file), *file).unwrap();
+        }
+        Manifest::get(&repo_dir.join("jtd.yaml")).unwrap()
+    }
+
+    #[test]
+    fn test_dotbot_config() {
+        let repo_dir = tempdir().unwrap();
+        let dotfiles = exportable_dotfiles(write_manifest(repo_dir.path()), ExportFormat::Dotbot);
+
+        assert_eq!(
+            serde_yaml::to_string(&dotbot_config(&dotfiles)).unwrap(),
+            "---\n- defaults:\n    link:\n      create: true\n      relink: true\n- link:\n    \
+            ~/.config/nvim/init.vim: init.vim\n    ~/.config/sway/config:\n      path: \
+            sway.conf\n      if: command -v sway\n    /etc/keyd/default.conf: keyd.conf\n- \
+            shell:\n    - nvim +PlugInstall +qa\n"
+        );
+    }
+
+    #[test]
+    fn test_export_stow() {
+        let repo_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let dotfiles = exportable_dotfiles(write_manifest(repo_dir.path()), ExportFormat::Stow);
+
+        let packages = export_stow(&dotfiles, repo_dir.path(), output_dir.path()).unwrap();
+
+        assert_eq!(packages, vec!["nvim", "sway"]);
+        assert_eq!(
+            fs::read_to_string(output_dir.path().join("nvim/.config/nvim/init.vim")).unwrap(),
+            "init.vim"
+        );
+        assert!(output_dir.path().join("sway/.config/sway/config").exists());
+        assert!(!output_dir.path().join("gitconfig").exists());
+    }
+}
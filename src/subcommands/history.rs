@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+
+use console::style;
+use git2::Oid;
+
+use super::which::open_installed_dotfile;
+use crate::cli::HistorySubcommandArgs;
+use crate::git::operations::{file_history, unshallow};
+use crate::structs::AggregatedDotfileMetadata;
+use crate::utils::{format_date, print_paged, set_offline};
+
+pub fn history_subcommand_handler(args: HistorySubcommandArgs) -> Result<(), Box<dyn Error>> {
+    set_offline(args.offline);
+
+    let aggregated_metadata = AggregatedDotfileMetadata::get()?
+        .ok_or("No dotfiles have been installed on this machine")?;
+    let (repo, dotfile, origin) =
+        open_installed_dotfile(&aggregated_metadata, &args.dotfile, args.from.as_deref())?;
+    if repo.is_shallow() {
+        if args.offline {
+            warn!("Only the history in the cached repository is shown as jtd is running offline");
+        } else {
+            info!("Fetching full history of the repository");
+            unshallow(&repo)?;
+        }
+    }
+
+    let commits = file_history(&repo, Path::new(&dotfile.file))?;
+    if commits.is_empty() {
+        return Err(format!("No commits have changed {}", dotfile.file).into());
+    }
+
+    // The installed version is the last change made to the file as of the commit it was
+    // installed from
+    let installed_from = aggregated_metadata
+        .dotfile(&origin.repository, &args.dotfile)
+        .and_then(|metadata| Oid::from_str(&metadata.commit_hash).ok());
+    let installed_commit = installed_from.and_then(|installed_from| {
+        commits
+            .iter()
+            .find(|commit| {
+                commit.id() == installed_from
+                    || repo
+                        .graph_descendant_of(installed_from, commit.id())
+                        .unwrap_or(false)
+            })
+            .map(|commit| commit.id())
+    });
+
+    let mut output = String::new();
+    for commit in commits.iter() {
+        let time = commit.author().when();
+        writeln!(
+            output,
+            "{} {} {} {}{}",
+            style(&commit.id().to_string()[..7]).yellow(),
+            format_date(time.seconds() + i64::from(time.offset_minutes()) * 60),
+            style(commit.author().name().unwrap_or("unknown")).cyan(),
+            commit.summary().unwrap_or_default(),
+            if Some(commit.id()) == installed_commit {
+                style(" (installed)").green().bold().to_string()
+            } else {
+                String::new()
+            }
+        )?;
+    }
+    print_paged(&output)
+}
@@ -1,28 +1,131 @@
 use std::error::Error;
 
+use console::style;
+
 use tempfile::tempdir;
 
 use crate::cli::InstallSubcommandArgs;
-use crate::git::operations::clone_repo;
+use crate::git::operations::{get_repo_dir, open_or_clone_repo, open_user_checkout};
 use crate::git::remote::get_host_git_url;
-use crate::structs::Manifest;
+use crate::log::{output_format, print_json, set_output_format, OutputFormat, ReportedError};
+use crate::picker::set_use_tui;
+use crate::structs::{
+    find_manifest, AggregatedDotfileMetadata, DotfileReport, DotfileStatus, Lockfile, Manifest,
+    OperationReport, RepositoryOrigin, LOCKFILE,
+};
+use crate::utils::{set_non_interactive, set_offline};
 
 pub fn install_subcommand_handler(args: InstallSubcommandArgs) -> Result<(), Box<dyn Error>> {
-    let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+    if args.yes {
+        set_non_interactive(true);
+    }
+    set_output_format(args.output);
+    set_offline(args.offline);
+    set_use_tui(args.tui);
+
+    let sources = if args.all_repos {
+        AggregatedDotfileMetadata::get_or_create()?
+            .installed_by_repository(&args.target_dotfiles)?
+    } else {
+        let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+        vec![(
+            RepositoryOrigin::new(&args.repository, &url, &args.manifest)
+                .with_subdir(args.subdir.as_deref()),
+            args.target_dotfiles.clone(),
+        )]
+    };
+
+    let mut reports = vec![];
+    for (origin, target_dotfiles) in sources {
+        if args.all_repos {
+            info!("Installing from {}", origin.repository);
+        }
+        reports.extend(install_from(&args, origin, target_dotfiles)?);
+    }
+
+    let failures = reports
+        .iter()
+        .filter(|report| report.status == DotfileStatus::Failed)
+        .count();
+    let error_message = format!("Failed to install {} dotfile(s)", failures);
 
+    if output_format() == OutputFormat::Json {
+        print_json(&OperationReport::new(reports, args.dry_run))?;
+        if failures > 0 {
+            return Err(ReportedError(error_message).into());
+        }
+    }
+
+    if failures > 0 {
+        return Err(error_message.into());
+    }
+    Ok(())
+}
+
+/// Install the target dotfiles from the manifest and repository of `origin`
+fn install_from(
+    args: &InstallSubcommandArgs,
+    mut origin: RepositoryOrigin,
+    target_dotfiles: Vec<String>,
+) -> Result<Vec<DotfileReport>, Box<dyn Error>> {
     let target_dir = tempdir()?;
-    let repo = clone_repo(&url, target_dir.path())?;
+    let repo = match &args.repo_dir {
+        Some(repo_dir) => open_user_checkout(repo_dir, &origin.repository, args.branch.as_deref())?,
+        None => open_or_clone_repo(
+            &origin.repository,
+            &origin.repository,
+            target_dir.path(),
+            args.branch.as_deref(),
+            Some(args.depth),
+            !args.no_cache,
+        )?,
+    };
 
-    let mut manifest_path = target_dir.path().to_path_buf();
-    manifest_path.push(args.manifest);
+    let repo_dir = get_repo_dir(&repo);
+    if args.repo_dir.is_some() {
+        origin.repo_dir = Some(repo_dir.to_path_buf());
+    }
+    let manifest_dir = origin.manifest_dir(repo_dir);
+    origin.manifest = find_manifest(&manifest_dir, &origin.manifest)?;
 
-    let manifest = Manifest::get(&manifest_path)?;
+    let mut manifest = Manifest::get(&manifest_dir.join(&origin.manifest))?;
+    manifest.set_origin(origin.clone());
+    manifest.load_trust(args.trust)?;
+    if args.keep_backup {
+        manifest.keep_backups();
+    }
+    if let Some(on_conflict) = args.on_conflict {
+        manifest.set_on_conflict(on_conflict);
+    }
+    if args.locked {
+        let lockfile = Lockfile::read(&manifest_dir)?.ok_or_else(|| {
+            format!(
+                "There is no {} next to the manifest of {}, create one with \"jtd lock\"",
+                LOCKFILE, origin.repository
+            )
+        })?;
+        manifest.set_lockfile(&repo, lockfile)?;
+    }
 
-    manifest.install(
+    let reports = manifest.install(
         &repo,
         args.all,
-        args.target_dotfiles,
+        target_dotfiles,
         args.force,
         args.trust,
-    )
+        args.dry_run,
+    )?;
+
+    if !args.dry_run
+        && reports
+            .iter()
+            .any(|report| report.status == DotfileStatus::Installed)
+    {
+        // Remember where the dotfiles were installed from, so that they can be synced and diffed
+        // without passing the repository again
+        let mut aggregated_metadata = AggregatedDotfileMetadata::get_or_create()?;
+        aggregated_metadata.installed_from = Some(origin);
+        aggregated_metadata.save()?;
+    }
+    Ok(reports)
 }
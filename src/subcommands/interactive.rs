@@ -1,18 +1,59 @@
 use console::style;
-use dialoguer::{Confirm, Input, Select};
+use dialoguer::{theme::Theme, Confirm, Input, MultiSelect, Select};
 use regex::Regex;
 use std::{error::Error, str::FromStr};
 use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter};
+use tempfile::tempdir;
 
 use crate::{
-    cli::InstallSubcommandArgs,
-    git::remote::{ConnectionMethod, RepoHostName},
-    utils::get_theme,
+    cli::{InstallSubcommandArgs, InteractiveSubcommandArgs},
+    git::{
+        operations::{get_head_branch, get_repo_dir, open_or_clone_repo},
+        remote::{ConnectionMethod, RepoHostName},
+    },
+    log::OutputFormat,
+    structs::{find_manifest, AggregatedDotfileMetadata, Manifest},
+    utils::{ensure_interactive, get_theme},
 };
 
 use super::install_subcommand_handler;
 
-pub fn interactive_subcommand_handler() -> Result<(), Box<dyn Error>> {
+/// The wizards that can be run by the interactive subcommand
+#[derive(clap::ArgEnum, Clone, Copy, EnumIter, Display, Debug, PartialEq)]
+pub enum Wizard {
+    Install,
+    Sync,
+}
+
+pub fn interactive_subcommand_handler(
+    args: InteractiveSubcommandArgs,
+) -> Result<(), Box<dyn Error>> {
+    ensure_interactive("The interactive subcommand requires input")?;
+
+    let theme = get_theme();
+
+    let wizard = match args.wizard {
+        Some(wizard) => wizard,
+        None => {
+            let wizards = Wizard::iter().collect::<Vec<Wizard>>();
+            let wizard_index = Select::with_theme(&theme)
+                .with_prompt("What would you like to do?")
+                .default(0)
+                .items(&wizards)
+                .interact()?;
+            wizards[wizard_index]
+        }
+    };
+
+    match wizard {
+        Wizard::Install => install_wizard(&theme),
+        Wizard::Sync => sync_wizard(&theme),
+    }
+}
+
+/// Guide the user through installing the dotfiles of a repository
+fn install_wizard(theme: &impl Theme) -> Result<(), Box<dyn Error>> {
     println!("\
         Welcome to JTD! \n\
         This wizard will guide you through installing your preconfigured dotfiles repo. \n\
@@ -21,10 +62,8 @@ pub fn interactive_subcommand_handler() -> Result<(), Box<dyn Error>> {
         \t{} https://github.com/dob9601/dotfiles/blob/master/jtd.yaml
     ", style("README:").cyan(), style("Example Manifest:").cyan());
 
-    let theme = get_theme();
-
     let repo_regex = Regex::new("[A-Za-z0-9]+/[A-Za-z0-9]+").unwrap();
-    let repository = Input::with_theme(&theme)
+    let repository = Input::with_theme(theme)
         .with_prompt("Target Repository: ")
         .validate_with(|input: &String| {
             if repo_regex.is_match(input) {
@@ -37,7 +76,7 @@ pub fn interactive_subcommand_handler() -> Result<(), Box<dyn Error>> {
         .unwrap();
 
     let repo_sources = RepoHostName::iter().collect::<Vec<RepoHostName>>();
-    let source_index = Select::with_theme(&theme)
+    let source_index = Select::with_theme(theme)
         .with_prompt("Repository Source: ")
         .default(0)
         .items(&repo_sources)
@@ -45,7 +84,7 @@ pub fn interactive_subcommand_handler() -> Result<(), Box<dyn Error>> {
         .unwrap();
 
     let methods = ConnectionMethod::iter().collect::<Vec<ConnectionMethod>>();
-    let method_index = Select::with_theme(&theme)
+    let method_index = Select::with_theme(theme)
         .with_prompt("Method: ")
         .default(0)
         .items(&methods)
@@ -54,7 +93,7 @@ pub fn interactive_subcommand_handler() -> Result<(), Box<dyn Error>> {
 
     let manifest_regex = Regex::new(r"\.yaml$|\.yml$").unwrap();
 
-    let manifest = Input::with_theme(&theme)
+    let manifest = Input::with_theme(theme)
         .with_prompt("Manifest: ")
         .default(String::from("jtd.yaml"))
         .validate_with(|input: &String| {
@@ -67,7 +106,7 @@ pub fn interactive_subcommand_handler() -> Result<(), Box<dyn Error>> {
         .interact_text()
         .unwrap();
 
-    let force = Confirm::with_theme(&theme)
+    let force = Confirm::with_theme(theme)
         .with_prompt("Overwrite existing dotfiles without prompting")
         .default(false)
         .wait_for_newline(true)
@@ -79,12 +118,172 @@ pub fn interactive_subcommand_handler() -> Result<(), Box<dyn Error>> {
         target_dotfiles: vec![],
         source: RepoHostName::from_str(repo_sources[source_index].to_string().as_str())?,
         force,
+        keep_backup: false,
+        locked: false,
+        on_conflict: None,
         manifest,
+        subdir: None,
+        branch: None,
+        depth: 1,
+        no_cache: false,
+        repo_dir: None,
+        offline: false,
         method: ConnectionMethod::from_str(methods[method_index].to_string().as_str())?,
         trust: false,
         all: false,
+        all_repos: false,
+        dry_run: false,
+        tui: false,
+        yes: false,
+        output: OutputFormat::Text,
     };
 
     install_subcommand_handler(install_args)?;
     Ok(())
 }
+
+/// Guide the user through syncing the installed dotfiles that have changed since they were last
+/// synced, letting them pick which ones to sync, the commit message and where to push the commit
+fn sync_wizard(theme: &impl Theme) -> Result<(), Box<dyn Error>> {
+    let aggregated_metadata = AggregatedDotfileMetadata::get()?
+        .ok_or("No dotfiles have been installed yet, so there is nothing to sync")?;
+
+    let mut origins = aggregated_metadata.known_repositories();
+    let mut origin = match origins.len() {
+        0 => {
+            return Err(
+                "Could not tell which repository the installed dotfiles were installed \
+                from, sync them with \"jtd sync\" instead"
+                    .into(),
+            )
+        }
+        1 => origins.remove(0),
+        _ => {
+            let repositories = origins
+                .iter()
+                .map(|origin| origin.repository.as_str())
+                .collect::<Vec<&str>>();
+            let origin_index = Select::with_theme(theme)
+                .with_prompt("Repository to sync with: ")
+                .default(0)
+                .items(&repositories)
+                .interact()?;
+            origins.remove(origin_index)
+        }
+    };
+
+    let target_dir = tempdir()?;
+    let repo = open_or_clone_repo(
+        &origin.repository,
+        &origin.repository,
+        target_dir.path(),
+        None,
+        None,
+        true,
+    )?;
+    let repo_dir = get_repo_dir(&repo);
+    let manifest_dir = origin.manifest_dir(repo_dir);
+    origin.manifest = find_manifest(&manifest_dir, &origin.manifest)?;
+    let mut manifest = Manifest::get(&manifest_dir.join(&origin.manifest))?;
+    manifest.set_origin(origin.clone());
+    manifest.load_trust(false)?;
+
+    info!("Checking which installed dotfiles have changed");
+    let mut changed_dotfiles = vec![];
+    for dotfile_name in aggregated_metadata.installed_dotfiles(&origin.repository) {
+        let dotfile = match manifest.get_dotfile(dotfile_name) {
+            Some(dotfile) => dotfile,
+            None => continue,
+        };
+        let metadata = match aggregated_metadata.dotfile(&origin.repository, dotfile_name) {
+            Some(metadata) => metadata,
+            None => continue,
+        };
+        match dotfile.has_changed(&repo, metadata) {
+            Ok(true) => changed_dotfiles.push(dotfile_name.to_string()),
+            Ok(false) => {}
+            Err(err) => {
+                warn!(
+                    "Could not tell whether {} has changed: {}",
+                    dotfile_name, err
+                );
+            }
+        }
+    }
+    if changed_dotfiles.is_empty() {
+        success!(
+            "None of the dotfiles installed from {} have changed since they were last synced",
+            origin.repository
+        );
+        return Ok(());
+    }
+
+    let selected_indices = MultiSelect::with_theme(theme)
+        .with_prompt("Changed dotfiles to sync: ")
+        .items(&changed_dotfiles)
+        .defaults(&vec![true; changed_dotfiles.len()])
+        .interact()?;
+    if selected_indices.is_empty() {
+        info!("No dotfiles selected, nothing to sync");
+        return Ok(());
+    }
+    let target_dotfiles = selected_indices
+        .into_iter()
+        .map(|index| changed_dotfiles[index].to_string())
+        .collect::<Vec<String>>();
+
+    let commit_msg = if manifest.squashes_commits() {
+        let default_commit_msg = manifest.default_commit_message(
+            target_dotfiles
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<&str>>(),
+        );
+        Some(
+            Input::with_theme(theme)
+                .with_prompt("Commit message: ")
+                .default(default_commit_msg)
+                .interact_text()?,
+        )
+    } else {
+        info!("The manifest doesn't squash sync commits, so each dotfile is committed with its own message");
+        None
+    };
+
+    let branch = get_head_branch(&repo)?;
+    let mut push_targets = vec![];
+    let mut push_target_names = vec![];
+    for remote_name in repo.remotes()?.iter().flatten() {
+        let remote = repo.find_remote(remote_name)?;
+        push_target_names.push(format!(
+            "Push to {} on {} ({})",
+            branch,
+            remote_name,
+            remote.url().unwrap_or("unknown URL")
+        ));
+        push_targets.push(Some(remote_name.to_string()));
+    }
+    push_target_names.push(format!("Don't push, only commit to {}", repo_dir.display()));
+    push_targets.push(None);
+    let push_target_index = Select::with_theme(theme)
+        .with_prompt("Push target: ")
+        .default(0)
+        .items(&push_target_names)
+        .interact()?;
+
+    manifest.sync(
+        &repo,
+        false,
+        target_dotfiles,
+        commit_msg.as_deref(),
+        AggregatedDotfileMetadata::get()?,
+        false,
+        false,
+        push_targets[push_target_index].as_deref(),
+    )?;
+
+    let mut aggregated_metadata = AggregatedDotfileMetadata::get_or_create()?;
+    aggregated_metadata.last_synced_from = Some(origin);
+    aggregated_metadata.save()?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use std::error::Error;
+
+use console::style;
+use tempfile::tempdir;
+
+use crate::{
+    archive::ensure_not_archive,
+    cli::LockSubcommandArgs,
+    git::{
+        operations::{ensure_clean_tree, get_repo_dir, mark_unpushed, open_or_clone_repo},
+        remote::{get_host_git_url, local_repository_path},
+    },
+    structs::{
+        find_manifest, AggregatedDotfileMetadata, Lockfile, Manifest, RepositoryOrigin, LOCKFILE,
+    },
+};
+
+pub fn lock_subcommand_handler(args: LockSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+    ensure_not_archive(&url, "locked")?;
+    let mut origin = RepositoryOrigin::new(&args.repository, &url, &args.manifest)
+        .with_subdir(args.subdir.as_deref());
+    let target_dir = tempdir()?;
+    let repo = open_or_clone_repo(
+        &origin.repository,
+        &origin.repository,
+        target_dir.path(),
+        args.branch.as_deref(),
+        None,
+        true,
+    )?;
+
+    // The lockfile is committed on top of whatever is checked out in the user's own clone
+    if local_repository_path(&origin.repository).is_some() && !args.dry_run {
+        ensure_clean_tree(&repo, "locking")?;
+    }
+
+    let manifest_dir = origin.manifest_dir(get_repo_dir(&repo));
+    origin.manifest = find_manifest(&manifest_dir, &origin.manifest)?;
+    let mut manifest = Manifest::get(&manifest_dir.join(&origin.manifest))?;
+    manifest.set_origin(origin);
+
+    let aggregated_metadata = AggregatedDotfileMetadata::get()?
+        .ok_or("No dotfiles have been installed on this machine")?;
+    let existing = Lockfile::read(&manifest_dir)?;
+    let lockfile = manifest.lock(&repo, &aggregated_metadata, existing.clone())?;
+    if existing.as_ref() == Some(&lockfile) {
+        success!("{} is already up to date", LOCKFILE);
+        return Ok(());
+    }
+
+    if args.dry_run {
+        info!("Would commit the following {}:", LOCKFILE);
+        print!("{}", serde_yaml::to_string(&lockfile)?);
+        return Ok(());
+    }
+    manifest.commit_lockfile(&repo, &manifest_dir, &lockfile)?;
+    if args.no_push {
+        mark_unpushed(&repo)?;
+        success!(
+            "Committed {} without pushing it. Run \"jtd push {}\" to push it",
+            LOCKFILE,
+            args.repository
+        );
+    } else {
+        manifest.push(&repo)?;
+        success!(
+            "Locked {} dotfiles in {}",
+            lockfile.dotfiles.len(),
+            LOCKFILE
+        );
+    }
+    Ok(())
+}
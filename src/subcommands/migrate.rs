@@ -0,0 +1,282 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use console::style;
+use git2::{ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use serde_yaml::{Mapping, Value};
+
+use crate::cli::MigrateSubcommandArgs;
+use crate::git::operations::{add_and_commit, get_head};
+use crate::structs::DEFAULT_MANIFEST;
+
+/// The file modes git records for regular files and executables
+const BLOB_MODE: i32 = 0o100644;
+const EXECUTABLE_MODE: i32 = 0o100755;
+
+pub fn migrate_subcommand_handler(args: MigrateSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    let git_dir = PathBuf::from(shellexpand::tilde(&args.git_dir).as_ref());
+    let dotfile_names = migrate_bare_repo(&git_dir, &args.destination, &args.work_tree)?;
+
+    success!(
+        "Migrated {} dotfiles from {} to {}",
+        dotfile_names.len(),
+        git_dir.display(),
+        args.destination.display()
+    );
+    info!(
+        "Check the generated {} and install the dotfiles with \"jtd install {} --all\"",
+        DEFAULT_MANIFEST,
+        args.destination.display()
+    );
+    Ok(())
+}
+
+/// Return the path a file tracked at `path` in the bare repository is stored at in the JTD
+/// repository, which is `path` with the leading dot of each component removed so that it isn't
+/// hidden
+fn repo_file_path(path: &str) -> String {
+    path.split('/')
+        .map(|component| component.strip_prefix('.').unwrap_or(component))
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+/// Record the paths already stored in the JTD repository, so that two tracked files that only
+/// differ in their leading dots (e.g. `.zshrc` and `zshrc`) aren't stored at the same path
+#[derive(Default)]
+struct StoredPaths {
+    files: HashSet<String>,
+    dirs: HashSet<String>,
+}
+
+impl StoredPaths {
+    /// Return whether `file` can't be stored, as it or one of its parent directories is already
+    /// taken by another file, or it is a directory of another file
+    fn is_taken(&self, file: &str) -> bool {
+        self.files.contains(file)
+            || self.dirs.contains(file)
+            || file
+                .match_indices('/')
+                .any(|(index, _)| self.files.contains(&file[..index]))
+    }
+
+    /// Return the path to store the file tracked at `path` at: [repo_file_path] if it is free,
+    /// else `path` itself, else either of them with underscores appended until it is free
+    fn store(&mut self, path: &str) -> String {
+        let mut file = repo_file_path(path);
+        if self.is_taken(&file) {
+            file = path.to_string();
+        }
+        while self.is_taken(&file) {
+            file.push('_');
+        }
+        for (index, _) in file.match_indices('/') {
+            self.dirs.insert(file[..index].to_string());
+        }
+        self.files.insert(file.clone());
+        file
+    }
+}
+
+/// Return the name of the dotfile for the file stored at `file` in the JTD repository, e.g.
+/// `nvim-init.vim` for `config/nvim/init.vim`
+fn dotfile_name(file: &str) -> String {
+    file.strip_prefix("config/")
+        .unwrap_or(file)
+        .replace('/', "-")
+}
+
+/// Copy the files committed to the bare repository at `git_dir` into a new JTD repository at
+/// `destination`, generating a manifest that installs each of them to where it is tracked in
+/// `work_tree`, and commit them. Returns the names of the dotfiles in the manifest
+fn migrate_bare_repo(
+    git_dir: &Path,
+    destination: &Path,
+    work_tree: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let bare_repo = Repository::open(git_dir)
+        .map_err(|err| format!("Could not open {}: {}", git_dir.display(), err))?;
+    if destination.exists() && destination.read_dir()?.next().is_some() {
+        return Err(format!("{} already exists and is not empty", destination.display()).into());
+    }
+
+    let mut entries = vec![];
+    get_head(&bare_repo)?
+        .tree()?
+        .walk(TreeWalkMode::PreOrder, |root, entry| {
+            let path = format!("{}{}", root, entry.name().unwrap_or_default());
+            match (entry.kind(), entry.filemode()) {
+                (Some(ObjectType::Blob), BLOB_MODE | EXECUTABLE_MODE) => {
+                    entries.push((path, entry.id(), entry.filemode() == EXECUTABLE_MODE));
+                }
+                (Some(ObjectType::Tree), _) => {}
+                _ => {
+                    warn!("Skipping {} as it is a symlink or submodule", path);
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+
+    let repo = Repository::init(destination)?;
+    let mut manifest = Mapping::new();
+    let mut dotfile_names = vec![];
+    let mut stored_paths = StoredPaths::default();
+    // The generated manifest takes the place of any tracked one
+    stored_paths.files.insert(DEFAULT_MANIFEST.to_string());
+    for (path, blob_id, executable) in entries {
+        let file = stored_paths.store(&path);
+        if file != repo_file_path(&path) {
+            warn!(
+                "Storing {} as {}, as {} is already taken",
+                path,
+                file,
+                repo_file_path(&path)
+            );
+        }
+        let file_path = destination.join(&file);
+        fs::create_dir_all(file_path.parent().unwrap())?;
+        fs::write(&file_path, bare_repo.find_blob(blob_id)?.content())?;
+
+        let mut dotfile = Mapping::new();
+        dotfile.insert("file".into(), file.as_str().into());
+        dotfile.insert(
+            "target".into(),
+            format!("{}/{}", work_tree.trim_end_matches('/'), path).into(),
+        );
+        if executable {
+            dotfile.insert("mode".into(), "0755".into());
+        }
+
+        let mut name = dotfile_name(&file);
+        while manifest.contains_key(&Value::from(name.as_str())) {
+            name.push('_');
+        }
+        manifest.insert(name.as_str().into(), Value::Mapping(dotfile));
+        dotfile_names.push(name);
+    }
+
+    fs::write(
+        destination.join(DEFAULT_MANIFEST),
+        serde_yaml::to_string(&manifest)?,
+    )?;
+    add_and_commit(
+        &repo,
+        None,
+        &format!("Migrate dotfiles from {}", git_dir.display()),
+        Some(vec![]),
+        Some("HEAD"),
+    )?;
+
+    Ok(dotfile_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::structs::Manifest;
+
+    #[test]
+    fn test_repo_file_path() {
+        assert_eq!(repo_file_path(".zshrc"), "zshrc");
+        assert_eq!(
+            repo_file_path(".config/nvim/init.vim"),
+            "config/nvim/init.vim"
+        );
+        assert_eq!(repo_file_path("bin/.hidden"), "bin/hidden");
+        assert_eq!(dotfile_name("config/nvim/init.vim"), "nvim-init.vim");
+        assert_eq!(dotfile_name("zshrc"), "zshrc");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_migrate_bare_repo() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let home = tempdir().unwrap();
+        let destination = tempdir().unwrap();
+        let destination = destination.path().join("dots");
+        let bare_repo = Repository::init(home.path()).unwrap();
+        fs::create_dir_all(home.path().join(".config/nvim")).unwrap();
+        fs::write(home.path().join(".config/nvim/init.vim"), "set number").unwrap();
+        fs::write(home.path().join(".zshrc"), "export EDITOR=nvim").unwrap();
+        fs::create_dir(home.path().join("bin")).unwrap();
+        fs::write(home.path().join("bin/backup"), "#!/bin/sh").unwrap();
+        fs::set_permissions(
+            home.path().join("bin/backup"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        add_and_commit(&bare_repo, None, "dotfiles", Some(vec![]), Some("HEAD")).unwrap();
+
+        let dotfile_names = migrate_bare_repo(home.path(), &destination, "~").unwrap();
+
+        assert_eq!(dotfile_names, vec!["nvim-init.vim", "zshrc", "bin-backup"]);
+        assert_eq!(
+            fs::read_to_string(destination.join("config/nvim/init.vim")).unwrap(),
+            "set number"
+        );
+        let manifest = Manifest::get(&destination.join(DEFAULT_MANIFEST)).unwrap();
+        let nvim = manifest.get_dotfile("nvim-init.vim").unwrap();
+        assert_eq!(nvim.file, "config/nvim/init.vim");
+        assert_eq!(nvim.target, PathBuf::from("~/.config/nvim/init.vim"));
+        assert_eq!(
+            manifest.get_dotfile("bin-backup").unwrap().mode,
+            Some(0o755)
+        );
+        assert!(get_head(&Repository::open(&destination).unwrap()).is_ok());
+
+        assert!(migrate_bare_repo(home.path(), &destination, "~").is_err());
+    }
+
+    #[test]
+    fn test_migrate_colliding_files() {
+        let home = tempdir().unwrap();
+        let destination = tempdir().unwrap();
+        let destination = destination.path().join("dots");
+        let bare_repo = Repository::init(home.path()).unwrap();
+        fs::write(home.path().join(".zshrc"), "export EDITOR=nvim").unwrap();
+        fs::write(home.path().join("zshrc"), "export EDITOR=vi").unwrap();
+        fs::write(home.path().join("jtd.yaml"), "tracked: manifest").unwrap();
+        fs::write(home.path().join("config"), "not a directory").unwrap();
+        fs::create_dir(home.path().join(".config")).unwrap();
+        fs::write(
+            home.path().join(".config/starship.toml"),
+            "add_newline = false",
+        )
+        .unwrap();
+        add_and_commit(&bare_repo, None, "dotfiles", Some(vec![]), Some("HEAD")).unwrap();
+
+        let dotfile_names = migrate_bare_repo(home.path(), &destination, "~").unwrap();
+
+        // Every tracked file is kept, in a file of its own
+        let manifest = Manifest::get(&destination.join(DEFAULT_MANIFEST)).unwrap();
+        let mut stored: Vec<(String, String)> = dotfile_names
+            .iter()
+            .map(|name| {
+                let dotfile = manifest.get_dotfile(name).unwrap();
+                (
+                    dotfile.target.to_string_lossy().to_string(),
+                    fs::read_to_string(destination.join(&dotfile.file)).unwrap(),
+                )
+            })
+            .collect();
+        stored.sort();
+        assert_eq!(
+            stored,
+            vec![
+                (
+                    "~/.config/starship.toml".to_string(),
+                    "add_newline = false".to_string()
+                ),
+                ("~/.zshrc".to_string(), "export EDITOR=nvim".to_string()),
+                ("~/config".to_string(), "not a directory".to_string()),
+                ("~/jtd.yaml".to_string(), "tracked: manifest".to_string()),
+                ("~/zshrc".to_string(), "export EDITOR=vi".to_string()),
+            ]
+        );
+    }
+}
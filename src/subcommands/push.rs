@@ -0,0 +1,99 @@
+use std::error::Error;
+
+use console::style;
+use git2::Repository;
+use tempfile::tempdir;
+
+use crate::{
+    archive::ensure_not_archive,
+    cli::PushSubcommandArgs,
+    git::{
+        operations::{
+            count_unpushed_commits, get_head, get_head_branch, get_repo_dir, open_or_clone_repo,
+        },
+        remote::get_host_git_url,
+    },
+    structs::{find_manifest, Manifest},
+};
+
+pub fn push_subcommand_handler(args: PushSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+    ensure_not_archive(&url, "pushed")?;
+    let target_dir = tempdir()?;
+    let repo = open_or_clone_repo(
+        &args.repository,
+        &url,
+        target_dir.path(),
+        args.branch.as_deref(),
+        None,
+        true,
+    )?;
+
+    let repo_dir = get_repo_dir(&repo);
+    let manifest_dir = match &args.subdir {
+        Some(subdir) => repo_dir.join(subdir),
+        None => repo_dir.to_path_buf(),
+    };
+    let manifest_path = manifest_dir.join(find_manifest(&manifest_dir, &args.manifest)?);
+    let mut manifest = Manifest::get(&manifest_path)?;
+    if let Some(strategy) = args.strategy {
+        manifest.set_merge_strategy(strategy);
+    }
+    if let Some(push_branch) = &args.push_branch {
+        manifest.set_push_branch(push_branch.to_string());
+    }
+
+    let branch = get_head_branch(&repo)?;
+    let push_branch = manifest.push_branch(&repo)?;
+    if args.force_with_lease {
+        return force_push(&args, &manifest, &repo, &branch, &push_branch);
+    }
+    let unpushed = count_unpushed_commits(&repo, "origin", &branch, &push_branch)?;
+    if unpushed == 0 {
+        success!("There are no unpushed commits on {}", branch);
+        return Ok(());
+    }
+
+    if args.dry_run {
+        info!(
+            "Would push {} commit(s) from {} to {} on the remote repo",
+            unpushed, branch, push_branch
+        );
+        return Ok(());
+    }
+    manifest.push(&repo)?;
+    success!("Pushed {} commit(s) to {}", unpushed, push_branch);
+    Ok(())
+}
+
+/// Replace `push_branch` on the remote with `branch`, unless they are already the same
+fn force_push(
+    args: &PushSubcommandArgs,
+    manifest: &Manifest,
+    repo: &Repository,
+    branch: &str,
+    push_branch: &str,
+) -> Result<(), Box<dyn Error>> {
+    let remote_tip = repo
+        .refname_to_id(&format!("refs/remotes/origin/{}", push_branch))
+        .ok();
+    if remote_tip == Some(get_head(repo)?.id()) {
+        success!("{} is already up to date with {}", push_branch, branch);
+        return Ok(());
+    }
+
+    if args.dry_run {
+        info!(
+            "Would replace {} on the remote repo with {}",
+            push_branch, branch
+        );
+        return Ok(());
+    }
+    manifest.force_push(repo)?;
+    success!(
+        "Replaced {} on the remote repo with {}",
+        push_branch,
+        branch
+    );
+    Ok(())
+}
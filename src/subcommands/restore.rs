@@ -0,0 +1,59 @@
+use std::error::Error;
+
+use console::style;
+use serde_json::json;
+
+use super::which::open_installed_dotfile;
+use crate::cli::RestoreSubcommandArgs;
+use crate::log::log_operation;
+use crate::structs::AggregatedDotfileMetadata;
+
+pub fn restore_subcommand_handler(args: RestoreSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    let mut aggregated_metadata = AggregatedDotfileMetadata::get()?
+        .ok_or("No dotfiles have been installed on this machine")?;
+    let (repo, dotfile, origin) =
+        open_installed_dotfile(&aggregated_metadata, &args.dotfile, args.from.as_deref())?;
+    let metadata = aggregated_metadata
+        .dotfile(&origin.repository, &args.dotfile)
+        .cloned()
+        .ok_or_else(|| format!("Dotfile {} is not installed", args.dotfile))?;
+    if !args.dry_run && !dotfile.confirm_root_write(&args.dotfile, args.trust)? {
+        return Ok(());
+    }
+    let dotfile = if args.trust {
+        dotfile.trusted()
+    } else {
+        dotfile
+    };
+
+    let new_metadata = dotfile.restore(
+        &repo,
+        &args.dotfile,
+        metadata,
+        &args.git_ref,
+        args.force,
+        args.dry_run,
+    )?;
+    if args.dry_run {
+        return Ok(());
+    }
+
+    log_operation(
+        "restored version",
+        json!({ "dotfile": args.dotfile, "ref": args.git_ref, "commit": new_metadata.commit_hash }),
+    );
+    aggregated_metadata.insert_dotfile(Some(&origin), &args.dotfile, new_metadata);
+    aggregated_metadata.save()?;
+
+    success!(
+        "Restored {} to its version at {}",
+        args.dotfile,
+        args.git_ref
+    );
+    info!(
+        "\"jtd update\" and \"jtd sync\" will leave it alone until it is installed again with \
+        \"jtd install {} {}\"",
+        origin.repository, args.dotfile
+    );
+    Ok(())
+}
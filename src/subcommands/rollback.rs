@@ -0,0 +1,91 @@
+use std::error::Error;
+use std::fs;
+
+use console::style;
+
+use serde_json::json;
+
+use crate::cli::RollbackSubcommandArgs;
+use crate::log::log_operation;
+use crate::prompt::prompter;
+use crate::structs::AggregatedDotfileMetadata;
+use crate::utils::{is_non_interactive, root_command, set_non_interactive, write_as_root};
+
+pub fn rollback_subcommand_handler(args: RollbackSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    if args.yes {
+        set_non_interactive(true);
+    }
+
+    let mut aggregated_metadata = AggregatedDotfileMetadata::get()?
+        .ok_or("Could not find any metadata on the currently installed dotfiles")?;
+
+    let dotfiles: Vec<(String, String)> = if args.target_dotfiles.is_empty() {
+        aggregated_metadata.last_installed()
+    } else {
+        let from = args.from.as_deref();
+        args.target_dotfiles
+            .into_iter()
+            .map(|dotfile_name| {
+                let repository = aggregated_metadata.locate_dotfile(&dotfile_name, from)?;
+                Ok((repository, dotfile_name))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?
+    };
+    if dotfiles.is_empty() {
+        return Err("No installs found to roll back".into());
+    }
+
+    for (repository, dotfile_name) in dotfiles {
+        let metadata = aggregated_metadata
+            .dotfile_mut(&repository, &dotfile_name)
+            .ok_or_else(|| format!("Dotfile {} is not installed", dotfile_name))?;
+
+        let (backup_path, target_path) = match (&metadata.backup_path, &metadata.target_path) {
+            (Some(backup_path), Some(target_path)) if backup_path.exists() => {
+                (backup_path.to_owned(), target_path.to_owned())
+            }
+            _ => {
+                warn!("No backup found for {}, skipping", dotfile_name);
+                continue;
+            }
+        };
+
+        if !is_non_interactive()
+            && !prompter().confirm(
+                &format!(
+                    "Restore {} from {}?",
+                    target_path.to_string_lossy(),
+                    backup_path.to_string_lossy()
+                ),
+                false,
+            )?
+        {
+            continue;
+        }
+
+        if metadata.requires_root {
+            write_as_root(
+                &root_command(),
+                &target_path,
+                &fs::read(&backup_path)?,
+                None,
+            )?;
+        } else {
+            fs::copy(&backup_path, &target_path)?;
+        }
+        metadata.backup_path = None;
+        // The target no longer matches the commit it was installed from, so it mustn't be synced
+        // back over the repository or updated over
+        metadata.rolled_back = true;
+        log_operation(
+            "restored",
+            json!({ "dotfile": dotfile_name, "target": target_path, "backup": backup_path }),
+        );
+        success!(
+            "Restored {} to its state before it was last installed",
+            dotfile_name
+        );
+    }
+
+    aggregated_metadata.save()
+}
@@ -0,0 +1,229 @@
+use std::env::consts::{ARCH, OS};
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use console::style;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::cli::SelfUpdateSubcommandArgs;
+use crate::prompt::prompter;
+use crate::utils::{http_get, is_non_interactive, set_non_interactive};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/dob9601/jointhedots/releases/latest";
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    tag_name: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+pub fn self_update_subcommand_handler(
+    args: SelfUpdateSubcommandArgs,
+) -> Result<(), Box<dyn Error>> {
+    if args.yes {
+        set_non_interactive(true);
+    }
+
+    let release: Release = serde_json::from_str(&http_get(LATEST_RELEASE_URL)?.into_string()?)
+        .map_err(|err| format!("Could not parse the latest release: {}", err))?;
+    let current_version = env!("CARGO_PKG_VERSION");
+    let latest_version = release.tag_name.trim_start_matches('v');
+
+    if !is_newer_version(latest_version, current_version) {
+        success!("jtd is up to date (version {})", current_version);
+        return Ok(());
+    }
+    info!(
+        "A new version of jtd is available: {} -> {}",
+        current_version, latest_version
+    );
+    if args.check {
+        return Ok(());
+    }
+
+    let asset_name = find_platform_asset(&release.assets, OS, ARCH).ok_or_else(|| {
+        format!(
+            "Release {} has no binary for {} {}",
+            release.tag_name, OS, ARCH
+        )
+    })?;
+    let asset = find_asset(&release.assets, &asset_name).unwrap();
+    let checksum_asset = find_asset(&release.assets, &format!("{}.sha256", asset_name))
+        .ok_or_else(|| {
+            format!(
+                "Release {} has no checksum for {}, refusing to update",
+                release.tag_name, asset_name
+            )
+        })?;
+
+    if !is_non_interactive()
+        && !prompter().confirm(&format!("Update jtd to version {}?", latest_version), true)?
+    {
+        return Ok(());
+    }
+
+    let mut binary = vec![];
+    http_get(&asset.browser_download_url)?
+        .into_reader()
+        .read_to_end(&mut binary)?;
+    let checksum = http_get(&checksum_asset.browser_download_url)?.into_string()?;
+    verify_checksum(&binary, &checksum)?;
+
+    let current_exe = std::env::current_exe()?;
+    replace_executable(&current_exe, &binary)?;
+    success!(
+        "Updated {} to version {}",
+        current_exe.to_string_lossy(),
+        latest_version
+    );
+    Ok(())
+}
+
+/// Return whether `candidate` is a later version than `current`, comparing each dot-separated
+/// component numerically
+fn is_newer_version(candidate: &str, current: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .split('.')
+            .map(|component| component.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(candidate) > parse(current)
+}
+
+fn find_asset<'a>(assets: &'a [ReleaseAsset], name: &str) -> Option<&'a ReleaseAsset> {
+    assets.iter().find(|asset| asset.name == name)
+}
+
+/// Return the name of the release asset containing the binary for the given OS and architecture.
+/// Binaries are named `jtd-<arch>-<os>`, except for older releases which only published an
+/// x86-64 Linux binary named `jtd`
+fn find_platform_asset(assets: &[ReleaseAsset], os: &str, arch: &str) -> Option<String> {
+    let suffix = if os == "windows" { ".exe" } else { "" };
+    let platform_name = format!("jtd-{}-{}{}", arch, os, suffix);
+    if find_asset(assets, &platform_name).is_some() {
+        Some(platform_name)
+    } else if os == "linux" && arch == "x86_64" && find_asset(assets, "jtd").is_some() {
+        Some("jtd".to_string())
+    } else {
+        None
+    }
+}
+
+/// Check `contents` against a checksum file in the format written by `sha256sum`
+fn verify_checksum(contents: &[u8], checksum_file: &str) -> Result<(), Box<dyn Error>> {
+    let expected = checksum_file
+        .split_whitespace()
+        .next()
+        .ok_or("The checksum of the release is empty")?;
+    let actual = hex::encode(Sha256::digest(contents));
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(format!(
+            "The downloaded binary does not match its checksum (expected {}, got {}), refusing \
+            to update",
+            expected, actual
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Replace the executable at `path` with `contents`. The new binary is written next to it and
+/// then moved into place, so a failed write never leaves a broken executable behind
+fn replace_executable(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let new_path = path.with_extension("new");
+    let old_path = path.with_extension("old");
+    fs::write(&new_path, contents)
+        .map_err(|err| format!("Could not write to {}: {}", new_path.to_string_lossy(), err))?;
+    fs::set_permissions(&new_path, fs::metadata(path)?.permissions())?;
+
+    // Running executables can't be overwritten on Windows, but they can be renamed
+    fs::rename(path, &old_path)?;
+    if let Err(err) = fs::rename(&new_path, path) {
+        fs::rename(&old_path, path)?;
+        return Err(err.into());
+    }
+    let _ = fs::remove_file(&old_path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(name: &str) -> ReleaseAsset {
+        ReleaseAsset {
+            name: name.to_string(),
+            browser_download_url: format!("https://example.com/{}", name),
+        }
+    }
+
+    #[test]
+    fn test_is_newer_version() {
+        assert!(is_newer_version("0.2.0", "0.1.9"));
+        assert!(is_newer_version("0.10.0", "0.9.0"));
+        assert!(!is_newer_version("0.1.0", "0.1.0"));
+        assert!(!is_newer_version("0.1.0", "0.2.0"));
+    }
+
+    #[test]
+    fn test_find_platform_asset() {
+        let assets = vec![
+            asset("jtd"),
+            asset("jtd.sha256"),
+            asset("jtd-aarch64-macos"),
+            asset("jtd-x86_64-windows.exe"),
+        ];
+        assert_eq!(
+            find_platform_asset(&assets, "macos", "aarch64"),
+            Some("jtd-aarch64-macos".to_string())
+        );
+        assert_eq!(
+            find_platform_asset(&assets, "windows", "x86_64"),
+            Some("jtd-x86_64-windows.exe".to_string())
+        );
+        assert_eq!(
+            find_platform_asset(&assets, "linux", "x86_64"),
+            Some("jtd".to_string())
+        );
+        assert_eq!(find_platform_asset(&assets, "linux", "aarch64"), None);
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  jtd\n";
+        assert!(verify_checksum(b"hello", checksum).is_ok());
+        assert!(verify_checksum(b"goodbye", checksum).is_err());
+        assert!(verify_checksum(b"hello", "").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jtd");
+        fs::write(&path, "old binary").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        replace_executable(&path, b"new binary").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new binary");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+        assert!(!path.with_extension("old").exists());
+        assert!(!path.with_extension("new").exists());
+    }
+}
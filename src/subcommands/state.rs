@@ -0,0 +1,111 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use console::style;
+
+use crate::cli::{StateAction, StateSubcommandArgs};
+use crate::structs::{AggregatedDotfileMetadata, DotfileMetadata, UNKNOWN_REPOSITORY};
+use crate::utils::format_date;
+
+pub fn state_subcommand_handler(args: StateSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    match args.action {
+        StateAction::List => {
+            let aggregated_metadata = AggregatedDotfileMetadata::get()?
+                .ok_or("No dotfiles have been installed on this machine")?;
+            for (repository, dotfile_name, metadata) in aggregated_metadata.dotfiles() {
+                print_installed_dotfile(repository, dotfile_name, metadata);
+            }
+        }
+        StateAction::Export => {
+            let aggregated_metadata = AggregatedDotfileMetadata::get()?
+                .ok_or("No dotfiles have been installed on this machine")?;
+            print!("{}", serde_yaml::to_string(&aggregated_metadata.export())?);
+        }
+        StateAction::Import { file, replace } => {
+            let contents = match file {
+                Some(path) if path != Path::new("-") => fs::read_to_string(&path)
+                    .map_err(|err| format!("Could not read {}: {}", path.display(), err))?,
+                _ => {
+                    let mut contents = String::new();
+                    io::stdin().read_to_string(&mut contents)?;
+                    contents
+                }
+            };
+            let imported: AggregatedDotfileMetadata = serde_yaml::from_str(&contents)
+                .map_err(|err| format!("Could not parse the state to import: {}", err))?;
+
+            let mut aggregated_metadata = if replace {
+                AggregatedDotfileMetadata::new()
+            } else {
+                AggregatedDotfileMetadata::get_or_create()?
+            };
+            let install_commands = imported
+                .installed_by_repository(&[])
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(origin, dotfile_names)| {
+                    format!(
+                        "jtd install {} --manifest {} {}",
+                        origin.repository,
+                        origin.manifest,
+                        dotfile_names.join(" ")
+                    )
+                })
+                .collect::<Vec<String>>();
+
+            let dotfile_count = aggregated_metadata.import(imported);
+            aggregated_metadata.save()?;
+            success!("Imported {} dotfiles", dotfile_count);
+            if !install_commands.is_empty() {
+                info!("Install them with:");
+                for install_command in install_commands {
+                    println!("    {}", install_command);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the name and target of an installed dotfile, followed by the repository and commit it was
+/// installed from and when and by which version of jtd, as far as they were recorded
+fn print_installed_dotfile(repository: &str, dotfile_name: &str, metadata: &DotfileMetadata) {
+    match &metadata.target_path {
+        Some(target_path) => println!(
+            "{} ({})",
+            style(dotfile_name).bold(),
+            target_path.to_string_lossy()
+        ),
+        None => println!("{}", style(dotfile_name).bold()),
+    }
+
+    let repo_url = metadata.repo_url.as_deref().unwrap_or(repository);
+    let short_hash = metadata
+        .commit_hash
+        .get(..7)
+        .unwrap_or(&metadata.commit_hash);
+    if repo_url == UNKNOWN_REPOSITORY {
+        println!("  from an unknown repository at {}", short_hash);
+    } else {
+        println!("  from {} at {}", repo_url, short_hash);
+    }
+
+    let installed = match (metadata.installed_at, &metadata.jtd_version) {
+        (Some(installed_at), Some(version)) => Some(format!(
+            "installed on {} by jtd {}",
+            format_date(installed_at as i64),
+            version
+        )),
+        (Some(installed_at), None) => {
+            Some(format!("installed on {}", format_date(installed_at as i64)))
+        }
+        (None, Some(version)) => Some(format!("installed by jtd {}", version)),
+        (None, None) => None,
+    };
+    if let Some(installed) = installed {
+        println!("  {}", installed);
+    }
+}
@@ -1,30 +1,332 @@
+use std::env;
 use std::error::Error;
+use std::ffi::OsString;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use console::{style, Term};
 use tempfile::tempdir;
 
 use crate::{
-    cli::SyncSubcommandArgs,
-    git::{operations::clone_repo, remote::get_host_git_url},
-    structs::{AggregatedDotfileMetadata, Manifest},
+    archive::ensure_not_archive,
+    cli::{Cli, JoinTheDots, SyncSubcommandArgs},
+    git::{
+        operations::{
+            ensure_clean_tree, get_head, get_head_branch, get_repo_dir, open_or_clone_repo,
+            open_user_checkout,
+        },
+        pull_request::{open_github_pull_request, open_gitlab_merge_request},
+        remote::{get_host_git_url, local_repository_path},
+    },
+    log::{output_format, print_json, set_logs_to_stderr, set_output_format, OutputFormat},
+    picker::set_use_tui,
+    prompt::prompter,
+    structs::{
+        find_manifest, AggregatedDotfileMetadata, DotfileReport, Manifest, OperationReport,
+        RepositoryOrigin, UserConfig,
+    },
+    utils::{is_non_interactive, set_non_interactive, RunLock},
 };
 
+/// The environment variable holding the number of days after which to remind the user to sync
+/// their dotfiles. Setting it to 0 disables the reminder
+pub const SYNC_REMINDER_ENV_VAR: &str = "JTD_SYNC_REMINDER_DAYS";
+const DEFAULT_SYNC_REMINDER_DAYS: u64 = 14;
+const SECS_PER_DAY: u64 = 60 * 60 * 24;
+
 pub fn sync_subcommand_handler(args: SyncSubcommandArgs) -> Result<(), Box<dyn Error>> {
-    let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
-    let target_dir = tempdir()?;
+    if args.yes {
+        set_non_interactive(true);
+    }
+    set_output_format(args.output);
+    set_use_tui(args.tui);
+
+    let sources = if args.all_repos {
+        AggregatedDotfileMetadata::get_or_create()?
+            .installed_by_repository(&args.target_dotfiles)?
+    } else {
+        let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+        vec![(
+            RepositoryOrigin::new(&args.repository, &url, &args.manifest)
+                .with_subdir(args.subdir.as_deref()),
+            args.target_dotfiles.clone(),
+        )]
+    };
+
+    let mut reports = vec![];
+    for (origin, target_dotfiles) in sources {
+        if args.all_repos {
+            info!("Syncing with {}", origin.repository);
+        }
+        reports.extend(sync_with(&args, origin, target_dotfiles)?);
+    }
+
+    if output_format() == OutputFormat::Json {
+        print_json(&OperationReport::new(reports, args.dry_run))?;
+    }
+    Ok(())
+}
 
-    let repo = clone_repo(&url, target_dir.path())?;
+/// Sync the target dotfiles with the manifest and repository of `origin`
+fn sync_with(
+    args: &SyncSubcommandArgs,
+    mut origin: RepositoryOrigin,
+    target_dotfiles: Vec<String>,
+) -> Result<Vec<DotfileReport>, Box<dyn Error>> {
+    ensure_not_archive(&origin.repository, "synced")?;
+    let target_dir = tempdir()?;
+    let repo = match &args.repo_dir {
+        Some(repo_dir) => open_user_checkout(repo_dir, &origin.repository, args.branch.as_deref())?,
+        None => open_or_clone_repo(
+            &origin.repository,
+            &origin.repository,
+            target_dir.path(),
+            args.branch.as_deref(),
+            args.depth,
+            !args.no_cache,
+        )?,
+    };
 
-    let mut manifest_path = target_dir.path().to_path_buf();
-    manifest_path.push(args.manifest);
+    let repo_dir = get_repo_dir(&repo);
+    // Syncing checks out and commits on other branches, which would get in the way of (or fail
+    // on) uncommitted work in the user's own clone
+    let is_user_clone =
+        args.repo_dir.is_some() || local_repository_path(&origin.repository).is_some();
+    if is_user_clone && !args.dry_run {
+        ensure_clean_tree(&repo, "syncing")?;
+    }
+    if args.repo_dir.is_some() {
+        origin.repo_dir = Some(repo_dir.to_path_buf());
+    }
+    let manifest_dir = origin.manifest_dir(repo_dir);
+    origin.manifest = find_manifest(&manifest_dir, &origin.manifest)?;
 
-    let manifest = Manifest::get(&manifest_path)?;
+    let mut manifest = Manifest::get(&manifest_dir.join(&origin.manifest))?;
+    manifest.set_origin(origin.clone());
+    manifest.load_trust(false)?;
+    if let Some(strategy) = args.strategy {
+        manifest.set_merge_strategy(strategy);
+    }
+    if let Some(push_branch) = &args.push_branch {
+        manifest.set_push_branch(push_branch.to_string());
+    }
+    if args.interactive_hunks {
+        manifest.pick_hunks();
+    }
+    let base_branch = get_head_branch(&repo)?;
+    let push_branch = manifest.push_branch(&repo)?;
+    if (args.create_pr || args.create_mr) && push_branch == base_branch {
+        return Err(format!(
+            "Cannot open a {} from {} into itself, pass --push-branch to push to another branch",
+            if args.create_mr {
+                "merge request"
+            } else {
+                "pull request"
+            },
+            base_branch
+        )
+        .into());
+    }
 
-    manifest.sync(
+    let reports = manifest.sync(
         &repo,
         args.all,
-        args.target_dotfiles,
+        target_dotfiles,
         args.commit_msg.as_deref(),
         AggregatedDotfileMetadata::get()?,
         args.naive,
-    )
+        args.dry_run,
+        (!args.no_push).then_some("origin"),
+    )?;
+    if args.no_push && !args.dry_run {
+        info!("Run \"jtd push {}\" to push them", origin.repository);
+    }
+
+    if args.create_pr || args.create_mr {
+        let request_name = if args.create_mr {
+            "merge request"
+        } else {
+            "pull request"
+        };
+        if args.dry_run {
+            info!(
+                "Would open a {} from {} into {}",
+                request_name, push_branch, base_branch
+            );
+        } else {
+            let remote = repo.find_remote("origin")?;
+            let url = remote
+                .url()
+                .ok_or("The URL of the remote repo isn't valid UTF-8")?;
+            let title = get_head(&repo)?.summary().unwrap_or_default().to_string();
+            let request_url = if args.create_mr {
+                if let (None, Some(manifest_url)) = (&args.gitlab_url, manifest.gitlab_url()) {
+                    warn!(
+                        "Ignoring the gitlab_url {} set in the manifest, as the manifest can't \
+                        choose where your token is sent. Pass it with \"--gitlab-url\" instead",
+                        manifest_url
+                    );
+                }
+                open_gitlab_merge_request(
+                    url,
+                    args.gitlab_url.as_deref(),
+                    &push_branch,
+                    &base_branch,
+                    &title,
+                )?
+            } else {
+                open_github_pull_request(url, &push_branch, &base_branch, &title)?
+            };
+            success!(
+                "Synced changes are up for review in the {} at {}",
+                request_name,
+                request_url
+            );
+        }
+    }
+
+    if !args.dry_run {
+        // Remember where the dotfiles were synced from, so that the sync reminder can offer to
+        // sync them again
+        let mut aggregated_metadata = AggregatedDotfileMetadata::get_or_create()?;
+        aggregated_metadata.last_synced_from = Some(origin);
+        aggregated_metadata.save()?;
+    }
+    Ok(reports)
+}
+
+/// Warn if the installed dotfiles haven't been synced for longer than the reminder period in
+/// [SYNC_REMINDER_ENV_VAR] and, when running interactively, offer to sync them immediately
+pub fn remind_to_sync() -> Result<(), Box<dyn Error>> {
+    let reminder_days = sync_reminder_days();
+    if reminder_days == 0 || output_format() == OutputFormat::Json {
+        return Ok(());
+    }
+    // A broken metadata file is reported by the commands that need it, not by the reminder
+    let aggregated_metadata = match AggregatedDotfileMetadata::get().ok().flatten() {
+        Some(aggregated_metadata) => aggregated_metadata,
+        None => return Ok(()),
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let days_since_sync = match aggregated_metadata.secs_since_sync(now) {
+        Some(secs) => secs / SECS_PER_DAY,
+        None => return Ok(()),
+    };
+    if days_since_sync < reminder_days {
+        return Ok(());
+    }
+
+    // The reminder shouldn't end up in the output of the command it follows
+    set_logs_to_stderr(true);
+    warn!(
+        "Your dotfiles haven't been synced for {} days",
+        days_since_sync
+    );
+    let origin = aggregated_metadata
+        .last_synced_from
+        .as_ref()
+        .or(aggregated_metadata.installed_from.as_ref());
+    let source = match origin {
+        Some(source) if !is_non_interactive() && Term::stderr().is_term() => source,
+        _ => {
+            info!(
+                "Run \"jtd sync\" to sync them, or set {}=0 to disable this reminder",
+                SYNC_REMINDER_ENV_VAR
+            );
+            return Ok(());
+        }
+    };
+    let sync_now =
+        prompter().confirm(&format!("Sync them with {} now?", source.repository), false)?;
+    set_logs_to_stderr(false);
+    if !sync_now {
+        return Ok(());
+    }
+
+    let command = sync_command(source, &aggregated_metadata);
+    match Cli::try_parse_with_defaults(command, &UserConfig::get()?, None)?.command {
+        JoinTheDots::Sync(args) => {
+            let _lock = RunLock::acquire()?;
+            sync_subcommand_handler(args)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Return the arguments of the `jtd sync` that syncs the installed dotfiles with `origin`, the
+/// repository they were last synced or installed from, in the same way as then
+fn sync_command(
+    origin: &RepositoryOrigin,
+    aggregated_metadata: &AggregatedDotfileMetadata,
+) -> Vec<OsString> {
+    let mut command: Vec<OsString> = vec!["jtd".into(), "sync".into()];
+    if aggregated_metadata.known_repositories().len() > 1 {
+        // Each repository is synced with the manifest it was installed with
+        command.extend([origin.repository.as_str().into(), "--all-repos".into()]);
+        return command;
+    }
+
+    command.extend([
+        origin.repository.as_str().into(),
+        "--manifest".into(),
+        origin.manifest.as_str().into(),
+    ]);
+    if let Some(subdir) = &origin.subdir {
+        command.extend(["--subdir".into(), subdir.into()]);
+    }
+    if let Some(repo_dir) = &origin.repo_dir {
+        command.extend(["--repo-dir".into(), repo_dir.into()]);
+    }
+    command.extend(
+        aggregated_metadata
+            .installed_dotfiles(&origin.repository)
+            .into_iter()
+            .map(OsString::from),
+    );
+    command
+}
+
+/// Return the number of days after which to remind the user to sync, as set in
+/// [SYNC_REMINDER_ENV_VAR]
+fn sync_reminder_days() -> u64 {
+    env::var(SYNC_REMINDER_ENV_VAR)
+        .ok()
+        .and_then(|days| days.trim().parse().ok())
+        .unwrap_or(DEFAULT_SYNC_REMINDER_DAYS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::structs::DotfileMetadata;
+
+    use super::*;
+
+    #[test]
+    fn test_sync_command() {
+        let origin = RepositoryOrigin {
+            repository: "git@github.com:dob9601/monorepo.git".to_string(),
+            host: Some("github.com".to_string()),
+            manifest: "hosts/laptop.yaml".to_string(),
+            subdir: Some("dotfiles".to_string()),
+            repo_dir: Some(PathBuf::from("/home/dob9601/monorepo")),
+        };
+        let mut aggregated_metadata = AggregatedDotfileMetadata::default();
+        aggregated_metadata.insert_dotfile(Some(&origin), "kitty", DotfileMetadata::default());
+
+        let command = sync_command(&origin, &aggregated_metadata);
+        match Cli::try_parse_with_defaults(command, &UserConfig::default(), None)
+            .unwrap()
+            .command
+        {
+            JoinTheDots::Sync(args) => {
+                assert_eq!(args.repository, origin.repository);
+                assert_eq!(args.manifest, origin.manifest);
+                assert_eq!(args.subdir, origin.subdir);
+                assert_eq!(args.repo_dir, origin.repo_dir);
+                assert_eq!(args.target_dotfiles, vec!["kitty".to_string()]);
+            }
+            _ => panic!("Expected a sync command"),
+        }
+    }
 }
@@ -0,0 +1,61 @@
+use std::error::Error;
+
+use console::style;
+
+use crate::cli::{TrustAction, TrustSubcommandArgs};
+use crate::git::remote::get_host_git_url;
+use crate::structs::{RepositoryOrigin, TrustStore};
+use crate::utils::trust_path;
+
+pub fn trust_subcommand_handler(args: TrustSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    let path = trust_path();
+    let mut trust_store = TrustStore::read(&path)?;
+
+    match args.action {
+        TrustAction::Add {
+            repository,
+            hash,
+            method,
+            source,
+        } => {
+            if let Some(hash) = hash {
+                trust_store.hashes.insert(hash.clone());
+                success!("Trusting install steps with hash {}", hash);
+            } else if let Some(repository) = repository {
+                // Recorded the same way as the repository of installed dotfiles, so that it is
+                // matched when installing from it
+                let url = get_host_git_url(&repository, &source, &method)?;
+                let origin = RepositoryOrigin::new(&repository, &url, "");
+                success!("Trusting the install steps of {}", origin.repository);
+                trust_store.repositories.insert(origin.repository);
+            }
+        }
+        TrustAction::Remove { repository, hash } => {
+            if let Some(hash) = hash {
+                if !trust_store.hashes.remove(&hash) {
+                    return Err(format!("Install steps with hash {} aren't trusted", hash).into());
+                }
+                success!("No longer trusting install steps with hash {}", hash);
+            } else if let Some(repository) = repository {
+                if !trust_store.remove_repository(&repository) {
+                    return Err(format!("{} isn't trusted", repository).into());
+                }
+                success!("No longer trusting the install steps of {}", repository);
+            }
+        }
+        TrustAction::List => {
+            if trust_store.repositories.is_empty() && trust_store.hashes.is_empty() {
+                info!("No repositories or install steps are trusted");
+            }
+            for repository in &trust_store.repositories {
+                println!("{}", repository);
+            }
+            for hash in &trust_store.hashes {
+                println!("{} {}", style("hash").dim(), hash);
+            }
+            return Ok(());
+        }
+    }
+
+    trust_store.write(&path)
+}
@@ -0,0 +1,97 @@
+use std::error::Error;
+
+use console::style;
+
+use crate::cli::UninstallSubcommandArgs;
+use crate::prompt::prompter;
+use crate::structs::{repository_matches, AggregatedDotfileMetadata};
+use crate::utils::{is_non_interactive, set_non_interactive};
+
+pub fn uninstall_subcommand_handler(args: UninstallSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    if args.yes {
+        set_non_interactive(true);
+    }
+
+    let mut aggregated_metadata = AggregatedDotfileMetadata::get()?
+        .ok_or("Could not find any metadata on the currently installed dotfiles")?;
+
+    let dotfiles: Vec<(String, String)> = if args.all {
+        aggregated_metadata
+            .dotfiles()
+            .into_iter()
+            .filter(|(repository, _, _)| {
+                args.from
+                    .as_deref()
+                    .is_none_or(|from| repository_matches(repository, from))
+            })
+            .map(|(repository, dotfile_name, _)| (repository.to_string(), dotfile_name.to_string()))
+            .collect()
+    } else {
+        let from = args.from.as_deref();
+        args.target_dotfiles
+            .into_iter()
+            .map(|dotfile_name| {
+                let repository = aggregated_metadata.locate_dotfile(&dotfile_name, from)?;
+                Ok((repository, dotfile_name))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?
+    };
+
+    let mut skip_uninstall_steps = false;
+    if !args.trust
+        && !args.dry_run
+        && dotfiles
+            .iter()
+            .filter_map(|(repository, dotfile_name)| {
+                aggregated_metadata.dotfile(repository, dotfile_name)
+            })
+            .any(|metadata| metadata.has_uninstall_steps())
+    {
+        warn!(
+            "Some of the dotfiles being uninstalled contain pre_uninstall and/or post_uninstall \
+            steps. If you do not trust them, you can skip running them."
+        );
+        skip_uninstall_steps = if is_non_interactive() {
+            info!("Running non-interactively, skipping pre/post uninstall steps. Pass \"--trust\" to run them");
+            true
+        } else {
+            prompter().confirm("Skip running pre/post uninstall?", false)?
+        };
+    }
+
+    for (repository, dotfile_name) in dotfiles {
+        let metadata = match aggregated_metadata.dotfile(&repository, &dotfile_name) {
+            Some(metadata) => metadata.clone(),
+            None => continue,
+        };
+
+        if !args.dry_run
+            && !is_non_interactive()
+            && !prompter().confirm(&format!("Uninstall {}?", dotfile_name), false)?
+        {
+            continue;
+        }
+
+        metadata.uninstall(&dotfile_name, skip_uninstall_steps, args.dry_run)?;
+        if let Some(backup_path) = metadata.backup_path.as_ref().filter(|path| path.exists()) {
+            info!(
+                "The file {} replaced when it was installed is backed up at {}",
+                dotfile_name,
+                backup_path.to_string_lossy()
+            );
+        }
+
+        if !args.dry_run {
+            // Saved after every dotfile, so that those already removed aren't still recorded as
+            // installed if a later one fails to uninstall
+            aggregated_metadata.remove_dotfile(&repository, &dotfile_name);
+            aggregated_metadata.save()?;
+            success!("Uninstalled {}", dotfile_name);
+        }
+    }
+
+    if args.dry_run {
+        info!("Dry run complete, no changes were made");
+    }
+    Ok(())
+}
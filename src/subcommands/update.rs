@@ -0,0 +1,69 @@
+use std::error::Error;
+
+use tempfile::tempdir;
+
+use crate::cli::UpdateSubcommandArgs;
+use crate::git::operations::{get_repo_dir, open_or_clone_repo};
+use crate::git::remote::get_host_git_url;
+use crate::log::{output_format, print_json, set_output_format, OutputFormat, ReportedError};
+use crate::structs::{find_manifest, DotfileStatus, Manifest, OperationReport, RepositoryOrigin};
+use crate::utils::{set_non_interactive, set_offline};
+
+pub fn update_subcommand_handler(args: UpdateSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    if args.yes {
+        set_non_interactive(true);
+    }
+    set_output_format(args.output);
+    set_offline(args.offline);
+
+    let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+
+    let target_dir = tempdir()?;
+    let repo = open_or_clone_repo(
+        &args.repository,
+        &url,
+        target_dir.path(),
+        args.branch.as_deref(),
+        Some(args.depth),
+        !args.no_cache,
+    )?;
+
+    let repo_dir = get_repo_dir(&repo);
+    let mut origin = RepositoryOrigin::new(&args.repository, &url, &args.manifest)
+        .with_subdir(args.subdir.as_deref());
+    let manifest_dir = origin.manifest_dir(repo_dir);
+    origin.manifest = find_manifest(&manifest_dir, &origin.manifest)?;
+
+    let mut manifest = Manifest::get(&manifest_dir.join(&origin.manifest))?;
+    manifest.set_origin(origin);
+    manifest.load_trust(args.trust)?;
+    if args.keep_backup {
+        manifest.keep_backups();
+    }
+
+    let reports = manifest.update(
+        &repo,
+        args.target_dotfiles,
+        args.force,
+        args.trust,
+        args.dry_run,
+    )?;
+
+    let failures = reports
+        .iter()
+        .filter(|report| report.status == DotfileStatus::Failed)
+        .count();
+    let error_message = format!("Failed to update {} dotfile(s)", failures);
+
+    if output_format() == OutputFormat::Json {
+        print_json(&OperationReport::new(reports, args.dry_run))?;
+        if failures > 0 {
+            return Err(ReportedError(error_message).into());
+        }
+    }
+
+    if failures > 0 {
+        return Err(error_message.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::path::Path;
+
+use console::style;
+use serde::Serialize;
+use tempfile::tempdir;
+
+use crate::cli::ValidateSubcommandArgs;
+use crate::git::operations::clone_repo;
+use crate::git::remote::{get_host_git_url, local_repository_path};
+use crate::log::{output_format, print_json, set_output_format, OutputFormat, ReportedError};
+use crate::structs::{find_manifest, validate_manifest, ManifestProblem, ProblemSeverity};
+
+#[derive(Serialize)]
+struct ValidationReport<'a> {
+    success: bool,
+    problems: &'a [ManifestProblem],
+}
+
+pub fn validate_subcommand_handler(args: ValidateSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    set_output_format(args.output);
+
+    let target_dir = tempdir()?;
+    let local_path = Path::new(&args.repository);
+
+    let (manifest_path, repo_dir) = if local_path.is_file() {
+        let repo_dir = local_path
+            .parent()
+            .ok_or("Could not determine repository directory of manifest")?;
+        (local_path.to_path_buf(), repo_dir.to_path_buf())
+    } else {
+        let repo_dir = if let Some(repo_dir) = local_repository_path(&args.repository) {
+            repo_dir
+        } else {
+            let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+            clone_repo(&url, target_dir.path(), None, Some(1))?;
+            target_dir.path().to_path_buf()
+        };
+        (
+            repo_dir.join(find_manifest(&repo_dir, &args.manifest)?),
+            repo_dir,
+        )
+    };
+
+    let problems = validate_manifest(&manifest_path, &repo_dir)?;
+
+    for problem in problems.iter() {
+        if problem.severity == ProblemSeverity::Error {
+            error!("{}", problem);
+        } else {
+            warn!("{}", problem);
+        }
+    }
+
+    let error_count = problems
+        .iter()
+        .filter(|problem| args.strict || problem.severity == ProblemSeverity::Error)
+        .count();
+
+    let error_message = format!("Manifest failed validation with {} problem(s)", error_count);
+
+    if output_format() == OutputFormat::Json {
+        print_json(&ValidationReport {
+            success: error_count == 0,
+            problems: &problems,
+        })?;
+        if error_count > 0 {
+            return Err(ReportedError(error_message).into());
+        }
+    }
+
+    if error_count > 0 {
+        return Err(error_message.into());
+    }
+
+    success!("Manifest is valid");
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use std::error::Error;
+
+use console::style;
+
+use crate::cli::{VarsAction, VarsSubcommandArgs};
+use crate::structs::MachineVariables;
+use crate::VARS_PATH;
+
+pub fn vars_subcommand_handler(args: VarsSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    let mut vars = MachineVariables::get()?;
+
+    match args.action {
+        VarsAction::Get { key } => {
+            let value = vars
+                .data
+                .get(&key)
+                .ok_or_else(|| format!("Variable {} is not set in {}", key, VARS_PATH))?;
+            println!("{}", value);
+        }
+        VarsAction::Set { key, value } => {
+            vars.data.insert(key.to_owned(), value.to_owned());
+            vars.save()?;
+            success!("Set {} to {}", key, value);
+        }
+        VarsAction::Unset { key } => {
+            vars.data
+                .remove(&key)
+                .ok_or_else(|| format!("Variable {} is not set in {}", key, VARS_PATH))?;
+            vars.save()?;
+            success!("Unset {}", key);
+        }
+        VarsAction::List => {
+            for (key, value) in vars.data.iter() {
+                println!("{}: {}", key, value);
+            }
+        }
+    }
+
+    Ok(())
+}
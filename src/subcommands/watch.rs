@@ -0,0 +1,182 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use console::style;
+use notify::{RecursiveMode, Watcher};
+use tempfile::tempdir;
+
+use crate::archive::ensure_not_archive;
+use crate::cli::WatchSubcommandArgs;
+use crate::git::operations::{ensure_clean_tree, get_repo_dir, open_or_clone_repo};
+use crate::git::remote::{get_host_git_url, local_repository_path};
+use crate::structs::{find_manifest, AggregatedDotfileMetadata, Manifest, RepositoryOrigin};
+use crate::utils::{set_non_interactive, set_wait_for_lock, RunLock};
+
+/// How often to check whether pending changes have settled or the watch has been stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+pub fn watch_subcommand_handler(args: WatchSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    // Nobody is around to answer prompts while watching, and changes are synced once other jtd
+    // runs have finished
+    set_non_interactive(true);
+    set_wait_for_lock(true);
+
+    let url = get_host_git_url(&args.repository, &args.source, &args.method)?;
+    ensure_not_archive(&url, "synced")?;
+    let target_dir = tempdir()?;
+    let repo = open_or_clone_repo(
+        &args.repository,
+        &url,
+        target_dir.path(),
+        args.branch.as_deref(),
+        None,
+        !args.no_cache,
+    )?;
+
+    if local_repository_path(&args.repository).is_some() {
+        ensure_clean_tree(&repo, "watching")?;
+    }
+
+    let repo_dir = get_repo_dir(&repo);
+    let mut origin = RepositoryOrigin::new(&args.repository, &url, &args.manifest)
+        .with_subdir(args.subdir.as_deref());
+    let manifest_dir = origin.manifest_dir(repo_dir);
+    origin.manifest = find_manifest(&manifest_dir, &origin.manifest)?;
+    let mut manifest = Manifest::get(&manifest_dir.join(&origin.manifest))?;
+
+    let aggregated_metadata = AggregatedDotfileMetadata::get()?
+        .ok_or("Could not find any metadata on the currently installed dotfiles")?;
+    let mut watched_targets: HashMap<PathBuf, String> = HashMap::new();
+    for dotfile_name in aggregated_metadata.installed_dotfiles(&origin.repository) {
+        if let Some(dotfile) = manifest.get_dotfile(dotfile_name) {
+            watched_targets.insert(watch_key(&dotfile.target_path()), dotfile_name.to_string());
+        }
+    }
+    if watched_targets.is_empty() {
+        return Err("None of the dotfiles in the manifest are installed".into());
+    }
+    manifest.set_origin(origin);
+    manifest.load_trust(false)?;
+
+    // Watch the directories containing the targets rather than the targets themselves, as many
+    // editors save by replacing the file
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let watched_dirs: BTreeSet<&Path> = watched_targets
+        .keys()
+        .filter_map(|target| target.parent())
+        .collect();
+    for dir in watched_dirs {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|err| format!("Could not watch {}: {}", dir.to_string_lossy(), err))?;
+    }
+
+    let stopped = Arc::new(AtomicBool::new(false));
+    let handler_stopped = stopped.clone();
+    ctrlc::set_handler(move || handler_stopped.store(true, Ordering::Relaxed))?;
+
+    let mut dotfile_names: Vec<&String> = watched_targets.values().collect();
+    dotfile_names.sort();
+    info!(
+        "Watching {} for changes, press Ctrl-C to stop",
+        dotfile_names
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    );
+
+    let settle_time = Duration::from_secs(args.settle_secs);
+    let push_interval = Duration::from_secs(args.push_interval_secs);
+    let mut pending: BTreeSet<String> = BTreeSet::new();
+    let mut last_change = Instant::now();
+    let mut last_push = Instant::now();
+    let mut unpushed = false;
+
+    while !stopped.load(Ordering::Relaxed) {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                for path in event.paths {
+                    if let Some(dotfile_name) = watched_targets.get(&watch_key(&path)) {
+                        pending.insert(dotfile_name.to_string());
+                        last_change = Instant::now();
+                    }
+                }
+            }
+            Ok(Err(err)) => {
+                warn!("Error while watching dotfiles: {}", err);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        if !pending.is_empty() && last_change.elapsed() >= settle_time {
+            let target_dotfiles: Vec<String> = pending.iter().cloned().collect();
+            pending.clear();
+            let lock = RunLock::acquire()?;
+            match manifest.sync(
+                &repo,
+                false,
+                target_dotfiles,
+                None,
+                AggregatedDotfileMetadata::get()?,
+                false,
+                false,
+                (!args.no_push).then_some("origin"),
+            ) {
+                Ok(_) => unpushed |= args.no_push,
+                Err(err) => {
+                    error!("Failed to sync: {}", err);
+                }
+            }
+            drop(lock);
+        }
+
+        if unpushed && last_push.elapsed() >= push_interval {
+            push_changes(&manifest, &repo, &mut unpushed);
+            last_push = Instant::now();
+        }
+    }
+
+    if unpushed {
+        info!("Pushing committed changes before stopping");
+        push_changes(&manifest, &repo, &mut unpushed);
+    }
+    Ok(())
+}
+
+fn push_changes(manifest: &Manifest, repo: &git2::Repository, unpushed: &mut bool) {
+    let _lock = match RunLock::acquire() {
+        Ok(lock) => lock,
+        Err(err) => {
+            error!("Failed to push changes: {}", err);
+            return;
+        }
+    };
+    match manifest.push(repo) {
+        Ok(()) => {
+            *unpushed = false;
+            success!("Pushed committed changes");
+        }
+        Err(err) => {
+            error!("Failed to push changes: {}", err);
+        }
+    }
+}
+
+/// Return the path to compare file events against `path` with. The parent directory is
+/// canonicalized so that targets behind symlinked directories still match
+fn watch_key(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => parent
+            .canonicalize()
+            .unwrap_or_else(|_| parent.to_path_buf())
+            .join(file_name),
+        _ => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,91 @@
+use std::error::Error;
+
+use git2::Repository;
+use tempfile::tempdir;
+
+use crate::cli::WhichSubcommandArgs;
+use crate::git::operations::{get_repo_dir, open_or_clone_repo};
+use crate::log::set_logs_to_stderr;
+use crate::structs::{
+    find_manifest, AggregatedDotfileMetadata, Dotfile, Manifest, RepositoryOrigin,
+};
+use crate::utils::set_offline;
+
+pub fn which_subcommand_handler(args: WhichSubcommandArgs) -> Result<(), Box<dyn Error>> {
+    // Only the path goes to stdout, so that it can be used as in `vim $(jtd which kitty)`
+    set_logs_to_stderr(true);
+
+    let aggregated_metadata = AggregatedDotfileMetadata::get()?
+        .ok_or("No dotfiles have been installed on this machine")?;
+
+    if !args.source {
+        let repository = aggregated_metadata.locate_dotfile(&args.dotfile, args.from.as_deref())?;
+        let target_path = aggregated_metadata
+            .dotfile(&repository, &args.dotfile)
+            .and_then(|metadata| metadata.target_path.clone());
+        if let Some(target_path) = target_path {
+            println!("{}", target_path.to_string_lossy());
+            return Ok(());
+        }
+    }
+
+    // The dotfile was installed by a version of jtd that didn't record its target, or its source
+    // is wanted, so look it up in the manifest of the local or cached clone of its repository
+    set_offline(true);
+    let (repo, dotfile, _) =
+        open_installed_dotfile(&aggregated_metadata, &args.dotfile, args.from.as_deref())?;
+
+    if args.source {
+        println!(
+            "{}",
+            get_repo_dir(&repo).join(&dotfile.file).to_string_lossy()
+        );
+    } else {
+        println!("{}", dotfile.target_path().to_string_lossy());
+    }
+    Ok(())
+}
+
+/// Open the local or cached clone of the repository the installed dotfile `dotfile_name` is from,
+/// narrowed down by `from` if it is installed from several. Returns the repository, the dotfile as
+/// it is in the manifest there and where the repository is from
+pub(super) fn open_installed_dotfile(
+    aggregated_metadata: &AggregatedDotfileMetadata,
+    dotfile_name: &str,
+    from: Option<&str>,
+) -> Result<(Repository, Dotfile, RepositoryOrigin), Box<dyn Error>> {
+    let repository = aggregated_metadata.locate_dotfile(dotfile_name, from)?;
+    let origin = aggregated_metadata
+        .known_repositories()
+        .into_iter()
+        .find(|origin| origin.repository == repository)
+        .ok_or_else(|| {
+            format!(
+                "The repository {} was installed from is unknown",
+                dotfile_name
+            )
+        })?;
+
+    let target_dir = tempdir()?;
+    let repo = open_or_clone_repo(
+        &origin.repository,
+        &origin.repository,
+        target_dir.path(),
+        None,
+        None,
+        true,
+    )?;
+    let repo_dir = get_repo_dir(&repo);
+    let manifest_dir = origin.manifest_dir(repo_dir);
+    let mut manifest =
+        Manifest::get(&manifest_dir.join(find_manifest(&manifest_dir, &origin.manifest)?))?;
+    manifest.set_origin(origin.clone());
+    manifest.load_trust(false)?;
+    let dotfile = manifest.get_dotfile(dotfile_name).cloned().ok_or_else(|| {
+        format!(
+            "Dotfile {} is no longer in the manifest of {}",
+            dotfile_name, origin.repository
+        )
+    })?;
+    Ok((repo, dotfile, origin))
+}
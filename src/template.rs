@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::env;
+use std::env::consts::OS;
+use std::error::Error;
+
+use handlebars::{no_escape, Handlebars};
+
+use crate::structs::MachineVariables;
+
+/// Return the variables available to every templated dotfile:
+///
+/// * `hostname` - The hostname of the current machine
+/// * `username` - The name of the current user
+/// * `os` - The current OS as given by [std::env::consts::OS] (e.g. `linux` or `macos`)
+pub fn builtin_variables() -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let hostname = whoami::fallible::hostname()
+        .map_err(|err| format!("Could not determine hostname: {}", err))?;
+    let username = whoami::fallible::username()
+        .map_err(|err| format!("Could not determine username: {}", err))?;
+
+    Ok(HashMap::from([
+        ("hostname".to_string(), hostname),
+        ("username".to_string(), username),
+        ("os".to_string(), OS.to_string()),
+    ]))
+}
+
+/// Return the variables available to templated dotfiles on this machine: the
+/// [builtin_variables] along with any variables set in the machine-local vars file. Variables in
+/// the vars file take precedence over the builtin variables.
+pub fn template_variables() -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut variables = builtin_variables()?;
+    variables.extend(MachineVariables::get()?.data);
+    Ok(variables)
+}
+
+/// Render a handlebars template with the given variables.
+///
+/// Rendering is strict, so referencing a variable that does not exist is an error rather than
+/// silently producing an empty string. Output is not HTML-escaped.
+pub fn render_template(
+    template: &str,
+    variables: &HashMap<String, String>,
+) -> Result<String, Box<dyn Error>> {
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(true);
+    handlebars.register_escape_fn(no_escape);
+
+    handlebars
+        .render_template(template, variables)
+        .map_err(|err| format!("Could not render template: {}", err).into())
+}
+
+/// Replace every `${VAR}` placeholder in `contents` with the value of the environment variable
+/// `VAR`. It is an error for a placeholder to reference a variable that is not set. Text that only
+/// looks similar, such as `$VAR` or `${VAR:-default}`, is left as it is
+pub fn expand_env_vars(contents: &str) -> Result<String, Box<dyn Error>> {
+    expand_vars(contents, |name| env::var(name).ok())
+}
+
+/// Replace every `${VAR}` placeholder in `contents` as [expand_env_vars] does, looking up the value
+/// of `VAR` with `get_var`
+fn expand_vars(
+    contents: &str,
+    get_var: impl Fn(&str) -> Option<String>,
+) -> Result<String, Box<dyn Error>> {
+    let mut expanded = String::with_capacity(contents.len());
+    let mut rest = contents;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let name = after.find('}').map(|end| &after[..end]).filter(|name| {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        match name {
+            Some(name) => {
+                let value = get_var(name)
+                    .ok_or_else(|| format!("Environment variable {} is not set", name))?;
+                expanded.push_str(&value);
+                rest = &after[name.len() + 1..];
+            }
+            None => {
+                expanded.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_variables() {
+        let variables = builtin_variables().unwrap();
+
+        assert_eq!(variables["os"], OS);
+        assert!(variables.contains_key("hostname"));
+        assert!(variables.contains_key("username"));
+    }
+
+    #[test]
+    fn test_render_template() {
+        let variables = HashMap::from([
+            ("hostname".to_string(), "laptop".to_string()),
+            ("os".to_string(), "linux".to_string()),
+        ]);
+
+        let rendered = render_template(
+            "font_size {{#if (eq hostname \"laptop\")}}10{{else}}12{{/if}}\n# <{{os}}>",
+            &variables,
+        )
+        .unwrap();
+
+        assert_eq!(rendered, "font_size 10\n# <linux>");
+    }
+
+    #[test]
+    fn test_expand_env_vars() {
+        let get_var = |name: &str| (name == "EMAIL").then(|| "dob9601@example.com".to_string());
+
+        let expanded = expand_vars(
+            "email = ${EMAIL}\nname = ${NAME:-me} $HOME ${}\nend = ${EMAIL}",
+            get_var,
+        )
+        .unwrap();
+
+        assert_eq!(
+            expanded,
+            "email = dob9601@example.com\nname = ${NAME:-me} $HOME ${}\nend = dob9601@example.com"
+        );
+        assert!(expand_vars("${JTD_TEST_UNSET_VARIABLE}", get_var).is_err());
+    }
+
+    #[test]
+    fn test_render_template_unknown_variable() {
+        let result = render_template("{{ hostnme }}", &HashMap::new());
+
+        assert!(result.is_err());
+    }
+}
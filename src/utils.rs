@@ -1,15 +1,34 @@
 use std::{
+    collections::HashMap,
+    env,
     error::Error,
+    ffi::OsStr,
+    fs::{self, File, OpenOptions, TryLockError},
     io::{self, Write},
-    process::Command,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use console::style;
+use console::{style, Term};
 use dialoguer::{
     console::Style,
     theme::{ColorfulTheme, Theme},
 };
-use sha1::{Digest, Sha1};
+use lazy_static::lazy_static;
+use serde_json::json;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::log::{log_operation, output_format, print_log, OutputFormat};
+use crate::{
+    BACKUPS_DIR, DEFAULT_DATA_DIR, MANIFEST_FILE, OPERATION_LOG_FILE, RUN_LOCK_FILE, TRUST_FILE,
+};
 
 pub const SPINNER_FRAMES: &[&str] = &[
     "⢀⠀", "⡀⠀", "⠄⠀", "⢂⠀", "⡂⠀", "⠅⠀", "⢃⠀", "⡃⠀", "⠍⠀", "⢋⠀", "⡋⠀", "⠍⠁", "⢋⠁", "⡋⠁", "⠍⠉", "⠋⠉",
@@ -19,23 +38,829 @@ pub const SPINNER_FRAMES: &[&str] = &[
 ];
 pub const SPINNER_RATE: u64 = 48;
 
-pub fn run_command_vec(command_vec: &[String]) -> Result<(), Box<dyn Error>> {
+/// A single line of progress output on stderr, prefixed with a spinner. Nothing is drawn if stderr
+/// isn't a terminal, and the line is cleared once the `ProgressLine` is dropped.
+pub(crate) struct ProgressLine {
+    term: Term,
+    started: Instant,
+    last_frame: Option<u128>,
+}
+
+impl ProgressLine {
+    pub(crate) fn new() -> Self {
+        ProgressLine {
+            term: Term::stderr(),
+            started: Instant::now(),
+            last_frame: None,
+        }
+    }
+
+    /// Redraw the line with `message`. The line is only redrawn once per spinner frame to avoid
+    /// flooding the terminal when progress is reported very frequently.
+    pub(crate) fn update(&mut self, message: &str) {
+        if !self.term.is_term() {
+            return;
+        }
+        let frame = self.started.elapsed().as_millis() / SPINNER_RATE as u128;
+        if self.last_frame == Some(frame) {
+            return;
+        }
+        self.last_frame = Some(frame);
+
+        let spinner = SPINNER_FRAMES[frame as usize % SPINNER_FRAMES.len()];
+        let _ = self.term.clear_line();
+        let _ = self
+            .term
+            .write_str(&format!("{} {}", style(spinner).cyan(), message));
+    }
+}
+
+impl Drop for ProgressLine {
+    fn drop(&mut self) {
+        if self.last_frame.is_some() {
+            let _ = self.term.clear_line();
+        }
+    }
+}
+
+pub const NON_INTERACTIVE_ENV_VAR: &str = "JTD_NONINTERACTIVE";
+
+/// The shell install steps are run with if none is configured
+pub const DEFAULT_SHELL: &str = "sh";
+
+/// The length of a SHA-1 hash as hex, which tells the hashes older versions of jtd recorded apart
+const SHA1_HEX_LEN: usize = 40;
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Set whether jtd should avoid prompting the user for input
+pub fn set_non_interactive(non_interactive: bool) {
+    NON_INTERACTIVE.store(non_interactive, Ordering::Relaxed);
+}
+
+/// Return whether jtd is running non-interactively, either because `--yes` was passed or because
+/// the `JTD_NONINTERACTIVE` environment variable is set.
+///
+/// When running non-interactively, confirmation prompts are assumed to have been answered with
+/// "yes" and any prompt that requires actual input fails instead.
+pub fn is_non_interactive() -> bool {
+    NON_INTERACTIVE.load(Ordering::Relaxed)
+        || env::var(NON_INTERACTIVE_ENV_VAR)
+            .map(|value| !matches!(value.as_str(), "" | "0" | "false"))
+            .unwrap_or(false)
+}
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Set whether jtd should avoid accessing the network
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+/// Return whether jtd is running offline (`--offline` was passed), in which case the cached clone
+/// of the repository is used as-is and nothing is fetched from the remote.
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Return the agent every HTTP request is sent with, identifying as jtd and going through the
+/// proxy in `--proxy` or the environment, if any
+pub(crate) fn http_agent() -> Result<ureq::Agent, Box<dyn Error>> {
+    let agent = match get_proxy() {
+        Some(proxy) => ureq::AgentBuilder::new().proxy(ureq::Proxy::new(proxy)?),
+        None => ureq::AgentBuilder::new().try_proxy_from_env(true),
+    };
+    Ok(agent
+        .user_agent(concat!("jointhedots/", env!("CARGO_PKG_VERSION")))
+        .build())
+}
+
+/// Send a GET request to `url` with the [http_agent]
+pub(crate) fn http_get(url: &str) -> Result<ureq::Response, Box<dyn Error>> {
+    http_agent()?
+        .get(url)
+        .call()
+        .map_err(|err| format!("Could not fetch {}: {}", url, err).into())
+}
+
+pub const DATA_DIR_ENV_VAR: &str = "JTD_DATA_DIR";
+
+lazy_static! {
+    static ref DATA_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+/// Set the directory jtd stores its data in (`--data-dir`), overriding [DATA_DIR_ENV_VAR]
+pub fn set_data_dir(data_dir: Option<PathBuf>) {
+    *DATA_DIR.write().unwrap() = data_dir;
+}
+
+/// Return the directory jtd stores its data in: the metadata of the installed dotfiles, the
+/// backups of overwritten files and the operation log. This is the directory set with
+/// `--data-dir` or [DATA_DIR_ENV_VAR], or `jointhedots` in `$XDG_DATA_HOME` (`~/.local/share` by
+/// default)
+pub fn data_dir() -> PathBuf {
+    resolve_data_dir(
+        DATA_DIR.read().unwrap().clone(),
+        env::var_os(DATA_DIR_ENV_VAR).map(PathBuf::from),
+        env::var_os("XDG_DATA_HOME").map(PathBuf::from),
+    )
+}
+
+fn resolve_data_dir(
+    data_dir: Option<PathBuf>,
+    env_data_dir: Option<PathBuf>,
+    xdg_data_home: Option<PathBuf>,
+) -> PathBuf {
+    let data_dir = data_dir
+        .or(env_data_dir.filter(|path| !path.as_os_str().is_empty()))
+        // Relative paths in XDG_DATA_HOME are invalid according to the XDG spec, so are ignored
+        .or_else(|| {
+            xdg_data_home
+                .filter(|path| path.is_absolute())
+                .map(|path| path.join("jointhedots"))
+        })
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_DIR));
+    PathBuf::from(shellexpand::tilde(&data_dir.to_string_lossy()).as_ref())
+}
+
+/// Return the path of the metadata of the installed dotfiles
+pub(crate) fn metadata_path() -> PathBuf {
+    data_dir().join(MANIFEST_FILE)
+}
+
+/// Return the path of the trust store, see [crate::structs::TrustStore]
+pub(crate) fn trust_path() -> PathBuf {
+    data_dir().join(TRUST_FILE)
+}
+
+/// Return the directory the backups of overwritten files are stored in
+pub(crate) fn backups_dir() -> PathBuf {
+    data_dir().join(BACKUPS_DIR)
+}
+
+/// Return the path of the operation log
+pub(crate) fn operation_log_path() -> PathBuf {
+    data_dir().join(OPERATION_LOG_FILE)
+}
+
+pub const SSH_KEY_ENV_VAR: &str = "JTD_SSH_KEY";
+
+lazy_static! {
+    static ref SSH_KEY: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+/// Set the SSH key to authenticate with (`--ssh-key`), overriding [SSH_KEY_ENV_VAR]
+pub fn set_ssh_key(ssh_key: Option<PathBuf>) {
+    *SSH_KEY.write().unwrap() = ssh_key;
+}
+
+/// Return the SSH key to authenticate with, if one was chosen with `--ssh-key` or
+/// [SSH_KEY_ENV_VAR]. Otherwise, the keys in ssh-agent and `~/.ssh` are tried
+pub fn get_ssh_key() -> Option<PathBuf> {
+    let ssh_key = SSH_KEY.read().unwrap().clone().or_else(|| {
+        env::var_os(SSH_KEY_ENV_VAR)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    })?;
+    Some(PathBuf::from(
+        shellexpand::tilde(&ssh_key.to_string_lossy()).as_ref(),
+    ))
+}
+
+lazy_static! {
+    static ref PROXY: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// Set the proxy to fetch, push and download through (`--proxy`), overriding the environment
+pub fn set_proxy(proxy: Option<String>) {
+    *PROXY.write().unwrap() = proxy;
+}
+
+/// Return the proxy to fetch, push and download through if one was chosen explicitly, with
+/// `--proxy` or with `all_proxy`. Otherwise the proxies in `https_proxy` and `http_proxy` (along
+/// with `no_proxy` and the `http.proxy` git config) are picked up by libgit2 itself
+pub fn get_proxy() -> Option<String> {
+    resolve_proxy(PROXY.read().unwrap().clone(), |name| {
+        env::var(name).ok().filter(|value| !value.is_empty())
+    })
+}
+
+fn resolve_proxy(
+    proxy: Option<String>,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    let any_set = |names: &[&str]| names.iter().find_map(|name| env_var(name));
+    proxy.or_else(|| {
+        // all_proxy is only a fallback for the more specific variables
+        match any_set(&["https_proxy", "HTTPS_PROXY", "http_proxy", "HTTP_PROXY"]) {
+            Some(_) => None,
+            None => any_set(&["all_proxy", "ALL_PROXY"]),
+        }
+    })
+}
+
+static WAIT_FOR_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Set whether to wait for other jtd runs to finish (`--wait`) rather than failing when one of
+/// them holds the [RunLock]
+pub fn set_wait_for_lock(wait: bool) {
+    WAIT_FOR_LOCK.store(wait, Ordering::Relaxed);
+}
+
+/// An exclusive lock on the data directory, held by the jtd run that is changing the installed
+/// dotfiles so that e.g. `jtd watch`, a scheduled `jtd sync` and a manual `jtd install` don't
+/// overwrite each other's metadata. Released when dropped, including when jtd exits or crashes
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Take the lock on the data directory. If another run holds it, this waits for it to be
+    /// released if `--wait` was passed, and fails otherwise
+    pub fn acquire() -> Result<RunLock, Box<dyn Error>> {
+        let data_dir = data_dir();
+        fs::create_dir_all(&data_dir)?;
+        RunLock::acquire_path(
+            &data_dir.join(RUN_LOCK_FILE),
+            WAIT_FOR_LOCK.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Take the lock on the lock file at `path`, waiting for it if `wait` is set
+    pub fn acquire_path(path: &Path, wait: bool) -> Result<RunLock, Box<dyn Error>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|err| format!("Could not open {}: {}", path.to_string_lossy(), err))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = fs::read_to_string(path).unwrap_or_default();
+                let holder = match holder.trim() {
+                    "" => "Another jtd run".to_string(),
+                    holder => format!("Another jtd run ({})", holder),
+                };
+                if !wait {
+                    return Err(format!(
+                        "{} is changing your dotfiles. Try again once it has finished, or pass \
+                        --wait to wait for it",
+                        holder
+                    )
+                    .into());
+                }
+                info!(
+                    "{} is changing your dotfiles, waiting for it to finish",
+                    holder
+                );
+                file.lock()?;
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(format!("Could not lock {}: {}", path.to_string_lossy(), err).into())
+            }
+        }
+
+        // Describe this run to whoever has to wait for it
+        file.set_len(0)?;
+        write!(
+            file,
+            "pid {}, {}",
+            std::process::id(),
+            env::args().collect::<Vec<String>>().join(" ")
+        )?;
+        trace!("Took the lock at {}", path.to_string_lossy());
+        Ok(RunLock { _file: file })
+    }
+}
+
+static DEBUG_GIT: AtomicBool = AtomicBool::new(false);
+
+/// Set whether to print what libgit2 is doing (`--debug-git`): the refs fetched, credentials
+/// tried, certificates checked and the updates negotiated with the remote when pushing
+pub fn set_debug_git(debug_git: bool) {
+    DEBUG_GIT.store(debug_git, Ordering::Relaxed);
+    if debug_git {
+        // Only has an effect if libgit2 was built with tracing support
+        git2::trace_set(git2::TraceLevel::Trace, |level, message| {
+            print_git_debug(&format!("[{:?}] {}", level, message))
+        });
+    }
+}
+
+pub fn is_debug_git() -> bool {
+    DEBUG_GIT.load(Ordering::Relaxed)
+}
+
+/// Print a line of `--debug-git` output. It is written to stderr regardless of the verbosity so
+/// that it can be captured separately from the rest of the output
+pub fn print_git_debug(message: &str) {
+    eprintln!("{} {}", style("git:").magenta(), message.trim_end());
+}
+
+/// Return an error if jtd is running non-interactively, explaining that `reason` requires input.
+pub(crate) fn ensure_interactive(reason: &str) -> Result<(), Box<dyn Error>> {
+    check_interactive(is_non_interactive(), reason)
+}
+
+/// Return an error if `non_interactive` is set, explaining that `reason` requires input
+fn check_interactive(non_interactive: bool, reason: &str) -> Result<(), Box<dyn Error>> {
+    if non_interactive {
+        Err(format!(
+            "{}, but jtd is running non-interactively (--yes or {} is set)",
+            reason, NON_INTERACTIVE_ENV_VAR
+        )
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Run each command in `command_vec` with `<shell> -c`, so that quoting, pipes, redirections etc.
+/// work as they would in a script. A leading `~` in any word of a command is expanded first. The
+/// variables in `env` are added to the environment of each command.
+///
+/// An error naming the failed step is returned as soon as a command exits with a non-zero status.
+/// If `timeout` is given, any command still running after it has elapsed is killed along with any
+/// processes it started and an error is returned.
+pub fn run_command_vec(
+    command_vec: &[String],
+    shell: &str,
+    env: &HashMap<String, String>,
+    timeout: Option<Duration>,
+) -> Result<(), Box<dyn Error>> {
+    trace!("Running steps with {} and environment {:?}", shell, env);
     for (stage, command) in command_vec.iter().enumerate() {
-        println!("{} {}", style(format!("Step #{}:", stage)).cyan(), command);
+        print_log(format!(
+            "{} {}",
+            style(format!("Step #{}:", stage)).cyan(),
+            command
+        ));
         io::stdout().flush()?;
 
-        let command_vec: Vec<String> = command
-            .split(' ')
-            .map(|component| shellexpand::tilde(component).to_string())
-            .collect();
-        Command::new(command_vec[0].as_str())
-            .args(&command_vec[1..])
-            .spawn()?
-            .wait_with_output()?;
+        let mut shell_command = Command::new(shell);
+        shell_command
+            .arg("-c")
+            .arg(expand_command_tilde(command))
+            .envs(env);
+        if output_format() == OutputFormat::Json {
+            // Keep stdout free for the JSON document
+            shell_command.stdout(Stdio::from(io::stderr()));
+        }
+        #[cfg(unix)]
+        if timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+            // Lead a process group of its own, so that the whole group can be killed on timeout
+            // rather than only the shell
+            shell_command.process_group(0);
+        }
+        let mut child = shell_command
+            .spawn()
+            .map_err(|err| format!("Could not run {}: {}", shell, err))?;
+
+        let status = if let Some(timeout) = timeout {
+            let started = Instant::now();
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if started.elapsed() >= timeout {
+                    kill_process_group(&mut child)?;
+                    child.wait()?;
+                    return Err(format!(
+                        "Step #{} \"{}\" timed out after {} seconds",
+                        stage,
+                        command,
+                        timeout.as_secs()
+                    )
+                    .into());
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        } else {
+            child.wait()?
+        };
+        log_operation(
+            "ran step",
+            json!({ "command": command, "exit_code": status.code() }),
+        );
+
+        if !status.success() {
+            return Err(match status.code() {
+                Some(code) => format!(
+                    "Step #{} \"{}\" failed with exit code {}",
+                    stage, command, code
+                ),
+                None => format!("Step #{} \"{}\" was killed by a signal", stage, command),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Kill `child`, which leads a process group of its own, along with every other process in its
+/// group (e.g. an installer started by a shell)
+fn kill_process_group(child: &mut Child) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        // Safe as killpg has no memory safety requirements. The group id is the pid of its leader
+        if unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } == 0 {
+            return Ok(());
+        }
+    }
+    child.kill()
+}
+
+/// Return whether every command in `command_vec` exits successfully when run with `<shell> -c`.
+/// Unlike [run_command_vec], the commands are not printed and their output is discarded, so this
+/// is suitable for checks such as `command -v sway`. Stops at the first command that fails.
+pub fn command_vec_succeeds(
+    command_vec: &[String],
+    shell: &str,
+    env: &HashMap<String, String>,
+) -> Result<bool, Box<dyn Error>> {
+    for command in command_vec {
+        let status = Command::new(shell)
+            .arg("-c")
+            .arg(expand_command_tilde(command))
+            .envs(env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|err| format!("Could not run {}: {}", shell, err))?;
+        if !status.success() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Pipe `input` through each command in `filters` in turn with `<shell> -c`, as in a shell
+/// pipeline, returning the output of the last one. The variables in `env` are added to the
+/// environment of each command. An error naming the filter is returned if one exits with a
+/// non-zero status
+pub(crate) fn run_filters(
+    filters: &[String],
+    shell: &str,
+    env: &HashMap<String, String>,
+    input: Vec<u8>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut contents = input;
+    for filter in filters {
+        trace!("Filtering through {}", filter);
+        let mut child = Command::new(shell)
+            .arg("-c")
+            .arg(expand_command_tilde(filter))
+            .envs(env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("Could not run {}: {}", shell, err))?;
+
+        // Write from another thread, so that a filter producing output before it has read all of
+        // its input can't deadlock on a full pipe
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = thread::spawn(move || stdin.write_all(&contents));
+        let output = child.wait_with_output()?;
+        // A filter that exits without reading all of its input closes the pipe, which is fine
+        let _ = writer.join();
+
+        if !output.status.success() {
+            return Err(match output.status.code() {
+                Some(code) => format!("Filter \"{}\" failed with exit code {}", filter, code),
+                None => format!("Filter \"{}\" was killed by a signal", filter),
+            }
+            .into());
+        }
+        contents = output.stdout;
+    }
+    Ok(contents)
+}
+
+/// The environment variable that overrides the command `requires_root` dotfiles are written
+/// with, e.g. `doas` or `pkexec`
+pub const ROOT_COMMAND_ENV_VAR: &str = "JTD_ROOT_COMMAND";
+
+/// The command `requires_root` dotfiles are written with if [ROOT_COMMAND_ENV_VAR] is unset
+pub const DEFAULT_ROOT_COMMAND: &str = "sudo";
+
+/// Return the command `requires_root` dotfiles are written with, from [ROOT_COMMAND_ENV_VAR] or
+/// [DEFAULT_ROOT_COMMAND]
+pub(crate) fn root_command() -> String {
+    env::var(ROOT_COMMAND_ENV_VAR)
+        .ok()
+        .filter(|root_command| !root_command.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_ROOT_COMMAND.to_string())
+}
+
+/// Return `path` as an absolute path, with any symlinks and `..` in its parent directory resolved
+/// as far as it exists, to show where a file is really written to
+pub(crate) fn resolved_path(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => match parent.canonicalize() {
+            Ok(parent) => parent.join(file_name),
+            Err(_) => resolved_path(parent).join(file_name),
+        },
+        _ => path,
+    }
+}
+
+/// Run `args` as root by prefixing them with `root_command` (e.g. `sudo`), writing `stdin` to the
+/// command if given. Only the single command is escalated rather than jtd itself
+fn run_as_root(
+    root_command: &str,
+    args: &[&OsStr],
+    stdin: Option<&[u8]>,
+) -> Result<(), Box<dyn Error>> {
+    let mut root_args = root_command.split_whitespace();
+    let program = root_args.next().ok_or("The root command is empty")?;
+
+    let mut command = Command::new(program);
+    command.args(root_args);
+    if program == "sudo" && is_non_interactive() {
+        // Fail instead of waiting for a password nobody will type
+        command.arg("-n");
+    }
+    command.args(args).stdout(Stdio::null());
+    if stdin.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    trace!("Running {:?} as root", args);
+
+    let mut child = command
+        .spawn()
+        .map_err(|err| format!("Could not run {}: {}", program, err))?;
+    if let Some(stdin) = stdin {
+        child.stdin.take().unwrap().write_all(stdin)?;
+    }
+    if !child.wait()?.success() {
+        return Err(format!(
+            "\"{} {}\" failed",
+            root_command,
+            args.iter()
+                .map(|arg| arg.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+        .into());
     }
     Ok(())
 }
 
+/// Write `contents` to `path` as root with `root_command`, creating its parent directories. Like
+/// [write_atomically], they are written to a temporary file only root can read, which is given
+/// `mode` (or else the permissions of the file at `path`) and then moved over `path`
+pub(crate) fn write_as_root(
+    root_command: &str,
+    path: &Path,
+    contents: &[u8],
+    mode: Option<u32>,
+) -> Result<(), Box<dyn Error>> {
+    let (path, temp_path) = atomic_write_paths(path)?;
+    let mode = mode
+        .or_else(|| {
+            fs::metadata(&path)
+                .ok()
+                .map(|metadata| permissions_mode(&metadata.permissions()))
+        })
+        .unwrap_or(0o644);
+    if let Some(parent) = path.parent() {
+        run_as_root(
+            root_command,
+            &["mkdir".as_ref(), "-p".as_ref(), parent.as_os_str()],
+            None,
+        )?;
+    }
+
+    let mode = format!("{:o}", mode);
+    let result = run_as_root(
+        root_command,
+        &[
+            "sh".as_ref(),
+            "-c".as_ref(),
+            "umask 077 && rm -f \"$0\" && cat > \"$0\"".as_ref(),
+            temp_path.as_os_str(),
+        ],
+        Some(contents),
+    )
+    .and_then(|_| {
+        run_as_root(
+            root_command,
+            &["chmod".as_ref(), mode.as_ref(), temp_path.as_os_str()],
+            None,
+        )
+    })
+    .and_then(|_| {
+        run_as_root(
+            root_command,
+            &[
+                "mv".as_ref(),
+                "-f".as_ref(),
+                temp_path.as_os_str(),
+                path.as_os_str(),
+            ],
+            None,
+        )
+    });
+    if result.is_err() {
+        let _ = remove_as_root(root_command, &temp_path);
+    }
+    result
+}
+
+#[cfg(unix)]
+fn permissions_mode(permissions: &fs::Permissions) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions_mode(permissions: &fs::Permissions) -> u32 {
+    if permissions.readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+/// Return the path a write to `path` really goes to, following it if it is a symlink so that the
+/// link is kept, along with the temporary file next to it that is renamed over it
+fn atomic_write_paths(path: &Path) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+        }
+        _ => path.to_path_buf(),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file", path.to_string_lossy()))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.jtd-{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    Ok((path, temp_path))
+}
+
+/// Write `contents` to the file at `path` atomically, by writing them to a temporary file in the
+/// same directory and renaming it over `path`, so that a crash part way through never leaves a
+/// truncated file behind. The file is given `permissions` if passed, in which case only the owner
+/// can read the temporary file until then. If `path` is a symlink, the file it links to is
+/// replaced instead, keeping the link
+pub(crate) fn write_atomically(
+    path: &Path,
+    contents: &[u8],
+    permissions: Option<fs::Permissions>,
+) -> Result<(), Box<dyn Error>> {
+    let (path, temp_path) = atomic_write_paths(path)?;
+
+    let result = (|| -> io::Result<()> {
+        // A temporary file left behind by a crash may have other permissions, so it is replaced
+        let _ = fs::remove_file(&temp_path);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        if permissions.is_some() {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&temp_path)?;
+        file.write_all(contents)?;
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp_path, &path)
+    })();
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(format!("Could not write {}: {}", path.to_string_lossy(), err).into());
+    }
+    Ok(())
+}
+
+/// Copy the file at `from` to `to` as root with `root_command`, keeping its permissions
+pub(crate) fn copy_as_root(
+    root_command: &str,
+    from: &Path,
+    to: &Path,
+) -> Result<(), Box<dyn Error>> {
+    run_as_root(
+        root_command,
+        &[
+            "cp".as_ref(),
+            "-p".as_ref(),
+            from.as_os_str(),
+            to.as_os_str(),
+        ],
+        None,
+    )
+}
+
+/// Remove the file at `path` as root with `root_command`
+pub(crate) fn remove_as_root(root_command: &str, path: &Path) -> Result<(), Box<dyn Error>> {
+    run_as_root(
+        root_command,
+        &["rm".as_ref(), "-f".as_ref(), path.as_os_str()],
+        None,
+    )
+}
+
+/// Return whether `contents` should be treated as binary rather than text, i.e. it isn't valid
+/// UTF-8 or has a NUL byte in its first 8000 bytes, as git checks
+pub(crate) fn is_binary(contents: &[u8]) -> bool {
+    contents.iter().take(8000).any(|byte| *byte == 0) || std::str::from_utf8(contents).is_err()
+}
+
+/// Format the UNIX `timestamp` as a `YYYY-MM-DD` date
+pub(crate) fn format_date(timestamp: i64) -> String {
+    // Convert days since the epoch to a date in the proleptic Gregorian calendar, counting years
+    // from March so that leap days fall at the end of the year
+    let days = timestamp.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Expand a leading `~` in each space-separated word of `command`
+fn expand_command_tilde(command: &str) -> String {
+    command
+        .split(' ')
+        .map(|component| shellexpand::tilde(component).to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Print `output` to stdout, piping it through `$PAGER` (or `less -R` if unset) when stdout is a
+/// terminal and the output is taller than it. Falls back to printing directly if the pager can't
+/// be started.
+pub fn print_paged(output: &str) -> Result<(), Box<dyn Error>> {
+    let term = Term::stdout();
+    let (height, _) = term.size();
+    if !term.is_term() || output.lines().count() < height as usize {
+        print!("{}", output);
+        return Ok(());
+    }
+
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| "less -R".to_string());
+    let mut child = match Command::new(DEFAULT_SHELL)
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => {
+            print!("{}", output);
+            return Ok(());
+        }
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may exit before reading everything (e.g. when quit early), so ignore broken
+        // pipes
+        if let Err(err) = stdin.write_all(output.as_bytes()) {
+            if err.kind() != io::ErrorKind::BrokenPipe {
+                return Err(err.into());
+            }
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Print the steps in a command vector without running them
+pub fn print_command_vec(command_vec: &[String]) {
+    for (stage, command) in command_vec.iter().enumerate() {
+        print_log(format!(
+            "{} {}",
+            style(format!("Step #{}:", stage)).cyan(),
+            command
+        ));
+    }
+}
+
 #[cfg(not(tarpaulin_include))]
 pub(crate) fn get_theme() -> impl Theme {
     ColorfulTheme {
@@ -44,28 +869,362 @@ pub(crate) fn get_theme() -> impl Theme {
     }
 }
 
+/// Return the SHA-256 hash of `command_vec` as hex, used to tell whether install steps have changed
+/// since they were run. Each command is prefixed with its length, so that e.g. `["ab"]` and
+/// `["a", "b"]` hash differently
 pub(crate) fn hash_command_vec(command_vec: &[String]) -> String {
-    let mut hasher = Sha1::new();
-    let bytes: Vec<u8> = command_vec.iter().map(|s| s.bytes()).flatten().collect();
+    let mut hasher = Sha256::new();
+    for command in command_vec {
+        hasher.update((command.len() as u64).to_le_bytes());
+        hasher.update(command.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
 
-    hasher.update(bytes);
-    hex::encode(&hasher.finalize()[..])
+/// Return whether `hash` is the hash of `command_vec` as given by [hash_command_vec], or the SHA-1
+/// hash that versions of jtd before the move to SHA-256 recorded, so that steps recorded by them
+/// aren't run again
+pub(crate) fn command_vec_matches_hash(command_vec: &[String], hash: &str) -> bool {
+    if hash.len() == SHA1_HEX_LEN {
+        let bytes: Vec<u8> = command_vec.iter().flat_map(|s| s.bytes()).collect();
+        hash == hex::encode(Sha1::digest(bytes))
+    } else {
+        hash == hash_command_vec(command_vec)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::Path;
+    use std::fs;
+
+    use tempfile::tempdir;
 
     use super::*;
 
+    #[test]
+    fn test_write_atomically() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, "old").unwrap();
+
+        write_atomically(&path, b"new", None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        // Only the written file is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link");
+            std::os::unix::fs::symlink(&path, &link).unwrap();
+            write_atomically(&link, b"linked", None).unwrap();
+            assert!(fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert_eq!(fs::read_to_string(&path).unwrap(), "linked");
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            // A temporary file left behind by an earlier crash is replaced rather than reused
+            let secret = dir.path().join("secret");
+            let (_, temp_path) = atomic_write_paths(&secret).unwrap();
+            fs::write(&temp_path, "stale").unwrap();
+            fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o644)).unwrap();
+            write_atomically(&secret, b"token", Some(fs::Permissions::from_mode(0o600))).unwrap();
+            assert_eq!(fs::read_to_string(&secret).unwrap(), "token");
+            assert_eq!(
+                fs::metadata(&secret).unwrap().permissions().mode() & 0o7777,
+                0o600
+            );
+            assert!(!temp_path.exists());
+        }
+
+        let missing_dir = dir.path().join("missing").join("config");
+        assert!(write_atomically(&missing_dir, b"new", None).is_err());
+    }
+
+    #[test]
+    fn test_run_lock() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(RUN_LOCK_FILE);
+
+        let lock = RunLock::acquire_path(&path, false).unwrap();
+        let err = RunLock::acquire_path(&path, false).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with(&format!("Another jtd run (pid {},", std::process::id())));
+
+        drop(lock);
+        assert!(RunLock::acquire_path(&path, false).is_ok());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_as_root() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // `env` runs the command unchanged, standing in for sudo
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keyd").join("default.conf");
+
+        write_as_root("env", &path, b"[ids]\n*\n", Some(0o640)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[ids]\n*\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o7777,
+            0o640
+        );
+
+        // Without a mode, the permissions of the file being replaced are kept
+        write_as_root("env", &path, b"[ids]\nk\n", None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[ids]\nk\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o7777,
+            0o640
+        );
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        remove_as_root("env", &path).unwrap();
+        assert!(!path.exists());
+
+        assert!(write_as_root("false", &path, b"", None).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_resolved_path() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("etc")).unwrap();
+        std::os::unix::fs::symlink(root.join("etc"), root.join("config")).unwrap();
+
+        assert_eq!(
+            resolved_path(
+                &root
+                    .join("config")
+                    .join("..")
+                    .join("config")
+                    .join("keyd.conf")
+            ),
+            root.join("etc").join("keyd.conf")
+        );
+        assert_eq!(
+            resolved_path(&root.join("config").join("keyd").join("default.conf")),
+            root.join("etc").join("keyd").join("default.conf")
+        );
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951782400), "2000-02-29");
+        assert_eq!(format_date(1792195199), "2026-10-16");
+        assert_eq!(format_date(-86400), "1969-12-31");
+    }
+
+    #[test]
+    fn test_is_binary() {
+        assert!(!is_binary(b""));
+        assert!(!is_binary("theme = \"dark\" # ☾\n".as_bytes()));
+        assert!(is_binary(b"\x1a\x01\x00\x00xterm"));
+        assert!(is_binary(b"\xff\xd8\xff\xe0"));
+    }
+
     #[test]
     fn test_run_command_vec() {
         let path = Path::new("/tmp/test-jtd");
         let command_vec = vec![format!("touch {}", path.to_string_lossy())];
-        run_command_vec(&command_vec).expect("Could not run command vec");
+        run_command_vec(&command_vec, DEFAULT_SHELL, &HashMap::new(), None)
+            .expect("Could not run command vec");
         assert!(Path::new("/tmp/test-jtd").exists());
     }
 
+    #[test]
+    fn test_command_vec_succeeds() {
+        let env = HashMap::from([("EXPECTED".to_string(), "yes".to_string())]);
+        assert!(command_vec_succeeds(
+            &[
+                "command -v sh".to_string(),
+                "[ \"$EXPECTED\" = yes ]".to_string()
+            ],
+            DEFAULT_SHELL,
+            &env
+        )
+        .unwrap());
+        assert!(!command_vec_succeeds(
+            &[
+                "true".to_string(),
+                "command -v jtd-missing-command".to_string()
+            ],
+            DEFAULT_SHELL,
+            &env
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_run_command_vec_shell_syntax() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output");
+        let command_vec = vec![format!(
+            "echo 'hello   world' | tr a-z A-Z > {} && echo done >> {}",
+            path.to_string_lossy(),
+            path.to_string_lossy()
+        )];
+
+        run_command_vec(&command_vec, DEFAULT_SHELL, &HashMap::new(), None)
+            .expect("Could not run command vec");
+
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "HELLO   WORLD\ndone\n"
+        );
+    }
+
+    #[test]
+    fn test_run_command_vec_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output");
+        let command_vec = vec![format!("echo \"$GREETING\" > {}", path.to_string_lossy())];
+        let env = HashMap::from([("GREETING".to_string(), "hello there".to_string())]);
+
+        run_command_vec(&command_vec, DEFAULT_SHELL, &env, None)
+            .expect("Could not run command vec");
+
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "hello there\n");
+    }
+
+    #[test]
+    fn test_run_command_vec_timeout() {
+        let command_vec = vec!["sleep 5".to_string()];
+        let started = Instant::now();
+
+        let result = run_command_vec(
+            &command_vec,
+            DEFAULT_SHELL,
+            &HashMap::new(),
+            Some(Duration::from_millis(100)),
+        );
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Step #0 \"sleep 5\" timed out after 0 seconds"
+        );
+    }
+
+    #[test]
+    fn test_run_command_vec_timeout_kills_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("never_created");
+        let command_vec = vec![format!(
+            "(sleep 1 && touch {}) & wait",
+            path.to_string_lossy()
+        )];
+
+        let result = run_command_vec(
+            &command_vec,
+            DEFAULT_SHELL,
+            &HashMap::new(),
+            Some(Duration::from_millis(100)),
+        );
+
+        assert!(result.is_err());
+        thread::sleep(Duration::from_millis(1500));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_run_command_vec_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("never_created");
+        let command_vec = vec![
+            "true".to_string(),
+            "exit 3".to_string(),
+            format!("touch {}", path.to_string_lossy()),
+        ];
+
+        let result = run_command_vec(&command_vec, DEFAULT_SHELL, &HashMap::new(), None);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Step #1 \"exit 3\" failed with exit code 3"
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_resolve_proxy() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let all_proxy = &[("ALL_PROXY", "http://all:3128")];
+        let https_proxy = &[
+            ("ALL_PROXY", "http://all:3128"),
+            ("https_proxy", "http://https:3128"),
+        ];
+
+        assert_eq!(resolve_proxy(None, env(&[])), None);
+        assert_eq!(
+            resolve_proxy(None, env(all_proxy)),
+            Some("http://all:3128".to_string())
+        );
+        assert_eq!(resolve_proxy(None, env(https_proxy)), None);
+        assert_eq!(
+            resolve_proxy(Some("http://flag:8080".to_string()), env(https_proxy)),
+            Some("http://flag:8080".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resolve_data_dir() {
+        let home = shellexpand::tilde("~").to_string();
+        assert_eq!(
+            resolve_data_dir(None, None, None),
+            Path::new(&home).join(".local/share/jointhedots")
+        );
+        assert_eq!(
+            resolve_data_dir(None, None, Some(PathBuf::from("/xdg"))),
+            Path::new("/xdg/jointhedots")
+        );
+        assert_eq!(
+            resolve_data_dir(None, None, Some(PathBuf::from("relative"))),
+            Path::new(&home).join(".local/share/jointhedots")
+        );
+        assert_eq!(
+            resolve_data_dir(
+                None,
+                Some(PathBuf::from("~/jtd")),
+                Some(PathBuf::from("/xdg"))
+            ),
+            Path::new(&home).join("jtd")
+        );
+        assert_eq!(
+            resolve_data_dir(
+                Some(PathBuf::from("/data")),
+                Some(PathBuf::from("/env")),
+                Some(PathBuf::from("/xdg"))
+            ),
+            Path::new("/data")
+        );
+    }
+
+    #[test]
+    fn test_check_interactive() {
+        // The flag is passed in rather than set, as other tests read it while they run
+        assert!(check_interactive(false, "Input is required").is_ok());
+        assert!(check_interactive(true, "Input is required")
+            .unwrap_err()
+            .to_string()
+            .starts_with("Input is required, but jtd is running non-interactively"));
+    }
+
     #[test]
     fn test_hash_command_vec() {
         let command_vec = vec![
@@ -74,9 +1233,26 @@ mod tests {
             String::from("echo \"Farewell!\""),
         ];
 
+        let hash = hash_command_vec(&command_vec);
         assert_eq!(
-            hash_command_vec(&command_vec),
-            "b51a85b8eeee922159d23463ffc057ab25fbaf9b".to_string()
+            hash,
+            "dd0acc5bcac9c34725e2952074b12f2350426b053a67f1a1d4afa5defd6f1bf2"
+        );
+        assert!(command_vec_matches_hash(&command_vec, &hash));
+        // Recorded by older versions of jtd
+        assert!(command_vec_matches_hash(
+            &command_vec,
+            "b51a85b8eeee922159d23463ffc057ab25fbaf9b"
+        ));
+        assert!(!command_vec_matches_hash(
+            &command_vec[1..],
+            "b51a85b8eeee922159d23463ffc057ab25fbaf9b"
+        ));
+        assert!(!command_vec_matches_hash(&command_vec, ""));
+
+        assert_ne!(
+            hash_command_vec(&[String::from("ab")]),
+            hash_command_vec(&[String::from("a"), String::from("b")])
         );
     }
 }